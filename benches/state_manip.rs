@@ -1,7 +1,7 @@
 use std::hint::black_box;
 use criterion::{criterion_group, criterion_main, Criterion};
 use futures::executor::block_on;
-use hexstack::{Piece, Species, Player, Position, Tile};
+use hexstack::{Species, Player, Position, Tile};
 
 fn criterion_benchmark(c: &mut Criterion) {
     let state0 = Position::setup();
@@ -24,6 +24,23 @@ fn criterion_benchmark(c: &mut Criterion) {
     }));
 
 
+    c.bench_function("clone apply", |b| b.iter(|| {
+        for &ply in av_moves.iter(){
+            let mut state = state0.clone();
+            state.apply_move(ply);
+            black_box(&state);
+        }
+    }));
+
+    let mut state_mk = state0.clone();
+    c.bench_function("make unmake", |b| b.iter(|| {
+        for &ply in av_moves.iter(){
+            let undo = state_mk.make_move(ply);
+            black_box(&state_mk);
+            state_mk.unmake_move(undo);
+        }
+    }));
+
     c.bench_function("stage translate", |b|b.iter(||{
         let mut state = state0.clone();
         state.stage_translate(*ply);
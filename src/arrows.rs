@@ -59,7 +59,7 @@ pub fn draw_arrow_bowed(
     );
 }
 
-#[allow(dead_code)]
+#[allow(dead_code, clippy::too_many_arguments)]
 pub fn draw_arrow_outlined(start : Vec2, end : Vec2, color : Color, thickness : f32, head_length : f32, head_width : f32, outline_color : Color, outline_thickness : f32){
 
     draw_arrow(
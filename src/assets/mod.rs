@@ -39,7 +39,7 @@ impl RandomClip{
     }
 }
 
-const FONT_PATH : &'static str = "gfx/Lexend-Light.ttf";
+const FONT_PATH : &str = "gfx/Lexend-Light.ttf";
 
 #[derive(Clone, Copy,PartialEq, Eq)]
 pub enum PieceSet{
@@ -252,7 +252,7 @@ impl Assets{
                                 egui::CentralPanel::default()
                                 .show(egui_ctx, |ui|{
                                     ui.label(
-                                        &format!("Error loading assets: {}", error)
+                                        format!("Error loading assets: {}", error)
                                     )
                                 });
                             });
@@ -313,7 +313,7 @@ impl Assets{
         let font = load_ttf_font(FONT_PATH).await?;
         font.set_filter(FilterMode::Linear);
 
-        let font_bytes = macroquad::file::load_file(&FONT_PATH)
+        let font_bytes = macroquad::file::load_file(FONT_PATH)
             .await?;
 
         
@@ -446,10 +446,8 @@ pub trait Content : Clone + Copy{
     fn load_unchecked(path : &str) -> impl Future<Output = Self> + Send;
 }
 impl Content for Texture2D{
-    fn load_unchecked(path : &str) -> impl Future<Output = Self> + Send {
-        async {
-            load_texture(path).await.unwrap()
-        }
+    async fn load_unchecked(path : &str) -> Self {
+        load_texture(path).await.unwrap()
     }
 }
 
@@ -0,0 +1,103 @@
+//! Opening book builder.
+//!
+//! Usage:
+//! - `book build [--analysis <file>]... [--records <file>]... [--plies <n>] [--min-weight <n>] [--out <file>]`
+//! - `book show <file> [<position string>]`
+//!
+//! Analysis files are reports of the `openings` binary; records files hold game
+//! records one after the other, of which the first plies are taken.
+//! An existing output book is extended.
+
+use hexstack::tokonoma::{Captured, GameRecord, OpeningBook, PlayerMap, Position};
+
+const DEFAULT_BOOK : &str = "book.hxob";
+const DEFAULT_PLIES : usize = 12;
+
+fn usage() -> !{
+    eprintln!("usage: book build [--analysis <file>]... [--records <file>]... [--plies <n>] [--min-weight <n>] [--out <file>]");
+    eprintln!("       book show <file> [<position string>]");
+    std::process::exit(2);
+}
+
+fn fail(message : String) -> !{
+    eprintln!("{}", message);
+    std::process::exit(2);
+}
+
+fn read(path : &str) -> String{
+    std::fs::read_to_string(path).unwrap_or_else(|e|fail(format!("could not read {}: {}", path, e)))
+}
+
+fn main(){
+    let args : Vec<String> = std::env::args().skip(1).collect();
+
+    match args.first().map(|s|s.as_str()){
+        Some("build") => build(&args[1..]),
+        Some("show") => show(&args[1..]),
+        _ => usage()
+    }
+}
+
+fn build(args : &[String]){
+    let mut out = DEFAULT_BOOK.to_owned();
+    let mut analyses = vec![];
+    let mut records = vec![];
+    let mut plies = DEFAULT_PLIES;
+    let mut min_weight = 1;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next(){
+        let mut value = ||args.next().unwrap_or_else(||usage()).clone();
+        match arg.as_str(){
+            "--analysis" => analyses.push(value()),
+            "--records" => records.push(value()),
+            "--plies" => plies = value().parse().unwrap_or_else(|_|usage()),
+            "--min-weight" => min_weight = value().parse().unwrap_or_else(|_|usage()),
+            "--out" => out = value(),
+            _ => usage()
+        }
+    }
+    if analyses.is_empty() && records.is_empty(){
+        usage();
+    }
+
+    let mut book = OpeningBook::load(&out).unwrap_or_default();
+    for path in &analyses{
+        if let Err(e) = book.add_analysis(&read(path)){
+            fail(format!("{}: {}", path, e));
+        }
+    }
+
+    // only the games are filtered by weight; analysis moves count once each
+    let mut from_games = OpeningBook::new();
+    for path in &records{
+        for (index, record) in GameRecord::parse_all(&read(path)).into_iter().enumerate(){
+            if let Err(e) = record.and_then(|record|from_games.add_record(&record, plies)){
+                eprintln!("{}, record {}: {}", path, index + 1, e);
+            }
+        }
+    }
+    from_games.prune(min_weight);
+    book.merge(&from_games);
+
+    book.save(&out).unwrap_or_else(|e|fail(format!("could not write {}: {}", out, e)));
+    println!("{} positions in {}", book.len(), out);
+}
+
+fn show(args : &[String]){
+    let (path, position) = match args{
+        [path] => (path, Position::setup()),
+        [path, position] => (path, position.parse().unwrap_or_else(|e|fail(format!("invalid position string: {:?}", e)))),
+        _ => usage()
+    };
+    let book = OpeningBook::load(path).unwrap_or_else(|e|fail(format!("could not load {}: {}", path, e)));
+
+    let moves = book.moves(&position);
+    if moves.is_empty(){
+        println!("not in the book");
+    }
+    for book_move in moves{
+        let entry = position.compute_history_entry(book_move.ply, PlayerMap::twin(Captured::empty()));
+        println!("{:<8} {:>6} {:>5.1}%", entry.to_string(), book_move.weight, 100.0 * book_move.score);
+    }
+}
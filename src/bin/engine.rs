@@ -0,0 +1,167 @@
+//! The hexstack engine behind the text protocol described in `hexstack::engine_protocol`,
+//! for front ends, tournament managers and scripts.
+//!
+//! Reads commands on stdin and answers on stdout. At the end of the input,
+//! waits for the running search, if any, before leaving.
+
+use std::io::{self, BufRead};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+use futures::executor::block_on;
+use web_time::Instant;
+
+use hexstack::engine_protocol::{EngineReply, EngineScore, GoParams, GuiCommand, SearchInfo};
+use hexstack::tokonoma::{EvalParams, OpeningBook, Position, SearchLimits, StopToken, Tablebase, TranspositionalTable};
+
+struct Engine{
+    position : Position,
+    transposition_table : Arc<TranspositionalTable>,
+    threads : usize,
+    eval : EvalParams,
+    /// Variety of book moves, see `OpeningBook::choose`; `None` without a book.
+    book_variety : Option<f32>,
+    book : Option<Arc<OpeningBook>>,
+    tablebase : Option<Arc<Tablebase>>,
+    search : Option<(StopToken, JoinHandle<()>)>,
+}
+
+fn reply(reply : EngineReply){
+    println!("{}", reply);
+}
+
+fn main(){
+    let mut engine = Engine{
+        position : Position::setup(),
+        transposition_table : Arc::new(TranspositionalTable::new()),
+        threads : 1,
+        eval : EvalParams::DEFAULT,
+        book_variety : None,
+        book : None,
+        tablebase : None,
+        search : None,
+    };
+
+    for line in io::stdin().lock().lines(){
+        let Ok(line) = line else {break};
+        if line.trim().is_empty(){
+            continue;
+        }
+        match line.parse::<GuiCommand>(){
+            Ok(GuiCommand::Quit) => {
+                engine.stop();
+                return;
+            },
+            Ok(command) => engine.handle(command),
+            Err(e) => reply(EngineReply::InfoString(e.to_string())),
+        }
+    }
+    // the GUI is gone: an infinite search would never be stopped otherwise
+    engine.stop();
+}
+
+impl Engine{
+    fn handle(&mut self, command : GuiCommand){
+        match command{
+            GuiCommand::Hello => {
+                reply(EngineReply::Id{key : "name".to_owned(), value : format!("hexstack {}", env!("CARGO_PKG_VERSION"))});
+                reply(EngineReply::HelloOk);
+            },
+            GuiCommand::IsReady => reply(EngineReply::ReadyOk),
+            GuiCommand::SetOption { name, value } => self.set_option(&name, &value),
+            GuiCommand::NewGame => {
+                self.stop();
+                self.transposition_table.clear();
+            },
+            GuiCommand::Position { setup, moves } => {
+                self.position = GuiCommand::position_after(&setup, &moves);
+            },
+            GuiCommand::Go(params) => self.go(params),
+            GuiCommand::Stop => self.stop(),
+            GuiCommand::Quit => unreachable!(),
+        }
+    }
+
+    fn set_option(&mut self, name : &str, value : &str){
+        let result = match name.to_ascii_lowercase().as_str(){
+            "threads" => value.parse().map(|n : usize|self.threads = n.max(1)).map_err(|_|"not a number".to_owned()),
+            "hash" => value.parse().map(|mb|{
+                self.stop();
+                self.transposition_table = Arc::new(TranspositionalTable::with_size_mb(mb));
+            }).map_err(|_|"not a number".to_owned()),
+            "evalfile" => EvalParams::load(value).map(|params|{
+                self.stop();
+                self.eval = params;
+                self.transposition_table.clear();
+            }),
+            "book" => OpeningBook::load(value).map(|book|{
+                self.book = Some(Arc::new(book));
+                self.book_variety.get_or_insert(0.0);
+            }).map_err(|e|e.to_string()),
+            "bookvariety" => value.parse().ok().filter(|v : &f32|*v >= 0.0)
+                .map(|v|self.book_variety = Some(v)).ok_or_else(||"not a non-negative number".to_owned()),
+            "tablebase" => Tablebase::load_dir(value).map(|tablebase|self.tablebase = Some(Arc::new(tablebase))).map_err(|e|e.to_string()),
+            _ => Err("unknown option".to_owned())
+        };
+        if let Err(e) = result{
+            reply(EngineReply::InfoString(format!("setoption {}: {}", name, e)));
+        }
+    }
+
+    fn go(&mut self, params : GoParams){
+        self.stop();
+
+        // book moves are answered at once, like a finished search
+        if let Some(ply) = self.book_variety.and_then(|variety|
+            self.book.as_ref()?.choose(&self.position, variety, &mut rand::thread_rng())
+        ){
+            reply(EngineReply::InfoString("book move".to_owned()));
+            reply(EngineReply::BestMove(Some(ply)));
+            return;
+        }
+
+        let start = Instant::now();
+        let limits : SearchLimits = params.limits().with_threads(self.threads).with_eval(self.eval)
+            .with_tablebase(self.tablebase.clone());
+        let stop = StopToken::new();
+        let position = self.position.clone();
+        let transposition_table = self.transposition_table.clone();
+
+        let handle = std::thread::spawn({
+            let stop = stop.clone();
+            move ||{
+                let to_play = position.to_play();
+                let outcome = block_on(position.search(
+                    limits, stop, Some(transposition_table), false,
+                    |iteration|{
+                        if let Some((_, best)) = iteration.moves.first(){
+                            reply(EngineReply::Info(SearchInfo{
+                                depth : iteration.depth,
+                                score : EngineScore::of_root_move(best.score, to_play),
+                                nodes : iteration.nodes,
+                                time : start.elapsed(),
+                                pv : best.pv.clone(),
+                            }));
+                        }
+                    }
+                ));
+                reply(EngineReply::BestMove(outcome.best_move()));
+            }
+        });
+        self.search = Some((stop, handle));
+    }
+
+    /// Stops the running search; its `bestmove` is written before this returns.
+    fn stop(&mut self){
+        if let Some((stop, _)) = &self.search{
+            stop.stop();
+        }
+        self.wait();
+    }
+
+    fn wait(&mut self){
+        if let Some((_, handle)) = self.search.take(){
+            handle.join().unwrap();
+        }
+    }
+}
@@ -0,0 +1,84 @@
+//! Game server for online play, see `hexstack::networking::Server`.
+//!
+//! Usage: `hexstack-server [--listen <address>] [--takebacks on|off] [--records <file>] [--idle <minutes>]`
+//!
+//! - `--listen <address>`: where players connect (default `0.0.0.0:7878`); port 0 picks a free one
+//! - `--takebacks on|off`: whether players may take moves back (default off)
+//! - `--records <file>`: append the records of finished games (default `server_games.txt`)
+//! - `--idle <minutes>`: close rooms both players left that long ago, recording
+//!   their games unfinished (default 30)
+//!
+//! Players join with `tui --server <address> --room <code>`, `--room new` opening a room
+//! with a fresh invite code. Type `list` at the prompt for the rooms, `quit` to stop.
+
+use std::io::{self, BufRead, Write};
+use std::net::TcpListener;
+use std::time::Duration;
+
+use hexstack::networking::{with_default_port, Server, ServerConfig};
+
+const DEFAULT_RECORDS : &str = "server_games.txt";
+
+fn usage() -> !{
+    eprintln!("usage: hexstack-server [--listen address] [--takebacks on|off] [--records file] [--idle minutes]");
+    std::process::exit(2);
+}
+
+fn fail(message : String) -> !{
+    eprintln!("{}", message);
+    std::process::exit(2);
+}
+
+fn main(){
+    let mut address = "0.0.0.0".to_owned();
+    let mut config = ServerConfig{records : Some(DEFAULT_RECORDS.into()), ..Default::default()};
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next(){
+        let mut value = ||args.next().unwrap_or_else(||usage());
+        match arg.as_str(){
+            "--listen" => address = value(),
+            "--takebacks" => config.takebacks = match value().as_str(){
+                "on" => true,
+                "off" => false,
+                other => fail(format!("takebacks must be on or off, not {}", other))
+            },
+            "--records" => config.records = Some(value().into()),
+            "--idle" => config.idle_timeout = match value().parse::<u64>(){
+                Ok(minutes) => Duration::from_secs(60*minutes),
+                Err(_) => usage()
+            },
+            _ => usage()
+        }
+    }
+
+    let address = with_default_port(&address);
+    let listener = TcpListener::bind(&address).unwrap_or_else(|e|fail(format!("could not listen on {}: {}", address, e)));
+    println!("listening on {}", listener.local_addr().unwrap());
+    io::stdout().flush().unwrap();
+
+    let server = Server::new(config);
+    let serving = server.clone();
+    let serve = std::thread::spawn(move ||serving.serve(listener));
+
+    for line in io::stdin().lock().lines(){
+        let Ok(line) = line else {break};
+        match line.trim(){
+            "" => {},
+            "list" => {
+                let rooms = server.rooms();
+                if rooms.is_empty(){
+                    println!("no rooms");
+                }
+                for room in rooms{
+                    println!("{}", room);
+                }
+            },
+            "quit" => return,
+            other => println!("unknown command {}; try list or quit", other),
+        }
+        io::stdout().flush().unwrap();
+    }
+    // without a console, serve until killed
+    let _ = serve.join();
+}
@@ -34,7 +34,7 @@ impl Illustration{
         let img = self.tex.texture.get_texture_data();
         let mut path_temp = std::env::temp_dir();
         path_temp.push("tmpdiag.png");
-        img.export_png(&path_temp.to_str().unwrap());
+        img.export_png(path_temp.to_str().unwrap());
 
         let path_final = format!("diags/{}.webp",self.name);
         std::process::Command::new("magick")
@@ -49,7 +49,7 @@ impl Illustration{
 }
 
 fn draw_piece(color : Player, species : Species, tile : Tile){
-    let (x,y) = tile.to_world(false).into();
+    let (x,y) = tile.to_world(false);
     Piece{color,species}.draw(x, y,  1.0);
 }
 
@@ -104,12 +104,9 @@ async fn main(){
         for (cn,color) in [("white",Player::White), ("black",Player::Black)]{
 
             let start_tile = match pt {
-                Species::Lone(tall) => match tall{
-                    Tall::Star => match color{
-                        Player::White => Tile::from_xyz(0, -1, 1).unwrap(),
-                        Player::Black => Tile::from_xyz(0, 1, -1).unwrap(),
-                    },
-                    _ => center_tile
+                Species::Lone(Tall::Star) => match color{
+                    Player::White => Tile::from_xyz(0, -1, 1).unwrap(),
+                    Player::Black => Tile::from_xyz(0, 1, -1).unwrap(),
                 },
                 _ => center_tile
             };
@@ -152,10 +149,10 @@ async fn main(){
     );
 
     Tile::draw_board(false);
-    let (x,y) = Tile::from_xyz(0, 1, -1).unwrap().to_world(false).into();
+    let (x,y) = Tile::from_xyz(0, 1, -1).unwrap().to_world(false);
     Piece{color : Player::White, species : Species::Flat}.draw(x, y,  1.0);
 
-    let (x,y) = Tile::from_xyz(0, -1, 1).unwrap().to_world(false).into();
+    let (x,y) = Tile::from_xyz(0, -1, 1).unwrap().to_world(false);
     Piece{color : Player::White, species : Species::Lone(Tall::Hand)}.draw(x, y,  1.0);
 
 
@@ -166,10 +163,10 @@ async fn main(){
     );
 
     Tile::draw_board(false);
-    let (x,y) = Tile::from_xyz(0, 1, -1).unwrap().to_world(false).into();
+    let (x,y) = Tile::from_xyz(0, 1, -1).unwrap().to_world(false);
     Piece{color : Player::White, species : Species::Stack(Tall::Hand)}.draw(x, y,  1.0);
 
-    let (sx,sy) = Tile::from_xyz(0, -1, 1).unwrap().to_world(false).into();
+    let (sx,sy) = Tile::from_xyz(0, -1, 1).unwrap().to_world(false);
     
     draw_arrow((sx,sy).into(), (-1.0,y).into(), Color::from_hex(0x111111),
     0.10,
@@ -1,75 +1,10 @@
-use std::{fmt::Display, iter::repeat_n, sync::{Arc, Mutex}};
+use std::{iter::repeat_n, sync::Arc};
 
 use futures::executor::block_on;
 
-use hexstack::{tokonoma::{Captured, Player, PlayerMap, Position, Score, TranspositionalTable}, Ply};
+use hexstack::{tokonoma::{Captured, PlayerMap, Position, Score, SearchLimits, StopToken, TranspositionalTable}, Ply};
 use itertools::Itertools;
 
-// const OPENING_DEPTH : usize = 2;
-const SAMPLES : usize = 100;
-const DRAW_THRESHOLD_PLIES : usize = 100;
-
-const BOT_DEPTH : usize = 4;
-
-struct SimResults{
-    white_victories : usize,
-    black_victories : usize,
-    draws : usize
-}
-
-impl Display for SimResults{
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f,"{}/{}/{}",self.white_victories,self.draws,self.black_victories)
-    }
-}
-
-#[allow(dead_code)]
-fn simulate(starting_state : Position) -> SimResults{
-    let mut results = SimResults{
-        white_victories : 0,
-        black_victories : 0,
-        draws : 0
-    };
-
-    let bar = indicatif::ProgressBar::new(SAMPLES as u64);
-    (0..SAMPLES).for_each(|_sample_idx|{
-        bar.inc(1);
-
-        let mut state = starting_state.clone();
-
-        let mut winner = None;
-        for _ply_num in 0..DRAW_THRESHOLD_PLIES{
-            
-            // println!("{}/{}",_sample_idx,_ply_num);
-            if let Some(this_winner) = state.is_won(){
-                winner = Some(this_winner);
-                break;
-            }
-
-            let scored_moves = 
-                futures::executor::block_on(state.clone().moves_with_score(BOT_DEPTH,false, None));
-
-            let (ply,_) = scored_moves.first().unwrap();
-            state.apply_move(*ply);
-
-            
-        };
-
-        
-        
-        match winner{
-            None => results.draws += 1,
-            Some(Player::White) => results.white_victories+=1,
-            Some(Player::Black) => results.black_victories+=1
-        };
-    });
-    bar.finish();
-
-    
-
-    results
-}
-
 #[allow(dead_code)]
 fn expand_node(args : (Vec<Ply>, Position)) -> Vec<(Vec<Ply>,Position)>{
     let (moves_hist,pos) = args;
@@ -88,8 +23,11 @@ fn expand_node(args : (Vec<Ply>, Position)) -> Vec<(Vec<Ply>,Position)>{
 const OPENING_DEPTH : usize = 2;
 const SEARCH_DEPTH : usize = 9;
 
-fn print_section_report(position : Position, current_depth : usize, transp : Arc<Mutex<TranspositionalTable>>) -> String{
-    let res = block_on(position.clone().moves_with_score(SEARCH_DEPTH+OPENING_DEPTH-current_depth, false, Some(transp.clone())));
+fn print_section_report(position : Position, current_depth : usize, transp : Arc<TranspositionalTable>) -> String{
+    let res = block_on(position.clone().search(
+        SearchLimits::depth(SEARCH_DEPTH+OPENING_DEPTH-current_depth).with_threads(SearchLimits::available_threads()),
+        StopToken::new(), Some(transp.clone()), false, |_|{}
+    )).moves;
 
     //let mean_score = Score::mean(res.iter().map(|(_,ev)|ev.score).collect());
     let top_score = res.first().map_or(Score::EVEN,
@@ -109,11 +47,13 @@ fn print_section_report(position : Position, current_depth : usize, transp : Arc
             None
         };
         let he = position.compute_history_entry(ply,PlayerMap::twin(Captured::empty()));
+        let continuation = he.state_after.line_notation(&er.pv[1..]);
 
-        format!("{}{}\t{}\t[{}]{}",
+        format!("{}{}\t{}\t[{}]\t{}{}",
             repeat_n('\t',current_depth).join(""),
             he,
             er.score, er.nodes,
+            continuation,
             inner.map_or("".to_string(), |i|format!("\n{}",i))
         )
     }).join("\n")
@@ -141,11 +81,11 @@ fn main(){
     // println!("{} distinct positions from 2 plies. ({} sequences)", expanded_positions.len(), sequences_len);
 
     // expanded_positions.iter().for_each(|(p,h)|{
-    //     println!("{} {}", p.tabulation_hash(), h.iter().map(|v|format!("{}",v)).join(" "));
+    //     println!("{} {}", p.zobrist(), h.iter().map(|v|format!("{}",v)).join(" "));
     // });
 
 
-    let table = Arc::new(Mutex::new(TranspositionalTable::new()));
+    let table = Arc::new(TranspositionalTable::new());
     let state0 = Position::setup();
 
     println!("{}", print_section_report(state0, 0, table))
@@ -0,0 +1,48 @@
+//! Move generation counter.
+//!
+//! Usage: `perft <depth> [position string] [--divide]`
+//! Without a position string the standard setup is used.
+
+use std::time::Instant;
+
+use hexstack::tokonoma::Position;
+
+fn main(){
+    let args : Vec<String> = std::env::args().skip(1).collect();
+    let divide = args.iter().any(|a|a == "--divide");
+    let positional : Vec<&String> = args.iter().filter(|a|!a.starts_with("--")).collect();
+
+    let Some(depth) = positional.first().and_then(|d|d.parse::<usize>().ok()) else {
+        eprintln!("usage: perft <depth> [position string] [--divide]");
+        std::process::exit(2);
+    };
+
+    let position = match positional.get(1){
+        Some(pstring) => match pstring.parse::<Position>(){
+            Ok(position) => position,
+            Err(e) => {
+                eprintln!("invalid position string {}: {:?}", pstring, e);
+                std::process::exit(2);
+            }
+        },
+        None => Position::setup()
+    };
+
+    let start = Instant::now();
+    let total = if divide && depth > 0{
+        let counts = position.divide(depth);
+        for (ply, count) in &counts{
+            println!("{} {}", ply, count);
+        }
+        println!();
+        counts.iter().map(|(_,count)|count).sum()
+    } else {
+        position.perft(depth)
+    };
+    let elapsed = start.elapsed();
+
+    println!("{} nodes at depth {} ({:.2?}, {:.0} nodes/s)",
+        total, depth, elapsed,
+        total as f64 / elapsed.as_secs_f64().max(1e-9)
+    );
+}
@@ -15,12 +15,9 @@ fn search_shortest(state : Position, depth : usize) -> Option<Vec<Ply>>{
         let mut copy = state.clone();
         copy.apply_move(ply);
 
-        match search_shortest(copy, depth-1){
-            Some(mut winning_match) => {
-                winning_match.push(ply);
-                return Some(winning_match)
-            },
-            None => {}
+        if let Some(mut winning_match) = search_shortest(copy, depth-1){
+            winning_match.push(ply);
+            return Some(winning_match)
         }
     };
 
@@ -77,7 +74,7 @@ impl Searcher{
     }
     #[allow(dead_code)]
     fn search_max(&mut self, state : Position, depth : usize) -> (Score,Vec<Ply>){
-        match self.0.entry(state.tabulation_hash()){
+        match self.0.entry(state.zobrist()){
             Entry::Occupied(entry) => entry.get().clone(),
             Entry::Vacant(vacancy) => {
                 let value = search_max(state, depth);
@@ -88,7 +85,7 @@ impl Searcher{
     }
     #[allow(dead_code)]
     fn search_min(&mut self, state : Position, depth : usize) -> (Score,Vec<Ply>){
-        match self.0.entry(state.tabulation_hash()){
+        match self.0.entry(state.zobrist()){
             Entry::Occupied(entry) => entry.get().clone(),
             Entry::Vacant(vacancy) => {
                 let value = search_min(state, depth);
@@ -125,12 +122,9 @@ fn search_shortest_path(state : Position, depth : usize) -> Option<Vec<Ply>>{
         };
 
         if good {
-            match search_shortest_path(copy, depth-1){
-                Some(mut winning_match) => {
-                    winning_match.push(ply);
-                    return Some(winning_match)
-                },
-                None => {}
+            if let Some(mut winning_match) = search_shortest_path(copy, depth-1){
+                winning_match.push(ply);
+                return Some(winning_match)
             }
         }
     };
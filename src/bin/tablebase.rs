@@ -0,0 +1,93 @@
+//! Endgame tablebase generator.
+//!
+//! Usage:
+//! - `tablebase generate <signature>... [--out <directory>]`
+//! - `tablebase probe <directory> <position string>`
+//!
+//! Signatures list each side's pieces with position string letters,
+//! white then black separated by `_`, e.g. `fa_oh`.
+//! Tables of smaller signatures reachable by captures are generated as well.
+
+use std::time::Instant;
+
+use hexstack::tokonoma::{Position, Signature, Tablebase};
+
+const DEFAULT_DIRECTORY : &str = "tablebases";
+
+fn usage() -> !{
+    eprintln!("usage: tablebase generate <signature>... [--out <directory>]");
+    eprintln!("       tablebase probe <directory> <position string>");
+    std::process::exit(2);
+}
+
+fn main(){
+    let args : Vec<String> = std::env::args().skip(1).collect();
+
+    match args.first().map(|s|s.as_str()){
+        Some("generate") => generate(&args[1..]),
+        Some("probe") => probe(&args[1..]),
+        _ => usage()
+    }
+}
+
+fn generate(args : &[String]){
+    let mut directory = DEFAULT_DIRECTORY.to_owned();
+    let mut signatures = vec![];
+    let mut args = args.iter();
+    while let Some(arg) = args.next(){
+        if arg == "--out"{
+            directory = args.next().unwrap_or_else(||usage()).clone();
+        } else {
+            match arg.parse::<Signature>(){
+                Ok(signature) => signatures.push(signature),
+                Err(e) => {
+                    eprintln!("invalid signature {}: {:?}", arg, e);
+                    std::process::exit(2);
+                }
+            }
+        }
+    }
+    if signatures.is_empty(){
+        usage();
+    }
+
+    // extend what is already on disk
+    let mut tablebase = Tablebase::load_dir(&directory).unwrap_or_default();
+    for signature in signatures{
+        let mut start = Instant::now();
+        tablebase.generate(signature, |solved, positions|{
+            println!("{:>12} {:>10} positions ({:.2?})", solved.to_string(), positions, start.elapsed());
+            start = Instant::now();
+        });
+    }
+
+    if let Err(e) = tablebase.save_dir(&directory){
+        eprintln!("could not write {}: {}", directory, e);
+        std::process::exit(1);
+    }
+    println!("{} tables in {}", tablebase.signatures().count(), directory);
+}
+
+fn probe(args : &[String]){
+    let [directory, pstring] = args else {usage()};
+
+    let tablebase = match Tablebase::load_dir(directory){
+        Ok(tablebase) => tablebase,
+        Err(e) => {
+            eprintln!("could not read {}: {}", directory, e);
+            std::process::exit(1);
+        }
+    };
+    let position = match pstring.parse::<Position>(){
+        Ok(position) => position,
+        Err(e) => {
+            eprintln!("invalid position string {}: {:?}", pstring, e);
+            std::process::exit(2);
+        }
+    };
+
+    match tablebase.probe(&position){
+        Some(value) => println!("{:?}", value),
+        None => println!("not covered")
+    }
+}
@@ -0,0 +1,264 @@
+//! Engine tournaments: round robin or gauntlet, with Elo estimates and optional SPRT.
+//!
+//! Usage: `tournament [options] <contestant> <contestant>...`
+//!
+//! Contestants are comma-separated `key=value` lists, see `hexstack::tournament::Contestant`,
+//! e.g. `depth=5`, `level=sharp`, `depth=4,blunder=0.2,name=sloppy` or `engine=./engine,movetime=200`.
+//!
+//! Options:
+//! - `--games <n>`: games per pairing (default 100), each opening played with both colors;
+//!   the most games to play under `--sprt`
+//! - `--gauntlet`: the first contestant plays each of the others, instead of everyone playing everyone
+//! - `--concurrency <n>`: games played at once (default: hardware threads)
+//! - `--openings <file>`: position strings, one per line; by default all positions two plies in
+//! - `--records <file>`: append the game records
+//! - `--book <file>`: opening book for the contestants with a `book` variety
+//! - `--sprt <elo0>,<elo1>[,<alpha>,<beta>]`: for two contestants, stop as soon as
+//!   the first is shown to be at most `elo0` or at least `elo1` stronger
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+
+use hexstack::tokonoma::{DrawRules, GameRecord, OpeningBook, PlayerMap, Position, SearchLimits};
+use hexstack::tournament::{default_openings, play_game, Contestant, Mover, Sprt, SprtDecision, Tally};
+use hexstack::Player;
+
+const DEFAULT_GAMES : usize = 100;
+const DEFAULT_OPENING_PLIES : usize = 2;
+
+fn usage() -> !{
+    eprintln!("usage: tournament [--games n] [--gauntlet] [--concurrency n] [--openings file] [--records file]");
+    eprintln!("                  [--book file] [--sprt elo0,elo1[,alpha,beta]] <contestant> <contestant>...");
+    eprintln!("contestants: e.g. depth=5 | level=sharp | depth=4,blunder=0.2,name=sloppy | engine=./engine,movetime=200");
+    std::process::exit(2);
+}
+
+fn fail(message : String) -> !{
+    eprintln!("{}", message);
+    std::process::exit(2);
+}
+
+/// One game: which pairing, and its number within the pairing.
+#[derive(Clone, Copy)]
+struct Job{
+    pairing : usize,
+    game : usize,
+}
+
+struct Tournament{
+    contestants : Vec<Contestant>,
+    /// Indices into `contestants`, first one of each pair is the reference side.
+    pairings : Vec<(usize, usize)>,
+    games : usize,
+    openings : Vec<Position>,
+}
+
+impl Tournament{
+    fn job(&self, index : usize) -> Option<Job>{
+        (index < self.pairings.len() * self.games).then(||Job{
+            pairing : index % self.pairings.len(),
+            game : index / self.pairings.len()
+        })
+    }
+
+    /// Colors alternate, each opening is played twice in a row.
+    fn setup(&self, job : Job) -> (PlayerMap<usize>, &Position){
+        let (first, second) = self.pairings[job.pairing];
+        let sides = if job.game.is_multiple_of(2) {PlayerMap::new(first, second)} else {PlayerMap::new(second, first)};
+        (sides, &self.openings[(job.game / 2) % self.openings.len()])
+    }
+}
+
+fn main(){
+    let mut games = DEFAULT_GAMES;
+    let mut gauntlet = false;
+    let mut concurrency = SearchLimits::available_threads();
+    let mut openings = None;
+    let mut records_path = None;
+    let mut sprt = None;
+    let mut contestants = vec![];
+    let mut book = None;
+
+    let args : Vec<String> = std::env::args().skip(1).collect();
+    let mut args = args.iter();
+    while let Some(arg) = args.next(){
+        let mut value = ||args.next().unwrap_or_else(||usage()).clone();
+        match arg.as_str(){
+            "--games" => games = value().parse().unwrap_or_else(|_|usage()),
+            "--gauntlet" => gauntlet = true,
+            "--concurrency" => concurrency = value().parse::<usize>().unwrap_or_else(|_|usage()).max(1),
+            "--openings" => openings = Some(read_openings(&value())),
+            "--records" => records_path = Some(value()),
+            "--book" => {
+                let path = value();
+                book = Some(Arc::new(OpeningBook::load(&path).unwrap_or_else(|e|fail(format!("could not load {}: {}", path, e)))));
+            },
+            "--sprt" => sprt = Some(value().parse::<Sprt>().unwrap_or_else(|e|fail(e))),
+            "--help" | "-h" => usage(),
+            spec => contestants.push(spec.parse::<Contestant>()
+                .unwrap_or_else(|e|fail(format!("contestant {}: {}", spec, e)))),
+        }
+    }
+    if contestants.len() < 2 || games == 0{
+        usage();
+    }
+    for contestant in &mut contestants{
+        contestant.book = book.clone();
+    }
+
+    let pairings : Vec<(usize, usize)> = if gauntlet{
+        (1..contestants.len()).map(|j|(0, j)).collect()
+    } else {
+        (0..contestants.len()).flat_map(|i|(i + 1..contestants.len()).map(move |j|(i, j))).collect()
+    };
+    if sprt.is_some() && pairings.len() != 1{
+        fail("--sprt needs exactly two contestants".to_owned());
+    }
+
+    let tournament = Tournament{
+        contestants,
+        pairings,
+        games,
+        openings : openings.unwrap_or_else(||default_openings(DEFAULT_OPENING_PLIES)),
+    };
+    let mut records = records_path.map(|path|OpenOptions::new().create(true).append(true).open(&path)
+        .unwrap_or_else(|e|fail(format!("could not open {}: {}", path, e))));
+
+    let tallies = run(&tournament, concurrency, sprt, |record|{
+        if let Some(file) = &mut records{
+            if let Err(e) = writeln!(file, "{}", record){
+                eprintln!("could not write the record: {}", e);
+            }
+        }
+    });
+    report(&tournament, &tallies);
+}
+
+/// Plays the games on `concurrency` threads, printing each result.
+/// Returns the tally of each pairing, from its first contestant's point of view.
+fn run(tournament : &Tournament, concurrency : usize, sprt : Option<Sprt>, mut on_record : impl FnMut(&GameRecord)) -> Vec<Tally>{
+    let total = tournament.pairings.len() * tournament.games;
+    let next_job = AtomicUsize::new(0);
+    let finished = AtomicBool::new(false);
+    let mut tallies = vec![Tally::default(); tournament.pairings.len()];
+
+    std::thread::scope(|scope|{
+        let (sender, receiver) = mpsc::channel();
+        for _ in 0..concurrency.min(total){
+            let sender = sender.clone();
+            let (next_job, finished) = (&next_job, &finished);
+            scope.spawn(move ||{
+                // engines are started once per thread, when first needed
+                let mut movers : Vec<Option<Mover>> = tournament.contestants.iter().map(|_|None).collect();
+                while !finished.load(Ordering::Relaxed){
+                    let Some(job) = tournament.job(next_job.fetch_add(1, Ordering::Relaxed)) else {break};
+                    let (sides, opening) = tournament.setup(job);
+
+                    for index in [sides[Player::White], sides[Player::Black]]{
+                        movers[index].get_or_insert_with(||Mover::new(&tournament.contestants[index]));
+                    }
+                    let [white, black] = movers.get_disjoint_mut([sides[Player::White], sides[Player::Black]]).unwrap();
+                    let names = PlayerMap::new(
+                        tournament.contestants[sides[Player::White]].name.clone(),
+                        tournament.contestants[sides[Player::Black]].name.clone()
+                    );
+                    let record = play_game(
+                        &mut PlayerMap::new(white.as_mut().unwrap(), black.as_mut().unwrap()),
+                        &names, opening, DrawRules::default()
+                    );
+                    if sender.send((job, record)).is_err(){
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        for (played, (job, record)) in receiver.into_iter().enumerate(){
+            let (sides, _) = tournament.setup(job);
+            let (first, second) = tournament.pairings[job.pairing];
+            let first_side = if sides[Player::White] == first {Player::White} else {Player::Black};
+            let tally = &mut tallies[job.pairing];
+            tally.add(record.outcome, first_side);
+
+            println!("[{:>5}/{}] {} - {}  {}  {}, {} plies",
+                played + 1, total,
+                record.players[Player::White], record.players[Player::Black],
+                result_text(&record), record.termination, record.moves.len());
+            on_record(&record);
+
+            if let Some(sprt) = sprt{
+                let (lower, upper) = sprt.bounds();
+                println!("        {} vs {}: {}  LLR {:.2} [{:.2}, {:.2}]",
+                    tournament.contestants[first].name, tournament.contestants[second].name,
+                    tally, sprt.llr(tally), lower, upper);
+                match sprt.decision(tally){
+                    SprtDecision::Continue => {},
+                    decision => {
+                        println!("SPRT: {}", match decision{
+                            SprtDecision::AcceptH1 => format!("H1 accepted, {} is at least {} Elo stronger", tournament.contestants[first].name, sprt.elo1),
+                            _ => format!("H0 accepted, {} is at most {} Elo stronger", tournament.contestants[first].name, sprt.elo0),
+                        });
+                        // games in progress are finished but not counted
+                        finished.store(true, Ordering::Relaxed);
+                        break;
+                    }
+                }
+            }
+        }
+    });
+
+    tallies
+}
+
+fn result_text(record : &GameRecord) -> &'static str{
+    match record.outcome.map(|o|o.winner()){
+        Some(Some(Player::White)) => "1-0",
+        Some(Some(Player::Black)) => "0-1",
+        Some(None) => "1/2",
+        None => "*",
+    }
+}
+
+fn report(tournament : &Tournament, tallies : &[Tally]){
+    let names : Vec<&str> = tournament.contestants.iter().map(|c|c.name.as_str()).collect();
+    let width = names.iter().map(|n|n.len()).max().unwrap_or(0);
+
+    println!();
+    for (&(first, second), tally) in tournament.pairings.iter().zip(tallies){
+        println!("{:>width$} vs {:<width$}  {}", names[first], names[second], tally, width = width);
+    }
+
+    if tournament.pairings.len() > 1{
+        // everyone's results against the field
+        println!();
+        let mut standings = vec![Tally::default(); names.len()];
+        for (&(first, second), tally) in tournament.pairings.iter().zip(tallies){
+            for (index, tally) in [(first, *tally), (second, tally.flip())]{
+                standings[index].wins += tally.wins;
+                standings[index].draws += tally.draws;
+                standings[index].losses += tally.losses;
+            }
+        }
+        let mut order : Vec<usize> = (0..names.len()).collect();
+        order.sort_by(|&a, &b|standings[b].score().total_cmp(&standings[a].score()));
+        for index in order{
+            println!("{:>width$} vs field  {}", names[index], standings[index], width = width);
+        }
+    }
+}
+
+fn read_openings(path : &str) -> Vec<Position>{
+    let text = std::fs::read_to_string(path).unwrap_or_else(|e|fail(format!("could not read {}: {}", path, e)));
+    let openings : Vec<Position> = text.lines()
+        .map(|l|l.trim())
+        .filter(|l|!l.is_empty() && !l.starts_with('#'))
+        .map(|l|l.parse().unwrap_or_else(|e|fail(format!("invalid opening {}: {:?}", l, e))))
+        .collect();
+    if openings.is_empty(){
+        fail(format!("no openings in {}", path));
+    }
+    openings
+}
@@ -0,0 +1,606 @@
+//! Terminal front end, for playing and analysing without a window.
+//!
+//! Usage: `tui [--white <gamer>] [--black <gamer>] [--setup <position string>] [--book <file>]`
+//! Gamers are `human` or a bot level name such as `decent` or `beastly-6`.
+//! Bots play from the opening book, `book.hxob` by default, when there is one.
+//! Type `help` at the prompt for the commands.
+//!
+//! Online, against another front end (see `hexstack::networking`):
+//! - `tui --host <address> [--color white|black] [--takebacks on|off] [--name <name>]`
+//!   waits for a guest on `address`, such as `0.0.0.0:7878`; port 0 picks a free one.
+//!   The open seat is announced on the local network, for the lobby of the match setup
+//! - `tui --join <address> [--name <name>]` plays the host's terms
+//! - `tui --server <address> [--room <code>|new] [--color white|black] [--name <name>] [--key <key>]`
+//!   plays in a room of a `hexstack-server`, by default a new one whose code is printed
+//!   for the opponent; entering again with the key printed for the seat resumes the game
+//!
+//! By correspondence, without any connection (see `hexstack::tokonoma::correspondence`):
+//! - `tui --correspondence white|black` starts a game playing that side; after each move
+//!   a token is printed to send to the opponent, whose reply token is pasted at the prompt
+//! - `tui --token <token> [--correspondence white|black]` continues from a token received,
+//!   playing the side to move unless told otherwise
+//!
+//! Both sides are human unless `--white` or `--black` puts a bot in the local seat.
+
+use std::io::{self, BufRead, Write};
+use std::net::TcpListener;
+use std::sync::Arc;
+
+use futures::executor::block_on;
+use web_time::{Duration, Instant};
+
+use hexstack::gamer_spec::{BotFiles, GamerSpec};
+use hexstack::networking::{default_player_name, is_room_code, Advertiser, Connection, MatchOffer, OnlineMatch, RemoteEvent};
+use hexstack::tokonoma::correspondence::{self, TOKEN_PREFIX};
+use hexstack::tokonoma::{DrawRules, GameOutcome, MatchState, OpeningBook, Piece, PlayerMap, SearchLimits, StopToken, TranspositionalTable};
+use hexstack::{Player, Position, Tile};
+
+const HELP : &str = "\
+<move>            play a move in history notation, e.g. Bb5, Ad6b5, Fc3*
+moves             list the legal moves
+undo [n]          take back n plies (default: back to your previous turn)
+history           list the moves played
+pstring           show the position string
+record            print the game record
+engine [depth]    analyse the position (default depth 6)
+white <gamer>     set who plays white, e.g. human, noob, grandmaster, beastly-7
+black <gamer>     set who plays black
+new               restart from the setup
+setup <pstring>   restart from a position string
+flip              turn the board around
+coords            toggle tile names on the board
+token             show the correspondence token of the game
+hxc1-...          continue a correspondence game with the opponent's token
+resign            give up an online game
+draw              offer or accept a draw in an online game
+accept, decline   answer a takeback asked in an online game
+help              show this text
+quit              leave";
+
+const DEFAULT_ENGINE_DEPTH : usize = 6;
+const ENGINE_LINES : usize = 5;
+const DEFAULT_BOOK : &str = "book.hxob";
+
+/// How long to wait for the opponent's move online before giving up.
+const ONLINE_PATIENCE : Duration = Duration::from_secs(24 * 3600);
+
+struct Session{
+    match_state : MatchState,
+    /// Kept in step with `match_state`.
+    online : Option<OnlineMatch>,
+    /// The local side of a correspondence game.
+    correspondence : Option<Player>,
+    name : String,
+    setup : Position,
+    gamers : PlayerMap<GamerSpec>,
+    flipped : bool,
+    coords : bool,
+    transposition_table : Arc<TranspositionalTable>,
+    files : BotFiles,
+}
+
+fn parse_gamer(name : &str) -> Result<GamerSpec, String>{
+    name.parse().map_err(|_|format!("unknown gamer {}; try human, noob, decent, sharp, tough, grandmaster or beastly-<depth>", name))
+}
+
+fn fail(message : String) -> !{
+    eprintln!("{}", message);
+    std::process::exit(2);
+}
+
+enum Seat{
+    Host(String),
+    Guest(String),
+    /// A server's address.
+    Server(String),
+}
+
+fn main(){
+    let args : Vec<String> = std::env::args().skip(1).collect();
+    let mut gamers = PlayerMap::twin(None);
+    let mut setup = Position::setup();
+    let mut book = None;
+    let mut seat = None;
+    let mut color = None;
+    let mut room = None;
+    let mut key = None;
+    let mut correspondence = None;
+    let mut token = None;
+    let mut takebacks = true;
+    let mut name = default_player_name();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next(){
+        let value = args.next().unwrap_or_else(||{
+            eprintln!("missing value for {}", arg);
+            std::process::exit(2);
+        });
+        let parsed = match arg.as_str(){
+            "--white" => parse_gamer(value).map(|g|gamers[Player::White] = Some(g)),
+            "--black" => parse_gamer(value).map(|g|gamers[Player::Black] = Some(g)),
+            "--setup" => value.parse().map(|p|setup = p).map_err(|e|format!("invalid position string: {:?}", e)),
+            "--book" => OpeningBook::load(value).map(|loaded|book = Some(loaded)).map_err(|e|format!("could not load {}: {}", value, e)),
+            "--host" => {seat = Some(Seat::Host(value.clone())); Ok(())},
+            "--join" => {seat = Some(Seat::Guest(value.clone())); Ok(())},
+            "--server" => {seat = Some(Seat::Server(value.clone())); Ok(())},
+            "--room" => match value.as_str(){
+                "new" => {room = None; Ok(())},
+                code if is_room_code(code) => {room = Some(code.to_owned()); Ok(())},
+                _ => Err(format!("room codes are letters, digits, - and _, not {}", value))
+            },
+            "--key" => match value.as_str(){
+                key_value if is_room_code(key_value) => {key = Some(value.clone()); Ok(())},
+                _ => Err(format!("seat keys are letters, digits, - and _, not {}", value))
+            },
+            "--name" => {name = value.clone(); Ok(())},
+            "--color" => match value.as_str(){
+                "white" => {color = Some(Player::White); Ok(())},
+                "black" => {color = Some(Player::Black); Ok(())},
+                _ => Err(format!("colour must be white or black, not {}", value))
+            },
+            "--correspondence" => match value.as_str(){
+                "white" => {correspondence = Some(Player::White); Ok(())},
+                "black" => {correspondence = Some(Player::Black); Ok(())},
+                _ => Err(format!("correspondence side must be white or black, not {}", value))
+            },
+            "--token" => correspondence::decode(value).map(|decoded|token = Some(decoded))
+                .map_err(|e|format!("invalid token: {}", e)),
+            "--takebacks" => match value.as_str(){
+                "on" | "off" => {takebacks = value == "on"; Ok(())},
+                _ => Err(format!("takebacks must be on or off, not {}", value))
+            },
+            _ => Err(format!("unknown option {}", arg))
+        };
+        if let Err(e) = parsed{
+            fail(e);
+        }
+    }
+
+    if seat.is_some() && (correspondence.is_some() || token.is_some()){
+        fail("correspondence games are played without a connection".to_owned());
+    }
+    let correspondence = correspondence.or(token.as_ref().map(MatchState::to_play));
+
+    let online = seat.map(|seat|{
+        let connected = match seat{
+            Seat::Host(address) => {
+                let listener = TcpListener::bind(&address).unwrap_or_else(|e|fail(format!("could not listen on {}: {}", address, e)));
+                println!("waiting for an opponent on {}", listener.local_addr().unwrap());
+                io::stdout().flush().unwrap();
+                let offer = MatchOffer{host_plays : color.unwrap_or(Player::White), takebacks, draw_rules : DrawRules::default()};
+                let advertiser = Advertiser::start(&name, &offer, listener.local_addr().unwrap().port());
+                if let Err(e) = &advertiser{
+                    eprintln!("not announced on the local network: {}", e);
+                }
+                let (stream, _) = listener.accept().unwrap_or_else(|e|fail(e.to_string()));
+                drop(advertiser);
+                Connection::open(stream, &name).and_then(|connection|OnlineMatch::host(connection, offer, setup.clone()))
+            },
+            Seat::Guest(address) => Connection::connect(&address, &name).and_then(OnlineMatch::join),
+            Seat::Server(address) => Connection::connect(&address, &name).and_then(|mut connection|{
+                let (code, seat, key) = OnlineMatch::enter_room(&mut connection, room.as_deref(), color, key.as_deref())?;
+                println!("to come back to this seat: --room {} --key {}", code, key);
+                println!("playing {} in room {}; waiting for an opponent", player_name(seat), code);
+                io::stdout().flush().unwrap();
+                OnlineMatch::join_room(connection, Instant::now() + ONLINE_PATIENCE)
+            }),
+        };
+        let online = connected.unwrap_or_else(|e|fail(e.to_string()));
+        println!("playing {} against {}", player_name(online.local), online.peer_name());
+        online
+    });
+    // online or by correspondence, only the local seat can hold a bot
+    let defaults = match &online{
+        None if correspondence.is_none() => PlayerMap::new(GamerSpec::Human, GamerSpec::Decent),
+        _ => PlayerMap::twin(GamerSpec::Human),
+    };
+    if let Some(local) = correspondence{
+        gamers[local.flip()] = None;
+    }
+    let gamers = PlayerMap::new(
+        gamers[Player::White].clone().unwrap_or(defaults[Player::White].clone()),
+        gamers[Player::Black].clone().unwrap_or(defaults[Player::Black].clone()),
+    );
+
+    let files = BotFiles{
+        book : book.or_else(||OpeningBook::load(DEFAULT_BOOK).ok()).map(Arc::new),
+        ..BotFiles::default()
+    };
+
+    let match_state = match (&online, token){
+        (Some(online), _) => online.match_state().clone(),
+        (None, Some(token)) => token,
+        (None, None) => MatchState::setup_from(setup.clone()),
+    };
+    let mut session = Session{
+        match_state,
+        online,
+        correspondence,
+        name,
+        setup,
+        gamers,
+        flipped : false,
+        coords : false,
+        transposition_table : Arc::new(TranspositionalTable::new()),
+        files,
+    };
+    session.run();
+}
+
+impl Session{
+    fn run(&mut self){
+        let stdin = io::stdin();
+        let mut lines = stdin.lock().lines();
+        self.print_board();
+
+        loop{
+            let to_play = self.match_state.to_play();
+            if let Some(online) = &mut self.online{
+                let remote = online.local.flip();
+                // the opponent may also resign or offer a draw while it is our turn,
+                // and waits for an answer to its takeback request
+                let waiting = (to_play == remote && online.peer_asks_takeback().is_none()) || online.asks_takeback();
+                let event = match online.outcome(){
+                    None if waiting => {
+                        println!("waiting for {}...", online.peer_name());
+                        online.wait(Instant::now() + ONLINE_PATIENCE).map(Some)
+                    },
+                    _ => online.poll(),
+                };
+                match event{
+                    Ok(Some(event)) => {
+                        self.handle_remote(event);
+                        continue;
+                    },
+                    Ok(None) => {},
+                    // a finished game stays, to be looked at
+                    Err(_) if online.outcome().is_some() => {},
+                    Err(e) => {
+                        println!("{}; the online game is over", e);
+                        break;
+                    }
+                }
+            }
+            if self.outcome().is_none(){
+                if let Some(settings) = self.gamers[to_play].bot_settings(){
+                    let settings = settings.with_files(&self.files);
+                    let ply = settings.probe_book(self.match_state.present_state()).unwrap_or_else(||{
+                        let outcome = block_on(self.match_state.state_clone().search(
+                            settings.roll_limits(), StopToken::new(),
+                            Some(self.transposition_table.clone()), false, |_|{}
+                        ));
+                        outcome.best_move().expect("a game in progress has moves")
+                    });
+                    self.play(ply);
+                    continue;
+                }
+            }
+
+            print!("{} ({})> ", player_name(to_play), self.gamers[to_play].name());
+            io::stdout().flush().unwrap();
+            let Some(Ok(line)) = lines.next() else {break};
+
+            let words : Vec<&str> = line.split_whitespace().collect();
+            match words.as_slice(){
+                [] => {},
+                ["quit" | "exit"] => break,
+                ["help" | "?"] => println!("{}", HELP),
+                ["token"] => match self.correspondence{
+                    Some(_) => println!("{}", correspondence::encode(&self.match_state)),
+                    None => println!("not a correspondence game; start one with --correspondence"),
+                },
+                [token] if token.starts_with(TOKEN_PREFIX) => self.receive_token(token),
+                ["undo" | "takeback" | "white" | "black" | "new" | "setup", ..] if self.correspondence.is_some() =>
+                    println!("not in a correspondence game"),
+                ["moves"] => self.print_moves(),
+                ["undo" | "takeback"] => self.undo(None),
+                ["undo" | "takeback", n] => match n.parse(){
+                    Ok(n) => self.undo(Some(n)),
+                    Err(_) => println!("not a number: {}", n)
+                },
+                ["history"] => self.print_history(),
+                ["pstring"] => println!("{}", self.match_state.position_string(None).unwrap()),
+                ["record"] => print!("{}", self.record()),
+                ["engine" | "analyse"] => self.analyse(DEFAULT_ENGINE_DEPTH),
+                ["engine" | "analyse", depth] => match depth.parse(){
+                    Ok(depth) => self.analyse(depth),
+                    Err(_) => println!("not a depth: {}", depth)
+                },
+                ["resign"] => self.resign(),
+                ["draw"] => self.offer_draw(),
+                ["accept"] => self.answer_takeback(true),
+                ["decline"] => self.answer_takeback(false),
+                ["white" | "black" | "new" | "setup", ..] if self.online.is_some() => println!("not in an online game"),
+                [color @ ("white" | "black"), name] => match parse_gamer(name){
+                    Ok(gamer) => {
+                        let player = if *color == "white" {Player::White} else {Player::Black};
+                        self.gamers[player] = gamer;
+                    },
+                    Err(e) => println!("{}", e)
+                },
+                ["new"] => self.restart(self.setup.clone()),
+                ["setup", pstring] => match pstring.parse(){
+                    Ok(position) => self.restart(position),
+                    Err(e) => println!("invalid position string: {:?}", e)
+                },
+                ["flip"] => {self.flipped = !self.flipped; self.print_board()},
+                ["coords"] => {self.coords = !self.coords; self.print_board()},
+                [notation] => {
+                    if self.outcome().is_some(){
+                        println!("the game is over; undo, new or quit");
+                        continue;
+                    }
+                    if self.correspondence.is_some_and(|local|local != to_play){
+                        println!("waiting for {}'s move; paste their token", player_name(to_play));
+                        continue;
+                    }
+                    match self.match_state.present_state().parse_move(notation){
+                        Ok(ply) => self.play(ply),
+                        Err(e) => println!("{}: {}", notation, e)
+                    }
+                },
+                _ => println!("unknown command; type help"),
+            }
+        }
+    }
+
+    fn outcome(&self) -> Option<GameOutcome>{
+        match &self.online{
+            Some(online) => online.outcome(),
+            None => self.match_state.outcome(),
+        }
+    }
+
+    fn play(&mut self, ply : hexstack::Ply){
+        let mover = self.match_state.to_play();
+        if let Some(online) = self.online.as_mut().filter(|online|online.local == mover){
+            if let Err(e) = online.play(ply){
+                println!("{}", e);
+                return;
+            }
+        }
+        self.match_state.apply_move(ply);
+        let entry = self.match_state.history().last().unwrap();
+        println!("{} plays {}", player_name(mover), entry);
+        self.print_board();
+        if self.correspondence == Some(mover){
+            println!("send this token to {}: {}", player_name(mover.flip()), correspondence::encode(&self.match_state));
+        }
+    }
+
+    /// Continues a correspondence game with the opponent's move.
+    fn receive_token(&mut self, token : &str){
+        let Some(local) = self.correspondence else {
+            println!("not a correspondence game; start one with --token");
+            return;
+        };
+        match correspondence::continue_from(&self.match_state, token, local){
+            Ok(match_state) => {
+                self.match_state = match_state;
+                let entry = self.match_state.history().last().unwrap();
+                println!("{} plays {}", player_name(local.flip()), entry);
+                self.print_board();
+            },
+            Err(e) => println!("token rejected: {}", e),
+        }
+    }
+
+    fn handle_remote(&mut self, event : RemoteEvent){
+        let Some(online) = &self.online else {return};
+        let (peer, local) = (online.peer_name().to_owned(), online.local);
+        match event{
+            RemoteEvent::Moved(ply) => self.play(ply),
+            RemoteEvent::TakebackAsked(plies) => {
+                // bots keep their moves
+                if self.gamers[local].bot_settings().is_some(){
+                    self.answer_takeback(false);
+                } else {
+                    println!("{} asks to take back {} plies; type accept or decline", peer, plies);
+                }
+            },
+            RemoteEvent::TookBack(plies) => {
+                println!("{} agrees to take back {} plies", peer, plies);
+                self.match_state.undo_moves(plies);
+                self.print_board();
+            },
+            RemoteEvent::TakebackDeclined => println!("{} declines the takeback", peer),
+            RemoteEvent::Resigned => println!("{} resigns; {}", peer, online.outcome().unwrap()),
+            RemoteEvent::DrawOffered => println!("{} offers a draw; type draw to accept", peer),
+            RemoteEvent::DrawAgreed => println!("{} accepts the draw", peer),
+        }
+    }
+
+    fn resign(&mut self){
+        let Some(online) = &mut self.online else {
+            println!("resigning is for online games; try new");
+            return;
+        };
+        match online.resign(){
+            Ok(()) => println!("{}", online.outcome().unwrap()),
+            Err(e) => println!("{}", e),
+        }
+    }
+
+    fn offer_draw(&mut self){
+        let Some(online) = &mut self.online else {
+            println!("draw offers are for online games");
+            return;
+        };
+        match online.offer_draw(){
+            Ok(true) => println!("{}", online.outcome().unwrap()),
+            Ok(false) => println!("draw offered to {}", online.peer_name()),
+            Err(e) => println!("{}", e),
+        }
+    }
+
+    fn answer_takeback(&mut self, accept : bool){
+        let Some(online) = &mut self.online else {
+            println!("takebacks are asked in online games");
+            return;
+        };
+        match online.answer_takeback(accept){
+            Ok(0) => println!("takeback declined"),
+            Ok(plies) => {
+                self.match_state.undo_moves(plies);
+                self.print_board();
+            },
+            Err(_) => println!("no takeback was asked"),
+        }
+    }
+
+    fn restart(&mut self, setup : Position){
+        self.setup = setup.clone();
+        self.match_state = MatchState::setup_from(setup);
+        self.transposition_table.clear();
+        self.print_board();
+    }
+
+    /// Without a count, goes back to the previous turn of the human to play.
+    /// Online, only to an earlier turn of this side, if the terms allow it and the opponent agrees.
+    fn undo(&mut self, count : Option<usize>){
+        let count = count.unwrap_or_else(||{
+            let opponent = self.match_state.to_play().flip();
+            match self.gamers[opponent]{
+                GamerSpec::Human if self.online.is_none() => 1,
+                _ => 2
+            }
+        });
+        let count = count.min(self.match_state.history().len());
+        if count == 0{
+            println!("nothing to take back");
+            return;
+        }
+        if let Some(online) = &mut self.online{
+            match online.take_back(count){
+                Ok(()) => println!("takeback asked of {}", online.peer_name()),
+                Err(e) => println!("{}", e),
+            }
+            return;
+        }
+        self.match_state.undo_moves(count);
+        self.print_board();
+    }
+
+    fn record(&self) -> String{
+        if let Some(online) = &self.online{
+            return online.to_record(&self.name).to_string();
+        }
+        let mut record = self.match_state.to_record();
+        record.players = PlayerMap::new(
+            self.gamers[Player::White].name(),
+            self.gamers[Player::Black].name()
+        );
+        record.to_string()
+    }
+
+    fn print_moves(&self){
+        let position = self.match_state.present_state();
+        let notations : Vec<String> = position.valid_moves().into_iter()
+            .map(|ply|position.line_notation(&[ply]))
+            .collect();
+        println!("{}", notations.join(" "));
+    }
+
+    fn print_history(&self){
+        let history = self.match_state.history();
+        if history.is_empty(){
+            println!("no moves yet");
+            return;
+        }
+        let black_first = history[0].state_before.to_play() == Player::Black;
+        let mut line = String::new();
+        for (index, entry) in history.iter().enumerate(){
+            let slot = index + black_first as usize;
+            if slot.is_multiple_of(2) || index == 0{
+                if !line.is_empty(){
+                    println!("{}", line);
+                }
+                line = format!("{:>3}.{}", slot / 2 + 1, if slot % 2 == 1 {" ..."} else {""});
+            }
+            line.push_str(&format!(" {}", entry));
+        }
+        println!("{}", line);
+        if let Ok(opening) = self.match_state.opening(){
+            println!("opening: {}", opening);
+        }
+    }
+
+    fn analyse(&mut self, depth : usize){
+        let position = self.match_state.state_clone();
+        if position.valid_moves().is_empty(){
+            println!("no moves to analyse");
+            return;
+        }
+        let outcome = block_on(position.clone().search(
+            SearchLimits::depth(depth).with_threads(SearchLimits::available_threads()),
+            StopToken::new(), Some(self.transposition_table.clone()), false,
+            |iteration|{
+                if let Some((_, best)) = iteration.moves.first(){
+                    println!("depth {:>2}  {:>10}  {}", iteration.depth, best.score.to_string(), position.line_notation(&best.pv));
+                }
+            }
+        ));
+        println!("{} nodes", outcome.nodes);
+        for (_, eval) in outcome.moves.iter().take(ENGINE_LINES){
+            println!("  {:>10}  {}", eval.score.to_string(), position.line_notation(&eval.pv));
+        }
+    }
+
+    fn print_board(&self){
+        print!("{}", board_diagram(self.match_state.present_state(), self.flipped, self.coords));
+        if let Some(outcome) = self.outcome(){
+            println!("{}", outcome);
+        }
+    }
+}
+
+fn player_name(player : Player) -> &'static str{
+    match player{
+        Player::White => "White",
+        Player::Black => "Black",
+    }
+}
+
+/// Hex board drawn with the same geometry as the window, optionally turned around.
+/// Each tile shows its piece (position string letters) or its name.
+fn board_diagram(position : &Position, flipped : bool, coords : bool) -> String{
+    // screen column in half-tiles and row, as `Tile::to_world` lays them out
+    let place = |t : &Tile|{
+        let (c, r) = (-(t.x() as i32 + 2 * t.y() as i32), -(t.x() as i32));
+        if flipped {(-c, -r)} else {(c, r)}
+    };
+
+    let min_col = Tile::ALL_TILES.iter().map(|t|place(t).0).min().unwrap();
+    let mut rows : Vec<(i32, Vec<Tile>)> = vec![];
+    for tile in Tile::ALL_TILES{
+        let r = place(&tile).1;
+        match rows.iter_mut().find(|(row, _)|*row == r){
+            Some((_, tiles)) => tiles.push(tile),
+            None => rows.push((r, vec![tile]))
+        }
+    }
+    rows.sort_by_key(|(r, _)|*r);
+
+    let mut diagram = String::new();
+    for (_, mut tiles) in rows{
+        tiles.sort_by_key(|t|place(t).0);
+        let mut line = format!("{}  ", tiles[0].to_string().chars().next().unwrap());
+        for tile in tiles{
+            let column = 3 + 2 * (place(&tile).0 - min_col) as usize;
+            while line.chars().count() < column{
+                line.push(' ');
+            }
+            let glyph = [Player::White, Player::Black].into_iter()
+                .find_map(|color|position.get_pieces(color).get(tile).map(|species|char::from(Piece{color, species})));
+            if coords{
+                line.push_str(&tile.to_string());
+            } else {
+                line.push(glyph.unwrap_or('.'));
+            }
+        }
+        diagram.push_str(line.trim_end());
+        diagram.push('\n');
+    }
+    diagram.push_str(&format!("{} to play\n", player_name(position.to_play())));
+    diagram
+}
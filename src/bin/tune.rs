@@ -0,0 +1,95 @@
+//! Fits the evaluation weights to the results of recorded games, see `hexstack::tuning`.
+//!
+//! Usage: `tune [options] <records file>...`
+//!
+//! Records files hold game records one after the other, as written by `tournament --records`.
+//!
+//! Options:
+//! - `--start <file>`: weights to start from (default: the built-in ones)
+//! - `--out <file>`: where to write the tuned weights (default: stdout)
+//! - `--epochs <n>`: optimizer steps (default 2000)
+//! - `--rate <r>`: learning rate (default 0.05)
+//! - `--skip <n>`: opening plies left out of each game (default 4)
+
+use hexstack::tokonoma::{EvalParams, GameRecord};
+use hexstack::tuning::{fit_scale, loss, samples_from_record, tune};
+
+const DEFAULT_EPOCHS : usize = 2000;
+const DEFAULT_RATE : f64 = 0.05;
+const DEFAULT_SKIP : usize = 4;
+
+fn usage() -> !{
+    eprintln!("usage: tune [--start file] [--out file] [--epochs n] [--rate r] [--skip n] <records file>...");
+    std::process::exit(2);
+}
+
+fn fail(message : String) -> !{
+    eprintln!("{}", message);
+    std::process::exit(2);
+}
+
+fn main(){
+    let mut start = EvalParams::DEFAULT;
+    let mut out = None;
+    let mut epochs = DEFAULT_EPOCHS;
+    let mut rate = DEFAULT_RATE;
+    let mut skip = DEFAULT_SKIP;
+    let mut paths = vec![];
+
+    let args : Vec<String> = std::env::args().skip(1).collect();
+    let mut args = args.iter();
+    while let Some(arg) = args.next(){
+        let mut value = ||args.next().unwrap_or_else(||usage()).clone();
+        match arg.as_str(){
+            "--start" => start = EvalParams::load(value()).unwrap_or_else(|e|fail(e)),
+            "--out" => out = Some(value()),
+            "--epochs" => epochs = value().parse().unwrap_or_else(|_|usage()),
+            "--rate" => rate = value().parse().unwrap_or_else(|_|usage()),
+            "--skip" => skip = value().parse().unwrap_or_else(|_|usage()),
+            "--help" | "-h" => usage(),
+            path => paths.push(path.to_owned()),
+        }
+    }
+    if paths.is_empty(){
+        usage();
+    }
+
+    let mut samples = vec![];
+    let mut games = 0;
+    for path in &paths{
+        let text = std::fs::read_to_string(path).unwrap_or_else(|e|fail(format!("could not read {}: {}", path, e)));
+        for (index, record) in GameRecord::parse_all(&text).into_iter().enumerate(){
+            match record.and_then(|record|samples_from_record(&record, skip)){
+                Ok(found) => {
+                    games += !found.is_empty() as usize;
+                    samples.extend(found);
+                },
+                Err(e) => eprintln!("{}, record {}: {}", path, index + 1, e),
+            }
+        }
+    }
+    if samples.is_empty(){
+        fail("no finished games to learn from".to_owned());
+    }
+    eprintln!("{} positions from {} games", samples.len(), games);
+
+    let k = fit_scale(&samples, &start);
+    eprintln!("scale {:.5}, loss {:.5}", k, loss(&samples, &start, k));
+
+    let tuned = tune(&samples, &start, k, epochs, rate, |epoch, loss, _|{
+        if epoch % 100 == 0{
+            eprintln!("epoch {:>5}  loss {:.5}", epoch, loss);
+        }
+    });
+    eprintln!("final loss {:.5}", loss(&samples, &tuned, k));
+
+    let (before, after) = (start.to_array(), tuned.to_array());
+    for ((name, before), after) in EvalParams::names().iter().zip(before).zip(after){
+        eprintln!("{:>18} {:>9.3} -> {:>9.3}", name, before, after);
+    }
+
+    match out{
+        Some(path) => std::fs::write(&path, tuned.to_string()).unwrap_or_else(|e|fail(format!("could not write {}: {}", path, e))),
+        None => print!("{}", tuned),
+    }
+}
@@ -0,0 +1,435 @@
+//! Line protocol between a front end (GUI, tournament manager, script) and an engine,
+//! in the spirit of UCI. Spoken by the `engine` binary.
+//!
+//! Front end to engine, one command per line:
+//! - `hxe`: handshake, answered by `id <key> <value>` lines, such as `id name ...`, and `hxeok`
+//! - `isready`: answered by `readyok`, also while searching
+//! - `setoption name <name> value <value>`: `Threads`, `Hash` (megabytes), `Tablebase` (directory),
+//!   `EvalFile` (evaluation weights, see `tokonoma::EvalParams`), `Book` (opening book file)
+//!   or `BookVariety` (see `tokonoma::OpeningBook::choose`, default 0)
+//! - `newgame`: forget what was learned in previous games
+//! - `position startpos|<position string> [moves <move>...]`
+//! - `go [depth <plies>] [movetime <ms>] [nodes <count>] [infinite]`:
+//!   without limits, searches until `stop`
+//! - `stop`: answer `bestmove` as soon as possible
+//! - `quit`
+//!
+//! Engine to front end:
+//! - `info depth <plies> score cp <hundredths>|win <plies>|loss <plies> nodes <count> time <ms> pv <move>...`
+//!   after each completed iteration, scores from the point of view of the side to play
+//! - `info string <text>`: anything else, such as complaints about commands
+//! - `bestmove <move>|none`
+//!
+//! Engines write moves as tile pairs (`d6b5`). Front ends may also use history notation (`Bb5`).
+
+use std::fmt::Display;
+use std::str::FromStr;
+
+use web_time::Duration;
+
+use crate::tokonoma::{Player, Ply, Position, Score, SearchLimits};
+
+/// Depth used when `go` has no depth limit.
+pub const UNLIMITED_DEPTH : usize = 64;
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ProtocolError(pub String);
+
+impl Display for ProtocolError{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+fn error<T>(message : impl Into<String>) -> Result<T, ProtocolError>{
+    Err(ProtocolError(message.into()))
+}
+
+fn parse_number<T : FromStr>(what : &str, word : Option<&str>) -> Result<T, ProtocolError>{
+    match word.map(|w|w.parse()){
+        Some(Ok(n)) => Ok(n),
+        Some(Err(_)) => error(format!("{} is not a number: {}", what, word.unwrap())),
+        None => error(format!("missing value for {}", what)),
+    }
+}
+
+/// Limits of a `go` command. All `None` means until `stop`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct GoParams{
+    pub depth : Option<usize>,
+    pub movetime : Option<Duration>,
+    pub nodes : Option<usize>,
+}
+
+impl GoParams{
+    pub fn depth(depth : usize) -> Self{
+        GoParams{depth : Some(depth), ..Default::default()}
+    }
+
+    pub fn movetime(movetime : Duration) -> Self{
+        GoParams{movetime : Some(movetime), ..Default::default()}
+    }
+
+    /// Search limits starting now.
+    pub fn limits(&self) -> SearchLimits{
+        let mut limits = SearchLimits::depth(self.depth.unwrap_or(UNLIMITED_DEPTH));
+        if let Some(movetime) = self.movetime{
+            limits = limits.with_time(movetime);
+        }
+        if let Some(nodes) = self.nodes{
+            limits = limits.with_nodes(nodes);
+        }
+        limits
+    }
+}
+
+/// Front end to engine.
+#[derive(Clone, PartialEq, Debug)]
+pub enum GuiCommand{
+    Hello,
+    IsReady,
+    SetOption{name : String, value : String},
+    NewGame,
+    Position{setup : Position, moves : Vec<Ply>},
+    Go(GoParams),
+    Stop,
+    Quit,
+}
+
+impl GuiCommand{
+    /// The position a `position` command describes.
+    pub fn position_after(setup : &Position, moves : &[Ply]) -> Position{
+        let mut position = setup.clone();
+        for &ply in moves{
+            position.apply_move(ply);
+        }
+        position
+    }
+}
+
+impl Display for GuiCommand{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self{
+            GuiCommand::Hello => write!(f, "hxe"),
+            GuiCommand::IsReady => write!(f, "isready"),
+            GuiCommand::SetOption { name, value } => write!(f, "setoption name {} value {}", name, value),
+            GuiCommand::NewGame => write!(f, "newgame"),
+            GuiCommand::Position { setup, moves } => {
+                write!(f, "position {}", setup.to_position_string())?;
+                if !moves.is_empty(){
+                    write!(f, " moves")?;
+                    for ply in moves{
+                        write!(f, " {}", ply)?;
+                    }
+                }
+                Ok(())
+            },
+            GuiCommand::Go(params) => {
+                write!(f, "go")?;
+                if let Some(depth) = params.depth{
+                    write!(f, " depth {}", depth)?;
+                }
+                if let Some(movetime) = params.movetime{
+                    write!(f, " movetime {}", movetime.as_millis())?;
+                }
+                if let Some(nodes) = params.nodes{
+                    write!(f, " nodes {}", nodes)?;
+                }
+                if *params == GoParams::default(){
+                    write!(f, " infinite")?;
+                }
+                Ok(())
+            },
+            GuiCommand::Stop => write!(f, "stop"),
+            GuiCommand::Quit => write!(f, "quit"),
+        }
+    }
+}
+
+/// Moves are checked against the position as they are read.
+impl FromStr for GuiCommand{
+    type Err = ProtocolError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let words : Vec<&str> = s.split_whitespace().collect();
+        let Some((&command, args)) = words.split_first() else {
+            return error("empty command");
+        };
+
+        match (command, args){
+            ("hxe", []) => Ok(GuiCommand::Hello),
+            ("isready", []) => Ok(GuiCommand::IsReady),
+            ("newgame", []) => Ok(GuiCommand::NewGame),
+            ("stop", []) => Ok(GuiCommand::Stop),
+            ("quit", []) => Ok(GuiCommand::Quit),
+            ("setoption", ["name", rest @ ..]) => {
+                let Some(split) = rest.iter().position(|&w|w == "value") else {
+                    return error("setoption without value");
+                };
+                Ok(GuiCommand::SetOption{
+                    name : rest[..split].join(" "),
+                    value : rest[split + 1..].join(" ")
+                })
+            },
+            ("position", [setup, rest @ ..]) => {
+                let setup = match *setup{
+                    "startpos" => Position::setup(),
+                    pstring => pstring.parse()
+                        .or_else(|e|error(format!("invalid position string {}: {:?}", pstring, e)))?
+                };
+                let notations = match rest{
+                    [] => &[][..],
+                    ["moves", notations @ ..] => notations,
+                    _ => return error(format!("expected moves, got {}", rest[0]))
+                };
+
+                let moves = parse_moves(&setup, notations)?;
+                Ok(GuiCommand::Position{setup, moves})
+            },
+            ("go", mut rest) => {
+                let mut params = GoParams::default();
+                while let Some((&key, tail)) = rest.split_first(){
+                    let value = tail.first().copied();
+                    match key{
+                        "depth" => params.depth = Some(parse_number(key, value)?),
+                        "movetime" => params.movetime = Some(Duration::from_millis(parse_number(key, value)?)),
+                        "nodes" => params.nodes = Some(parse_number(key, value)?),
+                        "infinite" => {
+                            rest = tail;
+                            continue;
+                        },
+                        _ => return error(format!("unknown go parameter {}", key))
+                    }
+                    rest = &tail[1..];
+                }
+                Ok(GuiCommand::Go(params))
+            },
+            _ => error(format!("unknown command {}", s.trim()))
+        }
+    }
+}
+
+/// Score from the point of view of the side to play.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum EngineScore{
+    /// Hundredths of the evaluation unit.
+    Centi(i32),
+    /// The side to play wins in that many plies, its own move included.
+    Win(u32),
+    Loss(u32),
+}
+
+impl EngineScore{
+    /// Root move scores are the values of the positions after the moves,
+    /// so distances gain the move itself.
+    pub fn of_root_move(score : Score, to_play : Player) -> Self{
+        match (score.finite_value(), score.win_distance()){
+            (Some(value), _) => {
+                let centi = (value * 100.0).round() as i32;
+                EngineScore::Centi(if to_play == Player::White {centi} else {-centi})
+            },
+            (None, Some((winner, plies))) if winner == to_play => EngineScore::Win(plies + 1),
+            (None, Some((_, plies))) => EngineScore::Loss(plies + 1),
+            (None, None) => unreachable!()
+        }
+    }
+}
+
+impl Display for EngineScore{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self{
+            EngineScore::Centi(centi) => write!(f, "cp {}", centi),
+            EngineScore::Win(plies) => write!(f, "win {}", plies),
+            EngineScore::Loss(plies) => write!(f, "loss {}", plies),
+        }
+    }
+}
+
+/// Report of a completed iteration.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SearchInfo{
+    pub depth : usize,
+    pub score : EngineScore,
+    pub nodes : usize,
+    pub time : Duration,
+    /// Starts with the best move.
+    pub pv : Vec<Ply>,
+}
+
+/// Engine to front end.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum EngineReply{
+    Id{key : String, value : String},
+    HelloOk,
+    ReadyOk,
+    Info(SearchInfo),
+    InfoString(String),
+    /// `None` when there is no legal move.
+    BestMove(Option<Ply>),
+}
+
+impl Display for EngineReply{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self{
+            EngineReply::Id { key, value } => write!(f, "id {} {}", key, value),
+            EngineReply::HelloOk => write!(f, "hxeok"),
+            EngineReply::ReadyOk => write!(f, "readyok"),
+            EngineReply::Info(info) => {
+                write!(f, "info depth {} score {} nodes {} time {} pv",
+                    info.depth, info.score, info.nodes, info.time.as_millis())?;
+                for ply in &info.pv{
+                    write!(f, " {}", ply)?;
+                }
+                Ok(())
+            },
+            EngineReply::InfoString(text) => write!(f, "info string {}", text),
+            EngineReply::BestMove(Some(ply)) => write!(f, "bestmove {}", ply),
+            EngineReply::BestMove(None) => write!(f, "bestmove none"),
+        }
+    }
+}
+
+/// Moves played one after the other from `setup`, each checked as it is read.
+pub(crate) fn parse_moves(setup : &Position, notations : &[&str]) -> Result<Vec<Ply>, ProtocolError>{
+    let mut position = setup.clone();
+    let mut moves = vec![];
+    for notation in notations{
+        if position.is_won().is_some(){
+            return error(format!("{}: the game is over", notation));
+        }
+        let ply = position.parse_move(notation)
+            .or_else(|e|error(format!("{}: {}", notation, e)))?;
+        position.apply_move(ply);
+        moves.push(ply);
+    }
+    Ok(moves)
+}
+
+pub(crate) fn parse_ply(word : &str) -> Result<Ply, ProtocolError>{
+    word.parse().or_else(|_|error(format!("not a move: {}", word)))
+}
+
+impl FromStr for EngineReply{
+    type Err = ProtocolError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let words : Vec<&str> = s.split_whitespace().collect();
+        match words.as_slice(){
+            ["id", key, value @ ..] => Ok(EngineReply::Id{key : key.to_string(), value : value.join(" ")}),
+            ["hxeok"] => Ok(EngineReply::HelloOk),
+            ["readyok"] => Ok(EngineReply::ReadyOk),
+            ["info", "string", ..] => {
+                let text = s.trim().strip_prefix("info").unwrap().trim_start()
+                    .strip_prefix("string").unwrap().trim_start();
+                Ok(EngineReply::InfoString(text.to_owned()))
+            },
+            ["info", fields @ ..] => {
+                let mut rest = fields;
+                let (mut depth, mut score, mut nodes, mut time) = (None, None, None, None);
+                let mut pv = vec![];
+                while let Some((&key, tail)) = rest.split_first(){
+                    let value = tail.first().copied();
+                    match key{
+                        "depth" => depth = Some(parse_number(key, value)?),
+                        "nodes" => nodes = Some(parse_number(key, value)?),
+                        "time" => time = Some(Duration::from_millis(parse_number(key, value)?)),
+                        "score" => {
+                            let amount = tail.get(1).copied();
+                            score = Some(match value{
+                                Some("cp") => EngineScore::Centi(parse_number("score", amount)?),
+                                Some("win") => EngineScore::Win(parse_number("score", amount)?),
+                                Some("loss") => EngineScore::Loss(parse_number("score", amount)?),
+                                _ => return error("score must be cp, win or loss")
+                            });
+                            rest = &tail[2..];
+                            continue;
+                        },
+                        "pv" => {
+                            pv = tail.iter().map(|w|parse_ply(w)).collect::<Result<_,_>>()?;
+                            break;
+                        },
+                        _ => return error(format!("unknown info field {}", key))
+                    }
+                    rest = &tail[1..];
+                }
+                match (depth, score, nodes, time){
+                    (Some(depth), Some(score), Some(nodes), Some(time)) =>
+                        Ok(EngineReply::Info(SearchInfo{depth, score, nodes, time, pv})),
+                    _ => error("info needs depth, score, nodes and time")
+                }
+            },
+            ["bestmove", "none"] => Ok(EngineReply::BestMove(None)),
+            ["bestmove", ply] => Ok(EngineReply::BestMove(Some(parse_ply(ply)?))),
+            _ => error(format!("unexpected reply {}", s.trim()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn test_protocol_roundtrip(){
+        let setup = Position::setup();
+        let moves = vec![setup.parse_move("Bb5").unwrap()];
+        let commands = [
+            GuiCommand::Hello,
+            GuiCommand::IsReady,
+            GuiCommand::SetOption{name : "Threads".to_owned(), value : "4".to_owned()},
+            GuiCommand::NewGame,
+            GuiCommand::Position{setup : setup.clone(), moves : vec![]},
+            GuiCommand::Position{setup : setup.clone(), moves : moves.clone()},
+            GuiCommand::Go(GoParams::depth(5)),
+            GuiCommand::Go(GoParams{depth : Some(3), movetime : Some(Duration::from_millis(250)), nodes : Some(1000)}),
+            GuiCommand::Go(GoParams::default()),
+            GuiCommand::Stop,
+            GuiCommand::Quit,
+        ];
+        for command in commands{
+            assert_eq!(command.to_string().parse(), Ok(command.clone()), "{}", command);
+        }
+
+        let replies = [
+            EngineReply::Id{key : "name".to_owned(), value : "hexstack 0.7.1".to_owned()},
+            EngineReply::HelloOk,
+            EngineReply::ReadyOk,
+            EngineReply::Info(SearchInfo{
+                depth : 4, score : EngineScore::Centi(-37), nodes : 12345,
+                time : Duration::from_millis(87), pv : moves.clone()
+            }),
+            EngineReply::Info(SearchInfo{
+                depth : 2, score : EngineScore::Loss(2), nodes : 10, time : Duration::ZERO, pv : vec![]
+            }),
+            EngineReply::InfoString("unknown command  xyzzy".to_owned()),
+            EngineReply::BestMove(moves.first().copied()),
+            EngineReply::BestMove(None),
+        ];
+        for reply in replies{
+            assert_eq!(reply.to_string().parse(), Ok(reply.clone()), "{}", reply);
+        }
+    }
+
+    #[test]
+    fn test_position_command(){
+        let mut position = Position::setup();
+        position.apply_move(position.parse_move("Bb5").unwrap());
+        let reply = position.valid_moves()[0];
+        let command : GuiCommand = format!("position startpos moves Bb5 {}", reply).parse().unwrap();
+        let GuiCommand::Position{setup, moves} = command else {panic!()};
+        assert_eq!(setup, Position::setup());
+        assert_eq!(moves.len(), 2);
+        assert_eq!(GuiCommand::position_after(&setup, &moves).to_play(), setup.to_play());
+
+        assert!("position startpos moves Fe1".parse::<GuiCommand>().is_err());
+        assert!("position nonsense".parse::<GuiCommand>().is_err());
+        assert!("position startpos Bb5".parse::<GuiCommand>().is_err());
+        assert!("go depth".parse::<GuiCommand>().is_err());
+        assert!("go depth x".parse::<GuiCommand>().is_err());
+    }
+
+    #[test]
+    fn test_engine_score(){
+        assert_eq!(EngineScore::of_root_move(Score::win_now(Player::White), Player::White), EngineScore::Win(1));
+        assert_eq!(EngineScore::of_root_move(Score::win_in(Player::White, 2), Player::Black), EngineScore::Loss(3));
+        assert_eq!(EngineScore::of_root_move(Score::EVEN.add(0.5), Player::Black), EngineScore::Centi(-50));
+    }
+}
@@ -0,0 +1,201 @@
+//! Client side of `engine_protocol`: runs another engine as a subprocess.
+
+use std::fmt::Display;
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, TryRecvError};
+
+use web_time::{Duration, Instant};
+
+use crate::engine_protocol::{EngineReply, GoParams, GuiCommand, ProtocolError, SearchInfo};
+use crate::tokonoma::{Ply, Position};
+
+/// How long an engine may take to answer the handshake.
+const HANDSHAKE_TIMEOUT : Duration = Duration::from_secs(10);
+
+/// Why an engine can no longer play.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum EngineError{
+    Launch(String),
+    /// The process exited or closed its output.
+    Crashed,
+    Protocol(ProtocolError),
+    /// `bestmove` named an illegal move, or `none` with moves available.
+    Illegal(Option<Ply>),
+    Timeout,
+}
+
+impl Display for EngineError{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self{
+            EngineError::Launch(e) => write!(f, "could not start the engine: {}", e),
+            EngineError::Crashed => write!(f, "the engine crashed"),
+            EngineError::Protocol(e) => write!(f, "protocol error: {}", e),
+            EngineError::Illegal(Some(ply)) => write!(f, "illegal move {}", ply),
+            EngineError::Illegal(None) => write!(f, "no move given"),
+            EngineError::Timeout => write!(f, "the engine did not answer in time"),
+        }
+    }
+}
+
+/// A running engine process.
+/// Searches one position at a time; dropping it ends the process.
+pub struct ExternalEngine{
+    child : Child,
+    stdin : ChildStdin,
+    replies : Receiver<Result<EngineReply, ProtocolError>>,
+    /// From `id name`, or the command.
+    pub name : String,
+    /// The position being searched.
+    searching : Option<Position>,
+    last_info : Option<SearchInfo>,
+}
+
+impl ExternalEngine{
+    /// Starts `command`, a program followed by its arguments separated by spaces,
+    /// and waits for the handshake.
+    pub fn launch(command : &str) -> Result<Self, EngineError>{
+        let mut words = command.split_whitespace();
+        let program = words.next().ok_or(EngineError::Launch("empty command".to_owned()))?;
+        let mut child = Command::new(program)
+            .args(words)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e|EngineError::Launch(e.to_string()))?;
+
+        let stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+        let (sender, replies) = mpsc::channel();
+        std::thread::spawn(move ||{
+            for line in BufReader::new(stdout).lines(){
+                let Ok(line) = line else {break};
+                if line.trim().is_empty(){
+                    continue;
+                }
+                // unknown chatter is tolerated, unreadable moves are not
+                let reply = line.parse::<EngineReply>();
+                if reply.is_err() && !line.trim_start().starts_with("bestmove"){
+                    continue;
+                }
+                if sender.send(reply).is_err(){
+                    break;
+                }
+            }
+        });
+
+        let mut engine = ExternalEngine{
+            child, stdin, replies,
+            name : command.to_owned(),
+            searching : None,
+            last_info : None,
+        };
+        engine.send(GuiCommand::Hello)?;
+        let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+        loop{
+            match engine.receive(deadline)?{
+                EngineReply::HelloOk => break,
+                EngineReply::Id{key, value} if key == "name" => engine.name = value,
+                _ => {}
+            }
+        }
+        Ok(engine)
+    }
+
+    fn send(&mut self, command : GuiCommand) -> Result<(), EngineError>{
+        writeln!(self.stdin, "{}", command)
+            .and_then(|_|self.stdin.flush())
+            .map_err(|_|EngineError::Crashed)
+    }
+
+    fn receive(&mut self, deadline : Instant) -> Result<EngineReply, EngineError>{
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        match self.replies.recv_timeout(timeout){
+            Ok(reply) => reply.map_err(EngineError::Protocol),
+            Err(RecvTimeoutError::Timeout) => Err(EngineError::Timeout),
+            Err(RecvTimeoutError::Disconnected) => Err(EngineError::Crashed),
+        }
+    }
+
+    pub fn new_game(&mut self) -> Result<(), EngineError>{
+        self.send(GuiCommand::NewGame)
+    }
+
+    /// Starts searching `position`, abandoning any previous search.
+    pub fn start_search(&mut self, position : &Position, params : GoParams) -> Result<(), EngineError>{
+        if self.searching.is_some(){
+            self.send(GuiCommand::Stop)?;
+            // the abandoned search still answers; skip up to its bestmove
+            loop{
+                if let EngineReply::BestMove(..) = self.receive(Instant::now() + HANDSHAKE_TIMEOUT)?{
+                    break;
+                }
+            }
+        }
+        self.last_info = None;
+        self.send(GuiCommand::Position{setup : position.clone(), moves : vec![]})?;
+        self.send(GuiCommand::Go(params))?;
+        self.searching = Some(position.clone());
+        Ok(())
+    }
+
+    /// Latest report of the current search.
+    pub fn last_info(&self) -> Option<&SearchInfo>{
+        self.last_info.as_ref()
+    }
+
+    /// The move found, if the engine has answered. Never blocks.
+    pub fn poll_move(&mut self) -> Result<Option<Ply>, EngineError>{
+        loop{
+            match self.replies.try_recv(){
+                Ok(reply) => if let Some(ply) = self.handle(reply.map_err(EngineError::Protocol)?)?{
+                    return Ok(Some(ply));
+                },
+                Err(TryRecvError::Empty) => return Ok(None),
+                Err(TryRecvError::Disconnected) => return Err(EngineError::Crashed),
+            }
+        }
+    }
+
+    /// Blocks until the move is found or the deadline passes.
+    pub fn wait_move(&mut self, deadline : Instant) -> Result<Ply, EngineError>{
+        loop{
+            let reply = self.receive(deadline)?;
+            if let Some(ply) = self.handle(reply)?{
+                return Ok(ply);
+            }
+        }
+    }
+
+    fn handle(&mut self, reply : EngineReply) -> Result<Option<Ply>, EngineError>{
+        match reply{
+            EngineReply::Info(info) => self.last_info = Some(info),
+            EngineReply::BestMove(ply) => {
+                let Some(position) = self.searching.take() else {return Ok(None)};
+                return match ply{
+                    Some(ply) if position.valid_moves().contains(&ply) => Ok(Some(ply)),
+                    _ => Err(EngineError::Illegal(ply))
+                };
+            },
+            _ => {}
+        }
+        Ok(None)
+    }
+}
+
+impl Drop for ExternalEngine{
+    fn drop(&mut self){
+        let _ = self.send(GuiCommand::Quit);
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// The `engine` binary next to the running executable, if there is one.
+/// Commands are split on spaces, so the path must not contain any.
+pub fn bundled_engine_command() -> Option<String>{
+    let name = format!("engine{}", std::env::consts::EXE_SUFFIX);
+    let path = std::env::current_exe().ok()?.with_file_name(name);
+    path.exists().then(||path.to_string_lossy().into_owned())
+}
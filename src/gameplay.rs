@@ -1,12 +1,17 @@
-use std::collections::HashSet;
-use std::sync::{Arc, Mutex};
+use std::cell::RefCell;
+use std::collections::{HashSet, VecDeque};
+use std::rc::Rc;
+use std::sync::Arc;
+#[cfg(not(target_arch="wasm32"))]
+use std::sync::mpsc;
 
 use crate::assets::get_assets_unchecked;
 use crate::assets::mipmaps::set_cam;
 use crate::theme::egui_ctx_setup;
-use crate::tokonoma::{board::Piece, EvalResult, Position};
+use crate::tokonoma::{board::Piece, Position};
 
-use crate::tokonoma::{HalfOpeningDetectionError, MatchState, PlayerMap, PositionString, TranspositionalTable};
+use crate::tokonoma::correspondence;
+use crate::tokonoma::{DrawReason, DrawRules, GameOutcome, GameRecord, MatchState, OpeningClassificationError, PlayerMap, PositionString, SearchOutcome, StopToken, TranspositionalTable};
 
 use crate::{theme::set_theme, ui::{editor::PositionEditor, rulesheet::read_rulesheet}};
 use crate::ui::{Button, MqUi};
@@ -17,7 +22,6 @@ use macroquad::audio::{play_sound, play_sound_once, PlaySoundParams};
 use macroquad::prelude::*;
 
 use macroquad::experimental::coroutines::{start_coroutine,Coroutine};
-use ::rand::distributions::Open01;
 use ::rand::Rng;
 
 #[cfg(target_arch="wasm32")]
@@ -25,59 +29,41 @@ use send_wrapper::SendWrapper;
 
 const MOVE_ANIM_DURATION : f32 = 0.15;
 
-#[derive(PartialEq, Eq, Clone, Copy)]
-pub enum GamerSpec{
-    Human,
-    Gibberish,
-    Noob,
-    Decent,
-    Sharp,
-    Tough,
-    GrandMaster,
-
-    Perfect{
-        depth : usize
+pub use crate::gamer_spec::{BotFiles, GamerSpec};
+use crate::gamer_spec::BotSettings;
+
+#[cfg(not(target_arch="wasm32"))]
+use crate::engine_protocol::GoParams;
+#[cfg(not(target_arch="wasm32"))]
+use crate::external_engine::{EngineError, ExternalEngine};
+#[cfg(not(target_arch="wasm32"))]
+use crate::networking::{NetworkError, OnlineMatch, RemoteEvent, Spectator, SpectatorEvent};
+
+/// Thinking time given to external engines.
+#[cfg(not(target_arch="wasm32"))]
+const EXTERNAL_MOVETIME : web_time::Duration = web_time::Duration::from_secs(2);
+/// Extra time before an external engine that has not answered forfeits.
+#[cfg(not(target_arch="wasm32"))]
+const EXTERNAL_GRACE : web_time::Duration = web_time::Duration::from_secs(5);
+
+/// `online` is the opponent connected for an online seat, `None` for a seat without one.
+fn make_gamer(spec : GamerSpec, allow_takeback : bool, files : &BotFiles, online : &mut Option<Box<dyn Gamer>>) -> Option<Box<dyn Gamer>>{
+    if let GamerSpec::Online { .. } = &spec{
+        return online.take();
     }
-}
-
-impl GamerSpec{
-    pub fn name(&self) -> String{
-        self.texts().0
+    if let GamerSpec::External { command } = &spec{
+        #[cfg(not(target_arch="wasm32"))]
+        return Some(ExternalGamer::launch(command));
+        #[cfg(target_arch="wasm32")]
+        unreachable!("external engines are not offered on the web, cannot run {}", command);
     }
-    pub fn description(&self) -> String{
-        self.texts().1
-    }
-
-    pub fn texts(&self) -> (String,String){
-        match self{
-            GamerSpec::Human => ("Human".to_owned(), "Human player.".to_owned()),
-            GamerSpec::Gibberish => ("Gibberish".to_owned(), "Random moves.".to_owned()),
-            GamerSpec::Noob => ("Noob".to_owned(), "Poor player.".to_owned()),
-            GamerSpec::Decent => ("Decent".to_owned(), "Solid player.".to_owned()),
-            GamerSpec::Sharp => ("Sharp".to_owned(), "Serious challenge.".to_owned()),
-            GamerSpec::Tough => ("Tough".to_owned(), "Very strong.".to_owned()),
-            GamerSpec::GrandMaster => ("Grandmaster".to_owned(), "Unbelievable.".to_owned()),
-
-            GamerSpec::Perfect { depth } => 
-                (format!("Beastly-{}",depth),format!("Perfect {}-plies",depth))
-        }
-    }
-
-    fn make(self, allow_takeback : bool) -> Box<dyn Gamer>{
-        match self{
-            GamerSpec::Human => Human::new_boxed( allow_takeback),
-            GamerSpec::Gibberish => Bot::new_boxed(0,0.0),
-            GamerSpec::Noob => Bot::new_boxed(1, 0.2),
-            GamerSpec::Decent => Bot::new_boxed(2, 0.2),
-            GamerSpec::Sharp => Bot::new_boxed(3, 0.4),
-            GamerSpec::Tough => Bot::new_boxed(5, 0.4),
-            GamerSpec::GrandMaster => Bot::new_boxed(6, 0.2),
-
-            GamerSpec::Perfect { depth } => Bot::new_boxed(depth, 0.0)
-        }
+    if let GamerSpec::Correspondence { .. } = &spec{
+        return Some(CorrespondenceGamer::new_boxed());
     }
-
-    
+    Some(match spec.bot_settings(){
+        None => Human::new_boxed(allow_takeback),
+        Some(settings) => Bot::new_boxed(settings.with_files(files)),
+    })
 }
 
 #[derive(Clone)]
@@ -85,16 +71,37 @@ pub struct MatchConfig{
     pub gamers : [GamerSpec;2],
     pub gamer_one_color : Option<Player>,
     pub allow_takeback : bool,
-    pub starting_position : Option<PositionEditor>
+    pub starting_position : Option<PositionEditor>,
+    pub draw_rules : DrawRules,
 }
 
+/// A game to look at rather than play.
+pub enum ViewConfig{
+    /// A room of a game server, see `networking::Spectator`.
+    #[cfg(not(target_arch="wasm32"))]
+    Live{address : String, room : String},
+    /// A saved game, which must replay.
+    Record(GameRecord),
+}
 
 
 
-#[derive(Clone, Copy)]
+
+#[derive(Clone)]
 enum Decision{
     Move(Ply),
-    TakeBack
+    TakeBack,
+    /// Undoes exactly these plies, as an online opponent took them back.
+    Rewind(usize),
+    /// Gives up the game, with the reason.
+    Forfeit(String),
+}
+
+/// A game ended whoever is to play.
+enum Ending{
+    /// The loser and the reason.
+    Forfeit(Player, String),
+    Draw,
 }
 
 trait Gamer{
@@ -107,31 +114,52 @@ trait Gamer{
     fn allows_takebacks(&self) -> bool;
 
     fn poll_grab_signal(&mut self) -> Option<()>;
+
+    /// Resignations and agreed draws, which need not wait for this gamer's turn.
+    fn poll_ending(&mut self) -> Option<Ending>{
+        None
+    }
+
+    /// No takebacks once the game is over.
+    fn final_results(&self) -> bool{
+        false
+    }
+
+    /// Whether this gamer answers in place of the one to play, which waits meanwhile,
+    /// as when a takeback was asked of an online opponent.
+    fn holds_turn(&self) -> bool{
+        false
+    }
+
+    /// Controls and news below the move list.
+    fn side_panel(&mut self, _ui : &mut egui::Ui, _as_player : Player, _match_state : &MatchState){
+    }
 }
 
 
 struct Bot{
-    depth : usize,
-    blundering_probability : f32,
+    settings : BotSettings,
 
-    result_future : Option<Coroutine<Vec<(Ply,EvalResult)>>>,
-    last_used_depth : Option<usize>,
-    transposition_table : Arc<Mutex<TranspositionalTable>>,
+    result_future : Option<Coroutine<SearchOutcome>>,
+    /// Played without searching.
+    book_move : Option<Ply>,
+    stop : StopToken,
+    transposition_table : Arc<TranspositionalTable>,
 }
 
 impl Bot{
-    fn new(depth : usize, blundering_probability : f32) -> Bot{
+    fn new(settings : BotSettings) -> Bot{
         Bot { 
-            depth ,
-            blundering_probability,
+            settings,
             result_future : None,
-            last_used_depth : None,
-            transposition_table : Arc::new(Mutex::new(TranspositionalTable::new())),
+            book_move : None,
+            stop : StopToken::new(),
+            transposition_table : Arc::new(TranspositionalTable::new()),
         }
     }
     
-    fn new_boxed(depth : usize, blundering_probability : f32) -> Box<Bot>{
-        Box::new(Self::new(depth,blundering_probability))
+    fn new_boxed(settings : BotSettings) -> Box<Bot>{
+        Box::new(Self::new(settings))
     }
 }
 
@@ -140,33 +168,39 @@ impl Gamer for Bot{
         false
     }
     fn assign_puzzle(&mut self, state : Position) {
-        let mut depth = self.depth;
+        let limits = self.settings.roll_limits();
+        let mquad_frame_await = limits.max_depth > 5;
 
-        let mut rng = ::rand::thread_rng();
-        while rng.sample::<f32,Open01>(Open01) < self.blundering_probability {
-            depth = depth.saturating_sub(1)
-        }
+        // a search still running for a previous puzzle is abandoned
+        self.stop.stop();
+        self.stop = StopToken::new();
 
-        self.last_used_depth = Some(depth);
+        self.book_move = self.settings.probe_book(&state);
+        if self.book_move.is_some(){
+            self.result_future = None;
+            return;
+        }
 
         self.result_future = Some(start_coroutine(
-            state.moves_with_score(depth,depth > 5,Some(self.transposition_table.clone()))));
+            state.search(
+                limits,
+                self.stop.clone(),
+                Some(self.transposition_table.clone()),
+                mquad_frame_await,
+                |_|{}
+            )));
     }
 
     fn poll_answer(&mut self) -> Option<Decision> {
-        let answer = self.result_future.as_ref().map(|future|
-            future.retrieve().map(|evals|{
-                println!("---- {:?} ----", self.last_used_depth);
-                evals.iter().for_each(|(ply,eval)|{
-                    println!("{} - {} [{}]", eval.score, ply, eval.nodes);
-                });
-                evals.first().unwrap().0
-            })
-        ).flatten();
+        if let Some(ply) = self.book_move.take(){
+            return Some(Decision::Move(ply));
+        }
+        let answer = self.result_future.as_ref().and_then(|future|
+            future.retrieve().map(|outcome|outcome.best_move().unwrap()));
 
         if answer.is_some() {self.result_future = None;}
 
-        answer.map(|rep|Decision::Move(rep))
+        answer.map(Decision::Move)
     }
     fn poll_grab_signal(&mut self) -> Option<()> {
         None
@@ -178,6 +212,434 @@ impl Gamer for Bot{
     fn avatar_offset(&self) -> usize {1}
 }
 
+impl Drop for Bot{
+    fn drop(&mut self){
+        self.stop.stop();
+    }
+}
+
+/// Another engine process. Crashes and illegal moves forfeit the game.
+#[cfg(not(target_arch="wasm32"))]
+struct ExternalGamer{
+    /// Started on a thread of its own, the handshake may take a while.
+    launching : Option<mpsc::Receiver<Result<ExternalEngine, EngineError>>>,
+    engine : Result<ExternalEngine, EngineError>,
+    /// Assigned before the engine was ready.
+    puzzle : Option<Position>,
+    deadline : Option<web_time::Instant>,
+}
+
+#[cfg(not(target_arch="wasm32"))]
+impl ExternalGamer{
+    fn launch(command : &str) -> Box<ExternalGamer>{
+        let (sender, receiver) = mpsc::channel();
+        let command = command.to_owned();
+        std::thread::spawn(move ||{
+            let _ = sender.send(ExternalEngine::launch(&command));
+        });
+        Box::new(ExternalGamer{
+            launching : Some(receiver),
+            engine : Err(EngineError::Launch("still starting".to_owned())),
+            puzzle : None,
+            deadline : None,
+        })
+    }
+
+    /// Takes the engine once launched, and gives it the waiting puzzle.
+    fn poll_launch(&mut self){
+        let Some(receiver) = &self.launching else {return};
+        self.engine = match receiver.try_recv(){
+            Ok(engine) => engine,
+            Err(mpsc::TryRecvError::Empty) => return,
+            Err(mpsc::TryRecvError::Disconnected) => Err(EngineError::Launch("launcher stopped".to_owned())),
+        };
+        self.launching = None;
+        if let Some(state) = self.puzzle.take(){
+            self.start_search(state);
+        }
+    }
+
+    fn start_search(&mut self, state : Position){
+        self.deadline = Some(web_time::Instant::now() + EXTERNAL_MOVETIME + EXTERNAL_GRACE);
+        if let Ok(engine) = &mut self.engine{
+            if let Err(e) = engine.start_search(&state, GoParams::movetime(EXTERNAL_MOVETIME)){
+                self.engine = Err(e);
+            }
+        }
+    }
+}
+
+#[cfg(not(target_arch="wasm32"))]
+impl Gamer for ExternalGamer{
+    fn allows_takebacks(&self) -> bool {
+        false
+    }
+
+    fn assign_puzzle(&mut self, state : Position) {
+        if self.launching.is_some(){
+            self.puzzle = Some(state);
+        } else {
+            self.start_search(state);
+        }
+    }
+
+    fn poll_answer(&mut self) -> Option<Decision> {
+        self.poll_launch();
+        if self.launching.is_some(){
+            return None;
+        }
+        let answer = match &mut self.engine{
+            Ok(engine) => engine.poll_move().and_then(|ply|{
+                let late = self.deadline.is_some_and(|d|web_time::Instant::now() > d);
+                if ply.is_none() && late {Err(EngineError::Timeout)} else {Ok(ply)}
+            }),
+            Err(e) => Err(e.clone()),
+        };
+        match answer{
+            Ok(ply) => ply.map(Decision::Move),
+            Err(e) => {
+                let reason = e.to_string();
+                self.engine = Err(e);
+                Some(Decision::Forfeit(reason))
+            }
+        }
+    }
+
+    fn poll_grab_signal(&mut self) -> Option<()> {
+        None
+    }
+
+    fn process(&mut self, _ui : &MqUi, _as_player : Player){
+    }
+
+    fn avatar_offset(&self) -> usize {1}
+}
+
+/// The other side of an online match. Its moves and takebacks come from the peer;
+/// those of the local side are sent when it is this gamer's turn again.
+#[cfg(not(target_arch="wasm32"))]
+struct RemoteGamer{
+    online : Result<OnlineMatch, NetworkError>,
+    answers : VecDeque<Decision>,
+    ending : Option<Ending>,
+    /// Latest news for the panel.
+    notice : Option<String>,
+}
+
+#[cfg(not(target_arch="wasm32"))]
+impl RemoteGamer{
+    fn new_boxed(online : OnlineMatch) -> Box<RemoteGamer>{
+        Box::new(RemoteGamer{online : Ok(online), answers : VecDeque::new(), ending : None, notice : None})
+    }
+
+    /// A broken connection forfeits the game of the side that broke it, unless it is over.
+    fn fail(&mut self, error : NetworkError, loser : Player){
+        if let Ok(online) = &self.online{
+            if online.outcome().is_none(){
+                self.ending = Some(Ending::Forfeit(loser, error.to_string()));
+            }
+        }
+        self.online = Err(error);
+    }
+}
+
+#[cfg(not(target_arch="wasm32"))]
+impl Gamer for RemoteGamer{
+    fn allows_takebacks(&self) -> bool {
+        false
+    }
+
+    fn assign_puzzle(&mut self, state : Position) {
+        // any move withdraws draw offers
+        self.notice = None;
+        if let Ok(online) = &mut self.online{
+            if let Err(e) = online.report(&state){
+                let local = online.local;
+                self.fail(e, local);
+            }
+        }
+    }
+
+    fn poll_answer(&mut self) -> Option<Decision> {
+        self.answers.pop_front()
+    }
+
+    fn poll_grab_signal(&mut self) -> Option<()> {
+        None
+    }
+
+    fn process(&mut self, _ui : &MqUi, as_player : Player){
+        let Ok(online) = &mut self.online else {return};
+        loop{
+            let event = match online.poll(){
+                Ok(Some(event)) => event,
+                Ok(None) => break,
+                Err(e) => return self.fail(e, as_player),
+            };
+            match event{
+                RemoteEvent::Moved(ply) => {
+                    self.answers.push_back(Decision::Move(ply));
+                    self.notice = None;
+                },
+                RemoteEvent::TakebackAsked(plies) =>
+                    self.notice = Some(format!("{} asks to take back {} plies.", online.peer_name(), plies)),
+                RemoteEvent::TookBack(plies) => {
+                    self.answers.push_back(Decision::Rewind(plies));
+                    self.notice = None;
+                },
+                RemoteEvent::TakebackDeclined => self.notice = Some(format!("{} declines the takeback.", online.peer_name())),
+                RemoteEvent::Resigned => self.ending = Some(Ending::Forfeit(as_player, "resigned".to_owned())),
+                RemoteEvent::DrawOffered => self.notice = Some(format!("{} offers a draw.", online.peer_name())),
+                RemoteEvent::DrawAgreed => self.ending = Some(Ending::Draw),
+            }
+        }
+    }
+
+    fn poll_ending(&mut self) -> Option<Ending>{
+        self.ending.take()
+    }
+
+    fn final_results(&self) -> bool{
+        true
+    }
+
+    fn holds_turn(&self) -> bool{
+        // a takeback agreed on comes on the local side's turn
+        !self.answers.is_empty() || self.online.as_ref().is_ok_and(|online|online.asks_takeback())
+    }
+
+    fn side_panel(&mut self, ui : &mut egui::Ui, as_player : Player, _match_state : &MatchState){
+        ui.add_space(10.0);
+        let online = match &mut self.online{
+            Ok(online) => online,
+            Err(e) => {
+                ui.label(format!("Offline: {}", e));
+                return;
+            }
+        };
+        ui.label(format!("Playing {} online.", online.peer_name()));
+        if online.outcome().is_some(){
+            return;
+        }
+        let mut result = Ok(());
+        ui.horizontal(|ui|{
+            let draw_label = if online.peer_offers_draw() {"Accept draw"} else {"Offer draw"};
+            if ui.button(draw_label).clicked(){
+                result = online.offer_draw().map(|agreed|match agreed{
+                    true => self.ending = Some(Ending::Draw),
+                    false => self.notice = Some("Draw offered.".to_owned()),
+                });
+            }
+            if ui.button("Resign").clicked(){
+                result = online.resign().map(|_|
+                    self.ending = Some(Ending::Forfeit(as_player.flip(), "resigned".to_owned())));
+            }
+        });
+        // back to the local side's previous turn
+        if online.can_take_back(2) && ui.button("Ask to take back").clicked(){
+            result = online.take_back(2).map(|_|self.notice = Some("Takeback asked.".to_owned()));
+        }
+        if online.peer_asks_takeback().is_some(){
+            ui.horizontal(|ui|{
+                for (label, accept) in [("Accept takeback", true), ("Decline", false)]{
+                    if ui.button(label).clicked(){
+                        result = online.answer_takeback(accept).map(|plies|{
+                            if plies > 0{
+                                self.answers.push_back(Decision::Rewind(plies));
+                            }
+                            self.notice = None;
+                        });
+                    }
+                }
+            });
+        }
+        if let Some(notice) = &self.notice{
+            ui.label(notice);
+        }
+        if let Err(e) = result{
+            self.fail(e, as_player.flip());
+        }
+    }
+
+    fn avatar_offset(&self) -> usize {0}
+}
+
+/// The other side of a correspondence game, see `tokonoma::correspondence`. The panel
+/// shows the token to send after each local move and takes the reply pasted back.
+struct CorrespondenceGamer{
+    /// Token of the game and its ply count, encoded again after each move.
+    token : Option<(usize, String)>,
+    reply : String,
+    answer : Option<Decision>,
+    error : Option<String>,
+}
+
+impl CorrespondenceGamer{
+    fn new_boxed() -> Box<CorrespondenceGamer>{
+        Box::new(CorrespondenceGamer{token : None, reply : String::new(), answer : None, error : None})
+    }
+}
+
+impl Gamer for CorrespondenceGamer{
+    fn allows_takebacks(&self) -> bool {
+        false
+    }
+
+    fn assign_puzzle(&mut self, _state : Position) {
+        self.reply.clear();
+        self.error = None;
+    }
+
+    fn poll_answer(&mut self) -> Option<Decision> {
+        self.answer.take()
+    }
+
+    fn poll_grab_signal(&mut self) -> Option<()> {
+        None
+    }
+
+    fn process(&mut self, _ui : &MqUi, _as_player : Player){
+    }
+
+    fn side_panel(&mut self, ui : &mut egui::Ui, as_player : Player, match_state : &MatchState){
+        let plies = match_state.history().len();
+        if self.token.as_ref().is_none_or(|(encoded, _)|*encoded != plies){
+            self.token = Some((plies, correspondence::encode(match_state)));
+        }
+        let token = &self.token.as_ref().unwrap().1;
+
+        ui.add_space(10.0);
+        let awaiting_reply = match_state.to_play() == as_player;
+        if !awaiting_reply && match_state.outcome().is_none(){
+            ui.label("Correspondence game: after your move, send the token shown here.");
+            return;
+        }
+        ui.label(if awaiting_reply {"Token to send:"} else {"Token of the game:"});
+        ui.horizontal(|ui|{
+            ui.add(egui::TextEdit::singleline(&mut token.as_str()).desired_width(220.0));
+            if ui.button("Copy").clicked(){
+                ui.output_mut(|output|output.copied_text = token.clone());
+            }
+        });
+        if match_state.outcome().is_some(){
+            return;
+        }
+        ui.label("Paste the reply:");
+        ui.horizontal(|ui|{
+            ui.add(egui::TextEdit::singleline(&mut self.reply).desired_width(220.0));
+            if ui.button("Continue").clicked() && self.answer.is_none(){
+                match correspondence::continue_from(match_state, &self.reply, as_player.flip()){
+                    Ok(next) => self.answer = Some(Decision::Move(next.history().last().unwrap().ply)),
+                    Err(e) => self.error = Some(format!("Token rejected: {}.", e)),
+                }
+            }
+        });
+        if let Some(error) = &self.error{
+            ui.label(error);
+        }
+    }
+
+    fn avatar_offset(&self) -> usize {0}
+}
+
+/// What is known of a game watched, shared by both of its seats.
+struct Feed{
+    #[cfg(not(target_arch="wasm32"))]
+    spectator : Option<Result<Spectator, NetworkError>>,
+    players : PlayerMap<String>,
+    answers : VecDeque<Decision>,
+    ending : Option<Ending>,
+    /// Latest news for the panel.
+    notice : Option<String>,
+}
+
+impl Feed{
+    /// Takes in what happened in the room watched, if anything.
+    #[cfg(not(target_arch="wasm32"))]
+    fn update(&mut self){
+        let Some(Ok(spectator)) = &mut self.spectator else {return};
+        loop{
+            let event = match spectator.poll(){
+                Ok(Some(event)) => event,
+                Ok(None) => break,
+                Err(e) => {
+                    // the server hangs up once the game is over
+                    if spectator.outcome().is_none(){
+                        self.notice = Some(format!("Offline: {}", e));
+                    }
+                    self.spectator = Some(Err(e));
+                    return;
+                }
+            };
+            let name = |player : Player|spectator.players()[player].clone().unwrap_or("?".to_owned());
+            match event{
+                SpectatorEvent::Seated(player) => self.players[player] = name(player),
+                SpectatorEvent::By(_, RemoteEvent::Moved(ply)) => {
+                    self.answers.push_back(Decision::Move(ply));
+                    self.notice = None;
+                },
+                SpectatorEvent::By(player, RemoteEvent::TakebackAsked(plies)) =>
+                    self.notice = Some(format!("{} asks to take back {} plies.", name(player), plies)),
+                SpectatorEvent::By(_, RemoteEvent::TookBack(plies)) => {
+                    self.answers.push_back(Decision::Rewind(plies));
+                    self.notice = None;
+                },
+                SpectatorEvent::By(player, RemoteEvent::TakebackDeclined) => self.notice = Some(format!("{} declines the takeback.", name(player))),
+                SpectatorEvent::By(player, RemoteEvent::Resigned) => self.ending = Some(Ending::Forfeit(player, "resigned".to_owned())),
+                SpectatorEvent::By(player, RemoteEvent::DrawOffered) => self.notice = Some(format!("{} offers a draw.", name(player))),
+                SpectatorEvent::By(_, RemoteEvent::DrawAgreed) => self.ending = Some(Ending::Draw),
+            }
+        }
+    }
+}
+
+/// A seat of a game watched or replayed: its moves come from the feed, not from here.
+struct Watched{
+    feed : Rc<RefCell<Feed>>,
+}
+
+impl Gamer for Watched{
+    fn allows_takebacks(&self) -> bool {
+        false
+    }
+
+    fn assign_puzzle(&mut self, _state : Position) {
+    }
+
+    fn poll_answer(&mut self) -> Option<Decision> {
+        self.feed.borrow_mut().answers.pop_front()
+    }
+
+    fn poll_grab_signal(&mut self) -> Option<()> {
+        None
+    }
+
+    fn process(&mut self, _ui : &MqUi, _as_player : Player){
+        #[cfg(not(target_arch="wasm32"))]
+        self.feed.borrow_mut().update();
+    }
+
+    fn poll_ending(&mut self) -> Option<Ending>{
+        self.feed.borrow_mut().ending.take()
+    }
+
+    fn final_results(&self) -> bool{
+        true
+    }
+
+    fn side_panel(&mut self, ui : &mut egui::Ui, as_player : Player, _match_state : &MatchState){
+        let feed = self.feed.borrow();
+        let color = match as_player {Player::White => "White", Player::Black => "Black"};
+        ui.label(format!("{}: {}", color, feed.players[as_player]));
+        // news once, after both names
+        if let Some(notice) = feed.notice.as_ref().filter(|_|as_player == Player::Black){
+            ui.label(notice);
+        }
+    }
+
+    fn avatar_offset(&self) -> usize {0}
+}
+
 struct Human{
     selected_tile : Option<Tile>,
     puzzle_state : Option<Position>,
@@ -232,7 +694,7 @@ impl Gamer for Human{
 
     fn assign_puzzle(&mut self, state : Position) {
         self.reset();
-        self.available_moves = Some(HashSet::from_iter(state.valid_moves().into_iter()));
+        self.available_moves = Some(HashSet::from_iter(state.valid_moves()));
         self.puzzle_state = Some(state);
         
     }
@@ -240,7 +702,7 @@ impl Gamer for Human{
     
     fn poll_answer(&mut self) -> Option<Decision> {
         if self.answer.is_some(){
-            let output = self.answer.clone();
+            let output = self.answer.take();
             self.reset();
             output
         } else {None}
@@ -249,10 +711,8 @@ impl Gamer for Human{
 
     fn process(&mut self, ui : &MqUi, as_player : Player) {
         if let Some(av_moves) = &self.available_moves{
-            if self.allow_takeback{
-                if self.btn_takeback.process(&ui){
-                    self.answer = Some(Decision::TakeBack);
-                }
+            if self.allow_takeback && self.btn_takeback.process(ui){
+                self.answer = Some(Decision::TakeBack);
             }
 
             
@@ -345,8 +805,8 @@ enum GameStateMachine{
     },
     Polling,
     Animating(MoveAnimState),
-    Won{
-        winner : Player
+    Over{
+        outcome : GameOutcome
     }
 }
 
@@ -377,6 +837,8 @@ struct GameApp{
     last_kill_tiles : Vec<Tile>,
 
     app_state : GameStateMachine,
+    /// Who gave up, and why. The match state does not know about it.
+    forfeit : Option<(Player, String)>,
 
     attack_patterns_alpha : f32,
     attack_patterns_toggle : bool,
@@ -393,6 +855,8 @@ struct GameApp{
     btn_rulesheet : Button,
 
     display_mode : DisplayMode,
+    /// For a game watched live, whether new moves are shown as they come.
+    follow_live : Option<bool>,
 
     poll_history_scroll : bool,
 
@@ -412,16 +876,34 @@ fn make_takeback_button() -> Button{
 }
 
 impl GameApp{
+    /// `online` plays the online seat of the configuration, if there is one.
+    /// `None` when an online seat is left without an opponent, as a second one would be.
     async fn new(
-            match_config : MatchConfig
-        )->GameApp{
+            match_config : MatchConfig,
+            files : &BotFiles,
+            mut online : Option<Box<dyn Gamer>>
+        )->Option<GameApp>{
 
         
 
 
         
         
-        let first_gamer_color = if let Some(color) = match_config.gamer_one_color{
+        // a correspondence game resumes from the token received, where the opponent
+        // moved last; moves sent cannot be taken back
+        let resumed = match_config.gamers.iter().enumerate().find_map(|(seat, gamer)|match gamer{
+            GamerSpec::Correspondence { token } if !token.trim().is_empty() =>
+                Some((seat, correspondence::decode(token).expect("tokens are checked in the menu"))),
+            _ => None
+        });
+        // online, takebacks are asked of the opponent instead
+        let allow_takeback = match_config.allow_takeback
+            && !match_config.gamers.iter().any(|g|matches!(g, GamerSpec::Correspondence{..} | GamerSpec::Online{..}));
+
+        let first_gamer_color = if let Some((seat, match_state)) = &resumed{
+            let opponent = match_state.to_play().flip();
+            if *seat == 0 {opponent} else {opponent.flip()}
+        } else if let Some(color) = match_config.gamer_one_color{
             color
         } else {
             if ::rand::thread_rng().gen::<bool>() {
@@ -431,10 +913,10 @@ impl GameApp{
             }
         };
         
-        let assets = get_assets_unchecked();
-        
-        let [gm0,gm1] = match_config.gamers.map(
-            |s|s.make( match_config.allow_takeback));
+        let [Some(gm0),Some(gm1)] = match_config.gamers.map(
+            |s|make_gamer(s, allow_takeback, files, &mut online)) else {
+            return None;
+        };
 
     
         let gamers = PlayerMap::new_on_player(first_gamer_color, gm0, gm1);
@@ -444,18 +926,39 @@ impl GameApp{
             .map(|ed|ed.get_state_clone())
             .unwrap_or(Position::setup());
 
-        let match_state = MatchState::setup_from(starting_position);
+        let match_state = match resumed{
+            Some((_, match_state)) => match_state,
+            None => MatchState::setup_from(starting_position).with_draw_rules(match_config.draw_rules),
+        };
+        Some(GameApp::from_parts(match_state, gamers, None))
+    }
+
+    /// A game to look at from `match_state` on, where `feed` brings any further moves.
+    fn watching(match_state : MatchState, feed : Feed, live : bool) -> GameApp{
+        let feed = Rc::new(RefCell::new(feed));
+        let gamers = PlayerMap::new(
+            Box::new(Watched{feed : feed.clone()}) as Box<dyn Gamer>,
+            Box::new(Watched{feed}) as Box<dyn Gamer>);
+        GameApp::from_parts(match_state, gamers, live.then_some(true))
+    }
+
+    fn from_parts(match_state : MatchState, gamers : PlayerMap<Box<dyn Gamer>>, follow_live : Option<bool>) -> GameApp{
+        let assets = get_assets_unchecked();
         let pstring = match_state.position_string(None).unwrap().clone();
+        // a game joined late opens on its last move
+        let poll_history_scroll = !match_state.history().is_empty();
  
-        let app_state = GameApp{
+        GameApp{
             
             match_state,
 
             display_mode : DisplayMode::Present,
+            follow_live,
             gamers ,
             
             last_touched_tiles : None,
             app_state : GameStateMachine::Setup{time:0.0},
+            forfeit : None,
 
             last_kill_tiles : vec![],
             attack_patterns_alpha : 0.0,
@@ -489,15 +992,11 @@ impl GameApp{
 
             
 
-            poll_history_scroll : false,
+            poll_history_scroll,
 
             pstring ,
             pstring_state : PStringClipBoard::Idle,
-        };
-
-        
-        
-        app_state
+        }
     }
     
     fn ask(&mut self){
@@ -508,7 +1007,16 @@ impl GameApp{
     }
 
     fn apply_move(&mut self, ply : Ply){
-        self.display_mode = DisplayMode::Present;
+        // a spectator not following the game keeps looking at the same position
+        match (&self.display_mode, self.follow_live){
+            (_, Some(false)) if self.match_state.history().is_empty() => {},
+            (DisplayMode::Present, Some(false)) => self.display_mode = DisplayMode::History { index: self.match_state.history().len() - 1 },
+            (DisplayMode::History { .. }, Some(false)) => {},
+            _ => {
+                self.display_mode = DisplayMode::Present;
+                self.poll_history_scroll = true;
+            }
+        }
         
 
 
@@ -519,9 +1027,18 @@ impl GameApp{
 
         self.match_state.apply_move(ply);
         self.last_touched_tiles = Some([ply.from_tile,ply.to_tile]);
+    }
 
-        self.poll_history_scroll = true;
-        
+    fn end(&mut self, ending : Ending){
+        play_sound_once(get_assets_unchecked().mate);
+        let outcome = match ending{
+            Ending::Forfeit(loser, reason) => {
+                self.forfeit = Some((loser, reason));
+                GameOutcome::won_by(loser.flip())
+            },
+            Ending::Draw => GameOutcome::Draw(DrawReason::Agreement),
+        };
+        self.app_state = GameStateMachine::Over { outcome };
     }
 
     fn undo_until_human(&mut self){
@@ -536,9 +1053,14 @@ impl GameApp{
 
 
     fn undo_moves(&mut self, count : usize){
-        self.display_mode = DisplayMode::Present;
-
         self.match_state.undo_moves(count);
+        self.display_mode = match self.display_mode{
+            DisplayMode::History { index } if self.follow_live == Some(false) && index + 1 < self.match_state.history().len()
+                => DisplayMode::History { index },
+            _ => DisplayMode::Present
+        };
+
+        self.forfeit = None;
         self.poll_history_scroll = true;
     
         self.last_kill_tiles = vec![];
@@ -558,7 +1080,7 @@ impl GameApp{
         ) * delta_t;
 
         let target_smooth_to_play = match self.app_state{
-            GameStateMachine::Won { .. } => 0.5,
+            GameStateMachine::Over { .. } => 0.5,
             _ => match self.match_state.to_play() {Player::Black => 1.0, Player::White => 0.0}
         };
 
@@ -622,12 +1144,9 @@ impl GameApp{
                     };
 
                     if ui.button(">").clicked(){
-                        match &mut self.display_mode{
-                            DisplayMode::History { index } => {
-                                *index = (*index+1).min(hlen-1);
-                                if *index == hlen-1 {self.display_mode = DisplayMode::Present}
-                            },
-                            _ => {}
+                        if let DisplayMode::History { index } = &mut self.display_mode {
+                            *index = (*index+1).min(hlen-1);
+                            if *index == hlen-1 {self.display_mode = DisplayMode::Present}
                         }
                     };
 
@@ -636,11 +1155,17 @@ impl GameApp{
                         self.poll_history_scroll = true;
                     }
                 });
+                if let Some(follow_live) = &mut self.follow_live{
+                    if ui.checkbox(follow_live, "Follow live").changed() && *follow_live{
+                        self.display_mode = DisplayMode::Present;
+                        self.poll_history_scroll = true;
+                    }
+                }
                 ui.add_space(10.0);
 
                 use PStringClipBoard as PS;
                 let pstring_text = egui::RichText::new(
-                    &format!("{}{}",self.pstring,
+                    format!("{}{}",self.pstring,
                             match self.pstring_state{
                                 PS::Copied(res) => match res {Ok(..) => " (copied.)", Err(..) => " (copy failed.)"},
                                 PS::Idle => "",
@@ -700,32 +1225,10 @@ impl GameApp{
 
 
                         {
-                            use Player as P;
-                            use HalfOpeningDetectionError as HODE;
-                            let opening_text = 
-                            match [P::White,P::Black].map(|p|self.match_state.half_opening(p)){
-                                [Err(HODE::NonStandardSetup), _] => "[Non-standard setup]",
-                                [Err(HODE::NotEnoughMoves),_] => "...",
-                                [Ok(..),Err(HODE::NonStandardSetup)] => unreachable!(),
-                                [Ok(whop),Err(HODE::NotEnoughMoves)]
-                                  =>  &format!("{} v. ...",whop.map(|h|h.name()).unwrap_or("[Irregular]")),
-                                [Ok(whop),Ok(bhop)] => {
-                                    match [whop,bhop] {
-                                        [None,None] => "[Irregular]",
-                                        _ => {
-                                            let [wfmt,bfmt] = [whop,bhop]
-                                            .map(|hop|
-                                                hop.map(|h|h.name())
-                                                .unwrap_or("[Irregular]")
-                                            );
-                                            if wfmt == bfmt{
-                                                &format!("Twin {}",wfmt)
-                                            } else {
-                                                &format!("{} v. {}",wfmt,bfmt)
-                                            }
-                                        }
-                                    }
-                                }
+                            let opening_text = match self.match_state.opening(){
+                                Ok(opening) => opening.name.clone(),
+                                Err(OpeningClassificationError::NonStandardSetup) => "[Non-standard setup]".to_owned(),
+                                Err(OpeningClassificationError::NotEnoughMoves) => "...".to_owned(),
                             };
                             ui.add(egui::Label::new(egui::RichText::new(
                                 opening_text
@@ -776,6 +1279,21 @@ impl GameApp{
                             });
                         });
                         
+                        if let Some(outcome) = self.match_state.outcome(){
+                            ui.add(egui::Label::new(egui::RichText::new(
+                                outcome.to_string()
+                            ).strong()).wrap(false));
+                        } else if let Some((loser, reason)) = &self.forfeit{
+                            let loser = match loser {Player::White => "White", Player::Black => "Black"};
+                            ui.add(egui::Label::new(egui::RichText::new(
+                                format!("{} forfeits: {}", loser, reason)
+                            ).strong()));
+                        } else if let GameStateMachine::Over { outcome } = self.app_state{
+                            ui.add(egui::Label::new(egui::RichText::new(
+                                outcome.to_string()
+                            ).strong()).wrap(false));
+                        }
+
                         let dummy = ui.label("");
                         if self.poll_history_scroll{
                             dummy.scroll_to_me(None);
@@ -783,20 +1301,19 @@ impl GameApp{
                         }
                     });
                 });
+
+                for player in [Player::White, Player::Black]{
+                    self.gamers[player].side_panel(ui, player, &self.match_state);
+                }
             });
         });
         egui_macroquad::draw();
 
         // write to clipboard
-        match self.pstring_state {
-            PStringClipBoard::Pending(co) => match co.retrieve(){
-                Some(res) => {
-                    self.pstring_state = PStringClipBoard::Copied(res);
-
-                },
-                _ => {}
-            } ,
-            _ => {}
+        if let PStringClipBoard::Pending(co) = self.pstring_state{
+            if let Some(res) = co.retrieve(){
+                self.pstring_state = PStringClipBoard::Copied(res);
+            }
         }
 
 
@@ -809,24 +1326,32 @@ impl GameApp{
                     *time += get_frame_time();
                 } else {
                     self.match_state.refresh();
-                    if let Some(winner) = self.match_state.is_won(){
+                    if let Some(outcome) = self.match_state.outcome(){
                         play_sound_once(get_assets_unchecked().mate);
-                        self.app_state = GameStateMachine::Won { winner }
+                        self.app_state = GameStateMachine::Over { outcome }
                     } else {
                         self.ask();
                     }
                 }
             },
             GameStateMachine::Polling => {
-                if let Some(_winner) = self.match_state.is_won() {
+                let ending = [Player::White, Player::Black].into_iter()
+                    .find_map(|player|self.gamers[player].poll_ending());
+                if let Some(ending) = ending{
+                    self.end(ending);
+                } else if let Some(_outcome) = self.match_state.outcome() {
 
                 } else {
-                    let to_move = self.match_state.to_play();
+                    let to_move = if self.gamers[self.match_state.to_play().flip()].holds_turn(){
+                        self.match_state.to_play().flip()
+                    } else {
+                        self.match_state.to_play()
+                    };
                     let gamer = &mut self.gamers[to_move];
         
                     match gamer.poll_answer() {
                         None => {
-                            if let Some(..) = gamer.poll_grab_signal(){
+                            if gamer.poll_grab_signal().is_some(){
                                 self.display_mode = DisplayMode::Present;
                             }
                         },
@@ -837,6 +1362,12 @@ impl GameApp{
                                 },
                                 Decision::TakeBack => {
                                     self.undo_until_human();
+                                },
+                                Decision::Rewind(plies) => {
+                                    self.undo_moves(plies);
+                                },
+                                Decision::Forfeit(reason) => {
+                                    self.end(Ending::Forfeit(to_move, reason));
                                 }
                             }
                         
@@ -851,11 +1382,11 @@ impl GameApp{
                     
                     self.last_kill_tiles = anim_state.kills.iter().map(|(t,_)|*t).collect();
                     let assets = get_assets_unchecked();
-                    if let Some(winner) = self.match_state.is_won(){
+                    if let Some(outcome) = self.match_state.outcome(){
                         play_sound_once(assets.mate);
-                        self.app_state = GameStateMachine::Won { winner }
+                        self.app_state = GameStateMachine::Over { outcome }
                     } else {
-                        if anim_state.kills.len()>0{
+                        if !anim_state.kills.is_empty(){
                             play_sound(assets.capture,PlaySoundParams{
                                 looped : false, volume : 0.5
                             });
@@ -866,7 +1397,7 @@ impl GameApp{
                 
             },
 
-            GameStateMachine::Won { .. } => {}
+            GameStateMachine::Over { .. } => {}
         }
 
         
@@ -877,32 +1408,28 @@ impl GameApp{
         // Draw highlights and underlays
         
 
-        match self.display_mode{
-            DisplayMode::Present => {
-                match self.app_state{
-                    GameStateMachine::Won { winner } => {
+        if let DisplayMode::Present = self.display_mode {
+            if let GameStateMachine::Over { outcome } = self.app_state {
+                if let Some(winner) = outcome.winner(){
                         for (player,color) in [(winner,Color::from_hex(0x66dd66)),(winner.flip(),Color::from_hex(0xdd6666))]{
                             self.match_state.get_pieces(player).clone().into_iter().for_each(|(t,_)|{
             
                                 t.draw_highlight_fill(color, false);
                             });
                         }
-                        
-                    },
-                    _ => {}
                 }
-
-                if let Some([from,to]) = self.last_touched_tiles{
-                    for (t,col) in [(from, Color::from_rgba(0xee, 0xdd,0x11, 90)), (to, Color::from_hex(0xeedd11))]{
-                        t.draw_highlight_fill(col, false)
+                        
                     }
+
+            if let Some([from,to]) = self.last_touched_tiles{
+                for (t,col) in [(from, Color::from_rgba(0xee, 0xdd,0x11, 90)), (to, Color::from_hex(0xeedd11))]{
+                    t.draw_highlight_fill(col, false)
                 }
+            }
 
-                self.last_kill_tiles.iter().for_each(|kt|
-                    kt.draw_highlight_fill(Color::from_hex(0xddaaaa), false)
-                );
-            },
-            _ => {}
+            self.last_kill_tiles.iter().for_each(|kt|
+                kt.draw_highlight_fill(Color::from_hex(0xddaaaa), false)
+            );
         }
 
 
@@ -959,8 +1486,9 @@ impl GameApp{
                     };
                 },
             DisplayMode::History { index } => {
-                self.match_state.draw_past(index,  self.attack_patterns_alpha)
-                .unwrap_or_else(|()| self.match_state.draw_present(self.attack_patterns_alpha))
+                if !self.match_state.draw_past(index,  self.attack_patterns_alpha){
+                    self.match_state.draw_present(self.attack_patterns_alpha)
+                }
             }
         };
 
@@ -980,8 +1508,11 @@ impl GameApp{
 
 
             let strength = match self.app_state{
-                GameStateMachine::Won { winner } 
-                    => if winner == player {1.0} else {0.5},
+                GameStateMachine::Over { outcome } 
+                    => match outcome.winner(){
+                        Some(winner) => if winner == player {1.0} else {0.5},
+                        None => 0.75
+                    },
                 _ => 
                 match player{
                     Player::Black => self.smoothed_to_play,
@@ -1037,15 +1568,17 @@ impl GameApp{
                 draw_rectangle(-12.0, -12.0, 24.0, 24.0, col);
             },
 
-            GameStateMachine::Won { winner } => {
-                let loser = winner.flip();
-                let loser = &self.gamers[loser];
+            GameStateMachine::Over { outcome } => {
+                // after a draw either side may take back
+                let can_take_back = match outcome.winner(){
+                    Some(winner) => self.gamers[winner.flip()].allows_takebacks(),
+                    None => self.gamers[Player::White].allows_takebacks() || self.gamers[Player::Black].allows_takebacks()
+                } && !(self.gamers[Player::White].final_results() || self.gamers[Player::Black].final_results());
 
-                if loser.allows_takebacks() {
-                    if self.btn_mate_takeback.process(&mqui){
+                if can_take_back
+                    && self.btn_mate_takeback.process(&mqui){
                         self.undo_until_human();
                     }
-                }
             }
             _=>{}
         }
@@ -1065,7 +1598,8 @@ pub fn window_conf()->Conf{
 }
 
 
-pub async fn main(match_config : MatchConfig) {
+/// Plays a match, after connecting its online seat if it has one.
+pub async fn main(match_config : MatchConfig, files : &BotFiles) {
     // await for loading of necessary assets
     // let assets = get_assets_unchecked();
     // loop {
@@ -1081,10 +1615,68 @@ pub async fn main(match_config : MatchConfig) {
     //     next_frame().await
     // }   
 
-    let mut state = GameApp::new(
-        match_config
-    ).await;
+    #[cfg(not(target_arch="wasm32"))]
+    let (match_config, online) = match crate::ui::online::connect_ui(match_config).await{
+        Some((match_config, online)) => (match_config, online.map(|online|RemoteGamer::new_boxed(online) as Box<dyn Gamer>)),
+        None => return
+    };
+    #[cfg(target_arch="wasm32")]
+    let online = None;
+
+    let Some(state) = GameApp::new(
+        match_config,
+        files,
+        online
+    ).await else {
+        return
+    };
+    run(state).await
+}
+
+/// Shows a game watched live or a saved one, with the history navigation of a match.
+pub async fn view(view_config : ViewConfig){
+    let feed = |players : PlayerMap<String>|Feed{
+        #[cfg(not(target_arch="wasm32"))]
+        spectator : None,
+        players,
+        answers : VecDeque::new(),
+        ending : None,
+        notice : None,
+    };
+    let state = match view_config{
+        #[cfg(not(target_arch="wasm32"))]
+        ViewConfig::Live { address, room } => {
+            let Some(spectator) = crate::ui::online::watch_ui(&address, &room).await else {return};
+            let name = |player : Player|spectator.players()[player].clone().unwrap_or("?".to_owned());
+            let mut feed = feed(PlayerMap::new(name(Player::White), name(Player::Black)));
+            let match_state = spectator.match_state().clone();
+            feed.notice = Some(format!("Watching room {}.", spectator.room));
+            feed.spectator = Some(Ok(spectator));
+            GameApp::watching(match_state, feed, true)
+        },
+        ViewConfig::Record(record) => {
+            let match_state = match MatchState::from_record(&record){
+                Ok(match_state) => match_state,
+                Err(e) => {
+                    eprintln!("cannot replay the record: {}", e);
+                    return;
+                }
+            };
+            let mut feed = feed(record.players.clone());
+            // results the moves do not show
+            if match_state.outcome().is_none(){
+                feed.ending = record.outcome.map(|outcome|match outcome.winner(){
+                    Some(winner) => Ending::Forfeit(winner.flip(), record.termination.to_string()),
+                    None => Ending::Draw,
+                });
+            }
+            GameApp::watching(match_state, feed, false)
+        },
+    };
+    run(state).await
+}
 
+async fn run(mut state : GameApp){
     loop{
         clear_background(theme::BG_COLOR);        
         
@@ -0,0 +1,161 @@
+//! Who plays a side: a human or one of the bot levels.
+//! Shared by every front end.
+
+use std::str::FromStr;
+use std::sync::Arc;
+
+use ::rand::distributions::Open01;
+use ::rand::Rng;
+
+use crate::tokonoma::{OpeningBook, Ply, Position, SearchLimits, Tablebase};
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum GamerSpec{
+    Human,
+    Gibberish,
+    Noob,
+    Decent,
+    Sharp,
+    Tough,
+    GrandMaster,
+
+    Perfect{
+        depth : usize
+    },
+
+    /// Another program speaking `engine_protocol`, see `ExternalEngine`.
+    External{
+        command : String
+    },
+
+    /// A player elsewhere, see `networking`. The host listens on `address`,
+    /// the guest connects to it.
+    Online{
+        host : bool,
+        address : String
+    },
+
+    /// A player sending moves as tokens, see `tokonoma::correspondence`.
+    /// An empty token starts a new game, otherwise the game continues from it.
+    Correspondence{
+        token : String
+    }
+}
+
+/// How a bot level searches.
+#[derive(Clone, Debug)]
+pub struct BotSettings{
+    pub depth : usize,
+    /// Chance, rolled repeatedly, of searching one ply shallower.
+    pub blundering_probability : f32,
+    pub threads : usize,
+    /// Variety of the opening book moves, see `OpeningBook::choose`;
+    /// `None` never consults the book.
+    pub book_variety : Option<f32>,
+    pub book : Option<Arc<OpeningBook>>,
+    /// Endgame tables the search consults.
+    pub tablebase : Option<Arc<Tablebase>>,
+}
+
+/// Files bots play with, loaded once by a front end.
+#[derive(Clone, Default, Debug)]
+pub struct BotFiles{
+    pub book : Option<Arc<OpeningBook>>,
+    pub tablebase : Option<Arc<Tablebase>>,
+}
+
+impl BotSettings{
+    pub fn with_files(self, files : &BotFiles) -> Self{
+        BotSettings{book : files.book.clone(), tablebase : files.tablebase.clone(), ..self}
+    }
+
+    /// A move from the opening book, if the bot uses it and the position is in it.
+    pub fn probe_book(&self, position : &Position) -> Option<Ply>{
+        let variety = self.book_variety?;
+        self.book.as_ref()?.choose(position, variety, &mut ::rand::thread_rng())
+    }
+
+    /// Search limits for one move, with the blunder rolls applied.
+    pub fn roll_limits(&self) -> SearchLimits{
+        let mut depth = self.depth;
+
+        let mut rng = ::rand::thread_rng();
+        while rng.sample::<f32,Open01>(Open01) < self.blundering_probability {
+            depth = depth.saturating_sub(1)
+        }
+
+        SearchLimits::depth(depth).with_threads(self.threads).with_tablebase(self.tablebase.clone())
+    }
+}
+
+impl GamerSpec{
+    /// Fixed levels, without the `Perfect` ones.
+    pub const LEVELS : [GamerSpec; 7] = [
+        GamerSpec::Human, GamerSpec::Gibberish, GamerSpec::Noob, GamerSpec::Decent,
+        GamerSpec::Sharp, GamerSpec::Tough, GamerSpec::GrandMaster,
+    ];
+
+    pub fn name(&self) -> String{
+        self.texts().0
+    }
+    pub fn description(&self) -> String{
+        self.texts().1
+    }
+
+    pub fn texts(&self) -> (String,String){
+        match self{
+            GamerSpec::Human => ("Human".to_owned(), "Human player.".to_owned()),
+            GamerSpec::Gibberish => ("Gibberish".to_owned(), "Random moves.".to_owned()),
+            GamerSpec::Noob => ("Noob".to_owned(), "Poor player.".to_owned()),
+            GamerSpec::Decent => ("Decent".to_owned(), "Solid player.".to_owned()),
+            GamerSpec::Sharp => ("Sharp".to_owned(), "Serious challenge.".to_owned()),
+            GamerSpec::Tough => ("Tough".to_owned(), "Very strong.".to_owned()),
+            GamerSpec::GrandMaster => ("Grandmaster".to_owned(), "Unbelievable.".to_owned()),
+
+            GamerSpec::Perfect { depth } =>
+                (format!("Beastly-{}",depth),format!("Perfect {}-plies",depth)),
+
+            GamerSpec::External { command } =>
+                ("External".to_owned(), format!("Runs {}", command)),
+
+            GamerSpec::Online { host : true, address } =>
+                ("Host online".to_owned(), format!("Waits for a guest on {}", address)),
+            GamerSpec::Online { host : false, address } =>
+                ("Join online".to_owned(), format!("Plays the host at {}", address)),
+
+            GamerSpec::Correspondence { .. } =>
+                ("Correspondence".to_owned(), "Moves sent as tokens.".to_owned()),
+        }
+    }
+
+    /// `None` for humans, external engines, online and correspondence players.
+    pub fn bot_settings(&self) -> Option<BotSettings>{
+        // weaker levels stray further from the main lines
+        let (depth, blundering_probability, threads, book_variety) = match self{
+            GamerSpec::Human | GamerSpec::External{..} | GamerSpec::Online{..} | GamerSpec::Correspondence{..} => return None,
+            GamerSpec::Gibberish => (0, 0.0, 1, None),
+            GamerSpec::Noob => (1, 0.2, 1, Some(2.0)),
+            GamerSpec::Decent => (2, 0.2, 1, Some(1.0)),
+            GamerSpec::Sharp => (3, 0.4, 1, Some(0.5)),
+            GamerSpec::Tough => (5, 0.4, 1, Some(0.2)),
+            GamerSpec::GrandMaster => (6, 0.2, SearchLimits::available_threads(), Some(0.05)),
+
+            // analysis strength, searched from scratch
+            GamerSpec::Perfect { depth } => (*depth, 0.0, SearchLimits::available_threads(), None)
+        };
+        Some(BotSettings{depth, blundering_probability, threads, book_variety, book : None, tablebase : None})
+    }
+}
+
+/// Reads back `name()`, as written in the player tags of game records.
+/// Case is ignored.
+impl FromStr for GamerSpec{
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Some(depth) = s.get(..8).filter(|p|p.eq_ignore_ascii_case("Beastly-")).and(s.get(8..)){
+            return depth.parse().map(|depth|GamerSpec::Perfect{depth}).map_err(|_|());
+        }
+        GamerSpec::LEVELS.into_iter().find(|spec|spec.name().eq_ignore_ascii_case(s)).ok_or(())
+    }
+}
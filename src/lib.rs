@@ -1,11 +1,25 @@
+#[cfg(feature = "render")]
 pub mod arrows;
 
 pub mod tokonoma;
 
+pub mod gamer_spec;
+
+pub mod engine_protocol;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod external_engine;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod tournament;
+pub mod tuning;
+
+#[cfg(feature = "render")]
 pub mod gameplay;
+#[cfg(feature = "render")]
 pub mod ui;
+#[cfg(feature = "render")]
 pub mod assets;
+#[cfg(feature = "render")]
 pub mod theme;
 pub mod networking;
 
-pub use tokonoma::{Position,Player, Ply,Tall, Tile, Piece, Species,neighbours_attack, neighbours_move,};
\ No newline at end of file
+pub use tokonoma::{Position,Player, Ply,Tall, Tile, Piece, Species,neighbours_attack, neighbours_move,};
@@ -19,15 +19,39 @@ async fn main(){
     
     load_assets().await;
 
+    #[allow(unused_mut)]
+    let mut files = gameplay::BotFiles::default();
+    // optional endgame tables, see the `tablebase` binary
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Ok(tablebase) = hexstack::tokonoma::Tablebase::load_dir("tablebases"){
+        files.tablebase = Some(std::sync::Arc::new(tablebase));
+    }
+    // opening names replacing the bundled ones, see `OpeningTree`
+    #[cfg(not(target_arch = "wasm32"))]
+    if std::path::Path::new("openings.txt").exists(){
+        match hexstack::tokonoma::OpeningTree::load("openings.txt"){
+            Ok(tree) => hexstack::tokonoma::OpeningTree::install(tree),
+            Err(e) => eprintln!("ignoring the opening names: {}", e),
+        }
+    }
+    // optional opening book, see the `book` binary
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Ok(book) = hexstack::tokonoma::OpeningBook::load("book.hxob"){
+        files.book = Some(std::sync::Arc::new(book));
+    }
+
     egui_macroquad::cfg(|egui_ctx |{
         theme::set_fonts(egui_ctx);
     });
 
     let mut last_match_config = None;
     loop{
-        let match_config = match_config_ui(last_match_config).await;    
+        let (match_config, view_config) = match_config_ui(last_match_config, &files).await;    
         
-        gameplay::main(match_config.clone()).await;
+        match view_config{
+            Some(view_config) => gameplay::view(view_config).await,
+            None => gameplay::main(match_config.clone(), &files).await,
+        }
         
         last_match_config = Some(match_config);
     }
@@ -0,0 +1,191 @@
+//! `protocol` messages over TCP.
+
+use std::fmt::Display;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{Shutdown, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, TryRecvError};
+
+use web_time::{Duration, Instant};
+
+use crate::engine_protocol::ProtocolError;
+
+use super::protocol::{Message, DEFAULT_PORT, PROTOCOL_VERSION};
+
+/// How long a peer may take to answer the handshake.
+pub const HANDSHAKE_TIMEOUT : Duration = Duration::from_secs(10);
+
+/// Why a connection, or the match played over it, failed.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum NetworkError{
+    Io(String),
+    /// The peer went away.
+    Closed,
+    Protocol(ProtocolError),
+    /// The peer speaks another version of the protocol.
+    Version(u32),
+    Declined,
+    Timeout,
+    /// The peer sent something the rules or the protocol do not allow at that point.
+    Illegal(String),
+}
+
+impl Display for NetworkError{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self{
+            NetworkError::Io(e) => write!(f, "network error: {}", e),
+            NetworkError::Closed => write!(f, "the connection was closed"),
+            NetworkError::Protocol(e) => write!(f, "protocol error: {}", e),
+            NetworkError::Version(version) => write!(f, "the other side speaks protocol version {}, not {}", version, PROTOCOL_VERSION),
+            NetworkError::Declined => write!(f, "the match was declined"),
+            NetworkError::Timeout => write!(f, "no answer in time"),
+            NetworkError::Illegal(what) => write!(f, "unexpected {}", what),
+        }
+    }
+}
+
+impl From<std::io::Error> for NetworkError{
+    fn from(e : std::io::Error) -> Self{
+        NetworkError::Io(e.to_string())
+    }
+}
+
+/// `host:port`, with the default port when none is given.
+pub fn with_default_port(address : &str) -> String{
+    let address = address.trim();
+    // a colon is also part of IPv6 addresses, which must then be bracketed
+    if address.rsplit_once(':').is_some_and(|(_, port)|port.parse::<u16>().is_ok()){
+        address.to_owned()
+    } else {
+        format!("{}:{}", address, DEFAULT_PORT)
+    }
+}
+
+/// A peer that said hello. Dropping it closes the connection.
+pub struct Connection{
+    stream : TcpStream,
+    incoming : Receiver<Result<Message, ProtocolError>>,
+    pub peer_name : String,
+}
+
+impl Connection{
+    /// Connects to `address`, see `with_default_port`, and says hello.
+    pub fn connect(address : &str, name : &str) -> Result<Connection, NetworkError>{
+        let address = with_default_port(address);
+        let target = address.to_socket_addrs()?.next()
+            .ok_or_else(||NetworkError::Io(format!("unknown address {}", address)))?;
+        let stream = TcpStream::connect_timeout(&target, HANDSHAKE_TIMEOUT)?;
+        Connection::open(stream, name)
+    }
+
+    /// Says hello on a connected stream, and waits for the peer's.
+    pub fn open(stream : TcpStream, name : &str) -> Result<Connection, NetworkError>{
+        stream.set_nodelay(true)?;
+        let reader = stream.try_clone()?;
+        let (sender, incoming) = mpsc::channel();
+        std::thread::spawn(move ||{
+            for line in BufReader::new(reader).lines(){
+                let Ok(line) = line else {break};
+                if line.trim().is_empty(){
+                    continue;
+                }
+                if sender.send(line.parse::<Message>()).is_err(){
+                    break;
+                }
+            }
+        });
+
+        let mut connection = Connection{stream, incoming, peer_name : String::new()};
+        connection.send(&Message::Hello{version : PROTOCOL_VERSION, name : name.to_owned()})?;
+        match connection.receive(Instant::now() + HANDSHAKE_TIMEOUT)?{
+            Message::Hello { version, .. } if version != PROTOCOL_VERSION => Err(NetworkError::Version(version)),
+            Message::Hello { name, .. } => {
+                connection.peer_name = name;
+                Ok(connection)
+            },
+            other => Err(NetworkError::Illegal(format!("{} before hello", other)))
+        }
+    }
+
+    pub fn send(&mut self, message : &Message) -> Result<(), NetworkError>{
+        writeln!(self.stream, "{}", message)
+            .and_then(|_|self.stream.flush())
+            .map_err(|_|NetworkError::Closed)
+    }
+
+    /// Blocks until a message arrives or the deadline passes.
+    pub fn receive(&mut self, deadline : Instant) -> Result<Message, NetworkError>{
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        match self.incoming.recv_timeout(timeout){
+            Ok(message) => message.map_err(NetworkError::Protocol),
+            Err(RecvTimeoutError::Timeout) => Err(NetworkError::Timeout),
+            Err(RecvTimeoutError::Disconnected) => Err(NetworkError::Closed),
+        }
+    }
+
+    /// The next message, if one has arrived. Never blocks.
+    pub fn try_receive(&mut self) -> Result<Option<Message>, NetworkError>{
+        match self.incoming.try_recv(){
+            Ok(message) => message.map(Some).map_err(NetworkError::Protocol),
+            Err(TryRecvError::Empty) => Ok(None),
+            Err(TryRecvError::Disconnected) => Err(NetworkError::Closed),
+        }
+    }
+}
+
+impl Drop for Connection{
+    fn drop(&mut self){
+        // also ends the reading thread
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use std::net::TcpListener;
+
+    use super::*;
+
+    #[test]
+    fn test_handshake(){
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let host = std::thread::spawn(move ||{
+            let (stream, _) = listener.accept().unwrap();
+            let mut connection = Connection::open(stream, "host").unwrap();
+            connection.send(&Message::Draw).unwrap();
+            connection.receive(Instant::now() + HANDSHAKE_TIMEOUT)
+        });
+
+        let mut guest = Connection::connect(&address, "guest").unwrap();
+        assert_eq!(guest.peer_name, "host");
+        assert_eq!(guest.receive(Instant::now() + HANDSHAKE_TIMEOUT), Ok(Message::Draw));
+        assert_eq!(guest.try_receive(), Ok(None));
+        guest.send(&Message::Resign).unwrap();
+        assert_eq!(host.join().unwrap(), Ok(Message::Resign));
+
+        // the host hung up
+        assert_eq!(guest.receive(Instant::now() + HANDSHAKE_TIMEOUT), Err(NetworkError::Closed));
+    }
+
+    #[test]
+    fn test_version_mismatch(){
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let old = std::thread::spawn(move ||{
+            let (mut stream, _) = listener.accept().unwrap();
+            writeln!(stream, "hexstack 0 someone from the past").unwrap();
+            // read the hello before hanging up
+            BufReader::new(stream).lines().next();
+        });
+        let stream = TcpStream::connect(address).unwrap();
+        assert_eq!(Connection::open(stream, "guest").err(), Some(NetworkError::Version(0)));
+        old.join().unwrap();
+    }
+
+    #[test]
+    fn test_default_port(){
+        assert_eq!(with_default_port("localhost"), format!("localhost:{}", DEFAULT_PORT));
+        assert_eq!(with_default_port(" 10.0.0.2:9000 "), "10.0.0.2:9000");
+        assert_eq!(with_default_port("[::1]"), format!("[::1]:{}", DEFAULT_PORT));
+    }
+}
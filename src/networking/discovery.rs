@@ -0,0 +1,235 @@
+//! Finding hosts on the local network without typing addresses.
+//!
+//! A host waiting for a guest runs an `Advertiser`, which announces its open seat
+//! every second over UDP, to the broadcast address and to loopback. A `Lobby` listens
+//! for those announcements on the first free port of a small range starting at
+//! `LOBBY_PORT`, which the advertisers all send to, so that several instances can
+//! browse on one machine. Announcements are single lines:
+//!
+//! - `hexstack-seat <version> <id> <port> <white|black> <on|off> <name>`: a host
+//!   listening on `port` of the sender's address, playing that colour, with or
+//!   without takebacks; `id` tells hosts apart
+//! - `hexstack-gone <id>`: the seat was taken or withdrawn
+
+use std::collections::HashMap;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::JoinHandle;
+
+use web_time::{Duration, Instant};
+
+use crate::tokonoma::Player;
+
+use super::protocol::{color_word, MatchOffer, DEFAULT_PORT, PROTOCOL_VERSION};
+
+/// First port lobbies listen on.
+pub const LOBBY_PORT : u16 = DEFAULT_PORT + 1;
+/// Lobbies on one machine each take the next free port.
+const LOBBY_PORTS : u16 = 8;
+
+const ANNOUNCE_INTERVAL : Duration = Duration::from_secs(1);
+/// Seats not announced again for this long are dropped.
+const SEAT_TIMEOUT : Duration = Duration::from_secs(4);
+
+/// A host waiting for a guest, as announced on the local network.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct OpenSeat{
+    pub name : String,
+    /// The colour the host plays; the guest gets the other.
+    pub host_plays : Player,
+    pub takebacks : bool,
+    /// Where the host listens.
+    pub address : SocketAddr,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum Announcement{
+    Seat{id : u64, port : u16, host_plays : Player, takebacks : bool, name : String},
+    Gone{id : u64},
+}
+
+impl Announcement{
+    fn to_line(&self) -> String{
+        match self{
+            Announcement::Seat { id, port, host_plays, takebacks, name } => format!("hexstack-seat {} {} {} {} {} {}",
+                PROTOCOL_VERSION, id, port, color_word(*host_plays), if *takebacks {"on"} else {"off"}, name),
+            Announcement::Gone { id } => format!("hexstack-gone {}", id),
+        }
+    }
+
+    /// `None` for anything else, including other protocol versions.
+    fn parse(line : &str) -> Option<Announcement>{
+        let words : Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice(){
+            ["hexstack-seat", version, id, port, host_plays, takebacks, name @ ..] if !name.is_empty()
+                && version.parse() == Ok(PROTOCOL_VERSION) => Some(Announcement::Seat{
+                id : id.parse().ok()?,
+                port : port.parse().ok()?,
+                host_plays : match *host_plays{
+                    "white" => Player::White,
+                    "black" => Player::Black,
+                    _ => return None
+                },
+                takebacks : match *takebacks{
+                    "on" => true,
+                    "off" => false,
+                    _ => return None
+                },
+                name : name.join(" "),
+            }),
+            ["hexstack-gone", id] => Some(Announcement::Gone{id : id.parse().ok()?}),
+            _ => None
+        }
+    }
+}
+
+/// Announces an open seat until dropped, then withdraws it.
+pub struct Advertiser{
+    stop : Option<Sender<()>>,
+    thread : Option<JoinHandle<()>>,
+}
+
+impl Advertiser{
+    /// Announces the host `name` listening on `port` and offering `offer`.
+    pub fn start(name : &str, offer : &MatchOffer, port : u16) -> io::Result<Advertiser>{
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+        socket.set_broadcast(true)?;
+        let id = ::rand::random();
+        let seat = Announcement::Seat{id, port, host_plays : offer.host_plays, takebacks : offer.takebacks, name : name.to_owned()};
+        let (stop, stopped) = mpsc::channel::<()>();
+
+        let thread = std::thread::spawn(move ||{
+            let announce = |announcement : &Announcement|{
+                let line = announcement.to_line();
+                // loopback first, so lobbies on this machine keep the address that works here;
+                // it also reaches them when the network has no broadcast route
+                for ip in [Ipv4Addr::LOCALHOST, Ipv4Addr::BROADCAST]{
+                    for port in LOBBY_PORT..LOBBY_PORT + LOBBY_PORTS{
+                        let _ = socket.send_to(line.as_bytes(), (ip, port));
+                    }
+                }
+            };
+            loop{
+                announce(&seat);
+                match stopped.recv_timeout(ANNOUNCE_INTERVAL){
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    _ => break,
+                }
+            }
+            announce(&Announcement::Gone{id});
+        });
+        Ok(Advertiser{stop : Some(stop), thread : Some(thread)})
+    }
+}
+
+impl Drop for Advertiser{
+    fn drop(&mut self){
+        self.stop.take();
+        if let Some(thread) = self.thread.take(){
+            let _ = thread.join();
+        }
+    }
+}
+
+/// The open seats announced on the local network.
+pub struct Lobby{
+    socket : UdpSocket,
+    seats : HashMap<u64, (OpenSeat, Instant)>,
+}
+
+impl Lobby{
+    /// Listens on the first free lobby port.
+    pub fn open() -> io::Result<Lobby>{
+        let mut last_error = None;
+        for port in LOBBY_PORT..LOBBY_PORT + LOBBY_PORTS{
+            match UdpSocket::bind((Ipv4Addr::UNSPECIFIED, port)){
+                Ok(socket) => {
+                    socket.set_nonblocking(true)?;
+                    return Ok(Lobby{socket, seats : HashMap::new()});
+                },
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error.unwrap())
+    }
+
+    /// Reads the announcements received since the last call, and gives the seats
+    /// still open, by name.
+    pub fn seats(&mut self) -> Vec<OpenSeat>{
+        let mut buffer = [0u8; 512];
+        let now = Instant::now();
+        while let Ok((length, sender)) = self.socket.recv_from(&mut buffer){
+            let Some(announcement) = std::str::from_utf8(&buffer[..length]).ok().and_then(Announcement::parse) else {continue};
+            match announcement{
+                Announcement::Seat { id, port, host_plays, takebacks, name } => {
+                    // a host also heard on loopback is on this machine, where it may only
+                    // listen on loopback
+                    let address = match self.seats.get(&id){
+                        Some((seat, _)) if !sender.ip().is_loopback() => seat.address,
+                        _ => SocketAddr::new(sender.ip(), port),
+                    };
+                    self.seats.insert(id, (OpenSeat{name, host_plays, takebacks, address}, now));
+                },
+                Announcement::Gone { id } => {
+                    self.seats.remove(&id);
+                },
+            }
+        }
+        self.seats.retain(|_, (_, heard)|now.duration_since(*heard) < SEAT_TIMEOUT);
+
+        let mut seats : Vec<OpenSeat> = self.seats.values().map(|(seat, _)|seat.clone()).collect();
+        seats.sort_by(|a, b|(&a.name, a.address).cmp(&(&b.name, b.address)));
+        seats
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use crate::tokonoma::DrawRules;
+
+    use super::*;
+
+    #[test]
+    fn test_announcement_roundtrip(){
+        let announcements = [
+            Announcement::Seat{id : 42, port : 7878, host_plays : Player::Black, takebacks : true, name : "Ada Lovelace".to_owned()},
+            Announcement::Seat{id : u64::MAX, port : 1, host_plays : Player::White, takebacks : false, name : "Bob".to_owned()},
+            Announcement::Gone{id : 42},
+        ];
+        for announcement in announcements{
+            assert_eq!(Announcement::parse(&announcement.to_line()), Some(announcement.clone()));
+        }
+        assert_eq!(Announcement::parse("hexstack-seat 999 1 7878 white on Ada"), None);
+        assert_eq!(Announcement::parse("hexstack-seat 1 1 7878 white on"), None);
+        assert_eq!(Announcement::parse("hello"), None);
+    }
+
+    #[test]
+    fn test_two_lobbies_see_a_seat(){
+        let mut lobbies = [Lobby::open().unwrap(), Lobby::open().unwrap()];
+        let name = format!("Discovery test {}", std::process::id());
+        let offer = MatchOffer{host_plays : Player::Black, takebacks : false, draw_rules : DrawRules::default()};
+        let advertiser = Advertiser::start(&name, &offer, 4321).unwrap();
+
+        let deadline = Instant::now() + SEAT_TIMEOUT;
+        let find = |lobby : &mut Lobby|lobby.seats().into_iter().find(|seat|seat.name == name);
+        for lobby in &mut lobbies{
+            let seat = loop{
+                if let Some(seat) = find(lobby){
+                    break seat;
+                }
+                assert!(Instant::now() < deadline, "no announcement");
+                std::thread::sleep(Duration::from_millis(20));
+            };
+            assert_eq!((seat.host_plays, seat.takebacks, seat.address.port()), (Player::Black, false, 4321));
+        }
+
+        // withdrawn when dropped
+        drop(advertiser);
+        std::thread::sleep(Duration::from_millis(100));
+        for lobby in &mut lobbies{
+            assert_eq!(find(lobby), None);
+        }
+    }
+}
@@ -0,0 +1,39 @@
+//! Online play: two front ends connected over TCP, each with one local player.
+//!
+//! The host listens and sets the terms (`MatchOffer`), the guest connects
+//! and accepts them. `OnlineMatch` keeps both sides' games in step.
+//! A `Server` can also host the games of players meeting in its rooms,
+//! which a `Spectator` may follow. Hosts waiting for a guest can be found on the
+//! local network with a `Lobby`.
+//! Sockets are not available on the web.
+
+pub mod protocol;
+#[cfg(not(target_arch = "wasm32"))]
+mod connection;
+#[cfg(not(target_arch = "wasm32"))]
+mod discovery;
+#[cfg(not(target_arch = "wasm32"))]
+mod online;
+#[cfg(not(target_arch = "wasm32"))]
+mod server;
+#[cfg(not(target_arch = "wasm32"))]
+mod spectator;
+
+pub use protocol::{is_room_code, MatchOffer, Message, DEFAULT_PORT, PROTOCOL_VERSION};
+#[cfg(not(target_arch = "wasm32"))]
+pub use connection::{with_default_port, Connection, NetworkError, HANDSHAKE_TIMEOUT};
+#[cfg(not(target_arch = "wasm32"))]
+pub use discovery::{Advertiser, Lobby, OpenSeat, LOBBY_PORT};
+#[cfg(not(target_arch = "wasm32"))]
+pub use online::{OnlineMatch, RemoteEvent};
+#[cfg(not(target_arch = "wasm32"))]
+pub use server::{RoomSummary, Server, ServerConfig, SERVER_NAME};
+#[cfg(not(target_arch = "wasm32"))]
+pub use spectator::{Spectator, SpectatorEvent};
+
+/// Name given to the other side, from the environment.
+pub fn default_player_name() -> String{
+    ["HEXSTACK_NAME", "USER", "USERNAME"].into_iter()
+        .find_map(|key|std::env::var(key).ok().filter(|name|!name.trim().is_empty()))
+        .unwrap_or_else(||"Anonymous".to_owned())
+}
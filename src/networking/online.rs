@@ -0,0 +1,479 @@
+//! A match against a peer, kept in step with the local game.
+
+use std::collections::VecDeque;
+
+use web_time::Instant;
+
+use crate::tokonoma::{DrawReason, GameOutcome, GameRecord, MatchState, Player, PlayerMap, Ply, Position, Termination};
+
+use super::connection::{Connection, NetworkError, HANDSHAKE_TIMEOUT};
+use super::protocol::{MatchOffer, Message};
+
+/// What the peer did.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RemoteEvent{
+    Moved(Ply),
+    /// The peer asks to undo that many plies, see `OnlineMatch::answer_takeback`.
+    TakebackAsked(usize),
+    /// Plies undone by agreement, after which the side that asked is to play again.
+    TookBack(usize),
+    TakebackDeclined,
+    Resigned,
+    DrawOffered,
+    DrawAgreed,
+}
+
+/// The game both sides of a match agree on, and how either side may change it.
+pub(super) struct Game{
+    pub offer : MatchOffer,
+    pub match_state : MatchState,
+    /// Who offered a draw since the last move.
+    pub draw_offer : Option<Player>,
+    /// Who asks to undo how many plies, until the other side answers.
+    pub takeback_request : Option<(Player, usize)>,
+    /// A resignation or an agreed draw.
+    ending : Option<(GameOutcome, Termination)>,
+}
+
+impl Game{
+    pub fn new(offer : MatchOffer, match_state : MatchState) -> Game{
+        Game{
+            offer,
+            match_state : match_state.with_draw_rules(offer.draw_rules),
+            draw_offer : None,
+            takeback_request : None,
+            ending : None,
+        }
+    }
+
+    /// The result, by the rules, a resignation or an agreement.
+    pub fn outcome(&self) -> Option<GameOutcome>{
+        self.ending.map(|(outcome, _)|outcome).or(self.match_state.outcome())
+    }
+
+    /// Record of the game, with the resignation or agreement, if any.
+    pub fn to_record(&self, players : PlayerMap<String>) -> GameRecord{
+        let mut record = self.match_state.to_record();
+        record.players = players;
+        if let Some((outcome, termination)) = self.ending{
+            record.outcome = Some(outcome);
+            record.termination = termination;
+        }
+        record
+    }
+
+    fn check_in_progress(&self, what : &str) -> Result<(), NetworkError>{
+        match self.outcome(){
+            Some(_) => Err(NetworkError::Illegal(format!("{} after the end of the game", what))),
+            None => Ok(())
+        }
+    }
+
+    /// Whether the side to play can ask to take `plies` back, to play again after.
+    pub fn can_take_back(&self, plies : usize) -> bool{
+        let history = self.match_state.history();
+        self.offer.takebacks && self.ending.is_none() && self.takeback_request.is_none()
+            && plies > 0 && plies <= history.len()
+            && history[history.len() - plies].state_before.to_play() == self.match_state.to_play()
+    }
+
+    /// Plays a move, takeback request or answer, resignation or draw offer of `by`,
+    /// if the rules and terms allow it.
+    pub fn apply(&mut self, by : Player, message : &Message) -> Result<RemoteEvent, NetworkError>{
+        let illegal = ||Err(NetworkError::Illegal(message.to_string()));
+        match *message{
+            Message::Move(ply) => {
+                self.check_in_progress("move")?;
+                // the side asking for a takeback waits for the answer
+                if self.match_state.to_play() != by || self.takeback_request.is_some()
+                    || !self.match_state.present_state().valid_moves().contains(&ply){
+                    return illegal();
+                }
+                self.match_state.apply_move(ply);
+                self.draw_offer = None;
+                Ok(RemoteEvent::Moved(ply))
+            },
+            Message::Takeback(plies) => {
+                if self.match_state.to_play() != by || !self.can_take_back(plies){
+                    return illegal();
+                }
+                self.takeback_request = Some((by, plies));
+                Ok(RemoteEvent::TakebackAsked(plies))
+            },
+            Message::Accept | Message::Decline => {
+                self.check_in_progress("takeback answer")?;
+                let Some((_, plies)) = self.takeback_request.filter(|(asking, _)|*asking == by.flip()) else {
+                    return illegal();
+                };
+                self.takeback_request = None;
+                if *message == Message::Decline{
+                    return Ok(RemoteEvent::TakebackDeclined);
+                }
+                self.match_state.undo_moves(plies);
+                self.draw_offer = None;
+                Ok(RemoteEvent::TookBack(plies))
+            },
+            Message::Resign => {
+                self.check_in_progress("resignation")?;
+                self.ending = Some((GameOutcome::won_by(by.flip()), Termination::Resignation));
+                Ok(RemoteEvent::Resigned)
+            },
+            Message::Draw => {
+                self.check_in_progress("draw offer")?;
+                if self.draw_offer == Some(by.flip()){
+                    self.ending = Some((GameOutcome::Draw(DrawReason::Agreement), Termination::Agreement));
+                    Ok(RemoteEvent::DrawAgreed)
+                } else {
+                    self.draw_offer = Some(by);
+                    Ok(RemoteEvent::DrawOffered)
+                }
+            },
+            _ => illegal()
+        }
+    }
+}
+
+/// One side of an online match. Checks the peer's messages against the rules
+/// and the agreed terms, so that both sides always see the same game.
+pub struct OnlineMatch{
+    connection : Connection,
+    /// The colour played on this side.
+    pub local : Player,
+    game : Game,
+    /// Events of a message not handed out yet.
+    events : VecDeque<RemoteEvent>,
+}
+
+impl OnlineMatch{
+    /// Offers a match from `setup`, and starts it if the peer accepts.
+    pub fn host(mut connection : Connection, offer : MatchOffer, setup : Position) -> Result<OnlineMatch, NetworkError>{
+        connection.send(&Message::Offer(offer))?;
+        match connection.receive(Instant::now() + HANDSHAKE_TIMEOUT)?{
+            Message::Accept => {},
+            Message::Decline => return Err(NetworkError::Declined),
+            other => return Err(NetworkError::Illegal(format!("{} instead of an answer", other)))
+        }
+        connection.send(&Message::Sync{setup : setup.clone(), moves : vec![]})?;
+        Ok(OnlineMatch::new(connection, offer.host_plays, Game::new(offer, MatchState::setup_from(setup))))
+    }
+
+    /// Accepts the match the peer offers, and waits for the game to start from.
+    pub fn join(mut connection : Connection) -> Result<OnlineMatch, NetworkError>{
+        let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+        let offer = match connection.receive(deadline)?{
+            Message::Offer(offer) => offer,
+            other => return Err(NetworkError::Illegal(format!("{} instead of an offer", other)))
+        };
+        connection.send(&Message::Accept)?;
+        let match_state = match connection.receive(deadline)?{
+            Message::Sync { setup, moves } => replay(setup, &moves, &offer)?,
+            other => return Err(NetworkError::Illegal(format!("{} instead of the game", other)))
+        };
+        Ok(OnlineMatch::new(connection, offer.host_plays.flip(), Game::new(offer, match_state)))
+    }
+
+    /// Takes a seat in a room of a game server, see `Server`; `None` opens a new room.
+    /// The `key` given with a seat takes it back after losing the connection.
+    /// Gives the room's code, the colour of the seat and its key.
+    pub fn enter_room(connection : &mut Connection, code : Option<&str>, color : Option<Player>, key : Option<&str>) -> Result<(String, Player, String), NetworkError>{
+        connection.send(&Message::Room{code : code.map(str::to_owned), color, key : key.map(str::to_owned)})?;
+        match connection.receive(Instant::now() + HANDSHAKE_TIMEOUT)?{
+            Message::Room { code : Some(code), color : Some(color), key : Some(key) } => Ok((code, color, key)),
+            Message::Decline => Err(NetworkError::Declined),
+            other => Err(NetworkError::Illegal(format!("{} instead of a seat", other)))
+        }
+    }
+
+    /// Waits until `deadline` for an opponent in the room entered, then joins the game,
+    /// which has moves already when resuming it.
+    pub fn join_room(mut connection : Connection, deadline : Instant) -> Result<OnlineMatch, NetworkError>{
+        match connection.receive(deadline)?{
+            Message::Opponent(name) => connection.peer_name = name,
+            other => return Err(NetworkError::Illegal(format!("{} instead of an opponent", other)))
+        }
+        OnlineMatch::join(connection)
+    }
+
+    fn new(connection : Connection, local : Player, game : Game) -> OnlineMatch{
+        OnlineMatch{connection, local, game, events : VecDeque::new()}
+    }
+
+    pub fn peer_name(&self) -> &str{
+        &self.connection.peer_name
+    }
+
+    pub fn offer(&self) -> MatchOffer{
+        self.game.offer
+    }
+
+    /// The game as both sides see it.
+    pub fn match_state(&self) -> &MatchState{
+        &self.game.match_state
+    }
+
+    /// The result, by the rules, a resignation or an agreement.
+    pub fn outcome(&self) -> Option<GameOutcome>{
+        self.game.outcome()
+    }
+
+    /// Whether the peer offered a draw since the last move.
+    pub fn peer_offers_draw(&self) -> bool{
+        self.game.draw_offer == Some(self.local.flip())
+    }
+
+    /// The plies the peer asks to take back, until answered.
+    pub fn peer_asks_takeback(&self) -> Option<usize>{
+        self.game.takeback_request.filter(|(asking, _)|*asking != self.local).map(|(_, plies)|plies)
+    }
+
+    /// Whether this side waits for the answer to its takeback request.
+    pub fn asks_takeback(&self) -> bool{
+        self.game.takeback_request.is_some_and(|(asking, _)|asking == self.local)
+    }
+
+    /// Whether this side may ask to take `plies` back now.
+    pub fn can_take_back(&self, plies : usize) -> bool{
+        self.match_state().to_play() == self.local && self.game.can_take_back(plies)
+    }
+
+    /// Record of the game, with the players named and the resignation or agreement, if any.
+    pub fn to_record(&self, local_name : &str) -> GameRecord{
+        let mut players = PlayerMap::twin(self.peer_name().to_owned());
+        players[self.local] = local_name.to_owned();
+        self.game.to_record(players)
+    }
+
+    /// Applies a message of this side, then sends it.
+    fn act(&mut self, message : Message) -> Result<(), NetworkError>{
+        self.game.apply(self.local, &message)?;
+        self.connection.send(&message)
+    }
+
+    /// Plays a move of this side.
+    pub fn play(&mut self, ply : Ply) -> Result<(), NetworkError>{
+        self.act(Message::Move(ply))
+    }
+
+    /// Asks to undo this side's last moves and the replies to them, on this side's turn.
+    /// The peer's answer comes as `TookBack` or `TakebackDeclined`; no move meanwhile.
+    pub fn take_back(&mut self, plies : usize) -> Result<(), NetworkError>{
+        self.act(Message::Takeback(plies))
+    }
+
+    /// Accepts or declines the takeback the peer asks for. Gives the plies undone.
+    pub fn answer_takeback(&mut self, accept : bool) -> Result<usize, NetworkError>{
+        let plies = self.peer_asks_takeback().unwrap_or(0);
+        self.act(if accept {Message::Accept} else {Message::Decline})?;
+        Ok(if accept {plies} else {0})
+    }
+
+    /// Catches up with a local game that reached `position`, one move of this side away.
+    pub fn report(&mut self, position : &Position) -> Result<(), NetworkError>{
+        let present = self.match_state().present_state();
+        if position == present{
+            return Ok(());
+        }
+        let ply = present.valid_moves().into_iter().find(|&ply|{
+            let mut after = present.clone();
+            after.apply_move(ply);
+            after == *position
+        });
+        match ply{
+            Some(ply) => self.play(ply),
+            None => Err(NetworkError::Illegal("position, not one move away from the game".to_owned()))
+        }
+    }
+
+    pub fn resign(&mut self) -> Result<(), NetworkError>{
+        self.act(Message::Resign)
+    }
+
+    /// Offers a draw, or accepts the peer's offer. True when the game is drawn.
+    pub fn offer_draw(&mut self) -> Result<bool, NetworkError>{
+        if self.game.draw_offer == Some(self.local) && self.outcome().is_none(){
+            return Ok(false);
+        }
+        self.act(Message::Draw)?;
+        Ok(self.outcome().is_some())
+    }
+
+    /// What the peer did next, if anything has arrived. Never blocks.
+    pub fn poll(&mut self) -> Result<Option<RemoteEvent>, NetworkError>{
+        while self.events.is_empty(){
+            match self.connection.try_receive()?{
+                Some(message) => self.handle(message)?,
+                None => return Ok(None)
+            }
+        }
+        Ok(self.events.pop_front())
+    }
+
+    /// Blocks until the peer does something or the deadline passes.
+    pub fn wait(&mut self, deadline : Instant) -> Result<RemoteEvent, NetworkError>{
+        while self.events.is_empty(){
+            let message = self.connection.receive(deadline)?;
+            self.handle(message)?;
+        }
+        Ok(self.events.pop_front().unwrap())
+    }
+
+    fn handle(&mut self, message : Message) -> Result<(), NetworkError>{
+        let remote = self.local.flip();
+        match message{
+            // the peer's version of the game, which may only differ by its next move:
+            // moves are only taken back by agreement
+            Message::Sync { ref setup, ref moves } => {
+                let history = self.match_state().history();
+                let ours_setup = history.first().map_or(self.match_state().present_state(), |entry|&entry.state_before);
+                if setup != ours_setup{
+                    return Err(NetworkError::Illegal(message.to_string()));
+                }
+                let common = history.iter().zip(moves).take_while(|(entry, ply)|entry.ply == **ply).count();
+                let added = &moves[common..];
+                if common < history.len() || added.len() > 1{
+                    return Err(NetworkError::Illegal(message.to_string()));
+                }
+                if let Some(&ply) = added.first(){
+                    self.handle(Message::Move(ply))?;
+                }
+            },
+            _ => {
+                let event = self.game.apply(remote, &message)?;
+                self.events.push_back(event);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The game of a `sync`, ending where the rules end it.
+pub(super) fn replay(setup : Position, moves : &[Ply], offer : &MatchOffer) -> Result<MatchState, NetworkError>{
+    let mut match_state = MatchState::setup_from(setup).with_draw_rules(offer.draw_rules);
+    for &ply in moves{
+        if match_state.outcome().is_some(){
+            return Err(NetworkError::Illegal(format!("move {} after the end of the game", ply)));
+        }
+        match_state.apply_move(ply);
+    }
+    Ok(match_state)
+}
+
+#[cfg(test)]
+mod tests{
+    use std::net::TcpListener;
+
+    use web_time::Duration;
+
+    use super::*;
+    use crate::tokonoma::DrawRules;
+
+    const OFFER : MatchOffer = MatchOffer{host_plays : Player::White, takebacks : true, draw_rules : DrawRules::NONE};
+
+    /// Host and guest of a match on loopback.
+    fn pair(offer : MatchOffer) -> (OnlineMatch, OnlineMatch){
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let host = std::thread::spawn(move ||{
+            let (stream, _) = listener.accept().unwrap();
+            OnlineMatch::host(Connection::open(stream, "host").unwrap(), offer, Position::setup()).unwrap()
+        });
+        let guest = OnlineMatch::join(Connection::connect(&address, "guest").unwrap()).unwrap();
+        (host.join().unwrap(), guest)
+    }
+
+    fn soon() -> Instant{
+        Instant::now() + Duration::from_secs(10)
+    }
+
+    fn parse(online : &OnlineMatch, notation : &str) -> Ply{
+        online.match_state().present_state().parse_move(notation).unwrap()
+    }
+
+    #[test]
+    fn test_moves_and_takebacks(){
+        let (mut host, mut guest) = pair(OFFER);
+        assert_eq!((host.local, guest.local), (Player::White, Player::Black));
+        assert_eq!(guest.peer_name(), "host");
+        assert_eq!(guest.offer(), OFFER);
+
+        let ply = parse(&host, "Bb5");
+        host.play(ply).unwrap();
+        assert_eq!(guest.wait(soon()), Ok(RemoteEvent::Moved(ply)));
+        // not the host's turn
+        assert!(host.play(host.match_state().present_state().valid_moves()[0]).is_err());
+
+        let ply = parse(&guest, "Bd2");
+        guest.play(ply).unwrap();
+        assert_eq!(host.wait(soon()), Ok(RemoteEvent::Moved(ply)));
+
+        // the host asks to take Bb5 back, and may not move before the answer
+        assert!(guest.take_back(2).is_err());
+        host.take_back(2).unwrap();
+        assert_eq!(guest.wait(soon()), Ok(RemoteEvent::TakebackAsked(2)));
+        assert_eq!(guest.peer_asks_takeback(), Some(2));
+        assert!(host.asks_takeback());
+        assert!(host.play(parse(&host, "Aa3")).is_err());
+        assert_eq!(guest.answer_takeback(false), Ok(0));
+        assert_eq!(host.wait(soon()), Ok(RemoteEvent::TakebackDeclined));
+        assert_eq!(host.match_state().history().len(), 2);
+
+        // asked again, the guest agrees, and the host plays Aa3 instead, as a front end reports it
+        host.take_back(2).unwrap();
+        guest.wait(soon()).unwrap();
+        assert_eq!(guest.answer_takeback(true), Ok(2));
+        assert_eq!(host.wait(soon()), Ok(RemoteEvent::TookBack(2)));
+        assert!(!host.asks_takeback());
+        let mut position = Position::setup();
+        position.apply_move(position.parse_move("Aa3").unwrap());
+        host.report(&position).unwrap();
+        assert_eq!(guest.wait(soon()), Ok(RemoteEvent::Moved(Position::setup().parse_move("Aa3").unwrap())));
+        assert_eq!(guest.match_state().present_state(), &position);
+        assert_eq!(guest.poll(), Ok(None));
+        // the guest has nothing to answer now
+        assert!(guest.answer_takeback(true).is_err());
+    }
+
+    #[test]
+    fn test_endings(){
+        let (mut host, mut guest) = pair(OFFER);
+        assert!(!host.offer_draw().unwrap());
+        assert_eq!(guest.wait(soon()), Ok(RemoteEvent::DrawOffered));
+        assert!(guest.peer_offers_draw());
+        assert!(guest.offer_draw().unwrap());
+        assert_eq!(host.wait(soon()), Ok(RemoteEvent::DrawAgreed));
+        assert_eq!(host.outcome(), Some(GameOutcome::Draw(DrawReason::Agreement)));
+        assert_eq!(guest.outcome(), host.outcome());
+        assert!(host.resign().is_err());
+
+        let (mut host, mut guest) = pair(OFFER);
+        guest.resign().unwrap();
+        assert_eq!(host.wait(soon()), Ok(RemoteEvent::Resigned));
+        let record = host.to_record("Ada");
+        assert_eq!(record.outcome, Some(GameOutcome::WhiteWins));
+        assert_eq!(record.termination, Termination::Resignation);
+        assert_eq!(record.players[Player::Black], "guest");
+        assert_eq!(record.to_string().parse::<GameRecord>().unwrap(), record);
+    }
+
+    #[test]
+    fn test_peer_breaking_the_rules(){
+        // takebacks are off, and the guest must wait for the host's move
+        let (mut host, mut guest) = pair(MatchOffer{takebacks : false, ..OFFER});
+        host.play(parse(&host, "Bb5")).unwrap();
+        guest.wait(soon()).unwrap();
+        guest.play(parse(&guest, "Bd2")).unwrap();
+        host.wait(soon()).unwrap();
+        assert!(host.take_back(2).is_err());
+
+        guest.connection.send(&Message::Move(parse(&host, "Aa3"))).unwrap();
+        assert!(matches!(host.wait(soon()), Err(NetworkError::Illegal(..))));
+        guest.connection.send(&Message::Takeback(2)).unwrap();
+        assert!(matches!(host.wait(soon()), Err(NetworkError::Illegal(..))));
+        // nor can a takeback nobody asked for be accepted
+        guest.connection.send(&Message::Accept).unwrap();
+        assert!(matches!(host.wait(soon()), Err(NetworkError::Illegal(..))));
+
+        drop(guest);
+        assert_eq!(host.wait(soon()), Err(NetworkError::Closed));
+    }
+}
@@ -0,0 +1,299 @@
+//! Messages between two front ends playing online, one per line.
+//!
+//! Both sides open with `hello`. The host then offers the match, the guest
+//! answers `accept` or `decline`, and the host sends the game to start from with `sync`.
+//! After that either side sends its own moves, takeback requests, resignation and draw offers.
+//!
+//! - `hexstack <version> <name>`: hello, with the protocol version and the player's name
+//! - `offer <white|black> takebacks <on|off> repetitions <n|-> nocapture <n|->`:
+//!   the colour the host plays, whether takebacks are allowed and the draw rules
+//! - `accept`, `decline`
+//! - `sync startpos|<position string> [moves <move>...]`: the whole game,
+//!   for starting or resuming one
+//! - `move <move>`
+//! - `takeback <plies>`: asks to undo the sender's last moves and the replies to them,
+//!   on the sender's turn, when the offer allows takebacks; the other side answers
+//!   `accept` or `decline`, and the sender does not move before the answer
+//! - `resign`
+//! - `draw`: offers a draw, or accepts the one offered since the last move
+//!
+//! A game server (see `Server`) stands in for the host of each player.
+//! Before the offer, a player takes a seat in a room and waits for the other:
+//!
+//! - `room <code>|new [white|black] [key <key>]`: enters a room, or opens a new one, preferably
+//!   with that colour; the server answers with the room's code, the seat's colour and
+//!   the key that takes the seat back after losing the connection, or `decline` when
+//!   the room is full
+//! - `opponent <name>`: the player in the other seat, who may play right after the offer
+//!   when a game is resumed
+//!
+//! Spectators follow a room instead, and send nothing after entering it:
+//!
+//! - `watch <code>`: enters a room as a spectator; the server answers with
+//!   `seat <white|black> <name>` for each player there (and later for those arriving),
+//!   the terms as an offer, whoever `host_plays`, and the game so far with `sync`,
+//!   or `decline` when there is no such room
+//! - `<white|black> <message>`: a move, takeback request or answer, resignation or draw offer of that side
+//!
+//! Moves are written as tile pairs (`d6b5`), history notation is also read.
+
+use std::fmt::Display;
+use std::str::FromStr;
+
+use crate::engine_protocol::{parse_moves, parse_ply, ProtocolError};
+use crate::tokonoma::{DrawRules, Player, Ply, Position};
+
+/// Bumped on any change peers of different versions would misunderstand.
+pub const PROTOCOL_VERSION : u32 = 2;
+
+/// Port used when an address does not name one.
+pub const DEFAULT_PORT : u16 = 7878;
+
+fn error<T>(message : impl Into<String>) -> Result<T, ProtocolError>{
+    Err(ProtocolError(message.into()))
+}
+
+/// Terms of a match, set by the host.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct MatchOffer{
+    /// The colour the host plays.
+    pub host_plays : Player,
+    pub takebacks : bool,
+    pub draw_rules : DrawRules,
+}
+
+impl Display for MatchOffer{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let limit = |limit : Option<usize>|limit.map_or("-".to_owned(), |n|n.to_string());
+        write!(f, "offer {} takebacks {} repetitions {} nocapture {}",
+            color_word(self.host_plays),
+            if self.takebacks {"on"} else {"off"},
+            limit(self.draw_rules.repetitions),
+            limit(self.draw_rules.max_plies_without_capture))
+    }
+}
+
+pub(super) fn color_word(player : Player) -> &'static str{
+    match player{
+        Player::White => "white",
+        Player::Black => "black",
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Message{
+    Hello{version : u32, name : String},
+    Offer(MatchOffer),
+    Accept,
+    Decline,
+    Sync{setup : Position, moves : Vec<Ply>},
+    Move(Ply),
+    /// Plies to undo, if the other side accepts.
+    Takeback(usize),
+    Resign,
+    Draw,
+    /// A room's code, a seat's colour and the key to the seat. The code and colour
+    /// are left to the server when entering, the key is only given to take a seat back.
+    Room{code : Option<String>, color : Option<Player>, key : Option<String>},
+    Opponent(String),
+    /// Enters the room with that code as a spectator.
+    Watch(String),
+    /// A player in a seat of the room watched.
+    Seat(Player, String),
+    /// What a side did, for spectators.
+    By(Player, Box<Message>),
+}
+
+impl Display for Message{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self{
+            Message::Hello { version, name } => write!(f, "hexstack {} {}", version, name),
+            Message::Offer(offer) => write!(f, "{}", offer),
+            Message::Accept => write!(f, "accept"),
+            Message::Decline => write!(f, "decline"),
+            Message::Sync { setup, moves } => {
+                write!(f, "sync {}", setup.to_position_string())?;
+                if !moves.is_empty(){
+                    write!(f, " moves")?;
+                    for ply in moves{
+                        write!(f, " {}", ply)?;
+                    }
+                }
+                Ok(())
+            },
+            Message::Move(ply) => write!(f, "move {}", ply),
+            Message::Takeback(plies) => write!(f, "takeback {}", plies),
+            Message::Resign => write!(f, "resign"),
+            Message::Draw => write!(f, "draw"),
+            Message::Room { code, color, key } => {
+                write!(f, "room {}", code.as_deref().unwrap_or("new"))?;
+                if let Some(color) = color{
+                    write!(f, " {}", color_word(*color))?;
+                }
+                match key{
+                    Some(key) => write!(f, " key {}", key),
+                    None => Ok(())
+                }
+            },
+            Message::Opponent(name) => write!(f, "opponent {}", name),
+            Message::Watch(code) => write!(f, "watch {}", code),
+            Message::Seat(player, name) => write!(f, "seat {} {}", color_word(*player), name),
+            Message::By(player, message) => write!(f, "{} {}", color_word(*player), message),
+        }
+    }
+}
+
+/// The moves of a `sync` are checked against its setup as they are read.
+impl FromStr for Message{
+    type Err = ProtocolError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let words : Vec<&str> = s.split_whitespace().collect();
+        let limit = |word : &str|match word{
+            "-" => Ok(None),
+            _ => word.parse().map(Some).or_else(|_|error(format!("not a limit: {}", word)))
+        };
+        let color = |word : &str|match word{
+            "white" => Ok(Player::White),
+            "black" => Ok(Player::Black),
+            _ => error(format!("not a colour: {}", word))
+        };
+        match words.as_slice(){
+            ["hexstack", version, name @ ..] if !name.is_empty() => Ok(Message::Hello{
+                version : version.parse().or_else(|_|error(format!("not a version: {}", version)))?,
+                name : name.join(" "),
+            }),
+            ["offer", host_plays, "takebacks", takebacks, "repetitions", repetitions, "nocapture", nocapture] => {
+                let host_plays = color(host_plays)?;
+                let takebacks = match *takebacks{
+                    "on" => true,
+                    "off" => false,
+                    _ => return error(format!("takebacks must be on or off, not {}", takebacks))
+                };
+                let draw_rules = DrawRules{
+                    repetitions : limit(repetitions)?,
+                    max_plies_without_capture : limit(nocapture)?,
+                };
+                Ok(Message::Offer(MatchOffer{host_plays, takebacks, draw_rules}))
+            },
+            ["accept"] => Ok(Message::Accept),
+            ["decline"] => Ok(Message::Decline),
+            ["sync", setup, rest @ ..] => {
+                let setup = match *setup{
+                    "startpos" => Position::setup(),
+                    pstring => pstring.parse()
+                        .or_else(|e|error(format!("invalid position string {}: {:?}", pstring, e)))?
+                };
+                let notations = match rest{
+                    [] => &[][..],
+                    ["moves", notations @ ..] => notations,
+                    _ => return error(format!("expected moves, got {}", rest[0]))
+                };
+                let moves = parse_moves(&setup, notations)?;
+                Ok(Message::Sync{setup, moves})
+            },
+            ["move", ply] => Ok(Message::Move(parse_ply(ply)?)),
+            ["takeback", plies] => Ok(Message::Takeback(
+                plies.parse().or_else(|_|error(format!("not a number of plies: {}", plies)))?
+            )),
+            ["resign"] => Ok(Message::Resign),
+            ["draw"] => Ok(Message::Draw),
+            ["room", code, rest @ ..] => {
+                let code = match *code{
+                    "new" => None,
+                    code if is_room_code(code) => Some(code.to_owned()),
+                    _ => return error(format!("not a room code: {}", code))
+                };
+                let (seat, key) = match rest{
+                    [] => (None, None),
+                    [seat] => (Some(color(seat)?), None),
+                    ["key", key] => (None, Some(key)),
+                    [seat, "key", key] => (Some(color(seat)?), Some(key)),
+                    _ => return error(format!("unknown room message {}", s.trim()))
+                };
+                let key = match key{
+                    Some(key) if !is_room_code(key) => return error(format!("not a key: {}", key)),
+                    key => key.map(|key|(*key).to_owned())
+                };
+                Ok(Message::Room{code, color : seat, key})
+            },
+            ["opponent", name @ ..] if !name.is_empty() => Ok(Message::Opponent(name.join(" "))),
+            ["watch", code] if is_room_code(code) => Ok(Message::Watch((*code).to_owned())),
+            ["seat", player, name @ ..] if !name.is_empty() => Ok(Message::Seat(color(player)?, name.join(" "))),
+            [player @ ("white" | "black"), rest @ ..] => {
+                let message = match rest.join(" ").parse()?{
+                    message @ (Message::Move(..) | Message::Takeback(..) | Message::Accept | Message::Decline
+                        | Message::Resign | Message::Draw) => message,
+                    other => return error(format!("{} is not something a side does", other))
+                };
+                Ok(Message::By(color(player)?, Box::new(message)))
+            },
+            _ => error(format!("unknown message {}", s.trim()))
+        }
+    }
+}
+
+/// Room codes are short words of letters, digits, `-` and `_`; `new` is not one.
+pub fn is_room_code(code : &str) -> bool{
+    code != "new" && (1..=32).contains(&code.len())
+        && code.chars().all(|c|c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn test_message_roundtrip(){
+        let setup = Position::setup();
+        let moves = vec![setup.parse_move("Bb5").unwrap()];
+        let messages = [
+            Message::Hello{version : PROTOCOL_VERSION, name : "Ada Lovelace".to_owned()},
+            Message::Offer(MatchOffer{host_plays : Player::Black, takebacks : true, draw_rules : DrawRules::default()}),
+            Message::Offer(MatchOffer{host_plays : Player::White, takebacks : false, draw_rules : DrawRules::NONE}),
+            Message::Accept,
+            Message::Decline,
+            Message::Sync{setup : setup.clone(), moves : vec![]},
+            Message::Sync{setup : setup.clone(), moves : moves.clone()},
+            Message::Move(moves[0]),
+            Message::Takeback(2),
+            Message::Resign,
+            Message::Draw,
+            Message::Room{code : None, color : None, key : None},
+            Message::Room{code : Some("friday-club".to_owned()), color : Some(Player::Black), key : None},
+            Message::Room{code : Some("friday-club".to_owned()), color : None, key : Some("k3y".to_owned())},
+            Message::Room{code : Some("friday-club".to_owned()), color : Some(Player::White), key : Some("k3y".to_owned())},
+            Message::Opponent("Bob".to_owned()),
+            Message::Watch("friday-club".to_owned()),
+            Message::Seat(Player::White, "Ada Lovelace".to_owned()),
+            Message::By(Player::Black, Box::new(Message::Move(moves[0]))),
+            Message::By(Player::White, Box::new(Message::Takeback(2))),
+            Message::By(Player::Black, Box::new(Message::Accept)),
+            Message::By(Player::White, Box::new(Message::Resign)),
+        ];
+        for message in messages{
+            assert_eq!(message.to_string().parse(), Ok(message.clone()), "{}", message);
+        }
+    }
+
+    #[test]
+    fn test_bad_messages(){
+        assert!("hexstack 1".parse::<Message>().is_err());
+        assert!("hexstack one Ada".parse::<Message>().is_err());
+        assert!("offer red takebacks on repetitions 3 nocapture 100".parse::<Message>().is_err());
+        assert!("offer white takebacks maybe repetitions 3 nocapture 100".parse::<Message>().is_err());
+        assert!("sync startpos moves Fe1".parse::<Message>().is_err());
+        assert!("move Bb5".parse::<Message>().is_err());
+        assert!("takeback all".parse::<Message>().is_err());
+        assert!("room".parse::<Message>().is_err());
+        assert!("room two words white".parse::<Message>().is_err());
+        assert!("room a/b".parse::<Message>().is_err());
+        assert!("room abc red".parse::<Message>().is_err());
+        assert!("room abc white key".parse::<Message>().is_err());
+        assert!("room abc key a/b".parse::<Message>().is_err());
+        assert!("watch".parse::<Message>().is_err());
+        assert!("seat green Ada".parse::<Message>().is_err());
+        assert!("white opponent Bob".parse::<Message>().is_err());
+        assert!("black white resign".parse::<Message>().is_err());
+        assert!("hello".parse::<Message>().is_err());
+    }
+}
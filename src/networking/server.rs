@@ -0,0 +1,631 @@
+//! A game server: players meet in rooms and play through it.
+//!
+//! The server keeps the game of each room and stands in for the host of both
+//! players, who connect as guests (see `OnlineMatch::enter_room`). It checks
+//! every move against the rules before relaying it, so that a broken client
+//! cannot desynchronise the game. A player who loses the connection takes
+//! their seat back by entering the room again with the key given with the seat,
+//! and is sent the game so far. Spectators may follow a room, from the game so far.
+//! Finished games are written to a records file, as are those of rooms left
+//! empty for `ServerConfig::idle_timeout`, unfinished.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+
+use rand::Rng;
+use web_time::{Duration, Instant};
+
+use crate::tokonoma::{DrawRules, MatchState, Player, PlayerMap, Position};
+
+use super::connection::{Connection, NetworkError, HANDSHAKE_TIMEOUT};
+use super::online::Game;
+use super::protocol::{MatchOffer, Message};
+
+/// The name the server says hello with.
+pub const SERVER_NAME : &str = "hexstack-server";
+
+/// How often a player's connection looks for messages relayed to it.
+const RELAY_POLL : Duration = Duration::from_millis(20);
+
+/// Letters of new room codes and seat keys, without look-alikes.
+const CODE_ALPHABET : &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+const CODE_LENGTH : usize = 6;
+const KEY_LENGTH : usize = 12;
+
+/// How often rooms are checked for having been left.
+const IDLE_CHECK : Duration = Duration::from_secs(1);
+
+/// Terms of every game on a server.
+#[derive(Clone, Debug)]
+pub struct ServerConfig{
+    pub takebacks : bool,
+    pub draw_rules : DrawRules,
+    /// File the records of finished games are appended to.
+    pub records : Option<PathBuf>,
+    /// How long a room with moves is kept once both players are gone.
+    pub idle_timeout : Duration,
+}
+
+impl Default for ServerConfig{
+    fn default() -> Self {
+        ServerConfig{
+            takebacks : false,
+            draw_rules : DrawRules::default(),
+            records : None,
+            idle_timeout : Duration::from_secs(30*60),
+        }
+    }
+}
+
+fn random_word(length : usize) -> String{
+    let mut rng = rand::thread_rng();
+    (0..length).map(|_|CODE_ALPHABET[rng.gen_range(0..CODE_ALPHABET.len())] as char).collect()
+}
+
+struct Seat{
+    name : String,
+    /// Given to the player, to take the seat back with.
+    key : String,
+    /// Tells apart the connections that held this seat; only the latest one plays.
+    session : u64,
+    /// Messages for that connection.
+    outbox : Sender<Message>,
+    /// Whether the connection is in the game, rather than waiting for an opponent.
+    playing : bool,
+}
+
+struct Room{
+    game : Game,
+    /// Seats are kept while their player is away.
+    seats : PlayerMap<Option<Seat>>,
+    /// Which seats have a connection.
+    present : PlayerMap<bool>,
+    /// The outboxes of spectators, by session.
+    spectators : Vec<(u64, Sender<Message>)>,
+    /// Since when neither player is connected.
+    deserted_since : Option<Instant>,
+}
+
+impl Room{
+    /// What `f` makes of each seat taken and whether its player is connected.
+    fn players<T>(&self, f : impl Fn(&Seat, bool) -> T) -> PlayerMap<Option<T>>{
+        let player = |player : Player|self.seats[player].as_ref().map(|seat|f(seat, self.present[player]));
+        PlayerMap::new(player(Player::White), player(Player::Black))
+    }
+
+    /// The whole game, for a player or spectator catching up.
+    fn sync(&self) -> Message{
+        let match_state = &self.game.match_state;
+        let setup = match_state.history().first().map_or(match_state.present_state(), |entry|&entry.state_before).clone();
+        Message::Sync{setup, moves : match_state.history().iter().map(|entry|entry.ply).collect()}
+    }
+
+    /// Sends `message` to the spectators still there.
+    fn tell_spectators(&mut self, message : &Message){
+        self.spectators.retain(|(_, outbox)|outbox.send(message.clone()).is_ok());
+    }
+}
+
+/// A room, as listed for the administrator.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct RoomSummary{
+    pub code : String,
+    /// Each seat's player, and whether they are connected.
+    pub players : PlayerMap<Option<(String, bool)>>,
+    pub plies : usize,
+    pub spectators : usize,
+}
+
+impl Display for RoomSummary{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let player = |seat : &Option<(String, bool)>|match seat{
+            None => "(open)".to_owned(),
+            Some((name, true)) => name.clone(),
+            Some((name, false)) => format!("{} (away)", name),
+        };
+        write!(f, "{}: {} vs {}, {} plies", self.code,
+            player(&self.players[Player::White]), player(&self.players[Player::Black]), self.plies)?;
+        match self.spectators{
+            0 => Ok(()),
+            spectators => write!(f, ", {} watching", spectators)
+        }
+    }
+}
+
+/// Rooms and their games, shared by the connections of all players.
+pub struct Server{
+    config : ServerConfig,
+    rooms : Mutex<HashMap<String, Room>>,
+    sessions : AtomicU64,
+}
+
+impl Server{
+    pub fn new(config : ServerConfig) -> Arc<Server>{
+        Arc::new(Server{config, rooms : Mutex::new(HashMap::new()), sessions : AtomicU64::new(0)})
+    }
+
+    /// Serves the players connecting to `listener`, on a thread each. Logs to stderr.
+    pub fn serve(self : &Arc<Self>, listener : TcpListener){
+        let server = self.clone();
+        std::thread::spawn(move ||loop{
+            std::thread::sleep(server.config.idle_timeout.min(IDLE_CHECK));
+            server.close_idle();
+        });
+        for stream in listener.incoming(){
+            let stream = match stream{
+                Ok(stream) => stream,
+                Err(e) => {
+                    eprintln!("could not accept a connection: {}", e);
+                    continue;
+                }
+            };
+            let server = self.clone();
+            std::thread::spawn(move ||{
+                let peer = stream.peer_addr().map_or("?".to_owned(), |address|address.to_string());
+                match server.attend(stream){
+                    Ok(()) | Err(NetworkError::Closed) => {},
+                    Err(e) => eprintln!("{}: {}", peer, e),
+                }
+            });
+        }
+    }
+
+    /// The rooms with a game in progress or a player waiting, by code.
+    pub fn rooms(&self) -> Vec<RoomSummary>{
+        let rooms = self.rooms.lock().unwrap();
+        let mut summaries : Vec<RoomSummary> = rooms.iter().map(|(code, room)|RoomSummary{
+            code : code.clone(),
+            players : room.players(|seat, present|(seat.name.clone(), present)),
+            plies : room.game.match_state.history().len(),
+            spectators : room.spectators.len(),
+        }).collect();
+        summaries.sort_by(|a, b|a.code.cmp(&b.code));
+        summaries
+    }
+
+    /// Seats one player, then plays their side of the game until they leave,
+    /// or relays the game of a room to a spectator.
+    fn attend(&self, stream : TcpStream) -> Result<(), NetworkError>{
+        let mut connection = Connection::open(stream, SERVER_NAME)?;
+        let (code, color, key) = match connection.receive(Instant::now() + HANDSHAKE_TIMEOUT)?{
+            Message::Room { code, color, key } => (code, color, key),
+            Message::Watch(code) => return self.watch(&mut connection, &code),
+            other => return Err(NetworkError::Illegal(format!("{} instead of a room", other)))
+        };
+        let (outbox, relayed) = mpsc::channel();
+        let Some((code, seat, session, key)) = self.seat(code, color, key.as_deref(), &connection.peer_name, outbox) else {
+            connection.send(&Message::Decline)?;
+            return Err(NetworkError::Declined);
+        };
+        let result = connection.send(&Message::Room{code : Some(code.clone()), color : Some(seat), key : Some(key)})
+            .and_then(|_|self.play(&mut connection, &code, seat, session, &relayed));
+        self.leave(&code, seat, session);
+        result
+    }
+
+    /// The room (a new one for `None`), seat, session and seat key of a player entering.
+    /// Players get their own seat back by its key, else a free seat, the colour asked for first.
+    /// A player with a seat is told of the opponent, now or when they arrive.
+    fn seat(&self, code : Option<String>, color : Option<Player>, key : Option<&str>, name : &str, outbox : Sender<Message>) -> Option<(String, Player, u64, String)>{
+        let mut rooms = self.rooms.lock().unwrap();
+        let code = code.unwrap_or_else(||loop{
+            let code = random_word(CODE_LENGTH);
+            if !rooms.contains_key(&code){
+                break code;
+            }
+        });
+        let room = rooms.entry(code.clone()).or_insert_with(||Room{
+            game : Game::new(self.offer(Player::White), MatchState::setup_from(Position::setup())),
+            seats : PlayerMap::new(None, None),
+            present : PlayerMap::twin(false),
+            spectators : vec![],
+            deserted_since : None,
+        });
+
+        let preferred = color.unwrap_or(Player::White);
+        let order = [preferred, preferred.flip()];
+        let own = |player : &Player|room.seats[*player].as_ref().is_some_and(|seat|Some(seat.key.as_str()) == key);
+        // an own seat still held is taken over, as its connection may be dead without the server knowing
+        let seat = order.into_iter().find(own)
+            .or_else(||order.into_iter().find(|player|room.seats[*player].is_none()))?;
+        let key = room.seats[seat].as_ref().map_or_else(||random_word(KEY_LENGTH), |held|held.key.clone());
+
+        let session = self.sessions.fetch_add(1, Ordering::Relaxed);
+        if let Some(opponent) = &room.seats[seat.flip()]{
+            let _ = outbox.send(Message::Opponent(opponent.name.clone()));
+            if !opponent.playing{
+                let _ = opponent.outbox.send(Message::Opponent(name.to_owned()));
+            }
+        }
+        if room.seats[seat].is_none(){
+            room.tell_spectators(&Message::Seat(seat, name.to_owned()));
+        }
+        room.seats[seat] = Some(Seat{name : name.to_owned(), key : key.clone(), session, outbox, playing : false});
+        room.present[seat] = true;
+        room.deserted_since = None;
+        Some((code, seat, session, key))
+    }
+
+    /// The terms as offered to the player of `seat`, for whom the server hosts the other side.
+    fn offer(&self, seat : Player) -> MatchOffer{
+        MatchOffer{host_plays : seat.flip(), takebacks : self.config.takebacks, draw_rules : self.config.draw_rules}
+    }
+
+    /// Runs the room under `code`, if the session still holds the seat.
+    fn with_room<T>(&self, code : &str, seat : Player, session : u64, f : impl FnOnce(&mut Room) -> T) -> Result<T, NetworkError>{
+        let mut rooms = self.rooms.lock().unwrap();
+        match rooms.get_mut(code).filter(|room|room.seats[seat].as_ref().is_some_and(|held|held.session == session)){
+            Some(room) => Ok(f(room)),
+            // the game is over, or the player came back on another connection
+            None => Err(NetworkError::Closed)
+        }
+    }
+
+    fn play(&self, connection : &mut Connection, code : &str, seat : Player, session : u64, relayed : &Receiver<Message>) -> Result<(), NetworkError>{
+        // the player may hang up while waiting for an opponent
+        loop{
+            match relayed.recv_timeout(RELAY_POLL){
+                Ok(message) => {
+                    connection.send(&message)?;
+                    break;
+                },
+                Err(RecvTimeoutError::Timeout) => {},
+                Err(RecvTimeoutError::Disconnected) => return Err(NetworkError::Closed),
+            }
+            if let Some(message) = connection.try_receive()?{
+                return Err(NetworkError::Illegal(format!("{} before the game", message)));
+            }
+        }
+
+        connection.send(&Message::Offer(self.offer(seat)))?;
+        match connection.receive(Instant::now() + HANDSHAKE_TIMEOUT)?{
+            Message::Accept => {},
+            Message::Decline => return Err(NetworkError::Declined),
+            other => return Err(NetworkError::Illegal(format!("{} instead of an answer", other)))
+        }
+        // the game so far, before any move relayed from now on
+        let sync = self.with_room(code, seat, session, |room|{
+            room.seats[seat].as_mut().unwrap().playing = true;
+            room.sync()
+        })?;
+        connection.send(&sync)?;
+        relay(connection, relayed, |message|self.act(code, seat, session, message))
+    }
+
+    /// Sends a spectator what happened in the room so far, then what happens in it.
+    fn watch(&self, connection : &mut Connection, code : &str) -> Result<(), NetworkError>{
+        let (outbox, relayed) = mpsc::channel();
+        let session = self.sessions.fetch_add(1, Ordering::Relaxed);
+        {
+            let mut rooms = self.rooms.lock().unwrap();
+            let Some(room) = rooms.get_mut(code) else {
+                connection.send(&Message::Decline)?;
+                return Err(NetworkError::Declined);
+            };
+            // queued here, so that nothing relayed comes before
+            for player in [Player::White, Player::Black]{
+                if let Some(seat) = &room.seats[player]{
+                    let _ = outbox.send(Message::Seat(player, seat.name.clone()));
+                }
+            }
+            let _ = outbox.send(Message::Offer(self.offer(Player::White)));
+            let _ = outbox.send(room.sync());
+            room.spectators.push((session, outbox));
+        }
+        let result = relay(connection, &relayed, |message|Err(NetworkError::Illegal(format!("{} from a spectator", message))));
+        if let Some(room) = self.rooms.lock().unwrap().get_mut(code){
+            room.spectators.retain(|(held, _)|*held != session);
+        }
+        result
+    }
+
+    /// Applies a message of the player of `seat` and relays it; a finished game is recorded.
+    fn act(&self, code : &str, seat : Player, session : u64, message : Message) -> Result<(), NetworkError>{
+        let finished = self.with_room(code, seat, session, |room| -> Result<bool, NetworkError>{
+            room.game.apply(seat, &message)?;
+            room.tell_spectators(&Message::By(seat, Box::new(message.clone())));
+            if let Some(opponent) = room.seats[seat.flip()].as_ref().filter(|opponent|opponent.playing){
+                let _ = opponent.outbox.send(message);
+            }
+            Ok(room.game.outcome().is_some())
+        })??;
+        if finished{
+            // dropping the room hangs up on everyone once they have the last message
+            let room = self.rooms.lock().unwrap().remove(code).unwrap();
+            self.record(code, &room);
+        }
+        Ok(())
+    }
+
+    fn record(&self, code : &str, room : &Room){
+        let players = room.players(|seat, _|seat.name.clone());
+        let mut record = room.game.to_record(PlayerMap::new(
+            players[Player::White].clone().unwrap_or("?".to_owned()),
+            players[Player::Black].clone().unwrap_or("?".to_owned())));
+        record.other_tags.push(("Room".to_owned(), code.to_owned()));
+        eprintln!("room {}: {} vs {}, {}", code, record.players[Player::White], record.players[Player::Black],
+            room.game.outcome().map_or("abandoned".to_owned(), |outcome|outcome.to_string()));
+        let Some(path) = &self.config.records else {return};
+        let written = OpenOptions::new().create(true).append(true).open(path)
+            .and_then(|mut file|writeln!(file, "{}", record));
+        if let Err(e) = written{
+            eprintln!("could not write the record to {}: {}", path.display(), e);
+        }
+    }
+
+    /// Marks the seat away, unless another connection took it over.
+    /// Rooms nobody is in and no move was played in are closed, others after `close_idle`.
+    fn leave(&self, code : &str, seat : Player, session : u64){
+        let mut rooms = self.rooms.lock().unwrap();
+        let Some(room) = rooms.get_mut(code) else {return};
+        let Some(held) = room.seats[seat].as_mut().filter(|held|held.session == session) else {return};
+        held.playing = false;
+        room.present[seat] = false;
+        if room.present[Player::White] || room.present[Player::Black]{
+            return;
+        }
+        if room.game.match_state.history().is_empty(){
+            rooms.remove(code);
+        } else {
+            room.deserted_since = Some(Instant::now());
+        }
+    }
+
+    /// Closes the rooms nobody came back to within the idle timeout, recording their games unfinished.
+    fn close_idle(&self){
+        let closed : Vec<(String, Room)> = {
+            let mut rooms = self.rooms.lock().unwrap();
+            let idle : Vec<String> = rooms.iter()
+                .filter(|(_, room)|room.deserted_since.is_some_and(|since|since.elapsed() >= self.config.idle_timeout))
+                .map(|(code, _)|code.clone())
+                .collect();
+            idle.into_iter().map(|code|{
+                let room = rooms.remove(&code).unwrap();
+                (code, room)
+            }).collect()
+        };
+        for (code, room) in closed{
+            self.record(&code, &room);
+        }
+    }
+}
+
+/// Sends what is relayed to the connection, and hands what it receives to `on_message`,
+/// until either fails or the relaying ends.
+fn relay(connection : &mut Connection, relayed : &Receiver<Message>, mut on_message : impl FnMut(Message) -> Result<(), NetworkError>) -> Result<(), NetworkError>{
+    loop{
+        loop{
+            match relayed.try_recv(){
+                Ok(message) => connection.send(&message)?,
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return Err(NetworkError::Closed),
+            }
+        }
+        match connection.receive(Instant::now() + RELAY_POLL){
+            Ok(message) => on_message(message)?,
+            Err(NetworkError::Timeout) => {},
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use crate::networking::{OnlineMatch, RemoteEvent, Spectator, SpectatorEvent};
+    use crate::tokonoma::{GameOutcome, GameRecord, Termination};
+
+    use super::*;
+
+    fn start(config : ServerConfig) -> (Arc<Server>, String){
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = Server::new(config);
+        let serving = server.clone();
+        std::thread::spawn(move ||serving.serve(listener));
+        (server, address)
+    }
+
+    fn soon() -> Instant{
+        Instant::now() + Duration::from_secs(10)
+    }
+
+    /// Enters a room, without waiting for the opponent.
+    fn enter(address : &str, name : &str, code : Option<&str>, color : Option<Player>, key : Option<&str>) -> (Connection, String, Player, String){
+        let mut connection = Connection::connect(address, name).unwrap();
+        assert_eq!(connection.peer_name, SERVER_NAME);
+        let (code, seat, key) = OnlineMatch::enter_room(&mut connection, code, color, key).unwrap();
+        (connection, code, seat, key)
+    }
+
+    /// Whether `name` is refused a seat in the room under `code`.
+    fn refused(address : &str, name : &str, code : &str, key : Option<&str>) -> bool{
+        let mut connection = Connection::connect(address, name).unwrap();
+        OnlineMatch::enter_room(&mut connection, Some(code), None, key) == Err(NetworkError::Declined)
+    }
+
+    fn parse(online : &OnlineMatch, notation : &str) -> crate::tokonoma::Ply{
+        online.match_state().present_state().parse_move(notation).unwrap()
+    }
+
+    /// Waits until the server lists the room as `expected`.
+    fn await_listing(server : &Server, expected : impl Fn(&[RoomSummary]) -> bool){
+        let deadline = soon();
+        while !expected(&server.rooms()){
+            assert!(Instant::now() < deadline, "{:?}", server.rooms());
+            std::thread::sleep(RELAY_POLL);
+        }
+    }
+
+    #[test]
+    fn test_room_and_reconnection(){
+        let records = std::env::temp_dir().join(format!("hexstack-server-idle-test-{}.txt", std::process::id()));
+        let _ = std::fs::remove_file(&records);
+        let (server, address) = start(ServerConfig{
+            takebacks : true,
+            records : Some(records.clone()),
+            idle_timeout : Duration::from_millis(200),
+            ..Default::default()
+        });
+        let (connection, code, seat, ada_key) = enter(&address, "Ada", None, Some(Player::Black), None);
+        assert_eq!(seat, Player::Black);
+        let ada = std::thread::spawn(move ||OnlineMatch::join_room(connection, soon()).unwrap());
+        let (connection, same_code, seat, bob_key) = enter(&address, "Bob", Some(&code), None, None);
+        assert_eq!((same_code, seat), (code.clone(), Player::White));
+        assert_ne!(ada_key, bob_key);
+        let mut bob = OnlineMatch::join_room(connection, soon()).unwrap();
+        let mut ada = ada.join().unwrap();
+        assert_eq!((ada.peer_name(), bob.peer_name()), ("Bob", "Ada"));
+        assert_eq!((ada.local, bob.local), (Player::Black, Player::White));
+        assert!(bob.offer().takebacks);
+
+        // a third player finds the room full, even under Ada's name
+        assert!(refused(&address, "Cy", &code, None));
+        assert!(refused(&address, "Ada", &code, None));
+
+        let ply = parse(&bob, "Bb5");
+        bob.play(ply).unwrap();
+        assert_eq!(ada.wait(soon()), Ok(RemoteEvent::Moved(ply)));
+        let ply = parse(&ada, "Bd2");
+        ada.play(ply).unwrap();
+        assert_eq!(bob.wait(soon()), Ok(RemoteEvent::Moved(ply)));
+        await_listing(&server, |rooms|rooms[0].to_string() == format!("{}: Bob vs Ada, 2 plies", code));
+
+        // Ada drops out, Bob plays on, and Ada comes back to the game so far
+        drop(ada);
+        await_listing(&server, |rooms|rooms[0].players[Player::Black] == Some(("Ada".to_owned(), false)));
+        let ply = parse(&bob, "Aa3");
+        bob.play(ply).unwrap();
+        // her seat takes her key, not her name
+        assert!(refused(&address, "Ada", &code, None));
+        assert!(refused(&address, "Ada", &code, Some("not-her-key")));
+        let (connection, _, seat, key) = enter(&address, "Ada", Some(&code), None, Some(&ada_key));
+        assert_eq!((seat, key), (Player::Black, ada_key));
+        let mut ada = OnlineMatch::join_room(connection, soon()).unwrap();
+        assert_eq!(ada.match_state().history().len(), 3);
+        assert_eq!(ada.match_state().present_state(), bob.match_state().present_state());
+
+        bob.take_back(1).unwrap_err();
+        ada.play(parse(&ada, "Se2")).unwrap();
+        bob.wait(soon()).unwrap();
+        bob.take_back(2).unwrap();
+        assert_eq!(ada.wait(soon()), Ok(RemoteEvent::TakebackAsked(2)));
+        assert_eq!(ada.answer_takeback(true), Ok(2));
+        assert_eq!(bob.wait(soon()), Ok(RemoteEvent::TookBack(2)));
+        assert_eq!(ada.match_state().history().len(), 2);
+        assert_eq!(bob.match_state().present_state(), ada.match_state().present_state());
+
+        // once both are gone for long enough, the game is recorded unfinished and the room closed
+        drop(ada);
+        drop(bob);
+        await_listing(&server, |rooms|rooms.is_empty());
+        let written = std::fs::read_to_string(&records).unwrap();
+        let _ = std::fs::remove_file(&records);
+        let record : GameRecord = written.parse().unwrap();
+        assert_eq!(record.players, PlayerMap::new("Bob".to_owned(), "Ada".to_owned()));
+        assert_eq!((record.outcome, record.termination), (None, Termination::Unterminated));
+        assert_eq!(record.moves.len(), 2);
+    }
+
+    #[test]
+    fn test_illegal_moves_and_records(){
+        let records = std::env::temp_dir().join(format!("hexstack-server-test-{}.txt", std::process::id()));
+        let _ = std::fs::remove_file(&records);
+        let (server, address) = start(ServerConfig{records : Some(records.clone()), ..Default::default()});
+
+        let (connection, code, _, _) = enter(&address, "Ada", Some("club"), None, None);
+        assert_eq!(code, "club");
+        let ada = std::thread::spawn(move ||OnlineMatch::join_room(connection, soon()).unwrap());
+        let (mut bob, _, _, key) = enter(&address, "Bob", Some("club"), None, None);
+        assert_eq!(bob.receive(soon()), Ok(Message::Opponent("Ada".to_owned())));
+        assert!(matches!(bob.receive(soon()), Ok(Message::Offer(..))));
+        bob.send(&Message::Accept).unwrap();
+        assert_eq!(bob.receive(soon()), Ok(Message::Sync{setup : Position::setup(), moves : vec![]}));
+        let mut ada = ada.join().unwrap();
+        assert_eq!(ada.local, Player::White);
+
+        // Bob plays out of turn: the server hangs up on him, and keeps the game as it was
+        bob.send(&Message::Move(parse(&ada, "Bb5"))).unwrap();
+        assert_eq!(bob.receive(soon()), Err(NetworkError::Closed));
+        ada.play(parse(&ada, "Bb5")).unwrap();
+        let mut bob = OnlineMatch::join_room(enter(&address, "Bob", Some("club"), None, Some(&key)).0, soon()).unwrap();
+        assert_eq!(bob.match_state().history().len(), 1);
+
+        bob.resign().unwrap();
+        assert_eq!(ada.wait(soon()), Ok(RemoteEvent::Resigned));
+        await_listing(&server, |rooms|rooms.is_empty());
+        let written = std::fs::read_to_string(&records).unwrap();
+        let _ = std::fs::remove_file(&records);
+        let record : GameRecord = written.parse().unwrap();
+        assert_eq!(record.players, PlayerMap::new("Ada".to_owned(), "Bob".to_owned()));
+        assert_eq!(record.outcome, Some(GameOutcome::WhiteWins));
+        assert_eq!(record.termination, Termination::Resignation);
+        assert_eq!(record.other_tags, vec![("Room".to_owned(), "club".to_owned())]);
+        assert_eq!(record.moves.len(), 1);
+    }
+
+    #[test]
+    fn test_spectators(){
+        let (server, address) = start(ServerConfig{takebacks : true, ..Default::default()});
+        let watch = |code : &str|Spectator::watch(Connection::connect(&address, "fan").unwrap(), code);
+        assert_eq!(watch("nowhere").err(), Some(NetworkError::Declined));
+
+        let (connection, code, _, _) = enter(&address, "Ada", None, None, None);
+        let mut early = watch(&code).unwrap();
+        assert_eq!(early.players(), &PlayerMap::new(Some("Ada".to_owned()), None));
+        let ada = std::thread::spawn(move ||OnlineMatch::join_room(connection, soon()).unwrap());
+        let mut bob = OnlineMatch::join_room(enter(&address, "Bob", Some(&code), None, None).0, soon()).unwrap();
+        let mut ada = ada.join().unwrap();
+        assert_eq!(early.wait(soon()), Ok(SpectatorEvent::Seated(Player::Black)));
+        assert_eq!(early.players()[Player::Black].as_deref(), Some("Bob"));
+
+        ada.play(parse(&ada, "Bb5")).unwrap();
+        bob.wait(soon()).unwrap();
+        bob.play(parse(&bob, "Bd2")).unwrap();
+        ada.wait(soon()).unwrap();
+
+        for player in [Player::White, Player::Black]{
+            assert!(matches!(early.wait(soon()), Ok(SpectatorEvent::By(by, RemoteEvent::Moved(..))) if by == player));
+        }
+
+        // a late spectator catches up with the game so far
+        let mut late = watch(&code).unwrap();
+        assert_eq!(late.match_state().history().len(), 2);
+        assert_eq!(late.to_record().players, PlayerMap::new("Ada".to_owned(), "Bob".to_owned()));
+        await_listing(&server, |rooms|rooms[0].to_string() == format!("{}: Ada vs Bob, 2 plies, 2 watching", code));
+
+        let aa3 = parse(&ada, "Aa3");
+        ada.play(aa3).unwrap();
+        bob.wait(soon()).unwrap();
+        let se2 = parse(&bob, "Se2");
+        bob.play(se2).unwrap();
+        ada.wait(soon()).unwrap();
+        ada.take_back(2).unwrap();
+        assert_eq!(bob.wait(soon()), Ok(RemoteEvent::TakebackAsked(2)));
+        bob.answer_takeback(true).unwrap();
+        assert_eq!(ada.wait(soon()), Ok(RemoteEvent::TookBack(2)));
+        for event in [
+            SpectatorEvent::By(Player::White, RemoteEvent::Moved(aa3)),
+            SpectatorEvent::By(Player::Black, RemoteEvent::Moved(se2)),
+            SpectatorEvent::By(Player::White, RemoteEvent::TakebackAsked(2)),
+            SpectatorEvent::By(Player::Black, RemoteEvent::TookBack(2)),
+        ]{
+            assert_eq!(early.wait(soon()), Ok(event.clone()));
+            assert_eq!(late.wait(soon()), Ok(event));
+        }
+        assert_eq!(late.match_state().present_state(), ada.match_state().present_state());
+
+        drop(early);
+        ada.resign().unwrap();
+        assert_eq!(late.wait(soon()), Ok(SpectatorEvent::By(Player::White, RemoteEvent::Resigned)));
+        assert_eq!(late.outcome(), Some(GameOutcome::BlackWins));
+        // the room closes with the game
+        assert_eq!(late.wait(soon()), Err(NetworkError::Closed));
+    }
+}
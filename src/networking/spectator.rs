@@ -0,0 +1,104 @@
+//! Following a room of a game server without playing.
+
+use std::collections::VecDeque;
+
+use web_time::Instant;
+
+use crate::tokonoma::{GameOutcome, GameRecord, MatchState, Player, PlayerMap};
+
+use super::connection::{Connection, NetworkError, HANDSHAKE_TIMEOUT};
+use super::online::{replay, Game, RemoteEvent};
+use super::protocol::Message;
+
+/// What happened in the room watched.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum SpectatorEvent{
+    /// A player took that seat.
+    Seated(Player),
+    By(Player, RemoteEvent),
+}
+
+/// A read-only view of a room, caught up with the game so far when entering it.
+/// The moves relayed are checked as a player's would be.
+pub struct Spectator{
+    connection : Connection,
+    pub room : String,
+    players : PlayerMap<Option<String>>,
+    game : Game,
+    events : VecDeque<SpectatorEvent>,
+}
+
+impl Spectator{
+    /// Enters the room with code `room` of the server at the other end.
+    pub fn watch(mut connection : Connection, room : &str) -> Result<Spectator, NetworkError>{
+        connection.send(&Message::Watch(room.to_owned()))?;
+        let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+        let mut players = PlayerMap::new(None, None);
+        let mut offer = None;
+        loop{
+            match (connection.receive(deadline)?, offer){
+                (Message::Seat(player, name), _) => players[player] = Some(name),
+                (Message::Offer(terms), None) => offer = Some(terms),
+                (Message::Sync { setup, moves }, Some(offer)) => {
+                    let game = Game::new(offer, replay(setup, &moves, &offer)?);
+                    return Ok(Spectator{connection, room : room.to_owned(), players, game, events : VecDeque::new()});
+                },
+                (Message::Decline, _) => return Err(NetworkError::Declined),
+                (other, _) => return Err(NetworkError::Illegal(format!("{} instead of the room", other)))
+            }
+        }
+    }
+
+    /// The players seated, by colour.
+    pub fn players(&self) -> &PlayerMap<Option<String>>{
+        &self.players
+    }
+
+    pub fn match_state(&self) -> &MatchState{
+        &self.game.match_state
+    }
+
+    /// The result, by the rules, a resignation or an agreement.
+    pub fn outcome(&self) -> Option<GameOutcome>{
+        self.game.outcome()
+    }
+
+    /// Record of the game so far, with the players named.
+    pub fn to_record(&self) -> GameRecord{
+        let name = |player : Player|self.players[player].clone().unwrap_or("?".to_owned());
+        self.game.to_record(PlayerMap::new(name(Player::White), name(Player::Black)))
+    }
+
+    /// What happened next, if anything has arrived. Never blocks.
+    pub fn poll(&mut self) -> Result<Option<SpectatorEvent>, NetworkError>{
+        while self.events.is_empty(){
+            match self.connection.try_receive()?{
+                Some(message) => self.handle(message)?,
+                None => return Ok(None)
+            }
+        }
+        Ok(self.events.pop_front())
+    }
+
+    /// Blocks until something happens or the deadline passes.
+    pub fn wait(&mut self, deadline : Instant) -> Result<SpectatorEvent, NetworkError>{
+        while self.events.is_empty(){
+            let message = self.connection.receive(deadline)?;
+            self.handle(message)?;
+        }
+        Ok(self.events.pop_front().unwrap())
+    }
+
+    fn handle(&mut self, message : Message) -> Result<(), NetworkError>{
+        let event = match message{
+            Message::Seat(player, name) => {
+                self.players[player] = Some(name);
+                SpectatorEvent::Seated(player)
+            },
+            Message::By(player, message) => SpectatorEvent::By(player, self.game.apply(player, &message)?),
+            other => return Err(NetworkError::Illegal(other.to_string()))
+        };
+        self.events.push_back(event);
+        Ok(())
+    }
+}
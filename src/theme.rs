@@ -184,10 +184,7 @@ pub enum BoardPaletteConfig{
 }
 impl BoardPaletteConfig{
     pub fn is_custom(&self)->bool{
-        match self{
-            BoardPaletteConfig::Custom(..) => true,
-            _ => false
-        }
+        matches!(self, BoardPaletteConfig::Custom(..))
     }
     pub fn to_palette(&self) -> BoardPalette{
         match self{
@@ -255,7 +252,7 @@ impl Default for ThemeConfig{
 
 impl ThemeConfig{
     pub fn get_pieceset(&self) -> PieceSet {self.pieceset}
-    pub async fn set_pieceset(&mut self, new_value : PieceSet){
+    pub fn set_pieceset(&mut self, new_value : PieceSet){
         // this is bad . But they forced my hand
         if self.pieceset != new_value{
             self.pieceset = new_value;
@@ -121,6 +121,7 @@ impl BitSet{
         mask
     };
 
+    #[allow(clippy::should_implement_trait)]
     pub fn into_iter(self) -> impl Iterator<Item = Tile>{
         BOARD_BITS.into_iter().flat_map(move |bit|{
             if self.0 & (1<<bit) > 0{
@@ -205,6 +206,12 @@ pub struct DoubleCounterBitset{
     bitplane0 : BitSet
 }
 
+impl Default for DoubleCounterBitset{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl DoubleCounterBitset{
     pub fn new()->Self{
         Self{bitplane0:BitSet::empty(),bitplane1:BitSet::empty()}
@@ -259,6 +266,7 @@ impl PieceMap{
     }
 
 
+    #[allow(clippy::should_implement_trait)]
     pub fn into_iter(self) -> impl Iterator<Item = (Tile,Species)>{
         
         BOARD_BITS.into_iter().flat_map(move |bit|{
@@ -418,6 +426,13 @@ impl PieceMap{
         kills_masked
     }
 
+    /// Puts back pieces previously removed by `kill`.
+    pub fn revive(&mut self, killed : &PieceMap){
+        self.flats |= killed.flats;
+        self.talls[0] |= killed.talls[0];
+        self.talls[1] |= killed.talls[1];
+    }
+
     pub fn is_not_empty(&self) -> bool{
         self.flats.is_not_empty() | self.talls[0].is_not_empty() | self.talls[1].is_not_empty()
     }
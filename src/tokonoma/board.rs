@@ -1,8 +1,6 @@
 
-use macroquad::prelude::*;
 use std::{collections::HashMap, fmt::Display, ops::{Index, IndexMut}, str::FromStr};
 use memoize::memoize;
-use crate::{arrows::draw_arrow, assets::{get_assets_unchecked, get_pieceset_unchecked, CompositionMode}, theme::{get_board_palette, get_theme_config, BoardTilesModeConfig}};
 use super::{bitboards::{bit_to_tile, BitSet, BOARD_BITS}, PositionStringParsingError};
 use lazy_static::lazy_static;
 
@@ -250,7 +248,7 @@ impl Tile{
         };
         
         offsets.map(|opt|
-            opt.map(|off|self.shift(off)).flatten()
+            opt.and_then(|off|self.shift(off))
         )
         
     }
@@ -259,8 +257,8 @@ impl Tile{
     
 
     pub fn to_world(&self, flip_board : bool) -> (f32,f32){
-        const SQRT3 : f32 = 1.73205080757;
-        const SQRT3_2 : f32 = 0.86602540378;
+        const SQRT3 : f32 = 1.732_050_8;
+        const SQRT3_2 : f32 = 0.866_025_4;
         let (x,y) = (1.5* (self.x() as f32) ,
                   SQRT3_2 * ( self.x() as f32) +    SQRT3 * (self.y() as f32));
 
@@ -269,14 +267,14 @@ impl Tile{
     }
 
     pub fn from_world(x : f32 , y : f32, flip_board : bool) -> Option<Tile>{
-        const SQRT3 : f32 = 1.73205080757;
-        const ONE_3 : f32 = 0.33333333333;
+        const SQRT3 : f32 = 1.732_050_8;
+        const ONE_3 : f32 = 1.0 / 3.0;
 
         
         let (x,y) = if flip_board{
-            (-x as f32, -y as f32)
+            (-x, -y)
         } else{
-            (x as f32, y as f32)
+            (x, y)
         };
 
         let (tx,ty) = (
@@ -337,165 +335,8 @@ impl Tile{
         out
     };
 
-    pub fn draw_highlight_outline(&self, thickness : f32, color : Color, flip_board : bool){
-        let (x,y) = self.to_world(flip_board);
-        draw_hexagon(x, y, 1.0, thickness, true, color, Color::from_rgba(0, 0,0,0));
-    }
-
-    pub fn draw_highlight_fill(&self, color : Color, flip_board : bool){
-        let (x,y) = self.to_world(flip_board);
-        draw_hexagon(x, y, 1.0, 0.0, true,BLACK, color);
-    }
-
-    pub fn draw_move_target(&self, color : Player,  flip_board : bool){
-        let tex = get_pieceset_unchecked().tex;
-        
-        let (x,y) = self.to_world(flip_board);
-        const R : f32 = 1.0;
-        let src_off = match color{
-            Player::White => 0.0,
-            Player::Black => 1.0
-        };
-
-        let tile_size = tex.width() * 0.25;
-
-        tex.draw(
-            x-R,
-            y-R,
-            WHITE, DrawTextureParams{
-                dest_size : Some(vec2(2.0*R,2.0*R)),
-                source : Some(Rect::new(0.0,tile_size*(1.0 + 2.0*src_off),tile_size,tile_size)),
-                ..Default::default()
-            }
-        )
-        
-    }
-
-    fn tile_color(&self) -> Color{
-        get_board_palette().sample(self.mod3())
-    }
-
-    const fn is_border(&self) -> bool{
-        let (ux,uy) = (self.ux(),self.uy());
-
-        match ux{
-            0|4 => true,
-            1  => (uy == 6) | (uy == 1),
-            2 => (uy==6) | (uy == 0),
-            3 => (uy==5) | (uy == 0),
-            _ => unreachable!()
-        }
-    }
-
-    pub fn draw_board(flip_board : bool){
-        use BoardTilesModeConfig as BTMC;
-        let conf = get_theme_config();
-        let board_mode = conf.board_mode;
-
-        const DARK_TILE : Tile = Tile::from_xyz_unchecked(0, -1, 1);
-        const LIGHT_TILE : Tile = Tile::from_xyz_unchecked(0, 1, -1);
-        let dark_color = DARK_TILE.tile_color();
-        let light_color = LIGHT_TILE.tile_color();
-        
-
-        match board_mode.tiles{
-            BTMC::WithBorder => {
-                Self::all_tiles().into_iter().filter(|t|t.is_border())
-                .for_each(|t|{
-                    let (x,y) = t.to_world(flip_board);
-                    draw_hexagon(x, y, 
-                        1.1, 
-                        0.0,//0.05, 
-                        true,
-                        Color::from_hex(0x111111),
-                        dark_color);
-                });
-            },
-            _ => {}
-        };
-        
-        match board_mode.tiles{
-            BTMC::Normal|BTMC::WithBorder|BTMC::Outline => {
-                Self::ALL_TILES.iter().for_each(|t|{
-                    let (x,y) = t.to_world(flip_board);
-                    
-
-                    let (fill,lthick) = match board_mode.tiles{
-                        BTMC::Outline => (Color::from_rgba(0,0,0,0),0.05),
-                        _ => (t.tile_color(),0.0)
-                    };
-
-                    draw_hexagon(x, y, 
-                        1.0, 
-                        lthick, 
-                        true,
-                        Color::from_hex(0x222222),
-                        fill);
-                });
-
-                for player in [Player::Black,Player::White]{
-                    let (x,y) = Tile::corner(player).to_world(flip_board);
-        
-                    
-                    let col = match player{
-                        Player::Black => dark_color,
-                        Player::White => light_color,
-                    };
-        
-                    draw_hexagon(x, y, 
-                        0.6, 
-                        0.0,
-                        true,
-                        Color::from_hex(0x111111),
-                        col);
-                }
-            },
-            BTMC::None => {}
-        };
-        
-        
-
-        if board_mode.trigrid{
-            Self::ALL_TILES.iter().for_each(|t|{
-                let (x,y) = t.to_world(flip_board).into();
-                t.adjacent().into_iter().flatten()
-                .for_each(|a|{
-                    let (xe,ye) = a.to_world(flip_board);
-                    let (x2,y2) = (0.5*(x+xe),0.5*(y+ye));
-
-                    draw_line(x,y,x2,y2,0.05,Color::from_hex(0x222222));
-
-                });
-            });
-        }
-    }
-
-    pub fn draw_tile_numbers( flip_board : bool){
-        let font = get_assets_unchecked().font;
-        Self::ALL_TILES.iter().for_each(|t|{
-            let (x,y) = t.to_world(flip_board);
-            let (x,y) = (x,y+0.4);
-
-            let mut tcol = t.tile_color();
-            tcol.a = 0.8;
-            // draw_rectangle(tx-0.03, ty-0.4, 0.5, 0.5, tcol);
-            draw_circle(x, y, 0.3, tcol);
-
-            let text = &format!("{}",t);
-            let (font_size, font_scale, font_scale_aspect) = camera_font_scale(0.5);
-            let center = get_text_center(text, Some(font), font_size, font_scale, 0.0);
-            draw_text_ex(text,x-center.x,y-center.y, TextParams{
-                font,
-                font_size, font_scale, font_scale_aspect,
-                color : Color::from_rgba(0x11, 0x11, 0x11, 160),
-                ..Default::default()
-            });
-
-        });
-    }
-
-    const CORNER_BLACK : Tile = Tile::from_xyz_unchecked(0,BOARD_RADIUS as i8,-(BOARD_RADIUS as i8));
-    const CORNER_WHITE : Tile = Tile::from_xyz_unchecked(0, -(BOARD_RADIUS as i8),BOARD_RADIUS as i8);
+    const CORNER_BLACK : Tile = Tile::from_xyz_unchecked(0,BOARD_RADIUS,-BOARD_RADIUS);
+    const CORNER_WHITE : Tile = Tile::from_xyz_unchecked(0, -BOARD_RADIUS,BOARD_RADIUS);
 
     pub const fn corner(color : Player) -> Tile{
         match color{
@@ -627,12 +468,12 @@ impl Display for Tile{
         write!(f,"{}{}",row_name,tile_nr)
     }
 }
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct TileParseError;
 impl FromStr for Tile{
     type Err = TileParseError;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if s.len() != 2 {return Err(TileParseError)};
+        if s.len() != 2 || !s.is_ascii() {return Err(TileParseError)};
         let mut chars = s.chars();
         let (letter,number) = (chars.next().unwrap(),chars.next().unwrap());
 
@@ -651,7 +492,7 @@ impl FromStr for Tile{
             _ => unreachable!()
         } - tile_nr;
 
-        Ok(Tile::from_xyz_unchecked(x, y, -x-y))
+        Tile::from_xyz(x, y, -x-y).ok_or(TileParseError)
         
     }
 }
@@ -668,19 +509,6 @@ impl Player{
             Player::Black=>Player::White
         }
     }
-    pub fn to_color(&self) -> Color{
-        match self{
-            Player::Black => Color::from_hex(0x000000),//Color::from_hex(0x8ec8fd),
-            Player::White => Color::from_hex(0xffffff),
-        }
-    }
-
-    pub fn ui_info_pos(&self) -> Vec2 {
-        vec2(5.5,4.0) * match self{
-            Player::White => 1.0,
-            Player::Black => -1.0,
-        } 
-    }
 }
 
 #[derive(Clone,Debug, PartialEq, Eq, Hash)]
@@ -767,17 +595,6 @@ pub enum Tall{
     Star
 }
 
-impl Tall{
-    fn value(&self) -> f32{
-        match self{
-            Self::Hand => 2.0,
-            Self::Blind => 3.0,
-            Self::Star => 3.0,
-        }
-    }
-}
-
-
 /// Piece species.
 #[derive(Clone, Copy,PartialEq, Eq,Hash, Debug)]
 pub enum Species{
@@ -799,14 +616,9 @@ impl Species{
 
     
 
+    /// Material value with the default evaluation weights.
     pub fn value(&self) -> f32{
-        match self {
-            Species::Flat => {
-                2.0
-            },
-            Species::Lone(tall) => tall.value(),
-            Species::Stack(tall) => (Species::Flat.value() + tall.value()) - 0.2,
-        }
+        super::EvalParams::DEFAULT.species_value(*self)
     }
 
     #[inline]
@@ -876,66 +688,6 @@ pub struct Piece{
 }
 
 impl Piece{
-    pub fn draw(&self, x : f32, y: f32,  scale: f32){
-        
-        let pieceset = get_pieceset_unchecked();
-
-        match pieceset.composition_mode {
-            CompositionMode::ComposeOnFlat => {
-                match self.species{
-                    Species::Stack(tall) => {
-                        Piece{color:self.color, species : Species::Flat}.draw(x, y, scale);
-                        Piece{color:self.color, species : Species::Lone(tall)}.draw(x, y, scale);
-                        return;
-                    },
-                    _ => {},
-                }
-
-            }
-            CompositionMode::Precomposed => {},
-        }
-
-        let sx_single = match self.species{
-            Species::Flat => 0,
-            Species::Lone(tall) | Species::Stack(tall) => match tall{
-                Tall::Hand => 1,
-                Tall::Star => 2,
-                Tall::Blind => 3
-            } 
-        };
-
-        let sx = sx_single;
-
-        let sy = match self.color{
-            Player::Black => 2,
-            Player::White => 0
-        } + match self.species{
-            Species::Stack(..) => 1,
-            _ => 0
-        };
-
-        
-        
-        let tex = pieceset.tex;
-
-        let tile_size = tex.width() * 0.25;
-        let world_size = pieceset.base_scale * scale;
-
-        let sx = sx as f32;
-        let sy = sy as f32;
-        
-        
-        tex.draw(
-            x - world_size * 0.5, y - world_size * 0.5,
-                WHITE, DrawTextureParams{
-            dest_size : Some(vec2(1.0, 1.0) * world_size),
-            source : Some(Rect{x:sx*tile_size,y: sy*tile_size,w:tile_size,h:tile_size}),
-            ..Default::default()
-            });
-        
-
-    }
-
     pub fn unstack(self) -> Box<dyn Iterator<Item=Piece>>{
         let color = self.color;
         match self.species{
@@ -951,7 +703,7 @@ impl Piece{
         self.species.value()
     }
 
-    const fn to_char(&self) -> char{
+    pub(crate) const fn to_char(self) -> char{
         let piece = self;
         match piece.color{
             Player::White => match piece.species{
@@ -1008,7 +760,7 @@ impl TryFrom<char> for Piece{
     type Error = PositionStringParsingError;
     fn try_from(value: char) -> Result<Self, Self::Error> {
         CHAR_PIECE_MAP.get(&value)
-        .map(|v|*v)
+        .copied()
         .ok_or(PositionStringParsingError::UnknownCharacter(value))
     }
 }
@@ -1020,23 +772,6 @@ pub struct Ply{
     pub to_tile : Tile
 }
 
-impl Ply{
-    pub fn draw(&self, flip_board : bool){
-        let (from_tile,to_tile) = (self.from_tile,self.to_tile) ; 
-
-        draw_arrow(
-            from_tile.to_world(flip_board).into(),
-                to_tile.to_world(flip_board).into(),
-                Color::new(1.0, 1.0, 0.0, 0.5), 
-                0.3,
-                0.7,
-                0.8
-            );
-        
-    }
-
-}
-
 
 impl Display for Ply{
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -1107,24 +842,14 @@ impl Captured{
         .count()
     }
 
+    pub fn is_empty(&self) -> bool{
+        self.len() == 0
+    }
+
     pub fn extend(&mut self, iterator : impl IntoIterator<Item = Species>){
         iterator.into_iter().for_each(|pt| self.push(pt))
     }
 
-    pub fn draw(&self, color : Player){
-        let capts = self;
-        let n_capt = 0.5*(capts.count().saturating_sub(1) as f32);
-        let basey = match color {Player::White => 4.7, Player::Black => -4.7};
-        
-        capts.iter().enumerate().for_each(|(i,piece_type)|{
-            let p = Piece{color : color.flip(), species : piece_type};
-            let x = 0.6*(i as f32 - n_capt);
-            let y = basey;
-            p.draw(x,y, 0.5);
-            
-            
-        });
-    }
 }
 
 
@@ -1139,7 +864,7 @@ mod tests{
     use super::*;
     #[test]
     fn test_tiles(){
-        let sbr = BOARD_RADIUS as i8;
+        let sbr = BOARD_RADIUS;
         (-sbr..=sbr).for_each(|y:i8|
             (-sbr..=sbr).for_each(|x:i8|{
                 let z = -x-y;
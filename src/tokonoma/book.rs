@@ -0,0 +1,358 @@
+//! Opening books: moves known to be good from early positions, played without searching.
+//!
+//! Each position, identified by its Zobrist hash, has a list of moves with a weight
+//! (how often the move was seen or recommended) and a score: the expected result
+//! for the side playing it, from 0 (loss) to 1 (win).
+//!
+//! Books are built from the output of the `openings` binary, where scores come
+//! from the search, or from game records, where they are the results obtained.
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use ::rand::Rng;
+
+use super::{GameRecord, MatchState, Player, Ply, Position, RecordError, Termination, Tile};
+
+/// Converts finite search scores into expected results, as `sigmoid(SCORE_SCALE * score)`.
+const SCORE_SCALE : f32 = 0.35;
+
+/// Scores within `variety` times this of the best move may still be played.
+const VARIETY_SCORE_RANGE : f32 = 0.1;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct BookMove{
+    pub ply : Ply,
+    pub weight : u32,
+    /// Expected result for the side playing the move, from 0 to 1.
+    pub score : f32,
+}
+
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct OpeningBook{
+    entries : HashMap<u64, Vec<BookMove>>,
+}
+
+impl OpeningBook{
+    const MAGIC : &'static [u8; 4] = b"HXOB";
+    const VERSION : u8 = 1;
+    pub const FILE_EXTENSION : &'static str = "hxob";
+
+    pub fn new() -> Self{
+        Self::default()
+    }
+
+    /// Number of positions.
+    pub fn len(&self) -> usize{
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool{
+        self.entries.is_empty()
+    }
+
+    /// Book moves of the position, best first. Moves that are not legal there,
+    /// left by a hash collision, are skipped.
+    pub fn moves(&self, position : &Position) -> Vec<BookMove>{
+        let Some(moves) = self.entries.get(&position.zobrist()) else {return vec![]};
+        let valid_moves = position.valid_moves();
+        let mut moves : Vec<BookMove> = moves.iter().filter(|m|valid_moves.contains(&m.ply)).copied().collect();
+        moves.sort_by(|a, b|b.score.total_cmp(&a.score).then(b.weight.cmp(&a.weight)));
+        moves
+    }
+
+    /// Adds `weight` observations of `ply` from `position` with the given score,
+    /// averaging the score with what is already known.
+    pub fn add(&mut self, position : &Position, ply : Ply, weight : u32, score : f32){
+        self.add_hashed(position.zobrist(), BookMove{ply, weight, score});
+    }
+
+    fn add_hashed(&mut self, hash : u64, book_move : BookMove){
+        let moves = self.entries.entry(hash).or_default();
+        match moves.iter_mut().find(|m|m.ply == book_move.ply){
+            Some(known) => {
+                let total = known.weight + book_move.weight;
+                if total > 0{
+                    known.score = (known.score * known.weight as f32 + book_move.score * book_move.weight as f32) / total as f32;
+                }
+                known.weight = total;
+            },
+            None => moves.push(book_move),
+        }
+    }
+
+    /// Merges another book into this one.
+    pub fn merge(&mut self, other : &OpeningBook){
+        for (hash, moves) in &other.entries{
+            for book_move in moves{
+                self.add_hashed(*hash, *book_move);
+            }
+        }
+    }
+
+    /// Picks a move for `position`, if the book knows it.
+    ///
+    /// With `variety` 0, always the best scored move. Higher values
+    /// let worse moves through, drawn in proportion to their weight
+    /// and fading with their score: at 1, moves scoring 0.1 less are
+    /// e times less likely than their weight says.
+    pub fn choose(&self, position : &Position, variety : f32, rng : &mut impl Rng) -> Option<Ply>{
+        let moves = self.moves(position);
+        let best = moves.first()?;
+        if variety <= 0.0{
+            return Some(best.ply);
+        }
+
+        let temperature = variety * VARIETY_SCORE_RANGE;
+        let odds : Vec<f32> = moves.iter()
+            .map(|m|m.weight.max(1) as f32 * ((m.score - best.score) / temperature).exp())
+            .collect();
+        let mut pick = rng.gen::<f32>() * odds.iter().sum::<f32>();
+        for (book_move, odd) in moves.iter().zip(&odds){
+            if pick < *odd{
+                return Some(book_move.ply);
+            }
+            pick -= odd;
+        }
+        Some(best.ply)
+    }
+
+    /// Adds the moves of an `openings` report: one move per line, indented by one
+    /// tab per ply from the standard setup, followed by a tab and the score
+    /// of the position after it.
+    pub fn add_analysis(&mut self, report : &str) -> Result<(), String>{
+        // positions along the current line, by depth
+        let mut line_positions = vec![Position::setup()];
+        for (index, line) in report.lines().enumerate(){
+            if line.trim().is_empty(){
+                continue;
+            }
+            let error = |message : String|format!("line {}: {}", index + 1, message);
+            let depth = line.chars().take_while(|c|*c == '\t').count();
+            if depth >= line_positions.len(){
+                return Err(error("indented deeper than the line above".to_owned()));
+            }
+            line_positions.truncate(depth + 1);
+            let position = &line_positions[depth];
+
+            let mut fields = line.trim_start_matches('\t').split('\t');
+            let notation = fields.next().unwrap_or("");
+            let ply = position.parse_move(notation).map_err(|e|error(format!("move {}: {}", notation, e)))?;
+            let white_score = fields.next().and_then(parse_white_score)
+                .ok_or_else(||error("missing or unreadable score".to_owned()))?;
+            let score = match position.to_play(){
+                Player::White => white_score,
+                Player::Black => 1.0 - white_score,
+            };
+
+            let position = position.clone();
+            self.add(&position, ply, 1, score);
+            let mut next = position;
+            next.apply_move(ply);
+            line_positions.push(next);
+        }
+        Ok(())
+    }
+
+    /// Adds the first `max_plies` moves of a finished game, scored with its result.
+    /// Forfeited and unfinished games are ignored.
+    pub fn add_record(&mut self, record : &GameRecord, max_plies : usize) -> Result<(), RecordError>{
+        let Some(outcome) = record.outcome else {return Ok(())};
+        if record.termination == Termination::Forfeit{
+            return Ok(());
+        }
+        let match_state = MatchState::from_record(record)?;
+        for entry in match_state.history().iter().take(max_plies){
+            let mover = entry.state_before.to_play();
+            let score = match outcome.winner(){
+                Some(winner) if winner == mover => 1.0,
+                Some(_) => 0.0,
+                None => 0.5,
+            };
+            self.add(&entry.state_before, entry.ply, 1, score);
+        }
+        Ok(())
+    }
+
+    /// Drops the moves seen fewer than `min_weight` times, and positions left without moves.
+    pub fn prune(&mut self, min_weight : u32){
+        self.entries.retain(|_, moves|{
+            moves.retain(|m|m.weight >= min_weight);
+            !moves.is_empty()
+        });
+    }
+
+    /// Magic, version and position count, then for each position in hash order:
+    /// the little-endian hash, a move count and per move its tiles,
+    /// weight and score, on two bytes each.
+    pub fn write(&self, writer : &mut impl Write) -> io::Result<()>{
+        writer.write_all(Self::MAGIC)?;
+        writer.write_all(&[Self::VERSION])?;
+        writer.write_all(&(self.entries.len() as u32).to_le_bytes())?;
+
+        let mut hashes : Vec<&u64> = self.entries.keys().collect();
+        hashes.sort();
+        for hash in hashes{
+            let moves = &self.entries[hash];
+            writer.write_all(&hash.to_le_bytes())?;
+            writer.write_all(&[moves.len().min(u8::MAX as usize) as u8])?;
+            for book_move in moves.iter().take(u8::MAX as usize){
+                let weight = book_move.weight.min(u16::MAX as u32) as u16;
+                let score = (book_move.score.clamp(0.0, 1.0) * u16::MAX as f32).round() as u16;
+                writer.write_all(&[book_move.ply.from_tile.to_bit(), book_move.ply.to_tile.to_bit()])?;
+                writer.write_all(&weight.to_le_bytes())?;
+                writer.write_all(&score.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn read(reader : &mut impl Read) -> io::Result<OpeningBook>{
+        let invalid = |what : &str|io::Error::new(io::ErrorKind::InvalidData, what.to_owned());
+
+        let mut header = [0u8; 9];
+        reader.read_exact(&mut header)?;
+        if &header[..4] != Self::MAGIC || header[4] != Self::VERSION{
+            return Err(invalid("not an opening book"));
+        }
+        let count = u32::from_le_bytes(header[5..9].try_into().unwrap());
+
+        let mut book = OpeningBook::new();
+        for _ in 0..count{
+            let mut hash = [0u8; 8];
+            reader.read_exact(&mut hash)?;
+            let mut move_count = [0u8; 1];
+            reader.read_exact(&mut move_count)?;
+
+            let mut moves = Vec::with_capacity(move_count[0] as usize);
+            for _ in 0..move_count[0]{
+                let mut bytes = [0u8; 6];
+                reader.read_exact(&mut bytes)?;
+                let tile = |bit|Tile::new(bit).ok_or_else(||invalid("bad tile"));
+                moves.push(BookMove{
+                    ply : Ply{from_tile : tile(bytes[0])?, to_tile : tile(bytes[1])?},
+                    weight : u16::from_le_bytes([bytes[2], bytes[3]]) as u32,
+                    score : u16::from_le_bytes([bytes[4], bytes[5]]) as f32 / u16::MAX as f32,
+                });
+            }
+            book.entries.insert(u64::from_le_bytes(hash), moves);
+        }
+        Ok(book)
+    }
+
+    pub fn save(&self, path : impl AsRef<Path>) -> io::Result<()>{
+        let mut writer = io::BufWriter::new(std::fs::File::create(path)?);
+        self.write(&mut writer)?;
+        writer.flush()
+    }
+
+    pub fn load(path : impl AsRef<Path>) -> io::Result<OpeningBook>{
+        Self::read(&mut io::BufReader::new(std::fs::File::open(path)?))
+    }
+}
+
+/// Expected result for white of a score as printed by the search:
+/// a number, or `+∞`/`-∞` followed by the distance to mate.
+fn parse_white_score(text : &str) -> Option<f32>{
+    let text = text.trim();
+    if text.starts_with("+∞"){
+        return Some(1.0);
+    }
+    if text.starts_with("-∞"){
+        return Some(0.0);
+    }
+    let value : f32 = text.parse().ok()?;
+    Some(1.0 / (1.0 + (-SCORE_SCALE * value).exp()))
+}
+
+#[cfg(test)]
+mod tests{
+    use ::rand::rngs::StdRng;
+    use ::rand::SeedableRng;
+
+    use super::*;
+    use crate::tokonoma::PlayerMap;
+
+    fn notation(position : &Position, ply : Ply) -> String{
+        position.compute_history_entry(ply, PlayerMap::twin(super::super::Captured::empty())).to_string()
+    }
+
+    #[test]
+    fn test_book_roundtrip(){
+        let setup = Position::setup();
+        let moves = setup.valid_moves();
+        let mut book = OpeningBook::new();
+        book.add(&setup, moves[0], 3, 0.75);
+        book.add(&setup, moves[1], 1, 0.25);
+        book.add(&setup, moves[0], 1, 0.25);
+
+        let mut bytes = vec![];
+        book.write(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), 9 + 9 + 2 * 6);
+        let read = OpeningBook::read(&mut bytes.as_slice()).unwrap();
+
+        let known = read.moves(&setup);
+        assert_eq!(known.iter().map(|m|(m.ply, m.weight)).collect::<Vec<_>>(), vec![(moves[0], 4), (moves[1], 1)]);
+        assert!((known[0].score - 0.625).abs() < 1e-4);
+
+        assert!(OpeningBook::read(&mut &b"HXTB\x01\0\0\0\0"[..]).is_err());
+        assert!(OpeningBook::read(&mut &bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_book_variety(){
+        let setup = Position::setup();
+        let moves = setup.valid_moves();
+        let mut book = OpeningBook::new();
+        book.add(&setup, moves[0], 1, 0.6);
+        book.add(&setup, moves[1], 1, 0.58);
+        book.add(&setup, moves[2], 1, 0.1);
+
+        let mut rng = StdRng::seed_from_u64(0);
+        assert!((0..50).all(|_|book.choose(&setup, 0.0, &mut rng) == Some(moves[0])));
+
+        let picks : Vec<Ply> = (0..200).filter_map(|_|book.choose(&setup, 1.0, &mut rng)).collect();
+        assert!(picks.contains(&moves[0]) && picks.contains(&moves[1]));
+        assert!(picks.iter().filter(|p|**p == moves[2]).count() < 5);
+
+        let mut elsewhere = setup.clone();
+        elsewhere.apply_move(moves[0]);
+        assert_eq!(book.choose(&elsewhere, 1.0, &mut rng), None);
+    }
+
+    #[test]
+    fn test_book_from_analysis_and_records(){
+        let setup = Position::setup();
+        let first = setup.valid_moves()[0];
+        let mut after = setup.clone();
+        after.apply_move(first);
+        let reply = after.valid_moves()[0];
+
+        let report = format!("{}\t0.500\t[100]\t...\n\t{}\t-∞ (3)\t[10]\t...\n",
+            notation(&setup, first), notation(&after, reply));
+        let mut book = OpeningBook::new();
+        book.add_analysis(&report).unwrap();
+        assert!(book.moves(&setup)[0].score > 0.5);
+        // black is winning after the reply
+        assert_eq!(book.moves(&after)[0].score, 1.0);
+
+        assert!(book.add_analysis("\t\tBb5\t0.1").is_err());
+        assert!(book.add_analysis("Zz9\t0.1").is_err());
+
+        let mut match_state = MatchState::setup();
+        while match_state.outcome().is_none(){
+            let ply = match_state.present_state().valid_moves()[0];
+            match_state.apply_move(ply);
+        }
+        let mut from_games = OpeningBook::new();
+        from_games.add_record(&match_state.to_record(), 4).unwrap();
+        assert_eq!(from_games.len(), 4);
+        book.merge(&from_games);
+        assert_eq!(book.moves(&setup)[0].weight, 2);
+
+        // the first two plies are in both
+        book.prune(2);
+        assert_eq!(book.len(), 2);
+    }
+}
@@ -0,0 +1,347 @@
+//! Correspondence play: a game travels as a short text token the players send each other.
+//!
+//! A token holds the setup position, the draw rules and every ply so far, each as its
+//! index in the list of valid moves of the position it was played from. It ends with a
+//! checksum, so a token damaged in transit or edited by hand is rejected rather than
+//! replayed into a different game.
+//!
+//! Layout, before the url-safe base64 and the `hxc1-` prefix:
+//! version byte, setup length and setup `PositionString`, draw rules, ply count, ply
+//! indices (all unsigned LEB128 varints, draw rule limits shifted by one so 0 is none),
+//! then the FNV-1a hash of everything before it, 4 bytes big endian.
+
+use std::fmt::Display;
+
+use super::{DrawRules, MatchState, Player, Position, PositionString, PositionStringParsingError};
+
+pub const TOKEN_PREFIX : &str = "hxc1-";
+const VERSION : u8 = 1;
+
+const BASE64 : &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+#[derive(Debug, PartialEq)]
+pub enum TokenError{
+    /// Not a token at all, or cut short.
+    Malformed,
+    /// Written by a newer version of the game.
+    UnknownVersion(u8),
+    /// The checksum does not match: the token was changed or damaged.
+    ChecksumMismatch,
+    BadSetup,
+    /// `ply` counts from 0 from the setup.
+    BadMove{ply : usize},
+    MoveAfterEnd{ply : usize},
+    /// Another setup or other moves than the game being continued.
+    NotThisGame,
+    /// Does not add a move to the game: sent before, or an older token.
+    Stale{token_plies : usize, game_plies : usize},
+    /// Adds more than one move: a token in between was skipped.
+    TooFarAhead{token_plies : usize, game_plies : usize},
+    /// The new move is not the opponent's.
+    NotOpponentsMove,
+}
+
+impl Display for TokenError{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self{
+            TokenError::Malformed => write!(f, "not a correspondence token (they start with {})", TOKEN_PREFIX),
+            TokenError::UnknownVersion(version) => write!(f, "token version {} is not supported", version),
+            TokenError::ChecksumMismatch => write!(f, "the token was altered or damaged: checksum mismatch"),
+            TokenError::BadSetup => write!(f, "the token has an invalid setup position"),
+            TokenError::BadMove { ply } => write!(f, "the token has an illegal move at ply {}", ply + 1),
+            TokenError::MoveAfterEnd { ply } => write!(f, "the token has a move at ply {} after the end of the game", ply + 1),
+            TokenError::NotThisGame => write!(f, "the token is from another game"),
+            TokenError::Stale { token_plies, game_plies } => write!(f,
+                "the token is out of date: it has {} plies and the game already has {}", token_plies, game_plies),
+            TokenError::TooFarAhead { token_plies, game_plies } => write!(f,
+                "the token has {} plies but the game only {}: a token in between is missing", token_plies, game_plies),
+            TokenError::NotOpponentsMove => write!(f, "the new move in the token is not the opponent's"),
+        }
+    }
+}
+
+impl From<PositionStringParsingError> for TokenError{
+    fn from(_ : PositionStringParsingError) -> Self {
+        TokenError::BadSetup
+    }
+}
+
+fn fnv1a(bytes : &[u8]) -> u32{
+    bytes.iter().fold(0x811c9dc5u32, |hash, &byte|(hash ^ byte as u32).wrapping_mul(0x01000193))
+}
+
+fn push_varint(buffer : &mut Vec<u8>, mut value : usize){
+    while value >= 0x80{
+        buffer.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+struct Reader<'a>{
+    bytes : &'a [u8],
+}
+
+impl Reader<'_>{
+    fn byte(&mut self) -> Result<u8, TokenError>{
+        let (&first, rest) = self.bytes.split_first().ok_or(TokenError::Malformed)?;
+        self.bytes = rest;
+        Ok(first)
+    }
+
+    fn varint(&mut self) -> Result<usize, TokenError>{
+        let mut value = 0usize;
+        for shift in (0..usize::BITS).step_by(7){
+            let byte = self.byte()?;
+            value |= ((byte & 0x7f) as usize) << shift;
+            if byte & 0x80 == 0{
+                return Ok(value);
+            }
+        }
+        Err(TokenError::Malformed)
+    }
+
+    fn take(&mut self, count : usize) -> Result<&[u8], TokenError>{
+        if count > self.bytes.len(){
+            return Err(TokenError::Malformed);
+        }
+        let (taken, rest) = self.bytes.split_at(count);
+        self.bytes = rest;
+        Ok(taken)
+    }
+}
+
+fn to_base64(bytes : &[u8]) -> String{
+    let mut text = String::with_capacity(bytes.len() * 4 / 3 + 2);
+    for chunk in bytes.chunks(3){
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, &byte)|bits | (byte as u32) << (16 - 8 * i));
+        for i in 0..=chunk.len(){
+            text.push(BASE64[(bits >> (18 - 6 * i) & 0x3f) as usize] as char);
+        }
+    }
+    text
+}
+
+fn from_base64(text : &str) -> Result<Vec<u8>, TokenError>{
+    let digits = text.bytes()
+        .map(|c|BASE64.iter().position(|&d|d == c).map(|d|d as u32).ok_or(TokenError::Malformed))
+        .collect::<Result<Vec<u32>, TokenError>>()?;
+    if digits.len() % 4 == 1{
+        return Err(TokenError::Malformed);
+    }
+    let mut bytes = Vec::with_capacity(digits.len() * 3 / 4);
+    for chunk in digits.chunks(4){
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, &digit)|bits | digit << (18 - 6 * i));
+        for i in 0..chunk.len() - 1{
+            bytes.push((bits >> (16 - 8 * i)) as u8);
+        }
+    }
+    Ok(bytes)
+}
+
+/// The token of the game so far.
+pub fn encode(match_state : &MatchState) -> String{
+    let setup = setup_of(match_state).to_string();
+    let draw_rules = match_state.draw_rules();
+
+    let mut bytes = vec![VERSION];
+    push_varint(&mut bytes, setup.len());
+    bytes.extend_from_slice(setup.as_bytes());
+    push_varint(&mut bytes, draw_rules.repetitions.map_or(0, |limit|limit + 1));
+    push_varint(&mut bytes, draw_rules.max_plies_without_capture.map_or(0, |limit|limit + 1));
+    push_varint(&mut bytes, match_state.history().len());
+    for entry in match_state.history(){
+        let index = entry.state_before.valid_moves().iter().position(|&ply|ply == entry.ply)
+            .expect("played moves are valid");
+        push_varint(&mut bytes, index);
+    }
+    bytes.extend_from_slice(&fnv1a(&bytes).to_be_bytes());
+
+    format!("{}{}", TOKEN_PREFIX, to_base64(&bytes))
+}
+
+/// Replays a token from its setup.
+pub fn decode(token : &str) -> Result<MatchState, TokenError>{
+    let body = token.trim().strip_prefix(TOKEN_PREFIX).ok_or(TokenError::Malformed)?;
+    let bytes = from_base64(body)?;
+    if bytes.len() < 5{
+        return Err(TokenError::Malformed);
+    }
+    let (payload, checksum) = bytes.split_at(bytes.len() - 4);
+    if fnv1a(payload).to_be_bytes() != checksum{
+        return Err(TokenError::ChecksumMismatch);
+    }
+
+    let mut reader = Reader{bytes : payload};
+    let version = reader.byte()?;
+    if version != VERSION{
+        return Err(TokenError::UnknownVersion(version));
+    }
+    let setup_len = reader.varint()?;
+    let setup = std::str::from_utf8(reader.take(setup_len)?).map_err(|_|TokenError::BadSetup)?;
+    let setup = Position::try_from(PositionString(setup.to_owned()))?;
+    let limit = |value : usize|value.checked_sub(1);
+    let draw_rules = DrawRules{
+        repetitions : limit(reader.varint()?),
+        max_plies_without_capture : limit(reader.varint()?),
+    };
+
+    let mut match_state = MatchState::setup_from(setup).with_draw_rules(draw_rules);
+    let plies = reader.varint()?;
+    for ply in 0..plies{
+        let index = reader.varint()?;
+        if match_state.outcome().is_some(){
+            return Err(TokenError::MoveAfterEnd{ply});
+        }
+        let valid_moves = match_state.present_state().valid_moves();
+        let &next = valid_moves.get(index).ok_or(TokenError::BadMove{ply})?;
+        match_state.apply_move(next);
+    }
+    if !reader.bytes.is_empty(){
+        return Err(TokenError::Malformed);
+    }
+    Ok(match_state)
+}
+
+/// Continues `current`, where `local` plays, with the opponent's reply in `token`.
+/// The token must be `current` plus exactly one move of the opponent.
+pub fn continue_from(current : &MatchState, token : &str, local : Player) -> Result<MatchState, TokenError>{
+    let next = decode(token)?;
+    let (token_plies, game_plies) = (next.history().len(), current.history().len());
+
+    let shared = token_plies.min(game_plies);
+    if setup_of(current) != setup_of(&next) || current.draw_rules() != next.draw_rules() || current.history()[..shared].iter().zip(&next.history()[..shared])
+        .any(|(a, b)|a.ply != b.ply){
+        return Err(TokenError::NotThisGame);
+    }
+
+    if token_plies <= game_plies{
+        return Err(TokenError::Stale{token_plies, game_plies});
+    }
+    if token_plies > game_plies + 1{
+        return Err(TokenError::TooFarAhead{token_plies, game_plies});
+    }
+    if current.to_play() == local{
+        return Err(TokenError::NotOpponentsMove);
+    }
+    Ok(next)
+}
+
+fn setup_of(match_state : &MatchState) -> PositionString{
+    match match_state.history().first(){
+        Some(entry) => entry.state_before.to_position_string(),
+        None => match_state.present_state().to_position_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use ::rand::seq::SliceRandom;
+
+    use super::*;
+
+    fn random_game(max_plies : usize) -> MatchState{
+        let mut rng = ::rand::thread_rng();
+        let mut match_state = MatchState::setup();
+        for _ in 0..max_plies{
+            if match_state.outcome().is_some(){
+                break;
+            }
+            let ply = *match_state.present_state().valid_moves().choose(&mut rng).unwrap();
+            match_state.apply_move(ply);
+        }
+        match_state
+    }
+
+    fn error(result : Result<MatchState, TokenError>) -> TokenError{
+        result.err().expect("the token should be rejected")
+    }
+
+    fn plies(match_state : &MatchState) -> Vec<String>{
+        match_state.history().iter().map(|entry|entry.to_string()).collect()
+    }
+
+    #[test]
+    fn test_base64_roundtrip(){
+        for len in 0..10{
+            let bytes : Vec<u8> = (0..len).map(|i|(i * 97 + 200) as u8).collect();
+            assert_eq!(from_base64(&to_base64(&bytes)).unwrap(), bytes);
+        }
+    }
+
+    #[test]
+    fn test_token_roundtrip(){
+        for max_plies in [0, 1, 8, 60, 400]{
+            let match_state = random_game(max_plies).with_draw_rules(DrawRules{
+                repetitions : None,
+                max_plies_without_capture : Some(150),
+            });
+            let token = encode(&match_state);
+            assert!(token.starts_with(TOKEN_PREFIX));
+            let decoded = decode(&token).unwrap();
+            assert_eq!(plies(&decoded), plies(&match_state));
+            assert!(decoded.draw_rules() == match_state.draw_rules());
+            assert_eq!(decoded.outcome(), match_state.outcome());
+        }
+    }
+
+    #[test]
+    fn test_tampered_tokens(){
+        let token = encode(&random_game(20));
+        assert_eq!(error(decode("hello")), TokenError::Malformed);
+        assert!(decode(&token[..token.len() - 3]).is_err());
+
+        // every single character changed is caught
+        let body = token.len() - TOKEN_PREFIX.len();
+        for i in 0..body{
+            let mut bytes = token.clone().into_bytes();
+            let at = TOKEN_PREFIX.len() + i;
+            bytes[at] = if bytes[at] == b'A' {b'B'} else {b'A'};
+            let tampered = String::from_utf8(bytes).unwrap();
+            // the last character may carry padding bits only
+            if i + 1 == body && decode(&tampered).is_ok(){
+                continue;
+            }
+            assert!(decode(&tampered).is_err(), "{}", tampered);
+        }
+    }
+
+    #[test]
+    fn test_continue_from(){
+        let mut white = MatchState::setup();
+        let first = white.present_state().valid_moves()[0];
+        white.apply_move(first);
+        let sent = encode(&white);
+
+        let black = decode(&sent).unwrap();
+        let mut replied = black.clone();
+        replied.apply_move(replied.present_state().valid_moves()[0]);
+        let reply = encode(&replied);
+
+        let continued = continue_from(&white, &reply, Player::White).unwrap();
+        assert_eq!(plies(&continued), plies(&replied));
+
+        // the token White sent itself, or pasted again
+        assert_eq!(error(continue_from(&white, &sent, Player::White)),
+            TokenError::Stale{token_plies : 1, game_plies : 1});
+        assert_eq!(error(continue_from(&continued, &reply, Player::White)),
+            TokenError::Stale{token_plies : 2, game_plies : 2});
+
+        // skipping a token
+        let mut later = replied.clone();
+        later.apply_move(later.present_state().valid_moves()[0]);
+        later.apply_move(later.present_state().valid_moves()[0]);
+        assert_eq!(error(continue_from(&white, &encode(&later), Player::White)),
+            TokenError::TooFarAhead{token_plies : 4, game_plies : 1});
+
+        // a move of one's own side
+        assert_eq!(error(continue_from(&MatchState::setup(), &sent, Player::White)),
+            TokenError::NotOpponentsMove);
+
+        // another game
+        let mut other = MatchState::setup();
+        other.apply_move(other.present_state().valid_moves()[1]);
+        other.apply_move(other.present_state().valid_moves()[0]);
+        assert_eq!(error(continue_from(&white, &encode(&other), Player::White)), TokenError::NotThisGame);
+    }
+}
@@ -0,0 +1,187 @@
+//! Differential tests between the two encodings of the movement rules:
+//! the shift tables behind `BitSet::generate_move_destinations` and the
+//! `Delta` offsets behind `neighbours_move`/`neighbours_attack`.
+//!
+//! The reference side below only uses `neighbours_*` and a plain
+//! tile-to-piece map, so it shares no code with the bitboards.
+
+use std::collections::{HashMap, HashSet};
+
+use ::rand::seq::SliceRandom;
+
+use super::*;
+
+type Board = HashMap<Tile, Piece>;
+
+fn to_board(position : &Position) -> Board{
+    [Player::White, Player::Black].into_iter().flat_map(|color|
+        position.get_pieces(color).clone().into_iter()
+        .map(move |(t,species)|(t, Piece{color, species}))
+    ).collect()
+}
+
+fn destinations(tile : Tile, piece : Piece) -> HashSet<Tile>{
+    neighbours_move(tile, piece).into_iter().flatten().collect()
+}
+
+fn reference_valid_moves(board : &Board, active : Player) -> HashSet<Ply>{
+    board.iter()
+    .filter(|(_,p)|p.color == active)
+    .flat_map(|(&from_tile, &piece)|
+        destinations(from_tile, piece).into_iter()
+        .filter(move |to_tile| match board.get(to_tile){
+            None => true,
+            Some(occupant) => (occupant.color == active)
+                && (occupant.species == Species::Flat)
+                && (piece.species != Species::Flat)
+        })
+        .map(move |to_tile|Ply{from_tile, to_tile})
+    ).collect()
+}
+
+fn reference_double_attacks(board : &Board, attacker : Player) -> HashSet<Tile>{
+    let mut counts : HashMap<Tile, usize> = HashMap::new();
+    board.iter()
+    .filter(|(_,p)|p.color == attacker)
+    .for_each(|(&t,&p)|
+        neighbours_attack(t, p).into_iter().flatten().for_each(|target|
+            *counts.entry(target).or_default() += 1
+        )
+    );
+    counts.into_iter().filter(|(_,c)|*c >= 2).map(|(t,_)|t).collect()
+}
+
+/// Plays `ply` on the tile map and returns the tiles captured.
+fn reference_apply(board : &mut Board, ply : Ply) -> HashSet<Tile>{
+    let moving = board.remove(&ply.from_tile).unwrap();
+    let color = moving.color;
+    let moved_species = match moving.species{
+        Species::Stack(tall) => {
+            board.insert(ply.from_tile, Piece{color, species : Species::Flat});
+            Species::Lone(tall)
+        },
+        species => species
+    };
+    let landed = match (board.get(&ply.to_tile), moved_species){
+        (Some(_), Species::Lone(tall)) => Species::Stack(tall),
+        (None, species) => species,
+        _ => unreachable!()
+    };
+    board.insert(ply.to_tile, Piece{color, species : landed});
+
+    let kills : HashSet<Tile> = reference_double_attacks(board, color).into_iter()
+        .filter(|t|board.get(t).is_some_and(|p|p.color != color))
+        .collect();
+    kills.iter().for_each(|t|{board.remove(t);});
+    kills
+}
+
+/// Board drawn row by row with piece letters; tiles only in `bitboard`
+/// are marked `+`, tiles only in `reference` are marked `-`.
+fn diagram(board : &Board, bitboard : &HashSet<Tile>, reference : &HashSet<Tile>) -> String{
+    let mut rows : Vec<Vec<Tile>> = vec![];
+    for t in Tile::ALL_TILES{
+        match rows.last_mut(){
+            Some(row) if row[0].x() == t.x() => row.push(t),
+            _ => rows.push(vec![t])
+        }
+    }
+
+    rows.into_iter().map(|mut row|{
+        row.sort_by_key(|t|-t.y());
+        let cells : Vec<String> = row.iter().map(|t|{
+            let glyph = board.get(t).map_or('.', |p|p.to_char());
+            let mark = match (bitboard.contains(t), reference.contains(t)){
+                (true, false) => '+',
+                (false, true) => '-',
+                _ => ' '
+            };
+            format!("{}{}", glyph, mark)
+        }).collect();
+        format!("{} {}{}", row[0].to_string().chars().next().unwrap(), " ".repeat(7 - row.len()), cells.join(" "))
+    }).collect::<Vec<String>>().join("\n")
+}
+
+fn check_tiles(what : &str, board : &Board, bitboard : HashSet<Tile>, reference : HashSet<Tile>){
+    assert!(bitboard == reference,
+        "{} mismatch (+ bitboard only, - reference only):\n{}",
+        what, diagram(board, &bitboard, &reference)
+    );
+}
+
+#[test]
+fn crosscheck_single_piece_destinations(){
+    for tile in Tile::ALL_TILES{
+        for color in [Player::White, Player::Black]{
+            for species in Species::ALL{
+                let piece = Piece{color, species};
+                let bitboard : HashSet<Tile> = BitSet::move_destinations_from_tile(tile, color, species).into_iter().collect();
+                let board = Board::from([(tile, piece)]);
+                check_tiles(&format!("{:?} on {}", piece, tile), &board, bitboard, destinations(tile, piece));
+            }
+        }
+    }
+}
+
+fn random_positions(count : usize) -> Vec<Position>{
+    const MAX_PLIES : usize = 120;
+    let mut rng = ::rand::thread_rng();
+    let mut positions = vec![];
+    while positions.len() < count{
+        let mut state = Position::setup();
+        for _ in 0..MAX_PLIES{
+            positions.push(state.clone());
+            let moves = state.valid_moves();
+            if state.is_won().is_some() || moves.is_empty(){
+                break;
+            }
+            state.apply_move(*moves.choose(&mut rng).unwrap());
+        }
+    }
+    positions.truncate(count);
+    positions
+}
+
+#[test]
+fn crosscheck_random_positions(){
+    for position in random_positions(3000){
+        let board = to_board(&position);
+        let active = position.to_play();
+
+        let bitboard_moves : HashSet<Ply> = position.valid_moves().into_iter().collect();
+        let reference_moves = reference_valid_moves(&board, active);
+        if bitboard_moves != reference_moves{
+            let destinations = |moves : &HashSet<Ply>|moves.iter().map(|m|m.to_tile).collect::<HashSet<Tile>>();
+            let differing : Vec<String> = bitboard_moves.symmetric_difference(&reference_moves).map(|m|m.to_string()).collect();
+            panic!("valid moves mismatch in {} ({}):\n{}",
+                position.to_position_string(), differing.join(" "),
+                diagram(&board, &destinations(&bitboard_moves), &destinations(&reference_moves))
+            );
+        }
+
+        for attacker in [Player::White, Player::Black]{
+            check_tiles(
+                &format!("double attacks by {:?} in {}", attacker, position.to_position_string()),
+                &board,
+                position.double_attack_map(attacker).into_iter().collect(),
+                reference_double_attacks(&board, attacker)
+            );
+        }
+
+        for ply in position.valid_moves(){
+            let mut after = position.clone();
+            after.stage_translate(ply);
+            let kills : HashSet<Tile> = after.stage_attack_scan(active).into_iter().map(|(t,_)|t).collect();
+
+            let mut reference_board = board.clone();
+            let reference_kills = reference_apply(&mut reference_board, ply);
+
+            check_tiles(
+                &format!("captures of {} in {}", ply, position.to_position_string()),
+                &reference_board, kills, reference_kills
+            );
+            assert_eq!(to_board(&after), reference_board,
+                "position after {} in {}", ply, position.to_position_string());
+        }
+    }
+}
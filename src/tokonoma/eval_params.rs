@@ -0,0 +1,321 @@
+//! Weights of the static evaluation.
+//!
+//! The evaluation is linear in the weights: `eval_features` gives the
+//! coefficient of each one, which is what the tuner fits.
+//!
+//! Parameter files hold one `name = value` line per weight, the passed flat
+//! tables as six values for distances 0 to 5. Missing weights keep their default;
+//! `#` starts a comment.
+
+use std::fmt::Display;
+use std::str::FromStr;
+
+use super::{Player, Position, Score, Species, Tall};
+
+const FLAT : usize = 0;
+const HAND : usize = 1;
+const BLIND : usize = 2;
+const STAR : usize = 3;
+const STACK_PENALTY : usize = 4;
+const MOBILITY : usize = 5;
+const PASSED_TO_PLAY : usize = 6;
+const PASSED_WAITING : usize = 12;
+const TEMPO : usize = 18;
+
+/// Passed flats further than this are not scored.
+const MAX_PASSED_DISTANCE : usize = 5;
+
+/// Finite evaluations are kept within this, whatever the weights.
+const MAX_EVAL : f32 = 400.0;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct EvalParams{
+    pub flat : f32,
+    pub hand : f32,
+    pub blind : f32,
+    pub star : f32,
+    /// Subtracted from the value of a flat and its tall piece when stacked.
+    pub stack_penalty : f32,
+    /// Per move available more than the opponent.
+    pub mobility : f32,
+    /// Bonus for the lone flat closest to the opponent's home, by distance,
+    /// when its owner is to play.
+    pub passed_to_play : [f32; 6],
+    /// The same when the opponent is to play.
+    pub passed_waiting : [f32; 6],
+    pub tempo : f32,
+}
+
+/// One weight's coefficient for a position, white minus black.
+pub type EvalFeatures = [f32; EvalParams::COUNT];
+
+impl EvalParams{
+    pub const COUNT : usize = 19;
+
+    pub const DEFAULT : EvalParams = EvalParams{
+        flat : 2.0,
+        hand : 2.0,
+        blind : 3.0,
+        star : 3.0,
+        stack_penalty : 0.2,
+        mobility : 0.1,
+        passed_to_play : [200.0, 200.0, 30.0, 10.0, 5.0, 1.0],
+        passed_waiting : [200.0, 100.0, 25.0, 7.0, 3.0, 0.5],
+        tempo : 0.0,
+    };
+
+    pub fn tall_value(&self, tall : Tall) -> f32{
+        match tall{
+            Tall::Hand => self.hand,
+            Tall::Blind => self.blind,
+            Tall::Star => self.star,
+        }
+    }
+
+    pub fn species_value(&self, species : Species) -> f32{
+        match species{
+            Species::Flat => self.flat,
+            Species::Lone(tall) => self.tall_value(tall),
+            Species::Stack(tall) => self.flat + self.tall_value(tall) - self.stack_penalty,
+        }
+    }
+
+    /// Weights in the order of `EvalFeatures`.
+    pub fn to_array(&self) -> [f32; Self::COUNT]{
+        let mut array = [0.0; Self::COUNT];
+        array[FLAT] = self.flat;
+        array[HAND] = self.hand;
+        array[BLIND] = self.blind;
+        array[STAR] = self.star;
+        array[STACK_PENALTY] = self.stack_penalty;
+        array[MOBILITY] = self.mobility;
+        array[PASSED_TO_PLAY..PASSED_TO_PLAY + 6].copy_from_slice(&self.passed_to_play);
+        array[PASSED_WAITING..PASSED_WAITING + 6].copy_from_slice(&self.passed_waiting);
+        array[TEMPO] = self.tempo;
+        array
+    }
+
+    pub fn from_array(array : &[f32; Self::COUNT]) -> Self{
+        EvalParams{
+            flat : array[FLAT],
+            hand : array[HAND],
+            blind : array[BLIND],
+            star : array[STAR],
+            stack_penalty : array[STACK_PENALTY],
+            mobility : array[MOBILITY],
+            passed_to_play : array[PASSED_TO_PLAY..PASSED_TO_PLAY + 6].try_into().unwrap(),
+            passed_waiting : array[PASSED_WAITING..PASSED_WAITING + 6].try_into().unwrap(),
+            tempo : array[TEMPO],
+        }
+    }
+
+    /// Name of each weight in the order of `EvalFeatures`.
+    pub fn names() -> [String; Self::COUNT]{
+        let mut names : [String; Self::COUNT] = Default::default();
+        for (index, name) in ["flat", "hand", "blind", "star", "stack_penalty", "mobility"].into_iter().enumerate(){
+            names[index] = name.to_owned();
+        }
+        for distance in 0..6{
+            names[PASSED_TO_PLAY + distance] = format!("passed_to_play[{}]", distance);
+            names[PASSED_WAITING + distance] = format!("passed_waiting[{}]", distance);
+        }
+        names[TEMPO] = "tempo".to_owned();
+        names
+    }
+
+    /// Evaluation of a position with these features.
+    pub fn evaluate(&self, features : &EvalFeatures) -> f32{
+        self.to_array().iter().zip(features).map(|(w, f)|w * f).sum::<f32>()
+            .clamp(-MAX_EVAL, MAX_EVAL)
+    }
+
+    pub fn load(path : impl AsRef<std::path::Path>) -> Result<EvalParams, String>{
+        let path = path.as_ref();
+        std::fs::read_to_string(path)
+            .map_err(|e|format!("{}: {}", path.display(), e))?
+            .parse()
+            .map_err(|e|format!("{}: {}", path.display(), e))
+    }
+}
+
+impl Default for EvalParams{
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl Display for EvalParams{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let table = |values : &[f32; 6]|values.map(|v|v.to_string()).join(" ");
+        writeln!(f, "flat = {}", self.flat)?;
+        writeln!(f, "hand = {}", self.hand)?;
+        writeln!(f, "blind = {}", self.blind)?;
+        writeln!(f, "star = {}", self.star)?;
+        writeln!(f, "stack_penalty = {}", self.stack_penalty)?;
+        writeln!(f, "mobility = {}", self.mobility)?;
+        writeln!(f, "passed_to_play = {}", table(&self.passed_to_play))?;
+        writeln!(f, "passed_waiting = {}", table(&self.passed_waiting))?;
+        writeln!(f, "tempo = {}", self.tempo)
+    }
+}
+
+impl FromStr for EvalParams{
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut params = EvalParams::DEFAULT;
+        for (index, line) in s.lines().enumerate(){
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty(){
+                continue;
+            }
+            let error = |message : &str|format!("line {}: {}", index + 1, message);
+            let (name, value) = line.split_once('=').ok_or_else(||error("expected name = value"))?;
+            let values = value.split_whitespace().map(|v|v.parse::<f32>())
+                .collect::<Result<Vec<f32>, _>>()
+                .map_err(|_|error("not a number"))?;
+
+            let table = |values : Vec<f32>|<[f32; 6]>::try_from(values).map_err(|_|error("expected 6 values"));
+            let single = |values : Vec<f32>|match values.as_slice(){
+                [value] => Ok(*value),
+                _ => Err(error("expected one value"))
+            };
+            match name.trim(){
+                "flat" => params.flat = single(values)?,
+                "hand" => params.hand = single(values)?,
+                "blind" => params.blind = single(values)?,
+                "star" => params.star = single(values)?,
+                "stack_penalty" => params.stack_penalty = single(values)?,
+                "mobility" => params.mobility = single(values)?,
+                "passed_to_play" => params.passed_to_play = table(values)?,
+                "passed_waiting" => params.passed_waiting = table(values)?,
+                "tempo" => params.tempo = single(values)?,
+                other => return Err(error(&format!("unknown parameter {}", other))),
+            }
+        }
+        Ok(params)
+    }
+}
+
+impl Position{
+    /// Coefficients of the evaluation weights,
+    /// or the score if the game is decided on the board.
+    pub fn eval_features(&self) -> Result<EvalFeatures, Score>{
+        if let Some(winner) = self.is_won_home(){
+            return Err(Score::win_now(winner));
+        }
+
+        let white_moves_count = self.mobility(Player::White);
+        if white_moves_count == 0{
+            return Err(Score::win_now(Player::Black));
+        }
+        let black_moves_count = self.mobility(Player::Black);
+        if black_moves_count == 0{
+            return Err(Score::win_now(Player::White));
+        }
+
+        let mut features = [0.0; EvalParams::COUNT];
+        features[MOBILITY] = white_moves_count as f32 - black_moves_count as f32;
+
+        for color in [Player::White, Player::Black]{
+            let sign = match color{
+                Player::White => 1.0,
+                Player::Black => -1.0
+            };
+
+            // pieces attacked twice are as good as lost
+            let double_attacked = self.double_attack_map(color.flip());
+            let masked_pieces = self.get_pieces(color).mask(!double_attacked);
+
+            for species in Species::ALL{
+                let instances = masked_pieces.locate_species(species);
+                if !instances.is_not_empty(){
+                    continue;
+                }
+                let count = sign * instances.count() as f32;
+                let tall_index = |tall|match tall{
+                    Tall::Hand => HAND,
+                    Tall::Blind => BLIND,
+                    Tall::Star => STAR,
+                };
+                match species{
+                    Species::Flat => features[FLAT] += count,
+                    Species::Lone(tall) => features[tall_index(tall)] += count,
+                    Species::Stack(tall) => {
+                        features[FLAT] += count;
+                        features[tall_index(tall)] += count;
+                        features[STACK_PENALTY] -= count;
+                    }
+                }
+            }
+
+            let distance = self.passed_flat_distance(color) as usize;
+            if distance <= MAX_PASSED_DISTANCE{
+                let table = if self.to_play == color {PASSED_TO_PLAY} else {PASSED_WAITING};
+                features[table + distance] += sign;
+            }
+        }
+
+        features[TEMPO] = match self.to_play{
+            Player::White => 1.0,
+            Player::Black => -1.0
+        };
+
+        Ok(features)
+    }
+
+    /// Static evaluation with the given weights.
+    pub fn eval_heuristic_with(&self, params : &EvalParams) -> Score{
+        match self.eval_features(){
+            Ok(features) => Score::finite(params.evaluate(&features)),
+            Err(score) => score
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use ::rand::seq::SliceRandom;
+
+    use super::*;
+
+    #[test]
+    fn test_params_file_roundtrip(){
+        let mut params = EvalParams::DEFAULT;
+        params.mobility = 0.125;
+        params.passed_waiting[3] = 6.5;
+        assert_eq!(params.to_string().parse::<EvalParams>(), Ok(params));
+        assert_eq!(EvalParams::from_array(&params.to_array()), params);
+
+        let partial : EvalParams = "# only this\nstar = 4\n\n".parse().unwrap();
+        assert_eq!(partial, EvalParams{star : 4.0, ..EvalParams::DEFAULT});
+
+        assert!("star = x".parse::<EvalParams>().is_err());
+        assert!("passed_to_play = 1 2 3".parse::<EvalParams>().is_err());
+        assert!("queen = 9".parse::<EvalParams>().is_err());
+        assert!("star 4".parse::<EvalParams>().is_err());
+    }
+
+    #[test]
+    fn test_eval_is_linear_in_params(){
+        let mut rng = ::rand::thread_rng();
+        let doubled = EvalParams::from_array(&EvalParams::DEFAULT.to_array().map(|w|w * 2.0));
+
+        let mut position = Position::setup();
+        for _ in 0..60{
+            if position.is_won().is_some(){
+                break;
+            }
+            if let Ok(features) = position.eval_features(){
+                let value = EvalParams::DEFAULT.evaluate(&features);
+                if value.abs() < MAX_EVAL / 2.0{
+                    assert!((doubled.evaluate(&features) - 2.0 * value).abs() < 1e-3);
+                }
+                assert_eq!(position.eval_heuristic(), Score::finite(value));
+            }
+            let ply = *position.valid_moves().choose(&mut rng).unwrap();
+            position.apply_move(ply);
+        }
+
+        assert_eq!(Species::Stack(Tall::Star).value(), EvalParams::DEFAULT.species_value(Species::Stack(Tall::Star)));
+    }
+}
@@ -1,20 +1,87 @@
 
-use std::{collections::HashMap, str::FromStr};
+use std::fmt::Display;
 
-use macroquad::color::Color;
-use lazy_static::lazy_static;
-use super::{Captured, HistoryEntry, PieceMap, Player, PlayerMap, Ply, Position, PositionString};
+use super::{Captured, HistoryEntry, OpeningClassificationError, OpeningName, OpeningTree, PieceMap, Player, PlayerMap, Ply, Position, PositionString};
 
+/// Rules that end a game in a draw. `None` disables a rule.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct DrawRules{
+    /// Draw when the same position (and side to play) occurs this many times.
+    pub repetitions : Option<usize>,
+    /// Draw after this many consecutive plies without a capture.
+    pub max_plies_without_capture : Option<usize>,
+}
+
+impl DrawRules{
+    pub const NONE : DrawRules = DrawRules{
+        repetitions : None,
+        max_plies_without_capture : None
+    };
+}
+
+impl Default for DrawRules{
+    fn default() -> Self {
+        DrawRules{
+            repetitions : Some(3),
+            max_plies_without_capture : Some(100)
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DrawReason{
+    Repetition,
+    NoCaptureLimit,
+    /// Never reached by `MatchState`, which knows nothing of the players.
+    Agreement,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GameOutcome{
+    WhiteWins,
+    BlackWins,
+    Draw(DrawReason),
+}
+
+impl GameOutcome{
+    pub fn won_by(winner : Player) -> GameOutcome{
+        match winner{
+            Player::White => GameOutcome::WhiteWins,
+            Player::Black => GameOutcome::BlackWins
+        }
+    }
+
+    pub fn winner(&self) -> Option<Player>{
+        match self{
+            GameOutcome::WhiteWins => Some(Player::White),
+            GameOutcome::BlackWins => Some(Player::Black),
+            GameOutcome::Draw(..) => None
+        }
+    }
+}
+
+impl Display for GameOutcome{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self{
+            GameOutcome::WhiteWins => write!(f,"White wins"),
+            GameOutcome::BlackWins => write!(f,"Black wins"),
+            GameOutcome::Draw(DrawReason::Repetition) => write!(f,"Draw by repetition"),
+            GameOutcome::Draw(DrawReason::NoCaptureLimit) => write!(f,"Draw, no captures"),
+            GameOutcome::Draw(DrawReason::Agreement) => write!(f,"Draw by agreement"),
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct MatchState{
     state : Position,
     valid_moves : Vec<Ply>,
-    is_won : Option<Player>,
+    outcome : Option<GameOutcome>,
+    draw_rules : DrawRules,
 
     history : Vec<HistoryEntry>,
 
-    half_openings : PlayerMap<
-        Result<Option<&'static HalfOpening>,HalfOpeningDetectionError>
-        >,
+    opening : Result<OpeningName, OpeningClassificationError>,
 
     beginning_pstring_cache : PositionString,
 }
@@ -32,35 +99,77 @@ impl MatchState{
         let mut match_state = MatchState{
             state,
             valid_moves,
-            is_won : None,
+            outcome : None,
+            draw_rules : DrawRules::default(),
             history : vec![],
-            half_openings : PlayerMap::twin(Err(HalfOpeningDetectionError::NotEnoughMoves)),
+            opening : Err(OpeningClassificationError::NotEnoughMoves),
             beginning_pstring_cache,
         };
         match_state.refresh();
         match_state
     }
 
+    pub fn with_draw_rules(mut self, draw_rules : DrawRules) -> MatchState{
+        self.draw_rules = draw_rules;
+        self.refresh();
+        self
+    }
+
+    pub fn draw_rules(&self) -> DrawRules{
+        self.draw_rules
+    }
+
     pub fn refresh(&mut self){
         self.valid_moves = self.state.valid_moves();
-        self.is_won = self.state.is_won();
+        self.outcome = self.compute_outcome();
+
+        self.opening = OpeningTree::installed().classify(self.beginning_state(), &self.history);
+
+    }
+
+    fn compute_outcome(&self) -> Option<GameOutcome>{
+        if let Some(winner) = self.state.is_won(){
+            return Some(GameOutcome::won_by(winner));
+        }
+
+        if let Some(limit) = self.draw_rules.max_plies_without_capture{
+            let quiet_plies = self.history.iter().rev()
+                .take_while(|entry|entry.kills.is_empty())
+                .count();
+            if quiet_plies >= limit{
+                return Some(GameOutcome::Draw(DrawReason::NoCaptureLimit));
+            }
+        }
 
-        for player in [Player::White,Player::Black]{
-            self.half_openings[player] = self.detect_half_opening(player);
+        if let Some(limit) = self.draw_rules.repetitions{
+            let hash = self.state.zobrist();
+            let occurrences = 1 + self.history.iter()
+                .filter(|entry|entry.state_before.zobrist() == hash)
+                .count();
+            if occurrences >= limit{
+                return Some(GameOutcome::Draw(DrawReason::Repetition));
+            }
         }
 
+        None
+    }
+
+    /// How the game ended, if it did.
+    pub fn outcome(&self) -> Option<GameOutcome>{
+        self.outcome
     }
 
     pub fn is_won(&self) -> Option<Player>{
-        self.is_won
+        self.outcome.and_then(|o|o.winner())
     }
 
-    pub fn half_opening(&self, player : Player) -> Result<Option<&'static HalfOpening>, HalfOpeningDetectionError>{
-        self.half_openings[player]
+    /// Name of the deepest known opening position reached, see `OpeningTree`.
+    pub fn opening(&self) -> Result<&OpeningName, OpeningClassificationError>{
+        self.opening.as_ref().map_err(|e|*e)
     }
 
     pub fn apply_move(&mut self, ply : Ply){
-        assert!(self.is_won.is_none());
+        assert!(self.outcome.is_none());
 
         let entry = self.state.compute_history_entry(ply, self.current_captured());
         self.history.push(entry);
@@ -88,39 +197,11 @@ impl MatchState{
         self.state.to_play()
     }
 
-    pub fn draw_position(&self, position : &Position, captures : &PlayerMap<Captured>, arrows_alpha : f32){
-        if arrows_alpha > 0.001{
-            position.draw_attacks(false, arrows_alpha);
-        }
-        
-        position.draw( false, false, false);
-        for (color, caps) in captures{
-            caps.draw(color);
-        }
-        
-    }
-
-    pub fn draw_present(&self, arrows_alpha : f32){
-        self.draw_position(&self.state, &self.current_captured(),  arrows_alpha);
-    }
-
-    pub fn draw_past(&self, index : usize,  arrows_alpha : f32) -> Result<(),()>{
-        if let Some(entry) = self.history.get(index){
-            entry.ply.from_tile.draw_highlight_fill(Color::from_hex(0x95eeee), false);
-            entry.ply.to_tile.draw_highlight_fill(Color::from_hex(0xa0ffff), false);
-            for (tile,_) in &entry.kills{
-                tile.draw_highlight_fill(Color::from_hex(0xddbbbb), false);
-            }
-            
-            
-            self.draw_position(&entry.state_after, &entry.captured_after,  arrows_alpha);
-            Ok(())
-        } else {
-            Err(())
-        }
+    /// The current position, as opposed to the ones stored in history.
+    pub fn present_state(&self) -> &Position{
+        &self.state
     }
 
-
     pub fn state_clone(&self) -> Position{
         self.state.clone()
     }
@@ -151,26 +232,6 @@ impl MatchState{
         }
     }
 
-    fn detect_half_opening(&self, player : Player) -> Result<Option<&'static HalfOpening>, HalfOpeningDetectionError>{
-        use HalfOpeningDetectionError as HODE;
-        use Player as P;
-        if *self.beginning_state() != Position::setup(){
-            return Err(HODE::NonStandardSetup)
-        }
-
-        let ply_index = match player{P::White => 2, P::Black=>3};
-        if self.history.len() <= ply_index{
-            Err(HODE::NotEnoughMoves)
-        } else {
-            let pos = self.history[ply_index].state_after.get_pieces(player);
-            let hop = match player{
-                P::White => HALF_OPENING_HASHMAP.get(pos),
-                P::Black => HALF_OPENING_HASHMAP_FLIPPED.get(pos),
-            }.map(|v|*v);
-            Ok(hop)
-        }
-    }
-
     pub fn position_string(&self, index : Option<usize>) -> Result<&PositionString,&'static str>{
         if let Some(hindex) = index{
             if let Some(entry) = self.history.get(hindex){
@@ -189,116 +250,55 @@ impl MatchState{
     }
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
-pub enum HalfOpeningDetectionError{
-    NonStandardSetup,
-    NotEnoughMoves,
-    
-}
-
-
-pub struct HalfOpening{
-    pub name : Option<&'static str>,
-    _white_moves : [Ply;2],
-    white_position : PieceMap,
-}
-
-impl HalfOpening{
-    fn new(name : Option<&'static str>, white_first_move : Ply, white_second_move : Ply)->Self{
-        let mut pos = Position::setup();
-        assert!(pos.valid_moves().contains(&white_first_move));
-        pos.apply_move(white_first_move);
-        pos.apply_move(*pos.valid_moves().first().unwrap());
-        assert!(pos.valid_moves().contains(&white_second_move));
-        pos.apply_move(white_second_move);
-
-        HalfOpening{
-            name, _white_moves : [white_first_move,white_second_move], white_position : pos.get_pieces(Player::White).clone()
+#[cfg(test)]
+mod tests{
+    use super::*;
+    /// Two plies per side that bring the setup back to itself without captures.
+    fn find_shuffle() -> [Ply;4]{
+        let start = Position::setup();
+        let back = |ply : Ply| Ply{from_tile : ply.to_tile, to_tile : ply.from_tile};
+        for white_ply in start.valid_moves(){
+            let mut after_white = start.clone();
+            after_white.apply_move(white_ply);
+            for black_ply in after_white.valid_moves(){
+                let shuffle = [white_ply, black_ply, back(white_ply), back(black_ply)];
+                let mut state = start.clone();
+                let legal = shuffle.iter().all(|&ply|{
+                    let legal = state.valid_moves().contains(&ply);
+                    if legal {state.apply_move(ply);}
+                    legal
+                });
+                if legal && state == start{
+                    return shuffle;
+                }
+            }
         }
+        panic!("no shuffle from the setup")
     }
 
-    fn named(name : &'static str, white_first_move : Ply, white_second_move : Ply) -> Self{
-        Self::new(Some(name),white_first_move,white_second_move)
-    }
-
-    #[allow(dead_code)]
-    fn anon(white_first_move : Ply, white_second_move : Ply)->Self{
-        Self::new(None,white_first_move,white_second_move)
-    }
+    #[test]
+    fn test_draw_rules(){
+        let shuffle = find_shuffle();
 
-    pub fn name(&self)->&str{
-        self.name.unwrap_or("[Anonymous]")
+        let mut match_state = MatchState::setup();
+        for ply in shuffle.iter().chain(shuffle.iter()){
+            assert_eq!(match_state.outcome(), None);
+            match_state.apply_move(*ply);
+        }
+        assert_eq!(match_state.outcome(), Some(GameOutcome::Draw(DrawReason::Repetition)));
+        assert_eq!(match_state.is_won(), None);
+
+        match_state.undo_moves(1);
+        assert_eq!(match_state.outcome(), None);
+
+        let mut match_state = MatchState::setup().with_draw_rules(DrawRules{
+            repetitions : None,
+            max_plies_without_capture : Some(6)
+        });
+        for ply in shuffle.iter().chain(shuffle.iter()).take(6){
+            assert_eq!(match_state.outcome(), None);
+            match_state.apply_move(*ply);
+        }
+        assert_eq!(match_state.outcome(), Some(GameOutcome::Draw(DrawReason::NoCaptureLimit)));
     }
 }
-
-
-lazy_static!{
-    static ref HALF_OPENINGS : Vec<HalfOpening> = {
-        let b_b5 = Ply::from_str("d6b5").unwrap();
-        let a_a3 = Ply::from_str("a5a3").unwrap();
-        let a_c5 = Ply::from_str("c7c5").unwrap();
-        let s_a4 = Ply::from_str("b6a4").unwrap();
-        let s_c5 = Ply::from_str("b6c5").unwrap();
-        let b_e4 = Ply::from_str("c6e4").unwrap();
-
-        let b_a4 = Ply::from_str("c6a4").unwrap();
-
-        vec![
-            HalfOpening::named("Devil", b_b5, a_a3),
-            HalfOpening::named("Magician", s_a4, a_c5),
-            HalfOpening::named("Hermit", s_c5,  a_a3),
-            HalfOpening::named("Chariot", a_c5, a_a3),
-            HalfOpening::named("Emperor", s_a4, a_a3),
-            HalfOpening::named("Hierophant", b_b5, a_c5),
-            HalfOpening::named("Sun", b_b5,s_a4),
-            HalfOpening::named("Moon",b_b5,s_c5),
-
-            HalfOpening::named("Fool",b_b5,b_e4),
-            HalfOpening::named("Hanged Man", s_a4,b_e4),
-
-            HalfOpening::named("Judgement", s_c5,b_e4),
-
-            HalfOpening::named("Lovers", b_b5,b_a4),
-
-            HalfOpening::named("Empress",a_c5,b_e4),
-            
-
-            HalfOpening::named("Seal", a_c5, Ply::from_str("c5d5").unwrap()),
-        ]
-    };
-
-    static ref HALF_OPENING_HASHMAP : HashMap<PieceMap, &'static HalfOpening> = {
-        let mut map = HashMap::new();
-        for ho in HALF_OPENINGS.iter(){
-            if map.insert(ho.white_position.clone(), ho).is_some(){
-                panic!("Duplicate half opening");
-            };
-        };
-        map
-    };
-
-    static ref HALF_OPENING_HASHMAP_FLIPPED : HashMap<PieceMap, &'static HalfOpening> = {
-        let mut map = HashMap::new();
-        for ho in HALF_OPENINGS.iter(){
-            if map.insert(ho.white_position.clone().flip(), ho).is_some(){
-                panic!("Duplicate half opening");
-            };
-        };
-        map
-    };
-}
-
-#[cfg(test)]
-mod tests{
-    use super::*;
-    use std::hint::black_box;
-    #[test]
-    fn test_half_openings(){
-        for ho in HALF_OPENINGS.iter(){
-            black_box(ho);
-        };
-        for (k,v) in HALF_OPENING_HASHMAP.iter(){
-            black_box((k,v));
-        };
-    }
-}
\ No newline at end of file
@@ -7,24 +7,60 @@ pub use bitboards::{BitSet,PieceMap};
 pub mod matches;
 pub use matches::*;
 
+pub mod zobrist;
+pub use zobrist::ZobristHash;
+
+pub mod transposition;
+pub use transposition::{Bound, TranspositionEntry, TranspositionalTable};
+
+pub mod eval_params;
+pub use eval_params::{EvalFeatures, EvalParams};
+
+pub mod search;
+pub use search::{SearchLimits, SearchOutcome, StopToken};
+use search::SearchGuard;
+
+mod perft;
+
+mod notation;
+pub use notation::MoveParseError;
+
+pub mod record;
+pub use record::{GameRecord, RecordError, Termination};
+
+pub mod tablebase;
+pub use tablebase::{Signature, Tablebase, TablebaseValue};
+
+pub mod opening_tree;
+pub use opening_tree::{OpeningClassificationError, OpeningName, OpeningTree};
+
+pub mod book;
+pub use book::{BookMove, OpeningBook};
+
+pub mod correspondence;
+pub use correspondence::TokenError;
+
+#[cfg(test)]
+mod crosscheck;
+
+#[cfg(feature = "render")]
+mod render;
+#[cfg(feature = "render")]
+pub use render::draw_attack_map;
+
 use core::f32;
 
 use std::collections::HashSet;
-use std::hash::{DefaultHasher, Hash, Hasher};
-use std::sync::{Arc, Mutex};
-use std::usize;
-use std::{collections::HashMap, fmt::Display};
-use std::collections::hash_map::Entry::{Occupied, Vacant};
-use macroquad::prelude::*;
+use std::sync::Arc;
+use std::fmt::Display;
+use std::str::FromStr;
 use ::rand::seq::SliceRandom;
 
-use crate::arrows;
-
 use lazy_static::lazy_static;
 
 
 
-#[derive(Copy,Clone, PartialEq, PartialOrd, Debug)]
+#[derive(Copy,Clone, PartialEq, Debug)]
 /// Evaluation score. Can be finite or win-in-N.
 /// Positive is for white, negative is for black.
 pub struct Score(f32);
@@ -48,6 +84,7 @@ impl Score{
         Score(val)
     }
 
+    #[allow(clippy::should_implement_trait)]
     pub fn add(self, shift : f32) -> Score{
         assert!(shift.abs() < Self::FINITE_THRESHOLD * 0.5);
         if self.is_finite(){
@@ -65,10 +102,32 @@ impl Score{
         }
     }
 
+    /// construct a score for a victory `plies` plies from now.
+    pub fn win_in(winner : Player, plies : u32) -> Score{
+        let value = Self::WIN_BASELINE - plies as f32;
+        assert!(value >= Self::FINITE_THRESHOLD);
+        match winner{
+            Player::Black => Score(-value),
+            Player::White => Score(value)
+        }
+    }
+
     fn is_finite(&self) -> bool{
         self.0.abs() < Self::FINITE_THRESHOLD
     }
 
+    /// The value for white, `None` for wins.
+    pub fn finite_value(&self) -> Option<f32>{
+        self.is_finite().then_some(self.0)
+    }
+
+    /// Winner and plies to the win, `None` for finite scores.
+    pub fn win_distance(&self) -> Option<(Player, u32)>{
+        if self.is_finite() {return None;}
+        let winner = if self.0 > 0.0 {Player::White} else {Player::Black};
+        Some((winner, self.moves()))
+    }
+
     fn sign_char(&self) -> char{
         if self.0 >= 0.0 {'+'} else {'-'}
     }
@@ -91,9 +150,15 @@ impl Score{
 
 impl Eq for Score{}
 
+impl PartialOrd for Score{
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
 impl Ord for Score{
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.partial_cmp(other).unwrap()
+        self.0.partial_cmp(&other.0).unwrap()
     }
 }
 
@@ -108,7 +173,7 @@ impl Display for Score{
 }
 
 
-#[derive(Clone, Copy,Debug)]
+#[derive(Clone,Debug)]
 /// Output of an engine node evaluation.
 pub struct EvalResult{
     /// Computed score
@@ -116,12 +181,15 @@ pub struct EvalResult{
     /// Total number of sub-nodes examined in the computation of the score
     /// Does not include pruned branches or cache hits.
     pub nodes : usize,
+    /// Principal variation: the expected line from the evaluated position.
+    /// For root moves it starts with the move itself.
+    pub pv : Vec<Ply>,
 }
 
 impl EvalResult{
     fn immediate(score : Score) -> EvalResult{
         EvalResult{
-            score, nodes : 1
+            score, nodes : 1, pv : vec![]
         }
     }
 }
@@ -130,10 +198,11 @@ impl EvalResult{
 
 #[derive(Clone,Debug, PartialEq, Eq, Hash)]
 /// A game position.
-/// It derives hash but it's not necessarily what we use for tabulation hashes
+/// It derives hash, but transposition tables key on `zobrist()` instead.
 pub struct Position{
     to_play : Player,
     pieces : PlayerMap<PieceMap>,
+    zobrist : ZobristHash,
 }
 
 impl Position{
@@ -147,120 +216,25 @@ impl Position{
         &mut self.pieces[color]
     }
 
-  
-
-    pub fn tabulation_hash(&self) -> u64{
-        let mut s = DefaultHasher::new();
-        self.get_pieces(Player::White).hash(&mut s);
-        self.get_pieces(Player::Black).hash(&mut s);
-        self.to_play.hash(&mut s);
-        s.finish()
+    fn new(to_play : Player, pieces : PlayerMap<PieceMap>) -> Position{
+        let mut position = Position{to_play, pieces, zobrist : ZobristHash::EMPTY};
+        position.zobrist = ZobristHash::compute(&position);
+        position
     }
 
-    pub fn setup()->Position{
-        STANDARD_SETUP.clone()
+    /// Zobrist hash, kept up to date by every mutation.
+    #[inline]
+    pub fn zobrist(&self) -> u64{
+        self.zobrist.value()
     }
 
-    pub fn draw_attacks(&self, flip_board : bool, alpha:f32){
-        for color in [Player::White,Player::Black]{
-            self.get_pieces(color).clone().into_iter().for_each(|(t,pt)|{
-                let p = Piece{color, species : pt};
-                neighbours_attack(t,p).into_iter()
-                .flatten()
-                .for_each(|target|{
-                    let origin : Vec2 = t.to_world(flip_board).into();
-                    
-                    let target_cent : Vec2 = target.to_world(flip_board).into();
-                    let dir = (target_cent-origin).normalize();
-
-                    let start = origin + dir * 0.6;
-                    let end = target_cent-dir * 0.6;
-
-
-                    let mut color = p.color.to_color();
-                    color.a = alpha;
-
-                    arrows::draw_arrow(
-                        start,// + orth_disp, 
-                        end,// + orth_disp, 
-                        color, 
-                        0.1, 0.2, 0.4,
-                    )
-                });
-            })
-        }
+    #[deprecated(note = "use `zobrist()`, which is maintained incrementally")]
+    pub fn tabulation_hash(&self) -> u64{
+        self.zobrist()
     }
 
-    pub fn draw(&self, 
-            flip_board : bool,
-            draw_attacks : bool,
-            draw_tile_numbers : bool,
-        ){
-        
-
-        if draw_attacks {
-            self.draw_attacks(flip_board,1.0)
-        }
-
-        for color in [Player::White,Player::Black]{
-            self.get_pieces(color).clone().into_iter().for_each(|(t,species)|{ 
-                let (x,y) = t.to_world(flip_board);
-                let piece = Piece{color, species};
-                piece.draw(x,y,  1.0);
-            });
-        }
-
-        
-        
-        // self.captured.iter().for_each(|(player,capts,)|{
-            
-        // });
-        
-
-        if draw_tile_numbers {
-            Tile::draw_tile_numbers( flip_board);
-        }
-
-        // draw_text_ex(
-        //     &format!("{} to play.", 
-        //     match self.to_play{
-        //         Player::Black => "Black",
-        //         Player::White => "White",
-        //     }),
-        //     -2.0*(BOARD_RADIUS as f32), -2.0*(BOARD_RADIUS as f32),
-        //     TextParams{
-        //         font, 
-        //         font_size : 32,
-        //         font_scale : 1.0/32.0,
-        //         color : BLACK,
-        //         ..Default::default()
-        //     }
-        // );
-
-        // let (font_size, font_scale, font_scale_aspect) = camera_font_scale(0.4);
-
-        // draw_text_ex(
-        //     &format!("{:?}", self.zobrist_hash),
-        //     -3.0,
-        //     5.0,
-        //     TextParams{font,font_scale,font_scale_aspect,font_size,
-        //         color : Color::from_rgba(0x11, 0x11, 0x11, 127),
-        //         ..Default::default()
-        //     }
-        // );
-        
-        // let (font_size, font_scale, font_scale_aspect) = camera_font_scale(0.4);
-
-        // draw_text_ex(
-        //     &format!("{:?}", self.max_white_flat_hor()),
-        //     0.0,
-        //     0.0,
-        //     TextParams{font,font_scale,font_scale_aspect,font_size,
-        //         color : Color::from_rgba(0x11, 0x11, 0x11, 127),
-        //         ..Default::default()
-        //     }
-        // );
-        
+    pub fn setup()->Position{
+        STANDARD_SETUP.clone()
     }
 
     pub fn valid_moves(&self) -> Vec<Ply>{
@@ -279,7 +253,7 @@ impl Position{
 
 
         active_pieces.clone().into_iter()
-        .map(|(from_tile,species)|{
+        .flat_map(|(from_tile,species)|{
             // let piece = Piece{color : active, species};
 
             BitSet::move_destinations_from_tile(from_tile, active, species)
@@ -292,44 +266,19 @@ impl Position{
             )
 
         })
-        .flatten().collect()
+        .collect()
     }
 
     #[inline]
     pub fn pull_moving_piece(&mut self, color : Player, from_tile : Tile) -> Species{
-        
         let pieces = self.get_pieces_mut(color);
-        
-        // let pulled = match pieces.entry(from_tile){
-        //     Occupied(mut entry) => {
-        //         let original = entry.get().clone();
-                
-
-        //         match original {
-        //             PieceType::Flat | PieceType::Lone(..) => {
-        //                 hash.toggle_piece(&from_tile,color, original);
-        //                 entry.remove()
-        //             },
-        //             PieceType::Stack(tall) => {
-        //                 hash.toggle_piece(&from_tile,color, original);
-        //                 let replacement = PieceType::Flat;
-        //                 hash.toggle_piece(&from_tile,color,replacement);
-        //                 entry.insert(replacement);
-
-        //                 PieceType::Lone(tall)
-        //             },
-        //         }
-        //     },
-        //     Vacant(..) => panic!() 
-        // };
-
-        // self.zobrist_hash = hash;
-
+        let original = pieces.get(from_tile).unwrap();
         let pulled = pieces.pull_moving_piece(from_tile);
-        // self.zobrist_hash.toggle_piece(&from_tile, color, original);
-        // if let Some(remainder) = remainder{
-        //     self.zobrist_hash.toggle_piece(&from_tile, color, remainder);
-        // }
+
+        self.zobrist.toggle_piece(&from_tile, color, original);
+        if let Species::Stack(..) = original{
+            self.zobrist.toggle_piece(&from_tile, color, Species::Flat);
+        }
 
         pulled
     }
@@ -338,38 +287,77 @@ impl Position{
     pub fn stage_attack_scan(&mut self, attacking : Player) -> PieceMap{
         let double_attacked_tiles = self.double_attack_map(attacking);
         let defending = attacking.flip();
-        self.get_pieces_mut(defending).kill(double_attacked_tiles)
+        let kills = self.get_pieces_mut(defending).kill(double_attacked_tiles);
+        kills.clone().into_iter().for_each(|(t,species)|
+            self.zobrist.toggle_piece(&t, defending, species)
+        );
+        kills
     }
 
     pub fn to_play(&self)->Player{
         self.to_play
     }
 
-    pub fn stage_translate(&mut self, ply : Ply){
+    /// Moves the piece without resolving captures.
+    /// Returns the species that was pulled from `ply.from_tile`.
+    pub fn stage_translate(&mut self, ply : Ply) -> Species{
         let active = self.to_play;
         let (from_tile,to_tile) = (ply.from_tile, ply.to_tile);
 
         
         let moving_piece = self.pull_moving_piece(active,from_tile);
 
-        self.get_pieces_mut(active).toss(to_tile, moving_piece);
+        let pieces = self.get_pieces_mut(active);
+        let underneath = pieces.get(to_tile);
+        pieces.toss(to_tile, moving_piece);
 
-    }
+        if let Some(underneath) = underneath{
+            self.zobrist.toggle_piece(&to_tile, active, underneath);
+        }
+        let landed = self.get_pieces(active).get(to_tile).unwrap();
+        self.zobrist.toggle_piece(&to_tile, active, landed);
 
-    
+        moving_piece
+    }
 
-    pub fn apply_move(&mut self, ply : Ply) -> MoveApplyReport{
-        self.stage_translate(ply);
+    /// Plays a move in place and returns what is needed to take it back
+    /// with `unmake_move`.
+    pub fn make_move(&mut self, ply : Ply) -> UndoInfo{
+        let zobrist = self.zobrist;
+        let moved = self.stage_translate(ply);
 
         let attacking_player = self.to_play;
+        let kills = self.stage_attack_scan(attacking_player);
 
-        let kills : PieceMap = self.stage_attack_scan(attacking_player);
-        let has_captured =  kills.is_not_empty();
+        self.flip_to_move();
+
+        UndoInfo{
+            ply, moved, kills, zobrist
+        }
+    }
 
+    /// Reverts the move recorded in `undo`.
+    /// Must be called on the position `make_move` left, in LIFO order.
+    pub fn unmake_move(&mut self, undo : UndoInfo){
         self.to_play = self.to_play.flip();
+        let active = self.to_play;
+        let (from_tile,to_tile) = (undo.ply.from_tile, undo.ply.to_tile);
+
+        self.pieces[active.flip()].revive(&undo.kills);
+
+        let pieces = self.get_pieces_mut(active);
+        let moved = pieces.pull_moving_piece(to_tile);
+        debug_assert_eq!(moved, undo.moved);
+        pieces.toss(from_tile, moved);
+
+        self.zobrist = undo.zobrist;
+    }
+
+    pub fn apply_move(&mut self, ply : Ply) -> MoveApplyReport{
+        let undo = self.make_move(ply);
 
         MoveApplyReport{
-            has_captured
+            has_captured : undo.has_captured()
         }
     }
 
@@ -380,19 +368,16 @@ impl Position{
 
         let moves = state_before.valid_moves();
 
-        let moved_piece = state_before.clone().pull_moving_piece(self.to_play,ply.from_tile);
-        
-        let mut state_simulate_kills = state_before.clone();
-        state_simulate_kills.stage_translate(ply);
-        let killmap = 
-            state_simulate_kills.stage_attack_scan(state_simulate_kills.to_play);
-            
-        let kills = killmap.clone().into_iter().collect();
+        let mut state_after = state_before.clone();
+        let undo = state_after.make_move(ply);
+        let moved_piece = undo.moved;
+
+        let kills = undo.kills.clone().into_iter().collect();
 
         let mut captured_after = captured_before;
 
         
-        captured_after[active].extend(killmap.into_iter().map(|(_,species)|species));
+        captured_after[active].extend(undo.kills.into_iter().map(|(_,species)|species));
 
         let disambiguate = match moves.iter().filter(|&av|{
             (av.to_tile == ply.to_tile) & 
@@ -405,9 +390,6 @@ impl Position{
             _ => true
         };
 
-        let mut state_after = state_before.clone();
-        state_after.apply_move(ply);
-
         let win = state_after.is_won();
 
         let pstring_after : PositionString = state_after.to_position_string();
@@ -430,9 +412,12 @@ impl Position{
     // }
 
     pub fn clear_tile(&mut self, location : &Tile){
-        // not zobrist hash safe!
-        let _ = self.pieces[Player::White].clear_tile(*location);
-        let _ = self.pieces[Player::Black].clear_tile(*location);
+        for color in [Player::White,Player::Black]{
+            if let Some(species) = self.get_pieces(color).get(*location){
+                self.zobrist.toggle_piece(location, color, species);
+            }
+            self.pieces[color].clear_tile(*location);
+        }
     }
 
     #[inline]
@@ -446,51 +431,62 @@ impl Position{
         double_attacks.get_doubles()
     }
 
-    pub async fn moves_with_score(self, depth : usize, mquad_frame_await : bool, transp : Option<Arc<Mutex<TranspositionalTable>>>) -> Vec<(Ply, EvalResult)>{
-        
+    /// Fixed-depth search of every root move, best first.
+    /// See `search` for iterative deepening with limits.
+    pub async fn moves_with_score(self, depth : usize, mquad_frame_await : bool, transp : Option<Arc<TranspositionalTable>>) -> Vec<(Ply, EvalResult)>{
+        let mut position = self;
         if depth == 0{
-            let mut depth0_moves : Vec<(Ply, EvalResult)> = self.valid_moves().into_iter()
-            .map(|m| (m,EvalResult{score:Score::EVEN, nodes: 0}))
-            .collect();
-            
-            depth0_moves.shuffle(&mut ::rand::thread_rng());
-
-            return depth0_moves;
+            return position.score_root_moves_depth0();
         }
 
-        let heuristic = self.eval_heuristic();
+        let transp_table = transp.unwrap_or_default();
+        transp_table.new_search();
+
+        let root_order = position.valid_moves();
+        position.score_root_moves(root_order, depth, mquad_frame_await, &transp_table, &mut SearchGuard::unlimited()).await
+    }
+
+    /// All moves with an even score, shuffled.
+    fn score_root_moves_depth0(&self) -> Vec<(Ply, EvalResult)>{
+        let mut depth0_moves : Vec<(Ply, EvalResult)> = self.valid_moves().into_iter()
+        .map(|m| (m,EvalResult{score:Score::EVEN, nodes: 0, pv: vec![m]}))
+        .collect();
+        
+        depth0_moves.shuffle(&mut ::rand::thread_rng());
+
+        depth0_moves
+    }
+
+    /// Scores `root_moves` at `depth`, best first.
+    /// If the guard aborts, the returned scores are meaningless.
+    async fn score_root_moves(&mut self,
+        root_moves : Vec<Ply>,
+        depth : usize, mquad_frame_await : bool,
+        transp_table : &TranspositionalTable,
+        guard : &mut SearchGuard
+    ) -> Vec<(Ply, EvalResult)>{
+        let heuristic = self.eval_heuristic_with(&guard.eval);
         if !heuristic.is_finite(){
             return vec![]
         }
 
         let mut scored_moves : Vec<(Ply, EvalResult)> = vec![];
-        // let mut nodes_accum = 0;
-
-        let transp_table = if let Some(transp) = transp{
-            transp
-        } else {
-            Arc::new(Mutex::new(TranspositionalTable::new()))
-        };
-
-        
 
-        for m in self.valid_moves(){
+        for m in root_moves{
             if mquad_frame_await{
-                next_frame().await;
+                yield_frame().await;
             }
 
-            let mut copy = self.clone();
-            copy.apply_move(m);
-            let evaluation = copy.eval(depth-1,transp_table.clone());
+            let undo = self.make_move(m);
+            let mut evaluation = self.eval(depth-1,transp_table, guard);
+            self.extend_pv_from_table(&mut evaluation.pv, depth-1, transp_table);
+            self.unmake_move(undo);
+            evaluation.pv.insert(0, m);
             scored_moves.push((m, evaluation));
-            // nodes_accum += evaluation.nodes;
-
-            // if depth > 3{
-            //     while nodes_accum >= NODES_PER_FRAME{
-            //         nodes_accum -= NODES_PER_FRAME;
-            //         next_frame().await
-            //     }
-            // }
+
+            if guard.aborted(){
+                return scored_moves;
+            }
         };
         
         let mut rng = ::rand::thread_rng();
@@ -505,8 +501,36 @@ impl Position{
     }
     
     #[inline]
-    fn eval(self, depth : usize, transp : Arc<Mutex<TranspositionalTable>>) -> EvalResult{
-        self.eval_alphabeta(depth, Score::win_now(Player::Black), Score::win_now(Player::White), transp, 0)
+    fn eval(&mut self, depth : usize, transp : &TranspositionalTable, guard : &mut SearchGuard) -> EvalResult{
+        self.eval_alphabeta(depth, Score::win_now(Player::Black), Score::win_now(Player::White), transp, 0, guard)
+    }
+
+    /// Lengthens a line that was cut short by a table hit by following
+    /// stored best moves, up to `max_len` plies.
+    fn extend_pv_from_table(&self, pv : &mut Vec<Ply>, max_len : usize, table : &TranspositionalTable){
+        let mut position = self.clone();
+        for &ply in pv.iter(){
+            position.apply_move(ply);
+        }
+
+        while pv.len() < max_len && position.is_won().is_none(){
+            let Some(ply) = table.probe(position.zobrist()).and_then(|e|e.best_move) else {break};
+            if !position.valid_moves().contains(&ply){
+                break;
+            }
+            position.apply_move(ply);
+            pv.push(ply);
+        }
+    }
+
+    /// Short notation of a line of play from this position, e.g. "Fb5* Ac5 Sa4".
+    pub fn line_notation(&self, line : &[Ply]) -> String{
+        let mut position = self.clone();
+        line.iter().map(|&ply|{
+            let entry = position.compute_history_entry(ply, PlayerMap::twin(Captured::empty()));
+            position.apply_move(ply);
+            entry.to_string()
+        }).collect::<Vec<String>>().join(" ")
     }
 
     fn is_won_home(&self) -> Option<Player>{
@@ -527,7 +551,7 @@ impl Position{
             return Some(winner)
         };
 
-        if self.valid_moves().len() == 0{
+        if self.valid_moves().is_empty(){
             return Some(self.to_play.flip())
         };
 
@@ -595,9 +619,8 @@ impl Position{
                 neighbours_move(tile, Piece{color : player.flip(), species:Species::Flat})
                 .iter().for_each(|n|{
                     if let Some(n) = n{
-                        match opponent_pieces.get(*n){
-                            None => {new_active.insert(*n);},
-                            _ => {}
+                        if opponent_pieces.get(*n).is_none(){
+                            new_active.insert(*n);
                         }
                     }
                 });
@@ -610,33 +633,6 @@ impl Position{
         
     }
 
-    #[inline]
-    fn passed_flat_score(&self, player : Player) -> f32{
-        let distance = self.passed_flat_distance(player);
-        if self.to_play == player {
-            match distance{
-                0 => 200.0,
-                1 => 200.0,
-                2 => 30.0,
-                3 => 10.0,
-                4 => 5.0,
-                5 => 1.0,
-                _ => 0.0
-            }
-        }
-        else {
-            match distance{
-                0 => 200.0,
-                1 => 100.0,
-                2 => 25.0,
-                3 => 7.0,
-                4 => 3.0,
-                5 => 0.5,
-                _ => 0.0
-            }
-        }
-    }
-
     /// number of available moves
     fn mobility(&self, color : Player) -> u32{
         self.get_pieces(color).clone().into_iter()
@@ -646,118 +642,89 @@ impl Position{
         ).sum()
     }
 
+    /// Static evaluation with the default weights.
     pub fn eval_heuristic(&self) -> Score{
-        if let Some(winner)  = self.is_won_home(){
-            return Score::win_now(winner);
-        }
-        
-        let white_moves_count = self.mobility(Player::White);
-        if white_moves_count == 0{
-            return Score::win_now(Player::Black)
-        }
-        let black_moves_count = self.mobility(Player::Black);
-        if black_moves_count == 0{
-            return Score::win_now(Player::White)
-        }
-
-        let mobility_score = 0.1 * (
-            white_moves_count as f32
-            - black_moves_count as f32
-        );
-
-        
-
-
-        let finite_score = [Player::White,Player::Black].into_iter()
-        .map(|color|{
-            let double_attacked = self.double_attack_map(color.flip());
-
-            let multiplier = match color{
-                Player::Black => -1f32,
-                Player::White => 1f32
-            };
-
-            let masked_pieces = self.get_pieces(color).mask(!double_attacked);
-
-            Species::ALL.into_iter().map(|species|{
-                let instances = masked_pieces.locate_species(species);
-                if !instances.is_not_empty(){
-                    return 0.0
-                }
-
-                let signed_piece_value = multiplier * species.value() * (instances.count() as f32);
-
-                signed_piece_value 
-
-
-            }).sum::<f32>() as f32
-
-
-        }).sum::<f32>() as f32;
-        
-        
-        
-
-
-        let passed_flat_score = self.passed_flat_score(Player::White) 
-            - self.passed_flat_score(Player::Black);
-
-        const TEMPO_BONUS : f32 = 0.0;
-        let tempo_bonus_score = match self.to_play {
-            Player::White => TEMPO_BONUS,
-            Player::Black => - TEMPO_BONUS
-        };
-
-        Score::finite(finite_score + mobility_score + passed_flat_score + tempo_bonus_score)
-    
+        self.eval_heuristic_with(&EvalParams::DEFAULT)
     }
 
     const MAX_QSEARCH_DEPTH : usize = 2;
 
-    fn eval_alphabeta(self, 
+    fn eval_alphabeta(&mut self, 
         depth : usize, 
-        alpha : Score, beta : Score, transp : Arc<Mutex<TranspositionalTable>>,
-        qsearch_depth : usize
-    
+        alpha : Score, beta : Score, transp : &TranspositionalTable,
+        qsearch_depth : usize,
+        guard : &mut SearchGuard
     ) -> EvalResult{
+        if guard.visit(){
+            return EvalResult::immediate(Score::EVEN);
+        }
+
         // const NODES_PER_FRAME : usize = 500;
         
-        if let Some(score) = transp.lock().unwrap().query(self.tabulation_hash(), depth){
-            return EvalResult{score, nodes : 1}
+        let mut alpha = alpha;
+        let mut beta = beta;
+
+        let hash = self.zobrist();
+        let tt_entry = transp.probe(hash);
+        if let Some(entry) = tt_entry{
+            if entry.depth as usize >= depth{
+                match entry.bound{
+                    Bound::Exact => return EvalResult::immediate(entry.score),
+                    Bound::Lower => alpha = alpha.max(entry.score),
+                    Bound::Upper => beta = beta.min(entry.score),
+                }
+                if alpha >= beta{
+                    return EvalResult::immediate(entry.score)
+                }
+            }
+        }
+
+        // solved endgames are exact at any depth, including the leaves
+        if let Some(value) = guard.probe_tablebase(self){
+            return EvalResult::immediate(value.to_score(self.to_play));
         }
 
-        let heuristic = self.eval_heuristic();
+        let heuristic = self.eval_heuristic_with(&guard.eval);
         if !heuristic.is_finite(){
             return EvalResult::immediate(heuristic);
         }
 
-        let mut alpha = alpha;
-        let mut beta = beta;
         match depth{
             0 => EvalResult::immediate(heuristic),
             _ => {
                 let unsorted_moves = self.valid_moves();
 
-                if unsorted_moves.len() == 0 {
+                if unsorted_moves.is_empty() {
                     EvalResult::immediate(Score::win_now(self.to_play.flip()))
                 } else {
-                    let sorted_moves = match depth{
+                    let mut sorted_moves : Vec<Ply> = match depth{
                         1 => unsorted_moves,
                         _ => {
                             let mut moves_heuristic : Vec<(Ply, Score)> = vec![];
                             for ply in self.valid_moves(){
-                                let mut hc = self.clone();
-                                hc.apply_move(ply);
-                                moves_heuristic.push((ply,hc.eval_alphabeta(depth-2,alpha,beta,transp.clone(),qsearch_depth).score))
+                                let undo = self.make_move(ply);
+                                moves_heuristic.push((ply,self.eval_alphabeta(depth-2,alpha,beta,transp,qsearch_depth,guard).score));
+                                self.unmake_move(undo);
                             };
                             match self.to_play{
-                                Player::White => moves_heuristic.sort_by(|(_,s1),(_,s2)| s1.partial_cmp(&s2).unwrap().reverse()),
-                                Player::Black => moves_heuristic.sort_by(|(_,s1),(_,s2)| s1.partial_cmp(&s2).unwrap()),
+                                Player::White => moves_heuristic.sort_by(|(_,s1),(_,s2)| s1.cmp(s2).reverse()),
+                                Player::Black => moves_heuristic.sort_by_key(|(_,s)| *s),
                             };
                             moves_heuristic.into_iter().map(|(ply,_)|ply).collect()
                         }
                     };
+
+                    // the stored best move goes first
+                    if let Some(tt_move) = tt_entry.and_then(|e|e.best_move){
+                        if let Some(i) = sorted_moves.iter().position(|&m|m == tt_move){
+                            sorted_moves[..=i].rotate_right(1);
+                        }
+                    }
+
+                    let (alpha_searched, beta_searched) = (alpha, beta);
                     let mut value = Score::win_now(self.to_play.flip());
+                    let mut best_move = None;
+                    let mut best_pv = vec![];
                     let mut nodes_count = 1;
 
                     let lmr_cutoff = match depth{
@@ -767,14 +734,13 @@ impl Position{
                         _ => 1
                     };
                     for (mindex,&m) in sorted_moves.iter().enumerate() {
-                        let mut copy = self.clone();
-                        let application_report = copy.apply_move(m);
+                        let undo = self.make_move(m);
 
                     
                         // quiescence
 
                         let nonquiescent = (qsearch_depth < Self::MAX_QSEARCH_DEPTH) 
-                            & application_report.has_captured;
+                            & undo.has_captured();
                         
                         let mut sub_depth = if nonquiescent{
                             depth
@@ -789,17 +755,22 @@ impl Position{
                             sub_depth -= 1;
                         }
 
-                        let sub_tabhash = copy.tabulation_hash();
-                        let sub_result = copy.eval_alphabeta(sub_depth, alpha, beta, transp.clone(), sub_qsearch_depth);
-                        transp.lock().unwrap().insert(sub_tabhash, sub_depth, sub_result.score);
+                        let sub_result = self.eval_alphabeta(sub_depth, alpha, beta, transp, sub_qsearch_depth, guard);
+                        self.unmake_move(undo);
 
                         let sub_score = sub_result.score.propagate();
                         nodes_count += sub_result.nodes;
 
-                        value = match self.to_play{
-                            Player::White => value.max(sub_score),
-                            Player::Black => value.min(sub_score),
+                        let improves = match self.to_play{
+                            Player::White => sub_score > value,
+                            Player::Black => sub_score < value,
                         };
+                        if improves || best_move.is_none(){
+                            value = sub_score;
+                            best_move = Some(m);
+                            best_pv = sub_result.pv;
+                            best_pv.insert(0, m);
+                        }
 
                         match self.to_play{
                             Player::White => {
@@ -814,10 +785,24 @@ impl Position{
 
                         
                     };
+
+                    if guard.aborted(){
+                        return EvalResult{score : value, nodes : nodes_count, pv : best_pv};
+                    }
+
+                    let bound = if value <= alpha_searched{
+                        Bound::Upper
+                    } else if value >= beta_searched{
+                        Bound::Lower
+                    } else {
+                        Bound::Exact
+                    };
+                    transp.store(hash, depth, bound, value, best_move);
                     
                     EvalResult{
                         score : value,
-                        nodes : nodes_count
+                        nodes : nodes_count,
+                        pv : best_pv
                     }
                 }
             }
@@ -829,10 +814,7 @@ impl Position{
     pub fn max_white_flat_hor(&self) -> Option<i8>{
         self.get_pieces(Player::White).clone().into_iter()
         .filter(|(_,species)|
-            match species{
-                Species::Flat | Species::Stack(..) => true,
-                _ => false
-            }
+            matches!(species, Species::Flat | Species::Stack(..))
         )
 
         .map(|(t,_)|t.x() + 2*t.y())
@@ -846,6 +828,7 @@ impl Position{
         self.clear_tile(location);
         if let Some(piece) = brush{
             self.get_pieces_mut(piece.color).set(*location, piece.species);
+            self.zobrist.toggle_piece(location, piece.color, piece.species);
         }
 
         
@@ -853,7 +836,7 @@ impl Position{
 
     pub fn flip_to_move(&mut self){
         self.to_play = self.to_play.flip();
-        
+        self.zobrist.toggle_to_play();
     }
 
 
@@ -872,7 +855,8 @@ impl Position{
         pieces : PlayerMap::new(
             PieceMap::EMPTY,
             PieceMap::EMPTY
-        )
+        ),
+        zobrist : ZobristHash::EMPTY,
     };
 
     pub fn to_position_string(&self) -> PositionString{
@@ -930,9 +914,9 @@ impl TryFrom<PositionString> for Position{
         let mut buffer = value.0;
         
         let mut position = Position::EMPTY_WHITE;
-        position.to_play = match buffer.pop().ok_or(E::Empty)?{
-            'W' => Player::White,
-            'D' => Player::Black,
+        match buffer.pop().ok_or(E::Empty)?{
+            'W' => {},
+            'D' => position.flip_to_move(),
             c => {return Err(E::WrongToMove(c))}
         };
 
@@ -964,13 +948,19 @@ impl TryFrom<PositionString> for Position{
 }
 
 
+impl FromStr for Position{
+    type Err = PositionStringParsingError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Position::try_from(PositionString(s.trim().to_owned()))
+    }
+}
 
 lazy_static! {
     static ref STANDARD_SETUP : Position = {
         let mut white_pieces = PieceMap::EMPTY;
         let mut black_pieces = PieceMap::EMPTY;
 
-        let sbr = BOARD_RADIUS as i8;
+        let sbr = BOARD_RADIUS;
 
 
         [
@@ -994,69 +984,39 @@ lazy_static! {
         
         let pieces = PlayerMap::new(white_pieces,black_pieces);
 
-        Position {  to_play: Player::White, pieces }
+        Position::new(Player::White, pieces)
     };
 }
 
-pub struct MoveApplyReport{
-    has_captured : bool
+/// Hands control back to the macroquad frame loop, so that long searches
+/// running in a coroutine don't freeze the window.
+/// Without the `render` feature there is no frame loop and this is a no-op.
+async fn yield_frame(){
+    #[cfg(feature = "render")]
+    macroquad::window::next_frame().await;
 }
 
-pub struct TranspositionalTable(HashMap<u64, (usize,Score)>);
-
-impl TranspositionalTable{
-    pub fn new()->Self{
-        Self(HashMap::new())
-    }
-
-    pub fn query(&self, hash : u64, min_depth : usize) -> Option<Score>{
-        if let Some((depth, score)) = self.0.get(&hash){
-            if *depth >= min_depth {
-                Some(*score)
-            } else {None}
-        } else {None}
-    }
-
-    pub fn insert(&mut self, hash : u64, depth : usize, score : Score){
-        match self.0.entry(hash){
-            Vacant(vacancy) => {vacancy.insert((depth,score));},
-
-            Occupied(mut entry) => {
-                let occupant = entry.get();
-                if depth > occupant.0 {
-                    entry.insert((depth,score));
-                }
-            }
-        }
-    }
+pub struct MoveApplyReport{
+    pub has_captured : bool
 }
 
-
-
-
-pub fn draw_attack_map(player : Player, attack_map : &HashMap<Tile, u8>, flip_board : bool){
-    attack_map.iter().for_each(|(&t,&a)|{
-        if a > 0{
-            let (cx,cy) = t.to_world(flip_board);
-
-            let x = cx + match player{
-                Player::White => -0.2,
-                Player::Black => 0.1
-            };
-            let y = cy + 0.5;
-
-            const RECT_SZ : f32 = 0.1;
-            const RECT_OUT : f32 = 0.13;
-            draw_rectangle(x-RECT_OUT, y-RECT_OUT, 2.0*RECT_OUT, 2.0*RECT_OUT, BLACK);
-            draw_rectangle(x-RECT_SZ, y-RECT_SZ, 2.0*RECT_SZ, 2.0*RECT_SZ, player.to_color());
-            
-            
-        }
-
-    });
+/// Everything `Position::unmake_move` needs to revert a move.
+#[derive(Clone, Debug)]
+pub struct UndoInfo{
+    pub ply : Ply,
+    /// Species pulled from the origin tile (a stack moves as its lone tall)
+    pub moved : Species,
+    /// Defender pieces removed by the attack scan
+    pub kills : PieceMap,
+    /// Hash of the position before the move
+    pub zobrist : ZobristHash,
 }
 
-
+impl UndoInfo{
+    pub fn has_captured(&self) -> bool{
+        self.kills.is_not_empty()
+    }
+}
 
 #[derive(Clone)]
 pub struct HistoryEntry{
@@ -1084,15 +1044,7 @@ impl Display for HistoryEntry{
         };
 
         write!(f,"{}{}{}{}",
-            match self.moved_piece{
-                Species::Flat => "F",
-                Species::Lone(tall) => match tall{
-                    Tall::Blind => "B",
-                    Tall::Hand => "A",
-                    Tall::Star => "S"
-                },
-                Species::Stack(..) => "?"
-            },
+            self.moved_piece.notation_letter(),
 
             move_rep,
 
@@ -1105,6 +1057,9 @@ impl Display for HistoryEntry{
 
 #[cfg(test)]
 mod tests{
+    use std::collections::HashMap;
+    use std::collections::hash_map::Entry::{Occupied, Vacant};
+
     use super::*;
     #[test]
     fn test_hash_and_pstring(){
@@ -1118,7 +1073,7 @@ mod tests{
             while let Some(&ply) = state.valid_moves().choose(&mut rng){
                 state.apply_move(ply);
 
-                let tabhash = state.tabulation_hash();
+                let tabhash = state.zobrist();
 
                 match already_seen.entry(tabhash){
                     Occupied(entry) => {
@@ -1145,4 +1100,91 @@ mod tests{
         }
 
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_zobrist_incremental(){
+        const MAX_PLIES : usize = 200;
+        let mut rng = ::rand::thread_rng();
+        for _game in 0..500{
+            let mut state = Position::setup();
+            assert_eq!(state.zobrist, ZobristHash::compute(&state));
+
+            for _ply_num in 0..MAX_PLIES{
+                if state.is_won().is_some(){
+                    break;
+                }
+                let ply = *state.valid_moves().choose(&mut rng).unwrap();
+
+                let mut staged = state.clone();
+                staged.stage_translate(ply);
+                assert_eq!(staged.zobrist, ZobristHash::compute(&staged), "after stage_translate {}", ply);
+                staged.stage_attack_scan(staged.to_play());
+                assert_eq!(staged.zobrist, ZobristHash::compute(&staged), "after stage_attack_scan {}", ply);
+
+                state.apply_move(ply);
+                assert_eq!(state.zobrist, ZobristHash::compute(&state));
+
+                let parsed = Position::try_from(state.to_position_string()).unwrap();
+                assert_eq!(parsed.zobrist(), state.zobrist());
+            }
+
+            let tile = *Tile::ALL_TILES.choose(&mut rng).unwrap();
+            let species = *Species::ALL.choose(&mut rng).unwrap();
+            state.paint(&tile, Some(Piece{color : Player::Black, species}));
+            assert_eq!(state.zobrist, ZobristHash::compute(&state));
+            state.paint(&tile, None);
+            assert_eq!(state.zobrist, ZobristHash::compute(&state));
+            state.flip_to_move();
+            assert_eq!(state.zobrist, ZobristHash::compute(&state));
+        }
+    }
+
+    #[test]
+    fn test_make_unmake(){
+        const MAX_PLIES : usize = 200;
+        let mut rng = ::rand::thread_rng();
+        for _game in 0..200{
+            let mut state = Position::setup();
+
+            for _ply_num in 0..MAX_PLIES{
+                if state.is_won().is_some(){
+                    break;
+                }
+                let before = state.clone();
+                for ply in before.valid_moves(){
+                    let mut applied = before.clone();
+                    applied.apply_move(ply);
+
+                    let undo = state.make_move(ply);
+                    assert_eq!(state, applied, "make_move {}", ply);
+                    state.unmake_move(undo);
+                    assert_eq!(state, before, "unmake_move {}", ply);
+                }
+
+                let ply = *state.valid_moves().choose(&mut rng).unwrap();
+                state.make_move(ply);
+            }
+        }
+    }
+
+    #[test]
+    fn test_principal_variation(){
+        let position = Position::setup();
+        const DEPTH : usize = 4;
+        let scored = futures::executor::block_on(position.clone().moves_with_score(DEPTH, false, None));
+
+        for (ply, eval) in scored{
+            assert_eq!(eval.pv.first(), Some(&ply));
+            assert!(eval.pv.len() <= DEPTH);
+
+            let mut state = position.clone();
+            for &pv_ply in &eval.pv{
+                assert!(state.valid_moves().contains(&pv_ply), "illegal pv move {} in {:?}", pv_ply, eval.pv);
+                state.apply_move(pv_ply);
+            }
+
+            let notation = position.line_notation(&eval.pv);
+            assert_eq!(notation.split(' ').count(), eval.pv.len());
+        }
+    }
+}
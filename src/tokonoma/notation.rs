@@ -0,0 +1,199 @@
+use std::fmt::Display;
+
+use super::{Position, Ply, Species, Tall, Tile, TileParseError};
+
+/// Why `Position::parse_move` rejected a move.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum MoveParseError{
+    Empty,
+    /// Only a destination, without a piece letter or an origin.
+    MissingPiece,
+    UnknownPiece(char),
+    BadTile(TileParseError),
+    /// Characters left over after the move and its markers.
+    TrailingCharacters(String),
+    /// The side to play has no such piece on the origin tile.
+    NoPieceAt(Tile),
+    /// No piece of the given kind can move there.
+    Illegal,
+    /// Several pieces of the given kind can move there, from these tiles.
+    Ambiguous(Vec<Tile>),
+    /// The number of `*` does not match the number of kills.
+    WrongCaptures{written : usize, actual : usize},
+    /// `#` was written but the move does not win.
+    NotAWin,
+}
+
+impl From<TileParseError> for MoveParseError{
+    fn from(value: TileParseError) -> Self {
+        MoveParseError::BadTile(value)
+    }
+}
+
+impl Display for MoveParseError{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self{
+            MoveParseError::Empty => write!(f, "empty move"),
+            MoveParseError::MissingPiece => write!(f, "missing piece letter or origin tile"),
+            MoveParseError::UnknownPiece(c) => write!(f, "unknown piece letter '{}'", c),
+            MoveParseError::BadTile(..) => write!(f, "bad tile"),
+            MoveParseError::TrailingCharacters(rest) => write!(f, "unexpected \"{}\"", rest),
+            MoveParseError::NoPieceAt(tile) => write!(f, "no such piece on {}", tile),
+            MoveParseError::Illegal => write!(f, "illegal move"),
+            MoveParseError::Ambiguous(from) => write!(f, "ambiguous, could come from {}",
+                from.iter().map(|t|t.to_string()).collect::<Vec<_>>().join(", ")),
+            MoveParseError::WrongCaptures { written, actual } =>
+                write!(f, "{} capture markers written, the move captures {}", written, actual),
+            MoveParseError::NotAWin => write!(f, "marked as a win, but does not win"),
+        }
+    }
+}
+
+impl Species{
+    /// Letter used in move notation. Stacks move their tall piece.
+    pub fn notation_letter(&self) -> char{
+        match self.to_lone(){
+            Species::Flat => 'F',
+            Species::Lone(Tall::Hand) => 'A',
+            Species::Lone(Tall::Blind) => 'B',
+            Species::Lone(Tall::Star) => 'S',
+            Species::Stack(..) => unreachable!()
+        }
+    }
+}
+
+impl Position{
+    /// Reads a move in history notation, e.g. `Fb5`, `Ad6b5*` or `Sc3#`.
+    ///
+    /// The piece letter may be left out when the origin is given.
+    /// Capture and win markers may be left out, but are checked when written.
+    pub fn parse_move(&self, notation : &str) -> Result<Ply, MoveParseError>{
+        use MoveParseError as E;
+
+        let notation = notation.trim();
+        let first = notation.chars().next().ok_or(E::Empty)?;
+
+        let (letter, rest) = if first.is_ascii_uppercase(){
+            if !"FABS".contains(first){
+                return Err(E::UnknownPiece(first));
+            }
+            (Some(first), &notation[1..])
+        } else {
+            (None, notation)
+        };
+
+        let tiles_len = rest.find(['*', '#']).unwrap_or(rest.len());
+        let (tiles, markers) = rest.split_at(tiles_len);
+        let (from, to) = match tiles.len(){
+            2 => (None, tiles.parse::<Tile>()?),
+            4 if tiles.is_char_boundary(2) => (Some(tiles[..2].parse::<Tile>()?), tiles[2..].parse::<Tile>()?),
+            _ if tiles.len() > 4 && tiles.is_char_boundary(4) => return Err(E::TrailingCharacters(tiles[4..].to_owned())),
+            _ => return Err(E::BadTile(TileParseError)),
+        };
+        if letter.is_none() && from.is_none(){
+            return Err(E::MissingPiece);
+        }
+
+        let written_kills = markers.chars().take_while(|&c|c == '*').count();
+        let written_win = markers[written_kills..].starts_with('#');
+        let leftover = &markers[written_kills + written_win as usize..];
+        if !leftover.is_empty(){
+            return Err(E::TrailingCharacters(leftover.to_owned()));
+        }
+
+        let own = self.get_pieces(self.to_play());
+        if let Some(from) = from{
+            match own.get(from){
+                Some(species) if letter.is_none_or(|l|l == species.notation_letter()) => {},
+                _ => return Err(E::NoPieceAt(from))
+            }
+        }
+
+        let candidates : Vec<Ply> = self.valid_moves().into_iter()
+            .filter(|m|m.to_tile == to)
+            .filter(|m|from.is_none_or(|f|f == m.from_tile))
+            .filter(|m|letter.is_none_or(|l|own.get(m.from_tile).unwrap().notation_letter() == l))
+            .collect();
+
+        let ply = match candidates.as_slice(){
+            [] => return Err(E::Illegal),
+            [ply] => *ply,
+            _ => return Err(E::Ambiguous(candidates.iter().map(|m|m.from_tile).collect()))
+        };
+
+        if written_kills > 0 || written_win{
+            let mut after = self.clone();
+            let undo = after.make_move(ply);
+            let actual = undo.kills.count() as usize;
+            if written_kills > 0 && written_kills != actual{
+                return Err(E::WrongCaptures{written : written_kills, actual});
+            }
+            if written_win && after.is_won().is_none(){
+                return Err(E::NotAWin);
+            }
+        }
+
+        Ok(ply)
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use ::rand::seq::SliceRandom;
+
+    use super::*;
+    use crate::tokonoma::{Captured, PlayerMap};
+
+    #[test]
+    fn test_parse_history_notation(){
+        let mut rng = ::rand::thread_rng();
+        for _ in 0..20{
+            let mut position = Position::setup();
+            while position.is_won().is_none(){
+                let moves = position.valid_moves();
+                for &ply in &moves{
+                    let entry = position.compute_history_entry(ply, PlayerMap::twin(Captured::empty()));
+                    let notation = entry.to_string();
+                    assert_eq!(position.parse_move(&notation), Ok(ply), "{}", notation);
+                    // the long form always works
+                    assert_eq!(position.parse_move(&ply.to_string()), Ok(ply));
+                }
+                position.apply_move(*moves.choose(&mut rng).unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_move_errors(){
+        use MoveParseError as E;
+        let position = Position::setup();
+
+        assert_eq!(position.parse_move(""), Err(E::Empty));
+        assert_eq!(position.parse_move("Xb5"), Err(E::UnknownPiece('X')));
+        assert_eq!(position.parse_move("Fz5"), Err(E::BadTile(TileParseError)));
+        assert_eq!(position.parse_move("Fa9"), Err(E::BadTile(TileParseError)));
+        assert_eq!(position.parse_move("b5"), Err(E::MissingPiece));
+        assert_eq!(position.parse_move("Bd6b5!"), Err(E::TrailingCharacters("!".to_owned())));
+
+        let ply = Ply{from_tile : "d6".parse().unwrap(), to_tile : "b5".parse().unwrap()};
+        assert_eq!(position.parse_move("Bb5"), Ok(ply));
+        assert_eq!(position.parse_move("Bd6b5"), Ok(ply));
+        assert_eq!(position.parse_move("Ad6b5"), Err(E::NoPieceAt(ply.from_tile)));
+        assert_eq!(position.parse_move("Bb5*"), Err(E::WrongCaptures{written : 1, actual : 0}));
+        assert_eq!(position.parse_move("Bb5#"), Err(E::NotAWin));
+        assert_eq!(position.parse_move("Fe1"), Err(E::Illegal));
+
+        // two pieces of a kind reaching the same tile
+        let ambiguous = position.valid_moves().into_iter().find_map(|m|{
+            let letter = position.get_pieces(position.to_play()).get(m.from_tile).unwrap().notation_letter();
+            let notation = format!("{}{}", letter, m.to_tile);
+            match position.parse_move(&notation){
+                Err(E::Ambiguous(from)) => Some((m, from)),
+                _ => None
+            }
+        });
+        if let Some((m, from)) = ambiguous{
+            assert!(from.contains(&m.from_tile) && from.len() > 1);
+        }
+    }
+}
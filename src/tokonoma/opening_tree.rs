@@ -0,0 +1,246 @@
+//! Opening names, from a data file (`openings.txt` by default).
+//!
+//! Names are keyed on positions rather than move orders, so transpositions
+//! are recognised. Half openings name one side's arrangement of pieces,
+//! whatever the opponent does; lines name whole positions, typically a
+//! sub-variation such as "Devil, Hermit defence".
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+
+use lazy_static::lazy_static;
+
+use super::{HistoryEntry, PieceMap, Player, PlayerMap, Position};
+
+/// Plies past the longest entry still searched for transpositions.
+const TRANSPOSITION_SLACK : usize = 4;
+
+/// Why a game has no opening name yet.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OpeningClassificationError{
+    NonStandardSetup,
+    NotEnoughMoves,
+}
+
+/// Deepest named node reached by a game.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct OpeningName{
+    /// E.g. "Devil v. Moon", "Twin Fool" or "Devil, Hermit defence".
+    pub name : String,
+    /// Plies played when it was reached; 0 for an irregular opening.
+    pub ply : usize,
+}
+
+impl Display for OpeningName{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct OpeningTree{
+    /// White's pieces; black's are flipped before lookup.
+    halves : HashMap<PieceMap, String>,
+    lines : HashMap<u64, String>,
+    /// Most plies of any entry.
+    depth : usize,
+}
+
+impl OpeningTree{
+    pub fn load(path : impl AsRef<std::path::Path>) -> Result<OpeningTree, String>{
+        let path = path.as_ref();
+        std::fs::read_to_string(path)
+            .map_err(|e|format!("{}: {}", path.display(), e))?
+            .parse()
+            .map_err(|e|format!("{}: {}", path.display(), e))
+    }
+
+    /// Half opening of `player`'s pieces.
+    fn half(&self, pieces : &PieceMap, player : Player) -> Option<&str>{
+        match player{
+            Player::White => self.halves.get(pieces),
+            Player::Black => self.halves.get(&pieces.clone().flip()),
+        }.map(|name|name.as_str())
+    }
+
+    /// Names the game from `setup` with these moves after the deepest position known,
+    /// within the first few plies.
+    pub fn classify(&self, setup : &Position, history : &[HistoryEntry]) -> Result<OpeningName, OpeningClassificationError>{
+        if *setup != Position::setup(){
+            return Err(OpeningClassificationError::NonStandardSetup);
+        }
+
+        let mut halves : PlayerMap<Option<(&str, usize)>> = PlayerMap::twin(None);
+        let mut line = None;
+        for (index, entry) in history.iter().enumerate().take(self.depth + TRANSPOSITION_SLACK){
+            let position = &entry.state_after;
+            let ply = index + 1;
+            if let Some(name) = self.lines.get(&position.zobrist()){
+                line = Some((name.as_str(), ply));
+            }
+            for player in [Player::White, Player::Black]{
+                // an arrangement is reached once, then kept while the other side moves
+                match self.half(position.get_pieces(player), player){
+                    Some(name) if halves[player].is_none_or(|(known, _)|known != name) => halves[player] = Some((name, ply)),
+                    _ => {}
+                }
+            }
+        }
+
+        let halves_ply = [Player::White, Player::Black].into_iter()
+            .filter_map(|p|halves[p].map(|(_, ply)|ply)).max();
+        if let Some((name, ply)) = line.filter(|(_, ply)|halves_ply.is_none_or(|h|*ply >= h)){
+            return Ok(OpeningName{name : name.to_owned(), ply});
+        }
+
+        // each side names its arrangement after its second move
+        const IRREGULAR : &str = "[Irregular]";
+        let plies = history.len();
+        let name = match [halves[Player::White], halves[Player::Black]]{
+            [None, None] if plies < 3 => return Err(OpeningClassificationError::NotEnoughMoves),
+            // white's arrangement is known from its second move, black's not yet
+            [white, None] if plies < 4 => format!("{} v. ...", white.map_or(IRREGULAR, |(n, _)|n)),
+            [None, None] => IRREGULAR.to_owned(),
+            [Some((white, _)), Some((black, _))] if white == black => format!("Twin {}", white),
+            [white, black] => format!("{} v. {}",
+                white.map_or(IRREGULAR, |(n, _)|n), black.map_or(IRREGULAR, |(n, _)|n)),
+        };
+        Ok(OpeningName{name, ply : halves_ply.unwrap_or(0)})
+    }
+
+    /// Makes this tree the one games are classified with.
+    pub fn install(tree : OpeningTree){
+        *INSTALLED.write().unwrap() = Arc::new(tree);
+    }
+
+    /// The installed tree, by default the bundled `openings.txt`.
+    pub fn installed() -> Arc<OpeningTree>{
+        INSTALLED.read().unwrap().clone()
+    }
+}
+
+/// `half <name>: <white moves>` and `line <name>: <moves>` entries, one per line,
+/// with `#` comments. Half openings are played against black's first legal moves.
+impl FromStr for OpeningTree{
+    type Err = String;
+    fn from_str(text : &str) -> Result<Self, Self::Err>{
+        let mut tree = OpeningTree::default();
+        for (index, line) in text.lines().enumerate(){
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty(){
+                continue;
+            }
+            let error = |message : String|format!("line {}: {}", index + 1, message);
+
+            let (kind, rest) = line.split_once(' ').ok_or_else(||error("expected half or line".to_owned()))?;
+            let (name, moves) = rest.split_once(':').ok_or_else(||error("expected <name>: <moves>".to_owned()))?;
+            let name = name.trim().to_owned();
+            let moves : Vec<&str> = moves.split_whitespace().collect();
+
+            let mut position = Position::setup();
+            let play = |position : &mut Position, notation : &str|{
+                let ply = position.parse_move(notation).map_err(|e|error(format!("move {}: {}", notation, e)))?;
+                position.apply_move(ply);
+                if position.is_won().is_some(){
+                    return Err(error(format!("the game is over after {}", notation)));
+                }
+                Ok(())
+            };
+            match kind{
+                "half" => {
+                    for (i, notation) in moves.iter().enumerate(){
+                        if i > 0{
+                            let reply = position.valid_moves()[0];
+                            position.apply_move(reply);
+                        }
+                        play(&mut position, notation)?;
+                    }
+                    tree.depth = tree.depth.max(2 * moves.len());
+                    if tree.halves.insert(position.get_pieces(Player::White).clone(), name).is_some(){
+                        return Err(error("same arrangement as an earlier half opening".to_owned()));
+                    }
+                },
+                "line" => {
+                    for notation in &moves{
+                        play(&mut position, notation)?;
+                    }
+                    tree.depth = tree.depth.max(moves.len());
+                    if tree.lines.insert(position.zobrist(), name).is_some(){
+                        return Err(error("same position as an earlier line".to_owned()));
+                    }
+                },
+                _ => return Err(error(format!("unknown entry kind {}", kind)))
+            }
+        }
+        Ok(tree)
+    }
+}
+
+lazy_static!{
+    static ref INSTALLED : RwLock<Arc<OpeningTree>> = RwLock::new(Arc::new(
+        include_str!("openings.txt").parse::<OpeningTree>().expect("bundled openings.txt")
+    ));
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::tokonoma::MatchState;
+
+    fn play(moves : &[&str]) -> MatchState{
+        let mut match_state = MatchState::setup();
+        for notation in moves{
+            let ply = match_state.present_state().parse_move(notation).unwrap_or_else(|e|panic!("{}: {}", notation, e));
+            match_state.apply_move(ply);
+        }
+        match_state
+    }
+
+    fn name(moves : &[&str]) -> Result<String, OpeningClassificationError>{
+        let match_state = play(moves);
+        OpeningTree::installed().classify(&Position::setup(), match_state.history()).map(|o|o.name)
+    }
+
+    #[test]
+    fn test_half_openings(){
+        assert_eq!(name(&[]), Err(OpeningClassificationError::NotEnoughMoves));
+        assert_eq!(name(&["Bb5", "Fa2", "Aa3"]).unwrap(), "Devil v. ...");
+        assert_eq!(name(&["Bb5", "Bd2", "Aa3", "Se2"]).unwrap(), "Devil v. Sun");
+        assert_eq!(name(&["Bb5", "Bd2", "Aa3", "Ae3"]).unwrap(), "Twin Devil");
+        assert_eq!(name(&["Sa4", "Bd2", "Aa3", "Ae3"]).unwrap(), "Emperor v. Devil");
+        assert_eq!(name(&["Sa4", "Fa2"]), Err(OpeningClassificationError::NotEnoughMoves));
+        assert_eq!(name(&["Sa4", "Fa2", "Sb6"]).unwrap(), "[Irregular] v. ...");
+        assert_eq!(name(&["Sa4", "Fa2", "Sb6", "Bd2"]).unwrap(), "[Irregular]");
+
+        // the same arrangement, moves in the other order
+        assert_eq!(name(&["Aa3", "Bd2", "Bb5", "Ae3"]).unwrap(), "Twin Devil");
+
+        let mut setup = Position::setup();
+        setup.apply_move(setup.valid_moves()[0]);
+        assert_eq!(OpeningTree::installed().classify(&setup, &[]), Err(OpeningClassificationError::NonStandardSetup));
+    }
+
+    #[test]
+    fn test_lines(){
+        let opening = OpeningTree::installed()
+            .classify(&Position::setup(), play(&["Bb5", "Sc3", "Aa3", "Ae3"]).history()).unwrap();
+        assert_eq!(opening, OpeningName{name : "Devil, Hermit defence".to_owned(), ply : 4});
+
+        // by transposition, and still named a few plies later
+        let later = play(&["Aa3", "Ae3", "Bb5", "Sc3", "Fe4", "Fa2"]);
+        let opening = OpeningTree::installed().classify(&Position::setup(), later.history()).unwrap();
+        assert_eq!(opening, OpeningName{name : "Devil, Hermit defence".to_owned(), ply : 4});
+    }
+
+    #[test]
+    fn test_tree_errors(){
+        assert!(str::parse::<OpeningTree>("half Devil Bb5").is_err());
+        assert!(str::parse::<OpeningTree>("half Devil: Zz9").is_err());
+        assert!(str::parse::<OpeningTree>("full Devil: Bb5").is_err());
+        assert!(str::parse::<OpeningTree>("half A: Bb5 Aa3\nhalf B: Aa3 Bb5").is_err());
+        let tree = str::parse::<OpeningTree>("# nothing but\n\nline Start: Bb5 # comment\n").unwrap();
+        assert_eq!(tree.lines.len(), 1);
+    }
+}
@@ -0,0 +1,51 @@
+use super::{Ply, Position};
+
+impl Position{
+    /// Number of leaf positions `depth` plies from here.
+    /// Won positions are terminal: they count as a leaf at depth 0
+    /// and have no children otherwise.
+    pub fn perft(&self, depth : usize) -> u64{
+        self.clone().perft_inplace(depth)
+    }
+
+    /// Perft of each move from here, one level shallower.
+    /// Sorted by move so that outputs can be diffed.
+    pub fn divide(&self, depth : usize) -> Vec<(Ply, u64)>{
+        assert!(depth > 0);
+        let mut position = self.clone();
+        if position.is_won().is_some(){
+            return vec![];
+        }
+
+        let mut counts : Vec<(Ply, u64)> = position.valid_moves().into_iter().map(|ply|{
+            let undo = position.make_move(ply);
+            let count = position.perft_inplace(depth-1);
+            position.unmake_move(undo);
+            (ply, count)
+        }).collect();
+
+        counts.sort_by_key(|(ply,_)|ply.to_string());
+        counts
+    }
+
+    fn perft_inplace(&mut self, depth : usize) -> u64{
+        if depth == 0{
+            return 1;
+        }
+        if self.is_won().is_some(){
+            return 0;
+        }
+
+        let moves = self.valid_moves();
+        if depth == 1{
+            return moves.len() as u64;
+        }
+
+        moves.into_iter().map(|ply|{
+            let undo = self.make_move(ply);
+            let count = self.perft_inplace(depth-1);
+            self.unmake_move(undo);
+            count
+        }).sum()
+    }
+}
@@ -0,0 +1,480 @@
+//! Text game records, modelled on PGN.
+//!
+//! ```text
+//! [White "Human"]
+//! [Black "Beastly-6"]
+//! [Date "2026.10.18"]
+//! [Setup "A3oS4MAB3MHB4Xf3HW"]
+//! [Result "0-1"]
+//! [Termination "home"]
+//!
+//! 1. Bb5 Bd2 2. Aa3 Ab2* ... 0-1
+//! ```
+//!
+//! Moves use the history notation. Every tag is optional:
+//! the setup defaults to the standard one and the draw rules to the default ones.
+
+use std::fmt::Display;
+use std::str::FromStr;
+
+use web_time::{SystemTime, UNIX_EPOCH};
+
+use super::{DrawReason, DrawRules, GameOutcome, MatchState, MoveParseError, Player, PlayerMap, Position, PositionString, PositionStringParsingError};
+
+/// Why a game ended.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Termination{
+    /// A flat reached the opponent's home corner.
+    Home,
+    /// The loser had no legal move.
+    Immobilized,
+    Repetition,
+    NoCaptureLimit,
+    /// The loser crashed, stalled or broke the rules; the moves do not show the result.
+    Forfeit,
+    Resignation,
+    /// A draw offered and accepted.
+    Agreement,
+    Unterminated,
+}
+
+impl Termination{
+    fn of(match_state : &MatchState) -> Termination{
+        match match_state.outcome(){
+            None => Termination::Unterminated,
+            Some(GameOutcome::Draw(DrawReason::Repetition)) => Termination::Repetition,
+            Some(GameOutcome::Draw(DrawReason::NoCaptureLimit)) => Termination::NoCaptureLimit,
+            Some(GameOutcome::Draw(DrawReason::Agreement)) => Termination::Agreement,
+            Some(_) => match match_state.present_state().is_won_home(){
+                Some(_) => Termination::Home,
+                None => Termination::Immobilized,
+            }
+        }
+    }
+
+    fn tag(&self) -> &'static str{
+        match self{
+            Termination::Home => "home",
+            Termination::Immobilized => "immobilized",
+            Termination::Repetition => "repetition",
+            Termination::NoCaptureLimit => "no capture",
+            Termination::Forfeit => "forfeit",
+            Termination::Resignation => "resignation",
+            Termination::Agreement => "agreement",
+            Termination::Unterminated => "unterminated",
+        }
+    }
+
+    fn from_tag(tag : &str) -> Option<Termination>{
+        [
+            Termination::Home, Termination::Immobilized,
+            Termination::Repetition, Termination::NoCaptureLimit,
+            Termination::Forfeit, Termination::Resignation,
+            Termination::Agreement, Termination::Unterminated,
+        ].into_iter().find(|t|t.tag() == tag)
+    }
+}
+
+impl Display for Termination{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.tag())
+    }
+}
+
+/// A game as written in a record.
+/// Moves are kept as text until `MatchState::from_record` replays them.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct GameRecord{
+    pub players : PlayerMap<String>,
+    pub date : String,
+    pub setup : String,
+    pub draw_rules : DrawRules,
+    pub outcome : Option<GameOutcome>,
+    pub termination : Termination,
+    pub moves : Vec<String>,
+    /// Unknown tags, kept in order.
+    pub other_tags : Vec<(String, String)>,
+}
+
+#[derive(Debug)]
+pub enum RecordError{
+    MalformedTag{line : usize},
+    BadTagValue{tag : String, value : String},
+    Setup(PositionStringParsingError),
+    /// `ply` counts from 0 from the setup, `label` is the move number as written.
+    Move{ply : usize, label : String, token : String, error : MoveParseError},
+    MoveAfterEnd{ply : usize, label : String, token : String},
+    ResultMismatch{recorded : Option<GameOutcome>, replayed : Option<GameOutcome>},
+}
+
+impl Display for RecordError{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self{
+            RecordError::MalformedTag { line } => write!(f, "malformed tag on line {}", line),
+            RecordError::BadTagValue { tag, value } => write!(f, "bad value for tag {}: \"{}\"", tag, value),
+            RecordError::Setup(e) => write!(f, "bad setup position: {:?}", e),
+            RecordError::Move { label, token, error, .. } => write!(f, "move {} {}: {}", label, token, error),
+            RecordError::MoveAfterEnd { label, token, .. } => write!(f, "move {} {} after the end of the game", label, token),
+            RecordError::ResultMismatch { recorded, replayed } => write!(f, "recorded result {} but the moves give {}",
+                result_token(*recorded), result_token(*replayed)),
+        }
+    }
+}
+
+fn result_token(outcome : Option<GameOutcome>) -> &'static str{
+    match outcome{
+        Some(GameOutcome::WhiteWins) => "1-0",
+        Some(GameOutcome::BlackWins) => "0-1",
+        Some(GameOutcome::Draw(..)) => "1/2-1/2",
+        None => "*",
+    }
+}
+
+const RESULT_TOKENS : [&str; 4] = ["1-0", "0-1", "1/2-1/2", "*"];
+
+/// Move number as written before the ply, e.g. `3.` or `3...`.
+fn move_label(ply : usize, first_to_play : Player) -> String{
+    let offset = match first_to_play {Player::White => 0, Player::Black => 1};
+    let number = (ply + offset) / 2 + 1;
+    if (ply + offset).is_multiple_of(2) {
+        format!("{}.", number)
+    } else {
+        format!("{}...", number)
+    }
+}
+
+fn limit_tag(limit : Option<usize>) -> String{
+    limit.map_or("-".to_owned(), |l|l.to_string())
+}
+
+/// Today as `yyyy.mm.dd`, UTC.
+fn today() -> String{
+    let days = SystemTime::now().duration_since(UNIX_EPOCH)
+        .map_or(0, |d|d.as_secs() / 86400) as i64;
+
+    // civil from days, proleptic Gregorian calendar
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 {mp + 3} else {mp - 9};
+    let year = yoe + era * 400 + (month <= 2) as i64;
+
+    format!("{:04}.{:02}.{:02}", year, month, day)
+}
+
+impl GameRecord{
+    fn first_to_play(&self) -> Player{
+        self.setup.parse::<Position>().map_or(Player::White, |p|p.to_play())
+    }
+
+    /// Every record of a file of records written one after the other.
+    /// A tag line following movetext starts a new record.
+    pub fn parse_all(text : &str) -> Vec<Result<GameRecord, RecordError>>{
+        let mut records = vec![];
+        let mut current = String::new();
+        let mut in_movetext = false;
+        for line in text.lines(){
+            let trimmed = line.trim();
+            if trimmed.starts_with('[') && in_movetext{
+                records.push(current.parse());
+                current.clear();
+                in_movetext = false;
+            }
+            in_movetext |= !trimmed.is_empty() && !trimmed.starts_with('[');
+            current.push_str(line);
+            current.push('\n');
+        }
+        if !current.trim().is_empty(){
+            records.push(current.parse());
+        }
+        records
+    }
+}
+
+impl Display for GameRecord{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let escape = |value : &str|value.replace('\\', "\\\\").replace('"', "\\\"");
+        let tags = [
+            ("White", self.players[Player::White].clone()),
+            ("Black", self.players[Player::Black].clone()),
+            ("Date", self.date.clone()),
+            ("Setup", self.setup.clone()),
+            ("Result", result_token(self.outcome).to_owned()),
+            ("Termination", self.termination.tag().to_owned()),
+            ("Repetitions", limit_tag(self.draw_rules.repetitions)),
+            ("NoCaptureLimit", limit_tag(self.draw_rules.max_plies_without_capture)),
+        ];
+        for (name, value) in tags.iter().map(|(n,v)|(*n, v.as_str()))
+            .chain(self.other_tags.iter().map(|(n,v)|(n.as_str(), v.as_str()))){
+            writeln!(f, "[{} \"{}\"]", name, escape(value))?;
+        }
+        writeln!(f)?;
+
+        const LINE_WIDTH : usize = 80;
+        let first_to_play = self.first_to_play();
+        let mut line = String::new();
+        for (ply, token) in self.moves.iter().enumerate(){
+            let label = move_label(ply, first_to_play);
+            let word = if ply == 0 || label.ends_with('.') && !label.ends_with("..."){
+                format!("{} {}", label, token)
+            } else {
+                token.clone()
+            };
+            if !line.is_empty() && line.len() + 1 + word.len() > LINE_WIDTH{
+                writeln!(f, "{}", line)?;
+                line.clear();
+            }
+            if !line.is_empty(){
+                line.push(' ');
+            }
+            line.push_str(&word);
+        }
+        if !line.is_empty(){
+            line.push(' ');
+        }
+        line.push_str(result_token(self.outcome));
+        writeln!(f, "{}", line)
+    }
+}
+
+impl FromStr for GameRecord{
+    type Err = RecordError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut record = GameRecord{
+            players : PlayerMap::twin("?".to_owned()),
+            date : "?".to_owned(),
+            setup : Position::setup().to_position_string().to_string(),
+            draw_rules : DrawRules::default(),
+            outcome : None,
+            termination : Termination::Unterminated,
+            moves : vec![],
+            other_tags : vec![],
+        };
+        let mut result_tag = None;
+        let mut termination = None;
+
+        let mut movetext = String::new();
+        for (index, line) in s.lines().enumerate(){
+            let line = line.trim();
+            if !line.starts_with('[') || !movetext.trim().is_empty(){
+                movetext.push_str(line);
+                movetext.push('\n');
+                continue;
+            }
+
+            let (name, value) = parse_tag(line).ok_or(RecordError::MalformedTag{line : index + 1})?;
+            let bad_value = ||RecordError::BadTagValue{tag : name.clone(), value : value.clone()};
+            let parse_limit = |value : &str|match value{
+                "-" => Ok(None),
+                _ => value.parse().map(Some).map_err(|_|bad_value())
+            };
+            match name.as_str(){
+                "White" => record.players[Player::White] = value,
+                "Black" => record.players[Player::Black] = value,
+                "Date" => record.date = value,
+                "Setup" => record.setup = value,
+                "Result" => result_tag = Some(
+                    RESULT_TOKENS.into_iter().find(|r|*r == value).ok_or_else(bad_value)?
+                ),
+                "Termination" => termination = Some(Termination::from_tag(&value).ok_or_else(bad_value)?),
+                "Repetitions" => record.draw_rules.repetitions = parse_limit(&value)?,
+                "NoCaptureLimit" => record.draw_rules.max_plies_without_capture = parse_limit(&value)?,
+                _ => record.other_tags.push((name, value)),
+            }
+        }
+
+        for token in movetext.split_whitespace(){
+            if let Some(result) = RESULT_TOKENS.into_iter().find(|r|*r == token){
+                result_tag = result_tag.or(Some(result));
+                break;
+            }
+            // move numbers, possibly glued to the move
+            let token = token.trim_start_matches(|c : char|c.is_ascii_digit() || c == '.');
+            if !token.is_empty(){
+                record.moves.push(token.to_owned());
+            }
+        }
+
+        let termination = termination.unwrap_or(Termination::Unterminated);
+        let bad_result = ||RecordError::BadTagValue{
+            tag : "Result".to_owned(),
+            value : result_tag.unwrap_or("*").to_owned()
+        };
+        record.outcome = match result_tag.unwrap_or("*"){
+            "1-0" => Some(GameOutcome::WhiteWins),
+            "0-1" => Some(GameOutcome::BlackWins),
+            "1/2-1/2" => Some(GameOutcome::Draw(match termination{
+                Termination::Repetition => DrawReason::Repetition,
+                Termination::NoCaptureLimit => DrawReason::NoCaptureLimit,
+                Termination::Agreement => DrawReason::Agreement,
+                _ => return Err(bad_result())
+            })),
+            _ => None
+        };
+        record.termination = termination;
+
+        Ok(record)
+    }
+}
+
+/// `[Name "value"]`, with `\"` and `\\` escapes.
+fn parse_tag(line : &str) -> Option<(String, String)>{
+    let inner = line.strip_prefix('[')?.strip_suffix(']')?;
+    let (name, quoted) = inner.split_once(' ')?;
+    let quoted = quoted.trim().strip_prefix('"')?.strip_suffix('"')?;
+
+    let mut value = String::new();
+    let mut chars = quoted.chars();
+    while let Some(c) = chars.next(){
+        match c{
+            '\\' => value.push(chars.next()?),
+            '"' => return None,
+            _ => value.push(c)
+        }
+    }
+    (!name.is_empty()).then(||(name.to_owned(), value))
+}
+
+impl MatchState{
+    /// Record of the game so far. Players are unknown (`?`) and the date is today;
+    /// the opening name, if any, is in an `Opening` tag.
+    pub fn to_record(&self) -> GameRecord{
+        let setup = match self.history().first(){
+            Some(entry) => entry.state_before.to_position_string(),
+            None => self.present_state().to_position_string(),
+        };
+
+        GameRecord{
+            players : PlayerMap::twin("?".to_owned()),
+            date : today(),
+            setup : setup.to_string(),
+            draw_rules : self.draw_rules(),
+            outcome : self.outcome(),
+            termination : Termination::of(self),
+            moves : self.history().iter().map(|entry|entry.to_string()).collect(),
+            other_tags : self.opening().map(|opening|("Opening".to_owned(), opening.name.clone())).into_iter().collect(),
+        }
+    }
+
+    /// Replays a record from its setup.
+    pub fn from_record(record : &GameRecord) -> Result<MatchState, RecordError>{
+        let setup = Position::try_from(PositionString(record.setup.trim().to_owned()))
+            .map_err(RecordError::Setup)?;
+        let first_to_play = setup.to_play();
+        let mut match_state = MatchState::setup_from(setup).with_draw_rules(record.draw_rules);
+
+        for (index, token) in record.moves.iter().enumerate(){
+            let label = move_label(index, first_to_play);
+            if match_state.outcome().is_some(){
+                return Err(RecordError::MoveAfterEnd{ply : index, label, token : token.clone()});
+            }
+
+            let ply = match_state.present_state().parse_move(token)
+                .map_err(|error|RecordError::Move{ply : index, label, token : token.clone(), error})?;
+            match_state.apply_move(ply);
+        }
+
+        if record.outcome.is_some() && match_state.outcome().is_some() && record.outcome != match_state.outcome(){
+            return Err(RecordError::ResultMismatch{recorded : record.outcome, replayed : match_state.outcome()});
+        }
+
+        Ok(match_state)
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use ::rand::seq::SliceRandom;
+
+    use super::*;
+
+    fn random_game(max_plies : usize) -> MatchState{
+        let mut rng = ::rand::thread_rng();
+        let mut match_state = MatchState::setup();
+        for _ in 0..max_plies{
+            if match_state.outcome().is_some(){
+                break;
+            }
+            let ply = *match_state.present_state().valid_moves().choose(&mut rng).unwrap();
+            match_state.apply_move(ply);
+        }
+        match_state
+    }
+
+    #[test]
+    fn test_record_roundtrip(){
+        for max_plies in [0, 1, 7, 40, 400]{
+            let match_state = random_game(max_plies);
+            let mut record = match_state.to_record();
+            record.players = PlayerMap::new("Human".to_owned(), "Beastly \"6\"".to_owned());
+            record.other_tags.push(("Event".to_owned(), "Test".to_owned()));
+
+            let text = record.to_string();
+            let parsed : GameRecord = text.parse().unwrap();
+            assert_eq!(parsed, record, "{}", text);
+
+            let replayed = MatchState::from_record(&parsed).unwrap();
+            assert_eq!(replayed.present_state(), match_state.present_state());
+            assert_eq!(replayed.outcome(), match_state.outcome());
+            assert_eq!(replayed.history().len(), match_state.history().len());
+        }
+    }
+
+    #[test]
+    fn test_record_from_black_setup(){
+        let mut setup = Position::setup();
+        setup.apply_move(setup.valid_moves()[0]);
+        let mut match_state = MatchState::setup_from(setup);
+        for _ in 0..3{
+            let ply = match_state.present_state().valid_moves()[0];
+            match_state.apply_move(ply);
+        }
+        let text = match_state.to_record().to_string();
+        assert!(text.contains("\n1... "), "{}", text);
+        assert!(text.contains(" 2. "), "{}", text);
+
+        let replayed = MatchState::from_record(&text.parse().unwrap()).unwrap();
+        assert_eq!(replayed.present_state(), match_state.present_state());
+    }
+
+    #[test]
+    fn test_parse_all(){
+        let records : Vec<GameRecord> = [0, 30, 400].map(|plies|random_game(plies).to_record()).into();
+        let text = records.iter().map(|r|format!("{}\n", r)).collect::<String>();
+        let parsed : Vec<GameRecord> = GameRecord::parse_all(&text).into_iter().map(|r|r.unwrap()).collect();
+        assert_eq!(parsed, records);
+
+        assert!(GameRecord::parse_all("\n\n").is_empty());
+    }
+
+    #[test]
+    fn test_record_errors(){
+        let match_state = random_game(10);
+        let record = match_state.to_record();
+
+        let mut illegal = record.clone();
+        illegal.moves[5] = "Sz0".to_owned();
+        match MatchState::from_record(&illegal){
+            Err(RecordError::Move { ply: 5, label, token, error : MoveParseError::BadTile(..) }) => {
+                assert_eq!(label, "3...");
+                assert_eq!(token, "Sz0");
+            },
+            other => panic!("{:?}", other.map(|_|()))
+        }
+
+        let mut marked = record.clone();
+        marked.moves[2].push('#');
+        assert!(matches!(MatchState::from_record(&marked), Err(RecordError::Move { ply: 2, error : MoveParseError::NotAWin, .. })));
+
+        assert!(matches!("[White \"x]\n".parse::<GameRecord>(), Err(RecordError::MalformedTag { line: 1 })));
+        assert!(matches!("[Result \"2-0\"]\n".parse::<GameRecord>(), Err(RecordError::BadTagValue { .. })));
+        assert!(matches!(
+            MatchState::from_record(&"[Setup \"zz\"]\n".parse().unwrap()),
+            Err(RecordError::Setup(..))
+        ));
+    }
+}
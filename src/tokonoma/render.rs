@@ -0,0 +1,391 @@
+//! Macroquad drawing for the rules types.
+//! Only compiled with the `render` feature, so that the rules core
+//! stays free of graphics dependencies.
+
+use std::collections::HashMap;
+
+use macroquad::prelude::*;
+
+use crate::{arrows::{self, draw_arrow}, assets::{get_assets_unchecked, get_pieceset_unchecked, CompositionMode}, theme::{get_board_palette, get_theme_config, BoardTilesModeConfig}};
+
+use super::{neighbours_attack, Captured, MatchState, Piece, Player, PlayerMap, Ply, Position, Species, Tall, Tile};
+
+
+impl Position{
+    pub fn draw_attacks(&self, flip_board : bool, alpha:f32){
+        for color in [Player::White,Player::Black]{
+            self.get_pieces(color).clone().into_iter().for_each(|(t,pt)|{
+                let p = Piece{color, species : pt};
+                neighbours_attack(t,p).into_iter()
+                .flatten()
+                .for_each(|target|{
+                    let origin : Vec2 = t.to_world(flip_board).into();
+
+                    let target_cent : Vec2 = target.to_world(flip_board).into();
+                    let dir = (target_cent-origin).normalize();
+
+                    let start = origin + dir * 0.6;
+                    let end = target_cent-dir * 0.6;
+
+
+                    let mut color = p.color.to_color();
+                    color.a = alpha;
+
+                    arrows::draw_arrow(
+                        start,// + orth_disp,
+                        end,// + orth_disp,
+                        color,
+                        0.1, 0.2, 0.4,
+                    )
+                });
+            })
+        }
+    }
+
+    pub fn draw(&self,
+            flip_board : bool,
+            draw_attacks : bool,
+            draw_tile_numbers : bool,
+        ){
+
+
+        if draw_attacks {
+            self.draw_attacks(flip_board,1.0)
+        }
+
+        for color in [Player::White,Player::Black]{
+            self.get_pieces(color).clone().into_iter().for_each(|(t,species)|{
+                let (x,y) = t.to_world(flip_board);
+                let piece = Piece{color, species};
+                piece.draw(x,y,  1.0);
+            });
+        }
+
+        if draw_tile_numbers {
+            Tile::draw_tile_numbers( flip_board);
+        }
+    }
+}
+
+
+pub fn draw_attack_map(player : Player, attack_map : &HashMap<Tile, u8>, flip_board : bool){
+    attack_map.iter().for_each(|(&t,&a)|{
+        if a > 0{
+            let (cx,cy) = t.to_world(flip_board);
+
+            let x = cx + match player{
+                Player::White => -0.2,
+                Player::Black => 0.1
+            };
+            let y = cy + 0.5;
+
+            const RECT_SZ : f32 = 0.1;
+            const RECT_OUT : f32 = 0.13;
+            draw_rectangle(x-RECT_OUT, y-RECT_OUT, 2.0*RECT_OUT, 2.0*RECT_OUT, BLACK);
+            draw_rectangle(x-RECT_SZ, y-RECT_SZ, 2.0*RECT_SZ, 2.0*RECT_SZ, player.to_color());
+
+
+        }
+
+    });
+}
+
+
+impl Tile{
+    pub fn draw_highlight_outline(&self, thickness : f32, color : Color, flip_board : bool){
+        let (x,y) = self.to_world(flip_board);
+        draw_hexagon(x, y, 1.0, thickness, true, color, Color::from_rgba(0, 0,0,0));
+    }
+
+    pub fn draw_highlight_fill(&self, color : Color, flip_board : bool){
+        let (x,y) = self.to_world(flip_board);
+        draw_hexagon(x, y, 1.0, 0.0, true,BLACK, color);
+    }
+
+    pub fn draw_move_target(&self, color : Player,  flip_board : bool){
+        let tex = get_pieceset_unchecked().tex;
+
+        let (x,y) = self.to_world(flip_board);
+        const R : f32 = 1.0;
+        let src_off = match color{
+            Player::White => 0.0,
+            Player::Black => 1.0
+        };
+
+        let tile_size = tex.width() * 0.25;
+
+        tex.draw(
+            x-R,
+            y-R,
+            WHITE, DrawTextureParams{
+                dest_size : Some(vec2(2.0*R,2.0*R)),
+                source : Some(Rect::new(0.0,tile_size*(1.0 + 2.0*src_off),tile_size,tile_size)),
+                ..Default::default()
+            }
+        )
+
+    }
+
+    fn tile_color(&self) -> Color{
+        get_board_palette().sample(self.mod3())
+    }
+
+    const fn is_border(&self) -> bool{
+        let (ux,uy) = (self.ux(),self.uy());
+
+        match ux{
+            0|4 => true,
+            1  => (uy == 6) | (uy == 1),
+            2 => (uy==6) | (uy == 0),
+            3 => (uy==5) | (uy == 0),
+            _ => unreachable!()
+        }
+    }
+
+    pub fn draw_board(flip_board : bool){
+        use BoardTilesModeConfig as BTMC;
+        let conf = get_theme_config();
+        let board_mode = conf.board_mode;
+
+        const DARK_TILE : Tile = Tile::from_xyz_unchecked(0, -1, 1);
+        const LIGHT_TILE : Tile = Tile::from_xyz_unchecked(0, 1, -1);
+        let dark_color = DARK_TILE.tile_color();
+        let light_color = LIGHT_TILE.tile_color();
+
+
+        if board_mode.tiles == BTMC::WithBorder{
+            Self::all_tiles().into_iter().filter(|t|t.is_border())
+            .for_each(|t|{
+                let (x,y) = t.to_world(flip_board);
+                draw_hexagon(x, y,
+                    1.1,
+                    0.0,//0.05,
+                    true,
+                    Color::from_hex(0x111111),
+                    dark_color);
+            });
+        }
+
+        match board_mode.tiles{
+            BTMC::Normal|BTMC::WithBorder|BTMC::Outline => {
+                Self::ALL_TILES.iter().for_each(|t|{
+                    let (x,y) = t.to_world(flip_board);
+
+
+                    let (fill,lthick) = match board_mode.tiles{
+                        BTMC::Outline => (Color::from_rgba(0,0,0,0),0.05),
+                        _ => (t.tile_color(),0.0)
+                    };
+
+                    draw_hexagon(x, y,
+                        1.0,
+                        lthick,
+                        true,
+                        Color::from_hex(0x222222),
+                        fill);
+                });
+
+                for player in [Player::Black,Player::White]{
+                    let (x,y) = Tile::corner(player).to_world(flip_board);
+
+
+                    let col = match player{
+                        Player::Black => dark_color,
+                        Player::White => light_color,
+                    };
+
+                    draw_hexagon(x, y,
+                        0.6,
+                        0.0,
+                        true,
+                        Color::from_hex(0x111111),
+                        col);
+                }
+            },
+            BTMC::None => {}
+        };
+
+
+
+        if board_mode.trigrid{
+            Self::ALL_TILES.iter().for_each(|t|{
+                let (x,y) = t.to_world(flip_board);
+                t.adjacent().into_iter().flatten()
+                .for_each(|a|{
+                    let (xe,ye) = a.to_world(flip_board);
+                    let (x2,y2) = (0.5*(x+xe),0.5*(y+ye));
+
+                    draw_line(x,y,x2,y2,0.05,Color::from_hex(0x222222));
+
+                });
+            });
+        }
+    }
+
+    pub fn draw_tile_numbers( flip_board : bool){
+        let font = get_assets_unchecked().font;
+        Self::ALL_TILES.iter().for_each(|t|{
+            let (x,y) = t.to_world(flip_board);
+            let (x,y) = (x,y+0.4);
+
+            let mut tcol = t.tile_color();
+            tcol.a = 0.8;
+            // draw_rectangle(tx-0.03, ty-0.4, 0.5, 0.5, tcol);
+            draw_circle(x, y, 0.3, tcol);
+
+            let text = &format!("{}",t);
+            let (font_size, font_scale, font_scale_aspect) = camera_font_scale(0.5);
+            let center = get_text_center(text, Some(font), font_size, font_scale, 0.0);
+            draw_text_ex(text,x-center.x,y-center.y, TextParams{
+                font,
+                font_size, font_scale, font_scale_aspect,
+                color : Color::from_rgba(0x11, 0x11, 0x11, 160),
+                ..Default::default()
+            });
+
+        });
+    }
+}
+
+
+impl Player{
+    pub fn to_color(&self) -> Color{
+        match self{
+            Player::Black => Color::from_hex(0x000000),//Color::from_hex(0x8ec8fd),
+            Player::White => Color::from_hex(0xffffff),
+        }
+    }
+
+    pub fn ui_info_pos(&self) -> Vec2 {
+        vec2(5.5,4.0) * match self{
+            Player::White => 1.0,
+            Player::Black => -1.0,
+        }
+    }
+}
+
+
+impl Piece{
+    pub fn draw(&self, x : f32, y: f32,  scale: f32){
+
+        let pieceset = get_pieceset_unchecked();
+
+        if let (CompositionMode::ComposeOnFlat, Species::Stack(tall)) = (&pieceset.composition_mode, self.species){
+            Piece{color:self.color, species : Species::Flat}.draw(x, y, scale);
+            Piece{color:self.color, species : Species::Lone(tall)}.draw(x, y, scale);
+            return;
+        }
+
+        let sx_single = match self.species{
+            Species::Flat => 0,
+            Species::Lone(tall) | Species::Stack(tall) => match tall{
+                Tall::Hand => 1,
+                Tall::Star => 2,
+                Tall::Blind => 3
+            }
+        };
+
+        let sx = sx_single;
+
+        let sy = match self.color{
+            Player::Black => 2,
+            Player::White => 0
+        } + match self.species{
+            Species::Stack(..) => 1,
+            _ => 0
+        };
+
+
+
+        let tex = pieceset.tex;
+
+        let tile_size = tex.width() * 0.25;
+        let world_size = pieceset.base_scale * scale;
+
+        let sx = sx as f32;
+        let sy = sy as f32;
+
+
+        tex.draw(
+            x - world_size * 0.5, y - world_size * 0.5,
+                WHITE, DrawTextureParams{
+            dest_size : Some(vec2(1.0, 1.0) * world_size),
+            source : Some(Rect{x:sx*tile_size,y: sy*tile_size,w:tile_size,h:tile_size}),
+            ..Default::default()
+            });
+
+
+    }
+}
+
+
+impl Ply{
+    pub fn draw(&self, flip_board : bool){
+        let (from_tile,to_tile) = (self.from_tile,self.to_tile) ;
+
+        draw_arrow(
+            from_tile.to_world(flip_board).into(),
+                to_tile.to_world(flip_board).into(),
+                Color::new(1.0, 1.0, 0.0, 0.5),
+                0.3,
+                0.7,
+                0.8
+            );
+
+    }
+}
+
+
+impl Captured{
+    pub fn draw(&self, color : Player){
+        let capts = self;
+        let n_capt = 0.5*(capts.count().saturating_sub(1) as f32);
+        let basey = match color {Player::White => 4.7, Player::Black => -4.7};
+
+        capts.iter().enumerate().for_each(|(i,piece_type)|{
+            let p = Piece{color : color.flip(), species : piece_type};
+            let x = 0.6*(i as f32 - n_capt);
+            let y = basey;
+            p.draw(x,y, 0.5);
+
+
+        });
+    }
+}
+
+
+impl MatchState{
+    pub fn draw_position(&self, position : &Position, captures : &PlayerMap<Captured>, arrows_alpha : f32){
+        if arrows_alpha > 0.001{
+            position.draw_attacks(false, arrows_alpha);
+        }
+
+        position.draw( false, false, false);
+        for (color, caps) in captures{
+            caps.draw(color);
+        }
+
+    }
+
+    pub fn draw_present(&self, arrows_alpha : f32){
+        self.draw_position(self.present_state(), &self.current_captured(),  arrows_alpha);
+    }
+
+    /// Draws the position after the history entry at `index`.
+    /// Returns false if there is no such entry.
+    pub fn draw_past(&self, index : usize,  arrows_alpha : f32) -> bool{
+        if let Some(entry) = self.history().get(index){
+            entry.ply.from_tile.draw_highlight_fill(Color::from_hex(0x95eeee), false);
+            entry.ply.to_tile.draw_highlight_fill(Color::from_hex(0xa0ffff), false);
+            for (tile,_) in &entry.kills{
+                tile.draw_highlight_fill(Color::from_hex(0xddbbbb), false);
+            }
+
+
+            self.draw_position(&entry.state_after, &entry.captured_after,  arrows_alpha);
+            true
+        } else {
+            false
+        }
+    }
+}
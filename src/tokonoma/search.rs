@@ -0,0 +1,352 @@
+use std::sync::{atomic::{AtomicBool, Ordering}, Arc};
+
+use web_time::{Duration, Instant};
+
+#[cfg(not(target_arch = "wasm32"))]
+use ::rand::seq::SliceRandom;
+
+use super::{EvalParams, EvalResult, Player, Ply, Position, Tablebase, TablebaseValue, TranspositionalTable};
+
+/// When a search should give up, and how many threads it may use.
+/// Depth 1 is always completed, whatever the node and time limits say.
+#[derive(Clone, Debug)]
+pub struct SearchLimits{
+    pub max_depth : usize,
+    pub max_nodes : Option<usize>,
+    pub deadline : Option<Instant>,
+    /// Total searcher threads, including the caller's.
+    /// Ignored on wasm, which always searches on the calling thread.
+    pub threads : usize,
+    /// Weights of the static evaluation.
+    pub eval : EvalParams,
+    /// Endgame tables consulted at every node, see `Tablebase`.
+    pub tablebase : Option<Arc<Tablebase>>,
+}
+
+impl SearchLimits{
+    /// Fixed depth, no node or time limit, single-threaded.
+    pub fn depth(max_depth : usize) -> Self{
+        SearchLimits{
+            max_depth,
+            max_nodes : None,
+            deadline : None,
+            threads : 1,
+            eval : EvalParams::DEFAULT,
+            tablebase : None,
+        }
+    }
+
+    pub fn with_threads(self, threads : usize) -> Self{
+        SearchLimits{threads : threads.max(1), ..self}
+    }
+
+    /// Number of hardware threads, 1 on wasm.
+    pub fn available_threads() -> usize{
+        #[cfg(not(target_arch = "wasm32"))]
+        return std::thread::available_parallelism().map_or(1, |n|n.get());
+        #[cfg(target_arch = "wasm32")]
+        return 1;
+    }
+
+    pub fn with_nodes(self, max_nodes : usize) -> Self{
+        SearchLimits{max_nodes : Some(max_nodes), ..self}
+    }
+
+    pub fn with_deadline(self, deadline : Instant) -> Self{
+        SearchLimits{deadline : Some(deadline), ..self}
+    }
+
+    pub fn with_eval(self, eval : EvalParams) -> Self{
+        SearchLimits{eval, ..self}
+    }
+
+    pub fn with_tablebase(self, tablebase : Option<Arc<Tablebase>>) -> Self{
+        SearchLimits{tablebase, ..self}
+    }
+
+    /// Deadline `budget` from now.
+    pub fn with_time(self, budget : Duration) -> Self{
+        self.with_deadline(Instant::now() + budget)
+    }
+}
+
+/// Shareable flag to cancel a running search from elsewhere.
+/// Clones refer to the same flag.
+#[derive(Clone, Default, Debug)]
+pub struct StopToken(Arc<AtomicBool>);
+
+impl StopToken{
+    pub fn new() -> Self{
+        Self::default()
+    }
+
+    pub fn stop(&self){
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_stopped(&self) -> bool{
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Per-search node counter and abort latch, checked at every node.
+pub(crate) struct SearchGuard{
+    stop : Option<StopToken>,
+    max_nodes : Option<usize>,
+    deadline : Option<Instant>,
+    /// Node and time limits are ignored while false.
+    enforce_limits : bool,
+    nodes : usize,
+    aborted : bool,
+    pub(crate) eval : EvalParams,
+    tablebase : Option<Arc<Tablebase>>,
+    /// Pieces in the largest table, to skip the lookup for bigger positions.
+    tablebase_units : u32,
+}
+
+impl SearchGuard{
+    /// How often the clock is read.
+    const CLOCK_INTERVAL : usize = 1024;
+
+    pub(crate) fn unlimited() -> Self{
+        SearchGuard{
+            stop : None,
+            max_nodes : None,
+            deadline : None,
+            enforce_limits : false,
+            nodes : 0,
+            aborted : false,
+            eval : EvalParams::DEFAULT,
+            tablebase : None,
+            tablebase_units : 0,
+        }
+    }
+
+    fn new(limits : &SearchLimits, stop : StopToken) -> Self{
+        SearchGuard{
+            stop : Some(stop),
+            max_nodes : limits.max_nodes,
+            deadline : limits.deadline,
+            eval : limits.eval,
+            tablebase : limits.tablebase.clone(),
+            tablebase_units : limits.tablebase.as_ref().map_or(0, |tablebase|tablebase.max_units()),
+            ..Self::unlimited()
+        }
+    }
+
+    /// The value of `position` in the endgame tables of the search, if there.
+    #[inline]
+    pub(crate) fn probe_tablebase(&self, position : &Position) -> Option<TablebaseValue>{
+        let tablebase = self.tablebase.as_ref()?;
+        let units = position.get_pieces(Player::White).count() + position.get_pieces(Player::Black).count();
+        if units > self.tablebase_units{
+            return None;
+        }
+        tablebase.probe(position)
+    }
+
+    /// Counts a node and tells whether the search must unwind.
+    #[inline]
+    pub(crate) fn visit(&mut self) -> bool{
+        if self.aborted {return true;}
+        self.nodes += 1;
+
+        let stopped = self.stop.as_ref().is_some_and(|s|s.is_stopped());
+        let out_of_nodes = self.enforce_limits
+            && self.max_nodes.is_some_and(|n|self.nodes > n);
+        let out_of_time = self.enforce_limits
+            && self.nodes.is_multiple_of(Self::CLOCK_INTERVAL)
+            && self.deadline.is_some_and(|d|Instant::now() >= d);
+
+        self.aborted = stopped | out_of_nodes | out_of_time;
+        self.aborted
+    }
+
+    /// Once true, results computed since are incomplete and must not be stored.
+    #[inline]
+    pub(crate) fn aborted(&self) -> bool{
+        self.aborted
+    }
+}
+
+/// Result of the deepest fully completed iteration.
+#[derive(Clone, Debug)]
+pub struct SearchOutcome{
+    /// 0 if not even depth 1 completed; moves are then in random order.
+    pub depth : usize,
+    /// Root moves, best first.
+    pub moves : Vec<(Ply, EvalResult)>,
+    /// Nodes visited over all iterations.
+    pub nodes : usize,
+}
+
+impl SearchOutcome{
+    pub fn best_move(&self) -> Option<Ply>{
+        self.moves.first().map(|(ply,_)|*ply)
+    }
+}
+
+/// Lazy SMP helpers: extra threads running their own iterative deepening
+/// on the same root, sharing only the transposition table.
+/// Their results are never read; they only fill the table for the main search.
+#[cfg(not(target_arch = "wasm32"))]
+struct Helpers{
+    stop : StopToken,
+    handles : Vec<std::thread::JoinHandle<usize>>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Helpers{
+    fn spawn(position : &Position, limits : &SearchLimits, transp_table : &Arc<TranspositionalTable>) -> Self{
+        let stop = StopToken::new();
+        let handles = (1..limits.threads).map(|id|{
+            let mut position = position.clone();
+            let transp_table = transp_table.clone();
+            let mut guard = SearchGuard::new(&SearchLimits{max_nodes : None, deadline : None, ..limits.clone()}, stop.clone());
+            let max_depth = limits.max_depth;
+
+            std::thread::spawn(move ||{
+                let mut rng = ::rand::thread_rng();
+                let mut root_order = position.valid_moves();
+                // odd helpers start one ply deeper to desynchronise from the main thread
+                for depth in (1 + id % 2)..=max_depth{
+                    root_order.shuffle(&mut rng);
+                    futures::executor::block_on(position.score_root_moves(
+                        root_order.clone(), depth, false, &transp_table, &mut guard
+                    ));
+                    if guard.aborted(){
+                        break;
+                    }
+                }
+                guard.nodes
+            })
+        }).collect();
+
+        Helpers{stop, handles}
+    }
+
+    /// Stops the helpers and returns how many nodes they visited.
+    fn finish(mut self) -> usize{
+        self.stop.stop();
+        std::mem::take(&mut self.handles).into_iter().map(|h|h.join().unwrap()).sum()
+    }
+}
+
+/// Also covers the search future being dropped before completion.
+#[cfg(not(target_arch = "wasm32"))]
+impl Drop for Helpers{
+    fn drop(&mut self){
+        self.stop.stop();
+    }
+}
+
+impl Position{
+    /// Iterative deepening search.
+    /// Returns the result of the last iteration completed within the limits;
+    /// `on_iteration` is called after each completed one.
+    pub async fn search(self,
+        limits : SearchLimits,
+        stop : StopToken,
+        transp : Option<Arc<TranspositionalTable>>,
+        mquad_frame_await : bool,
+        mut on_iteration : impl FnMut(&SearchOutcome),
+    ) -> SearchOutcome{
+        let transp_table = transp.unwrap_or_default();
+        transp_table.new_search();
+
+        let mut position = self;
+        let mut outcome = SearchOutcome{
+            depth : 0,
+            moves : position.score_root_moves_depth0(),
+            nodes : 0
+        };
+
+        let mut guard = SearchGuard::new(&limits, stop);
+
+        #[cfg(not(target_arch = "wasm32"))]
+        let helpers = Helpers::spawn(&position, &limits, &transp_table);
+
+        for depth in 1..=limits.max_depth{
+            let root_order = outcome.moves.iter().map(|(ply,_)|*ply).collect();
+
+            guard.enforce_limits = depth > 1;
+            let moves = position.score_root_moves(
+                root_order, depth, mquad_frame_await, &transp_table, &mut guard
+            ).await;
+
+            if guard.aborted(){
+                break;
+            }
+
+            outcome = SearchOutcome{depth, moves, nodes : guard.nodes};
+            on_iteration(&outcome);
+
+            if outcome.moves.is_empty(){
+                break;
+            }
+        }
+
+        outcome.nodes = guard.nodes;
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            outcome.nodes += helpers.finish();
+        }
+        outcome
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use futures::executor::block_on;
+
+    use super::*;
+
+    #[test]
+    fn test_search_limits(){
+        let position = Position::setup();
+
+        let mut depths = vec![];
+        let outcome = block_on(position.clone().search(
+            SearchLimits::depth(3), StopToken::new(), None, false,
+            |o|depths.push(o.depth)
+        ));
+        assert_eq!(depths, vec![1,2,3]);
+        assert_eq!(outcome.depth, 3);
+        assert_eq!(outcome.moves.len(), position.valid_moves().len());
+
+        // node budget too small for depth 2: depth 1 is still completed
+        let outcome = block_on(position.clone().search(
+            SearchLimits::depth(8).with_nodes(10), StopToken::new(), None, false, |_|{}
+        ));
+        assert_eq!(outcome.depth, 1);
+
+        // stopped before starting: nothing completes, moves are still available
+        let stop = StopToken::new();
+        stop.stop();
+        let outcome = block_on(position.clone().search(
+            SearchLimits::depth(8), stop, None, false, |_|{}
+        ));
+        assert_eq!(outcome.depth, 0);
+        assert!(outcome.best_move().is_some());
+
+        let outcome = block_on(position.search(
+            SearchLimits::depth(30).with_time(Duration::from_millis(200)), StopToken::new(), None, false, |_|{}
+        ));
+        assert!(outcome.depth >= 1 && outcome.depth < 30);
+    }
+
+    #[test]
+    fn test_lazy_smp(){
+        let position = Position::setup();
+        let table = Arc::new(TranspositionalTable::with_size_mb(4));
+
+        let outcome = block_on(position.clone().search(
+            SearchLimits::depth(4).with_threads(4), StopToken::new(), Some(table), false, |_|{}
+        ));
+        assert_eq!(outcome.depth, 4);
+        assert_eq!(outcome.moves.len(), position.valid_moves().len());
+        for (ply, eval) in &outcome.moves{
+            assert_eq!(eval.pv.first(), Some(ply));
+        }
+    }
+}
@@ -0,0 +1,631 @@
+//! Endgame tablebases.
+//!
+//! Every position with a given material signature is enumerated and solved
+//! by retrograde analysis: terminal positions are scored first, and values are
+//! propagated back one ply at a time until nothing changes. What is left is a draw.
+//!
+//! Captures only ever remove material, so a table depends on the tables of
+//! its sub-signatures, which are generated first.
+//!
+//! Tables keep decisive positions only, as a sorted zobrist key and a value byte
+//! each; draws are whatever is missing. That is nine bytes per decisive position,
+//! where a table indexed by the rank of each placement would take one byte per
+//! position, draws included. Keys cost more once over a ninth of the positions
+//! are decisive, at worst nine times more. That is accepted because ranking
+//! placements of lone pieces and stacks would take far more code than sorting,
+//! and tables small enough to be solved in memory are small files too. Keys
+//! cannot collide within a table: generation checks it, and a probe only looks
+//! in the table of the position's signature.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use itertools::Itertools;
+
+use super::{PieceMap, Player, PlayerMap, Position, Score, Species, Tall, Tile};
+
+const TALLS : [Tall; 3] = [Tall::Hand, Tall::Blind, Tall::Star];
+
+/// Pieces of one side, counting the two halves of a stack separately.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Debug, Default)]
+pub struct Material{
+    pub flats : u8,
+    /// Hand, blind, star
+    pub talls : [u8; 3],
+}
+
+impl Material{
+    pub fn of(pieces : &PieceMap) -> Material{
+        let stacks = TALLS.map(|t|pieces.locate_species(Species::Stack(t)).count() as u8);
+        Material{
+            flats : pieces.locate_lone_flats().count() as u8 + stacks.iter().sum::<u8>(),
+            talls : TALLS.map(|t|pieces.locate_talls(t).count() as u8),
+        }
+    }
+
+    pub fn units(&self) -> u32{
+        self.flats as u32 + self.talls.iter().map(|&t|t as u32).sum::<u32>()
+    }
+
+    fn is_within(&self, other : &Material) -> bool{
+        self.flats <= other.flats && (0..3).all(|i|self.talls[i] <= other.talls[i])
+    }
+
+    /// Every material with at most as many pieces of each kind, including self.
+    fn subsets(&self) -> Vec<Material>{
+        (0..=self.flats).cartesian_product(0..=self.talls[0])
+        .cartesian_product(0..=self.talls[1])
+        .cartesian_product(0..=self.talls[2])
+        .map(|(((flats, hand), blind), star)|Material{flats, talls : [hand, blind, star]})
+        .collect()
+    }
+
+    /// All placements of this material on an empty board.
+    fn placements(&self) -> Vec<PieceMap>{
+        let tiles = Tile::all_tiles();
+        let mut maps : Vec<PieceMap> = tiles.into_iter()
+        .combinations(self.flats as usize)
+        .map(|combination|{
+            let mut map = PieceMap::EMPTY;
+            combination.into_iter().for_each(|t|map.set(t, Species::Flat));
+            map
+        }).collect();
+
+        for (tall, count) in TALLS.into_iter().zip(self.talls){
+            maps = maps.into_iter().flat_map(|map|{
+                let free : Vec<Tile> = tiles.into_iter()
+                    .filter(|t|matches!(map.get(*t), None | Some(Species::Flat)))
+                    .collect();
+                free.into_iter().combinations(count as usize).map(move |combination|{
+                    let mut map = map.clone();
+                    for t in combination{
+                        let species = match map.get(t){
+                            Some(Species::Flat) => Species::Stack(tall),
+                            _ => Species::Lone(tall)
+                        };
+                        map.set(t, species);
+                    }
+                    map
+                })
+            }).collect();
+        }
+        maps
+    }
+
+    fn write_chars(&self, color : Player, f : &mut std::fmt::Formatter<'_>) -> std::fmt::Result{
+        let letters = match color{
+            Player::White => ['f', 'a', 'b', 's'],
+            Player::Black => ['o', 'h', 'm', 'x'],
+        };
+        let counts = [self.flats, self.talls[0], self.talls[1], self.talls[2]];
+        for (letter, count) in letters.into_iter().zip(counts){
+            for _ in 0..count{
+                write!(f, "{}", letter)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Material of both sides, written like `af_ho`
+/// with the piece letters of position strings.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Debug, Default)]
+pub struct Signature{
+    pub white : Material,
+    pub black : Material,
+}
+
+impl Signature{
+    pub fn of(position : &Position) -> Signature{
+        Signature{
+            white : Material::of(position.get_pieces(Player::White)),
+            black : Material::of(position.get_pieces(Player::Black)),
+        }
+    }
+
+    pub fn units(&self) -> u32{
+        self.white.units() + self.black.units()
+    }
+
+    /// Signatures reachable by captures, smallest first, ending with self.
+    pub fn closure(&self) -> Vec<Signature>{
+        let mut signatures : Vec<Signature> = self.white.subsets().into_iter()
+            .cartesian_product(self.black.subsets())
+            .map(|(white, black)|Signature{white, black})
+            .collect();
+        signatures.sort_by_key(|s|(s.units(), *s));
+        debug_assert!(signatures.iter().all(|s|s.white.is_within(&self.white) && s.black.is_within(&self.black)));
+        signatures
+    }
+
+    /// Every position with this material, with either side to play.
+    fn enumerate(&self) -> Vec<Position>{
+        let black_placements = self.black.placements();
+        let mut positions = vec![];
+        for white in self.white.placements(){
+            for black in &black_placements{
+                if (white.occupied() & black.occupied()).is_not_empty(){
+                    continue;
+                }
+                for to_play in [Player::White, Player::Black]{
+                    positions.push(Position::new(to_play, PlayerMap::new(white.clone(), black.clone())));
+                }
+            }
+        }
+        positions
+    }
+}
+
+impl Display for Signature{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.white.write_chars(Player::White, f)?;
+        write!(f, "_")?;
+        self.black.write_chars(Player::Black, f)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignatureParseError(pub String);
+
+impl FromStr for Signature{
+    type Err = SignatureParseError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (white_chars, black_chars) = s.trim().split_once('_')
+            .ok_or_else(||SignatureParseError(s.to_owned()))?;
+
+        let mut signature = Signature::default();
+        for (chars, material, letters) in [
+            (white_chars, &mut signature.white, ['f', 'a', 'b', 's']),
+            (black_chars, &mut signature.black, ['o', 'h', 'm', 'x']),
+        ]{
+            for c in chars.chars(){
+                match letters.iter().position(|&l|l == c){
+                    Some(0) => material.flats += 1,
+                    Some(i) => material.talls[i-1] += 1,
+                    None => return Err(SignatureParseError(s.to_owned()))
+                }
+            }
+        }
+        Ok(signature)
+    }
+}
+
+/// Value of a position for the side to play,
+/// with the number of plies until the game ends under optimal play.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TablebaseValue{
+    Win(u8),
+    Loss(u8),
+    Draw,
+}
+
+impl TablebaseValue{
+    const LOSS_FLAG : u8 = 0x80;
+    /// Longest distance that fits in the file encoding.
+    pub const MAX_DISTANCE : u8 = 0x7e;
+
+    /// One byte: 0 for draws, then wins and losses with the distance in the low bits.
+    fn encode(self) -> u8{
+        match self{
+            Self::Draw => 0,
+            Self::Win(d) => d + 1,
+            Self::Loss(d) => Self::LOSS_FLAG | (d + 1),
+        }
+    }
+
+    fn decode(byte : u8) -> Option<Self>{
+        match (byte & Self::LOSS_FLAG != 0, byte & !Self::LOSS_FLAG){
+            (false, 0) => Some(Self::Draw),
+            (_, 0) => None,
+            (false, d) => Some(Self::Win(d - 1)),
+            (true, d) => Some(Self::Loss(d - 1)),
+        }
+    }
+
+    /// Score as used by the search, for the given side to play.
+    pub fn to_score(self, to_play : Player) -> Score{
+        match self{
+            Self::Draw => Score::EVEN,
+            Self::Win(d) => Score::win_in(to_play, d as u32),
+            Self::Loss(d) => Score::win_in(to_play.flip(), d as u32),
+        }
+    }
+}
+
+/// Solved positions of one signature.
+/// Draws are implicit, and terminal positions are left to `Position::is_won`.
+struct Table{
+    /// Sorted zobrist hashes
+    keys : Vec<u64>,
+    values : Vec<u8>,
+}
+
+impl Table{
+    fn get(&self, hash : u64) -> TablebaseValue{
+        match self.keys.binary_search(&hash){
+            Ok(i) => TablebaseValue::decode(self.values[i]).unwrap(),
+            Err(_) => TablebaseValue::Draw
+        }
+    }
+}
+
+/// Move of a position under analysis: either into the same table,
+/// or a capture into an already solved one.
+#[derive(Clone, Copy)]
+enum Successor{
+    Index(u32),
+    Known(TablebaseValue),
+}
+
+/// Tables by signature. Searches consult one through `SearchLimits::tablebase`.
+#[derive(Default)]
+pub struct Tablebase{
+    tables : HashMap<Signature, Table>,
+}
+
+/// The signatures, not the tables.
+impl std::fmt::Debug for Tablebase{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.tables.keys()).finish()
+    }
+}
+
+impl Tablebase{
+    const MAGIC : &'static [u8; 4] = b"HXTB";
+    const VERSION : u8 = 1;
+    pub const FILE_EXTENSION : &'static str = "hxtb";
+
+    pub fn new() -> Self{
+        Self::default()
+    }
+
+    pub fn signatures(&self) -> impl Iterator<Item = &Signature>{
+        self.tables.keys()
+    }
+
+    pub fn contains(&self, signature : &Signature) -> bool{
+        self.tables.contains_key(signature)
+    }
+
+    /// Most pieces in any loaded table.
+    pub fn max_units(&self) -> u32{
+        self.tables.keys().map(|s|s.units()).max().unwrap_or(0)
+    }
+
+    /// Solves `signature` and all signatures reachable from it by captures,
+    /// skipping the ones already present. `on_table` is called after each one
+    /// with the number of positions enumerated.
+    pub fn generate(&mut self, signature : Signature, mut on_table : impl FnMut(&Signature, usize)){
+        for sub_signature in signature.closure(){
+            if !self.contains(&sub_signature){
+                let (table, enumerated) = self.solve(sub_signature);
+                self.tables.insert(sub_signature, table);
+                on_table(&sub_signature, enumerated);
+            }
+        }
+    }
+
+    /// Value of the position, if its material is covered.
+    pub fn probe(&self, position : &Position) -> Option<TablebaseValue>{
+        let table = self.tables.get(&Signature::of(position))?;
+        if let Some(winner) = position.is_won(){
+            return Some(if winner == position.to_play() {
+                TablebaseValue::Win(0)
+            } else {
+                TablebaseValue::Loss(0)
+            });
+        }
+        Some(table.get(position.zobrist()))
+    }
+
+    fn solve(&self, signature : Signature) -> (Table, usize){
+        let mut positions = signature.enumerate();
+        let index : HashMap<u64, u32> = positions.iter().enumerate()
+            .map(|(i,p)|(p.zobrist(), i as u32))
+            .collect();
+        assert_eq!(index.len(), positions.len(), "zobrist collision in {}", signature);
+
+        let mut values : Vec<Option<TablebaseValue>> = vec![None; positions.len()];
+        let mut offsets = vec![0];
+        let mut successors = vec![];
+        for (i, position) in positions.iter_mut().enumerate(){
+            if let Some(winner) = position.is_won(){
+                values[i] = Some(if winner == position.to_play() {
+                    TablebaseValue::Win(0)
+                } else {
+                    TablebaseValue::Loss(0)
+                });
+            } else {
+                for ply in position.valid_moves(){
+                    let undo = position.make_move(ply);
+                    successors.push(if undo.has_captured(){
+                        Successor::Known(self.probe(position)
+                            .expect("sub-signature tables are solved first"))
+                    } else {
+                        Successor::Index(index[&position.zobrist()])
+                    });
+                    position.unmake_move(undo);
+                }
+            }
+            offsets.push(successors.len());
+        }
+
+        // deepest distance known so far: propagation may not stop before it
+        let mut horizon = successors.iter().filter_map(|s|match s{
+            Successor::Known(TablebaseValue::Loss(d)) => Some(*d as usize),
+            _ => None
+        }).max().unwrap_or(0);
+
+        let mut distance = 1;
+        loop{
+            let mut updates = vec![];
+            for i in (0..positions.len()).filter(|&i|values[i].is_none()){
+                let mut wins = false;
+                let mut longest_loss = Some(0);
+                for successor in &successors[offsets[i]..offsets[i+1]]{
+                    let value = match successor{
+                        Successor::Index(j) => values[*j as usize],
+                        Successor::Known(value) => Some(*value)
+                    };
+                    match value{
+                        Some(TablebaseValue::Loss(d)) if d as usize + 1 == distance => wins = true,
+                        Some(TablebaseValue::Win(d)) => longest_loss = longest_loss.map(|l : usize|l.max(d as usize + 1)),
+                        _ => longest_loss = None
+                    }
+                }
+                if wins{
+                    updates.push((i, TablebaseValue::Win(distance as u8)));
+                } else if let Some(d) = longest_loss{
+                    assert!(d <= TablebaseValue::MAX_DISTANCE as usize, "distance overflow in {}", signature);
+                    horizon = horizon.max(d);
+                    updates.push((i, TablebaseValue::Loss(d as u8)));
+                }
+            }
+
+            if updates.is_empty() && distance > horizon{
+                break;
+            }
+            for (i, value) in updates{
+                values[i] = Some(value);
+            }
+            distance += 1;
+            assert!(distance <= TablebaseValue::MAX_DISTANCE as usize, "distance overflow in {}", signature);
+        }
+
+        let mut entries : Vec<(u64, u8)> = positions.iter().zip(&values)
+            .filter_map(|(position, value)|match value{
+                Some(TablebaseValue::Win(0) | TablebaseValue::Loss(0)) | None => None,
+                Some(value) => Some((position.zobrist(), value.encode()))
+            })
+            .collect();
+        entries.sort_unstable();
+
+        let (keys, values) = entries.into_iter().unzip();
+        (Table{keys, values}, positions.len())
+    }
+
+    /// Writes one table: magic, version, signature, entry count,
+    /// then sorted little-endian hashes followed by one value byte per hash.
+    pub fn write_table(&self, signature : &Signature, writer : &mut impl Write) -> io::Result<()>{
+        let table = self.tables.get(signature)
+            .ok_or_else(||io::Error::new(io::ErrorKind::NotFound, signature.to_string()))?;
+
+        let name = signature.to_string();
+        writer.write_all(Self::MAGIC)?;
+        writer.write_all(&[Self::VERSION, name.len() as u8])?;
+        writer.write_all(name.as_bytes())?;
+        writer.write_all(&(table.keys.len() as u32).to_le_bytes())?;
+        for key in &table.keys{
+            writer.write_all(&key.to_le_bytes())?;
+        }
+        writer.write_all(&table.values)
+    }
+
+    /// Reads a table written by `write_table`, returning its signature.
+    pub fn read_table(&mut self, reader : &mut impl Read) -> io::Result<Signature>{
+        let invalid = |what : &str|io::Error::new(io::ErrorKind::InvalidData, what.to_owned());
+
+        let mut header = [0u8; 6];
+        reader.read_exact(&mut header)?;
+        if &header[..4] != Self::MAGIC || header[4] != Self::VERSION{
+            return Err(invalid("not a tablebase file"));
+        }
+        let mut name = vec![0u8; header[5] as usize];
+        reader.read_exact(&mut name)?;
+        let signature : Signature = String::from_utf8(name).ok()
+            .and_then(|name|name.parse().ok())
+            .ok_or_else(||invalid("bad signature"))?;
+
+        let mut count = [0u8; 4];
+        reader.read_exact(&mut count)?;
+        let count = u32::from_le_bytes(count) as usize;
+
+        let mut keys = Vec::with_capacity(count);
+        let mut key = [0u8; 8];
+        for _ in 0..count{
+            reader.read_exact(&mut key)?;
+            keys.push(u64::from_le_bytes(key));
+        }
+        let mut values = vec![0u8; count];
+        reader.read_exact(&mut values)?;
+
+        if !keys.is_sorted() || values.iter().any(|&v|TablebaseValue::decode(v).is_none()){
+            return Err(invalid("corrupted table"));
+        }
+
+        self.tables.insert(signature, Table{keys, values});
+        Ok(signature)
+    }
+
+    /// Writes every table to `<directory>/<signature>.hxtb`.
+    pub fn save_dir(&self, directory : impl AsRef<Path>) -> io::Result<()>{
+        std::fs::create_dir_all(&directory)?;
+        for signature in self.tables.keys(){
+            let path = directory.as_ref().join(format!("{}.{}", signature, Self::FILE_EXTENSION));
+            let mut writer = io::BufWriter::new(std::fs::File::create(path)?);
+            self.write_table(signature, &mut writer)?;
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Loads every table file of a directory.
+    pub fn load_dir(directory : impl AsRef<Path>) -> io::Result<Tablebase>{
+        let mut tablebase = Tablebase::new();
+        for entry in std::fs::read_dir(directory)?{
+            let path = entry?.path();
+            if path.extension().is_some_and(|e|e == Self::FILE_EXTENSION){
+                let mut reader = io::BufReader::new(std::fs::File::open(path)?);
+                tablebase.read_table(&mut reader)?;
+            }
+        }
+        Ok(tablebase)
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use std::sync::Arc;
+
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::tokonoma::{PositionString, SearchLimits, StopToken};
+
+    fn small_tablebase() -> (Tablebase, Signature){
+        let signature : Signature = "fa_o".parse().unwrap();
+        let mut tablebase = Tablebase::new();
+        tablebase.generate(signature, |_,_|{});
+        (tablebase, signature)
+    }
+
+    #[test]
+    fn test_signature_notation(){
+        let signature : Signature = "ffab_hx".parse().unwrap();
+        assert_eq!(signature.white, Material{flats : 2, talls : [1, 1, 0]});
+        assert_eq!(signature.black, Material{flats : 0, talls : [1, 0, 1]});
+        assert_eq!(signature.to_string(), "ffab_hx");
+        assert!("fa".parse::<Signature>().is_err());
+        assert!("f_f".parse::<Signature>().is_err());
+
+        assert_eq!(Signature::of(&"A3oS4MAB3MHB4Xf3HW".parse::<Position>().unwrap()).to_string(),
+            "ffffffaabbs_oooooohhmmx");
+
+        let closure = "fa_o".parse::<Signature>().unwrap().closure();
+        assert_eq!(closure.len(), 8);
+        assert_eq!(closure.first().unwrap().to_string(), "_");
+        assert_eq!(closure.last().unwrap().to_string(), "fa_o");
+    }
+
+    #[test]
+    fn test_tablebase_consistency(){
+        let (tablebase, signature) = small_tablebase();
+        assert_eq!(tablebase.signatures().count(), 8);
+
+        let mut rng = ::rand::thread_rng();
+        let positions = signature.enumerate();
+        let mut decisive = 0;
+        for mut position in ::rand::seq::IteratorRandom::choose_multiple(positions.into_iter(), &mut rng, 2000){
+            let value = tablebase.probe(&position).unwrap();
+            if position.is_won().is_some(){
+                continue;
+            }
+
+            let children : Vec<TablebaseValue> = position.valid_moves().into_iter().map(|ply|{
+                let undo = position.make_move(ply);
+                let value = tablebase.probe(&position).unwrap();
+                position.unmake_move(undo);
+                value
+            }).collect();
+
+            let expected = if let Some(d) = children.iter().filter_map(|v|match v{
+                TablebaseValue::Loss(d) => Some(*d), _ => None
+            }).min(){
+                TablebaseValue::Win(d + 1)
+            } else if let Some(d) = children.iter().map(|v|match v{
+                TablebaseValue::Win(d) => Some(*d), _ => None
+            }).collect::<Option<Vec<u8>>>().and_then(|d|d.into_iter().max()){
+                TablebaseValue::Loss(d + 1)
+            } else {
+                TablebaseValue::Draw
+            };
+            assert_eq!(value, expected, "{}", PositionString::from(&position));
+            decisive += (value != TablebaseValue::Draw) as usize;
+        }
+        assert!(decisive > 0);
+    }
+
+    /// Exact value by plain minimax over the game rules, if decided within `depth` plies.
+    fn minimax(position : &mut Position, depth : usize) -> Option<TablebaseValue>{
+        if let Some(winner) = position.is_won(){
+            return Some(if winner == position.to_play() {TablebaseValue::Win(0)} else {TablebaseValue::Loss(0)});
+        }
+        if depth == 0{
+            return None;
+        }
+        let children : Vec<Option<TablebaseValue>> = position.valid_moves().into_iter().map(|ply|{
+            let undo = position.make_move(ply);
+            let value = minimax(position, depth - 1);
+            position.unmake_move(undo);
+            value
+        }).collect();
+
+        if let Some(d) = children.iter().filter_map(|v|match v{
+            Some(TablebaseValue::Loss(d)) => Some(*d), _ => None
+        }).min(){
+            return Some(TablebaseValue::Win(d + 1));
+        }
+        children.into_iter().map(|v|match v{
+            Some(TablebaseValue::Win(d)) => Some(d + 1), _ => None
+        }).collect::<Option<Vec<u8>>>().map(|d|TablebaseValue::Loss(d.into_iter().max().unwrap()))
+    }
+
+    #[test]
+    fn test_tablebase_agrees_with_search(){
+        let (tablebase, signature) = small_tablebase();
+        let mut rng = ::rand::thread_rng();
+
+        let short : Vec<(Position, TablebaseValue)> = signature.enumerate().into_iter()
+            .filter_map(|p|match tablebase.probe(&p){
+                Some(v @ (TablebaseValue::Win(1..=3) | TablebaseValue::Loss(2..=3))) => Some((p, v)),
+                _ => None
+            }).collect();
+        let sample = ::rand::seq::IteratorRandom::choose_multiple(short.into_iter(), &mut rng, 20);
+        assert!(!sample.is_empty());
+
+        for (position, value) in &sample{
+            assert_eq!(minimax(&mut position.clone(), 3), Some(*value), "{}", PositionString::from(position));
+        }
+
+        // with the tablebase, a shallow search plays the shortest win
+        let tablebase = Arc::new(tablebase);
+        for (position, value) in sample{
+            let TablebaseValue::Win(distance) = value else {continue};
+            let outcome = block_on(position.clone().search(
+                SearchLimits::depth(1).with_tablebase(Some(tablebase.clone())), StopToken::new(), None, false, |_|{}
+            ));
+            // root move scores are the values of the positions they lead to
+            let (_, best) = &outcome.moves[0];
+            assert_eq!(best.score, Score::win_in(position.to_play(), distance as u32 - 1), "{}", PositionString::from(&position));
+        }
+    }
+
+    #[test]
+    fn test_tablebase_file_roundtrip(){
+        let (tablebase, signature) = small_tablebase();
+        let mut buffer = vec![];
+        tablebase.write_table(&signature, &mut buffer).unwrap();
+
+        let mut loaded = Tablebase::new();
+        assert_eq!(loaded.read_table(&mut buffer.as_slice()).unwrap(), signature);
+        for position in signature.enumerate().into_iter().step_by(97){
+            assert_eq!(loaded.probe(&position), tablebase.probe(&position));
+        }
+
+        buffer[0] = b'X';
+        assert!(Tablebase::new().read_table(&mut buffer.as_slice()).is_err());
+    }
+}
@@ -0,0 +1,178 @@
+use std::sync::{atomic::{AtomicU8, Ordering}, Mutex};
+
+use super::{Ply, Score};
+
+/// How a stored score relates to the true value of the position.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Bound{
+    /// The search completed inside the window: the score is the value.
+    Exact,
+    /// The search failed high: the value is at least the score.
+    Lower,
+    /// The search failed low: the value is at most the score.
+    Upper,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TranspositionEntry{
+    /// Full hash, to reject index collisions
+    key : u64,
+    pub score : Score,
+    pub best_move : Option<Ply>,
+    pub depth : u8,
+    pub bound : Bound,
+    age : u8,
+}
+
+const BUCKET_SIZE : usize = 4;
+type Bucket = [Option<TranspositionEntry>; BUCKET_SIZE];
+
+/// Fixed-size transposition table.
+/// Entries are grouped in a power-of-two number of buckets indexed
+/// by the low bits of the hash. When a bucket is full the shallowest
+/// entry is replaced, with entries from previous searches counting as shallower.
+///
+/// Buckets are spread over independently locked shards, so that
+/// several search threads can share one table.
+pub struct TranspositionalTable{
+    shards : Vec<Mutex<Vec<Bucket>>>,
+    n_buckets : usize,
+    age : AtomicU8,
+}
+
+impl Default for TranspositionalTable{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TranspositionalTable{
+    pub const DEFAULT_SIZE_MB : usize = 16;
+
+    /// How many depth units a search generation is worth when picking a victim.
+    const AGE_WEIGHT : i32 = 4;
+
+    const SHARDS_LOG2 : u32 = 6;
+    const SHARDS : usize = 1 << Self::SHARDS_LOG2;
+
+    pub fn new()->Self{
+        Self::with_size_mb(Self::DEFAULT_SIZE_MB)
+    }
+
+    /// Table using at most `megabytes` of memory (rounded down to a power of two buckets).
+    pub fn with_size_mb(megabytes : usize) -> Self{
+        let bytes = megabytes.max(1) * 1024 * 1024;
+        let max_buckets = (bytes / std::mem::size_of::<Bucket>()).max(Self::SHARDS);
+        let n_buckets = 1 << max_buckets.ilog2();
+
+        TranspositionalTable{
+            shards : (0..Self::SHARDS).map(|_|
+                Mutex::new(vec![[None; BUCKET_SIZE]; n_buckets / Self::SHARDS])
+            ).collect(),
+            n_buckets,
+            age : AtomicU8::new(0)
+        }
+    }
+
+    pub fn capacity(&self) -> usize{
+        self.n_buckets * BUCKET_SIZE
+    }
+
+    /// Marks the start of a new search, so that older entries get replaced first.
+    pub fn new_search(&self){
+        self.age.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn clear(&self){
+        for shard in &self.shards{
+            shard.lock().unwrap().iter_mut().for_each(|b|*b = [None; BUCKET_SIZE]);
+        }
+        self.age.store(0, Ordering::Relaxed);
+    }
+
+    /// Shard and bucket within the shard.
+    #[inline]
+    fn bucket_index(&self, hash : u64) -> (usize, usize){
+        let index = (hash as usize) & (self.n_buckets - 1);
+        (index & (Self::SHARDS - 1), index >> Self::SHARDS_LOG2)
+    }
+
+    pub fn probe(&self, hash : u64) -> Option<TranspositionEntry>{
+        let (shard, index) = self.bucket_index(hash);
+        self.shards[shard].lock().unwrap()[index].iter()
+        .flatten()
+        .find(|e|e.key == hash)
+        .copied()
+    }
+
+    pub fn store(&self, hash : u64, depth : usize, bound : Bound, score : Score, best_move : Option<Ply>){
+        let age = self.age.load(Ordering::Relaxed);
+        let entry = TranspositionEntry{
+            key : hash,
+            score, best_move, bound,
+            depth : depth.min(u8::MAX as usize) as u8,
+            age
+        };
+
+        let (shard, index) = self.bucket_index(hash);
+        let mut shard = self.shards[shard].lock().unwrap();
+        let bucket = &mut shard[index];
+
+        if let Some(slot) = bucket.iter().position(|e|matches!(e, Some(e) if e.key == hash)){
+            let existing = bucket[slot].unwrap();
+            let replace = (entry.depth >= existing.depth)
+                | (bound == Bound::Exact && existing.bound != Bound::Exact)
+                | (existing.age != age);
+            if replace{
+                let best_move = entry.best_move.or(existing.best_move);
+                bucket[slot] = Some(TranspositionEntry{best_move, ..entry});
+            }
+            return;
+        }
+
+        let victim = (0..BUCKET_SIZE).min_by_key(|&slot|
+            match &bucket[slot]{
+                None => i32::MIN,
+                Some(e) => e.depth as i32 - Self::AGE_WEIGHT * (age.wrapping_sub(e.age) as i32)
+            }
+        ).unwrap();
+
+        bucket[victim] = Some(entry);
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn test_transposition_replacement(){
+        let table = TranspositionalTable::with_size_mb(1);
+        assert!(table.capacity().is_power_of_two());
+
+        let stride = table.n_buckets as u64;
+        table.store(7, 3, Bound::Exact, Score::EVEN, None);
+        assert_eq!(table.probe(7).unwrap().depth, 3);
+        // same bucket, different key: verification rejects it
+        assert!(table.probe(7 + stride).is_none());
+
+        // shallower result for the same key does not overwrite
+        table.store(7, 1, Bound::Lower, Score::EVEN, None);
+        assert_eq!(table.probe(7).unwrap().bound, Bound::Exact);
+
+        // fill the bucket; the shallowest entry is evicted
+        for i in 1..=BUCKET_SIZE as u64{
+            table.store(7 + i * stride, 5, Bound::Upper, Score::EVEN, None);
+        }
+        assert!(table.probe(7).is_none());
+
+        // entries from an older search are evicted before deeper fresh ones
+        table.new_search();
+        table.store(7, 2, Bound::Exact, Score::EVEN, None);
+        assert_eq!(table.probe(7).unwrap().depth, 2);
+        assert_eq!(
+            (1..=BUCKET_SIZE as u64).filter(|i|table.probe(7 + i * stride).is_some()).count(),
+            BUCKET_SIZE - 1
+        );
+    }
+}
@@ -0,0 +1,84 @@
+use super::{Player, Position, Species, Tile};
+
+/// Incrementally maintained Zobrist hash of a position.
+/// XOR of one fixed key per (tile, color, species) present on the board,
+/// plus a key when black is to play.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct ZobristHash(u64);
+
+/// Tile bits go up to 4*ROW_OFFSET+6, so 64 slots covers all of them.
+const KEY_SLOTS : usize = 64;
+
+const fn splitmix64(state : u64) -> (u64,u64){
+    let state = state.wrapping_add(0x9E3779B97F4A7C15);
+    let mut z = state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    (state, z ^ (z >> 31))
+}
+
+const PIECE_KEYS : [[[u64;7];2];KEY_SLOTS] = {
+    let mut keys = [[[0;7];2];KEY_SLOTS];
+    let mut state = 0x746F6B6F6E6F6D61;
+
+    let mut bit = 0;
+    while bit < KEY_SLOTS{
+        let mut color = 0;
+        while color < 2{
+            let mut code = 0;
+            while code < 7{
+                let (next_state, key) = splitmix64(state);
+                state = next_state;
+                keys[bit][color][code] = key;
+                code += 1;
+            }
+            color += 1;
+        }
+        bit += 1;
+    }
+    keys
+};
+
+const BLACK_TO_PLAY_KEY : u64 = splitmix64(0x686578737461636B).1;
+
+impl ZobristHash{
+    pub const EMPTY : ZobristHash = ZobristHash(0);
+
+    #[inline]
+    const fn piece_key(tile : &Tile, color : Player, species : Species) -> u64{
+        let color_idx = match color{
+            Player::White => 0,
+            Player::Black => 1
+        };
+        PIECE_KEYS[tile.to_bit() as usize][color_idx][species.code() as usize]
+    }
+
+    #[inline]
+    pub fn toggle_piece(&mut self, tile : &Tile, color : Player, species : Species){
+        self.0 ^= Self::piece_key(tile, color, species);
+    }
+
+    #[inline]
+    pub fn toggle_to_play(&mut self){
+        self.0 ^= BLACK_TO_PLAY_KEY;
+    }
+
+    /// Hash computed from scratch, for initialisation and checking.
+    pub fn compute(position : &Position) -> ZobristHash{
+        let mut hash = ZobristHash::EMPTY;
+        for color in [Player::White,Player::Black]{
+            position.get_pieces(color).clone().into_iter().for_each(|(t,species)|
+                hash.toggle_piece(&t, color, species)
+            );
+        }
+        if position.to_play() == Player::Black{
+            hash.toggle_to_play();
+        }
+        hash
+    }
+
+    #[inline]
+    pub const fn value(&self) -> u64{
+        self.0
+    }
+}
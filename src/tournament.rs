@@ -0,0 +1,499 @@
+//! Engine-versus-engine games and match statistics, for the `tournament` binary.
+
+use std::collections::HashSet;
+use std::fmt::Display;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use ::rand::rngs::StdRng;
+use ::rand::seq::SliceRandom;
+use ::rand::SeedableRng;
+use futures::executor::block_on;
+use web_time::{Duration, Instant};
+
+use crate::engine_protocol::GoParams;
+use crate::external_engine::{EngineError, ExternalEngine};
+use crate::gamer_spec::{BotSettings, GamerSpec};
+use crate::tokonoma::{DrawRules, GameOutcome, GameRecord, EvalParams, MatchState, OpeningBook, Player, PlayerMap, Ply, Position, SearchLimits, StopToken, Termination, TranspositionalTable};
+
+/// Depth of built-in contestants that do not say.
+pub const DEFAULT_DEPTH : usize = 4;
+
+/// Extra time an external engine gets past its movetime before forfeiting.
+const GRACE : Duration = Duration::from_secs(5);
+/// Time allowed to external engines searching to a depth or node count.
+const UNTIMED_ALLOWANCE : Duration = Duration::from_secs(600);
+
+/// An engine configuration taking part in a tournament.
+///
+/// Written as comma-separated `key=value` pairs, e.g. `depth=5,blunder=0.1,name=d5b`:
+/// - `name`
+/// - `level`: a bot level such as `sharp`, setting depth and blunders
+/// - `depth`, `blunder` (probability), `nodes`, `movetime` (milliseconds)
+/// - `book`: variety of the moves taken from the opening book of the tournament, see `OpeningBook::choose`
+/// - `eval`: file of evaluation weights for the built-in search, see `EvalParams`
+/// - `engine`: command of an external engine, which gets the depth, nodes and movetime given
+#[derive(Clone, PartialEq, Debug)]
+pub struct Contestant{
+    pub name : String,
+    /// External engine command; the built-in search otherwise.
+    pub command : Option<String>,
+    pub depth : Option<usize>,
+    pub blundering_probability : f32,
+    pub nodes : Option<usize>,
+    pub movetime : Option<Duration>,
+    /// Evaluation weights of the built-in search.
+    pub eval : EvalParams,
+    /// Opening book use of the built-in search, see `BotSettings`.
+    pub book_variety : Option<f32>,
+    /// The book consulted with `book_variety`.
+    pub book : Option<Arc<OpeningBook>>,
+}
+
+impl Contestant{
+    pub fn depth(depth : usize) -> Self{
+        Contestant{
+            name : format!("d{}", depth),
+            command : None,
+            depth : Some(depth),
+            blundering_probability : 0.0,
+            nodes : None,
+            movetime : None,
+            eval : EvalParams::DEFAULT,
+            book_variety : None,
+            book : None,
+        }
+    }
+
+    fn go_params(&self) -> GoParams{
+        let mut params = GoParams{depth : self.depth, movetime : self.movetime, nodes : self.nodes};
+        if params == GoParams::default(){
+            params.depth = Some(DEFAULT_DEPTH);
+        }
+        params
+    }
+
+    fn bot_settings(&self) -> BotSettings{
+        BotSettings{
+            depth : self.depth.unwrap_or(DEFAULT_DEPTH),
+            blundering_probability : self.blundering_probability,
+            threads : 1,
+            book_variety : self.book_variety,
+            book : self.book.clone(),
+            tablebase : None,
+        }
+    }
+
+    fn limits(&self) -> SearchLimits{
+        let mut limits = self.bot_settings().roll_limits().with_eval(self.eval);
+        if let Some(nodes) = self.nodes{
+            limits = limits.with_nodes(nodes);
+        }
+        if let Some(movetime) = self.movetime{
+            limits = limits.with_time(movetime);
+        }
+        limits
+    }
+
+    /// The program of external engines, the limits of built-in ones, e.g. `d5b0.1`.
+    fn default_name(&self) -> String{
+        if let Some(command) = &self.command{
+            let program = command.split_whitespace().next().unwrap_or("engine");
+            return program.rsplit(['/', '\\']).next().unwrap_or(program).to_owned();
+        }
+        let mut name = String::new();
+        if let Some(depth) = self.depth {name.push_str(&format!("d{}", depth))}
+        if let Some(nodes) = self.nodes {name.push_str(&format!("n{}", nodes))}
+        if let Some(movetime) = self.movetime {name.push_str(&format!("t{}", movetime.as_millis()))}
+        if name.is_empty() {name = format!("d{}", DEFAULT_DEPTH)}
+        if self.blundering_probability > 0.0 {name.push_str(&format!("b{}", self.blundering_probability))}
+        if let Some(variety) = self.book_variety {name.push_str(&format!("k{}", variety))}
+        name
+    }
+}
+
+impl FromStr for Contestant{
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut contestant = Contestant::depth(DEFAULT_DEPTH);
+        contestant.depth = None;
+        let (mut name, mut level_name) = (None, None);
+
+        for pair in s.split(',').filter(|p|!p.trim().is_empty()){
+            let (key, value) = pair.split_once('=').ok_or_else(||format!("expected key=value, got {}", pair))?;
+            let (key, value) = (key.trim(), value.trim());
+            let number = |value : &str|value.parse::<u64>().map_err(|_|format!("{} is not a number: {}", key, value));
+            match key{
+                "name" => name = Some(value.to_owned()),
+                "level" => {
+                    let spec : GamerSpec = value.parse().map_err(|_|format!("unknown level {}", value))?;
+                    let settings = spec.bot_settings().ok_or_else(||format!("{} is not a bot", value))?;
+                    contestant.depth = Some(settings.depth);
+                    contestant.blundering_probability = settings.blundering_probability;
+                    contestant.book_variety = settings.book_variety;
+                    level_name = Some(spec.name());
+                },
+                "depth" => contestant.depth = Some(number(value)? as usize),
+                "blunder" => contestant.blundering_probability = value.parse()
+                    .ok().filter(|p|(0.0..1.0).contains(p))
+                    .ok_or_else(||format!("blunder must be a probability below 1, got {}", value))?,
+                "nodes" => contestant.nodes = Some(number(value)? as usize),
+                "movetime" => contestant.movetime = Some(Duration::from_millis(number(value)?)),
+                "book" => contestant.book_variety = Some(value.parse().ok().filter(|v : &f32|*v >= 0.0)
+                    .ok_or_else(||format!("book variety must be a non-negative number, got {}", value))?),
+                "eval" => contestant.eval = EvalParams::load(value)?,
+                "engine" => contestant.command = Some(value.to_owned()),
+                _ => return Err(format!("unknown key {}", key))
+            }
+        }
+        if contestant.command.is_some() && (contestant.eval != EvalParams::DEFAULT || contestant.book_variety.is_some()){
+            return Err("eval and book only apply to the built-in search".to_owned());
+        }
+
+        contestant.name = name.or(level_name).unwrap_or_else(||contestant.default_name());
+        Ok(contestant)
+    }
+}
+
+/// A contestant ready to play: a transposition table, or a running engine.
+pub enum Mover{
+    Bot{contestant : Contestant, transposition_table : Arc<TranspositionalTable>},
+    External{contestant : Contestant, engine : Result<ExternalEngine, EngineError>},
+}
+
+impl Mover{
+    /// External engines are started here; failing to start forfeits their games.
+    pub fn new(contestant : &Contestant) -> Mover{
+        match &contestant.command{
+            None => Mover::Bot{
+                contestant : contestant.clone(),
+                transposition_table : Arc::new(TranspositionalTable::with_size_mb(16))
+            },
+            Some(command) => Mover::External{
+                contestant : contestant.clone(),
+                engine : ExternalEngine::launch(command)
+            },
+        }
+    }
+
+    pub fn new_game(&mut self){
+        match self{
+            Mover::Bot{transposition_table, ..} => transposition_table.clear(),
+            Mover::External{engine : Ok(engine), ..} => {
+                let _ = engine.new_game();
+            },
+            Mover::External{..} => {}
+        }
+    }
+
+    /// The move to play, or why the contestant forfeits.
+    pub fn choose(&mut self, position : &Position) -> Result<Ply, EngineError>{
+        match self{
+            Mover::Bot{contestant, transposition_table} => {
+                if let Some(ply) = contestant.bot_settings().probe_book(position){
+                    return Ok(ply);
+                }
+                let outcome = block_on(position.clone().search(
+                    contestant.limits(), StopToken::new(), Some(transposition_table.clone()), false, |_|{}
+                ));
+                Ok(outcome.best_move().expect("a game in progress has moves"))
+            },
+            Mover::External{contestant, engine} => {
+                let engine = engine.as_mut().map_err(|e|e.clone())?;
+                engine.start_search(position, contestant.go_params())?;
+                let allowance = contestant.movetime.map_or(UNTIMED_ALLOWANCE, |t|t + GRACE);
+                engine.wait_move(Instant::now() + allowance)
+            }
+        }
+    }
+}
+
+/// Plays a game from `opening`. The record names the contestants;
+/// a forfeit ends the game with `Termination::Forfeit`.
+pub fn play_game(movers : &mut PlayerMap<&mut Mover>, names : &PlayerMap<String>, opening : &Position, draw_rules : DrawRules) -> GameRecord{
+    let mut match_state = MatchState::setup_from(opening.clone()).with_draw_rules(draw_rules);
+    for player in [Player::White, Player::Black]{
+        movers[player].new_game();
+    }
+
+    let mut forfeit = None;
+    while match_state.outcome().is_none(){
+        let to_play = match_state.to_play();
+        match movers[to_play].choose(match_state.present_state()){
+            Ok(ply) => match_state.apply_move(ply),
+            Err(e) => {
+                forfeit = Some((to_play, e));
+                break;
+            }
+        }
+    }
+
+    let mut record = match_state.to_record();
+    record.players = names.clone();
+    if let Some((loser, error)) = forfeit{
+        record.outcome = Some(GameOutcome::won_by(loser.flip()));
+        record.termination = Termination::Forfeit;
+        record.other_tags.push(("Forfeit".to_owned(), error.to_string()));
+    }
+    record
+}
+
+/// Distinct positions `plies` plies after the standard setup, in a shuffled but fixed order.
+pub fn default_openings(plies : usize) -> Vec<Position>{
+    let mut positions = vec![Position::setup()];
+    for _ in 0..plies{
+        let mut seen = HashSet::new();
+        positions = positions.iter().flat_map(|position|position.valid_moves().into_iter().map(|ply|{
+            let mut next = position.clone();
+            next.apply_move(ply);
+            next
+        }))
+        .filter(|p|p.is_won().is_none() && seen.insert(p.zobrist()))
+        .collect();
+    }
+    positions.shuffle(&mut StdRng::seed_from_u64(0));
+    positions
+}
+
+/// Wins, draws and losses from one side's point of view.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct Tally{
+    pub wins : usize,
+    pub draws : usize,
+    pub losses : usize,
+}
+
+/// Elo difference for an expected score.
+fn elo_of_score(score : f64) -> f64{
+    -400.0 * (1.0 / score - 1.0).log10()
+}
+
+/// Expected score for an Elo difference.
+fn score_of_elo(elo : f64) -> f64{
+    1.0 / (1.0 + 10f64.powf(-elo / 400.0))
+}
+
+impl Tally{
+    pub fn add(&mut self, outcome : Option<GameOutcome>, side : Player){
+        match outcome.and_then(|o|o.winner()){
+            Some(winner) if winner == side => self.wins += 1,
+            Some(_) => self.losses += 1,
+            None => self.draws += 1,
+        }
+    }
+
+    pub fn games(&self) -> usize{
+        self.wins + self.draws + self.losses
+    }
+
+    /// The other side's point of view.
+    pub fn flip(&self) -> Tally{
+        Tally{wins : self.losses, draws : self.draws, losses : self.wins}
+    }
+
+    /// Mean points per game, draws counting half.
+    pub fn score(&self) -> f64{
+        (self.wins as f64 + 0.5 * self.draws as f64) / self.games() as f64
+    }
+
+    /// Variance of the points of one game.
+    fn variance(&self) -> f64{
+        let (n, s) = (self.games() as f64, self.score());
+        (self.wins as f64 * (1.0 - s).powi(2) + self.draws as f64 * (0.5 - s).powi(2) + self.losses as f64 * s.powi(2)) / n
+    }
+
+    /// Elo difference and the half-width of its 95% confidence interval.
+    /// `None` until both a point has been won and lost.
+    pub fn elo(&self) -> Option<(f64, f64)>{
+        let score = self.score();
+        if self.games() == 0 || score <= 0.0 || score >= 1.0{
+            return None;
+        }
+        let margin = 1.959964 * (self.variance() / self.games() as f64).sqrt();
+        let low = elo_of_score((score - margin).max(1e-6));
+        let high = elo_of_score((score + margin).min(1.0 - 1e-6));
+        Some((elo_of_score(score), (high - low) / 2.0))
+    }
+}
+
+impl Display for Tally{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "+{} ={} -{}", self.wins, self.draws, self.losses)?;
+        if self.games() > 0{
+            write!(f, " ({:.1}%)", 100.0 * self.score())?;
+        }
+        match self.elo(){
+            Some((elo, margin)) => write!(f, " Elo {:+.0} ± {:.0}", elo, margin),
+            None => write!(f, " Elo n/a"),
+        }
+    }
+}
+
+/// Sequential probability ratio test between two Elo hypotheses,
+/// with the normal approximation to the score distribution.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Sprt{
+    pub elo0 : f64,
+    pub elo1 : f64,
+    /// False positive rate.
+    pub alpha : f64,
+    /// False negative rate.
+    pub beta : f64,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SprtDecision{
+    Continue,
+    /// The change is no better than `elo0`.
+    AcceptH0,
+    /// The change is at least `elo1` better.
+    AcceptH1,
+}
+
+impl Sprt{
+    pub fn new(elo0 : f64, elo1 : f64) -> Self{
+        Sprt{elo0, elo1, alpha : 0.05, beta : 0.05}
+    }
+
+    /// Log-likelihood ratio of H1 over H0.
+    pub fn llr(&self, tally : &Tally) -> f64{
+        if tally.games() == 0{
+            return 0.0;
+        }
+        // a clean sweep has no variance; pretend one game went each way
+        let mut variance = tally.variance();
+        if variance <= 0.0{
+            variance = Tally{wins : tally.wins + 1, draws : tally.draws, losses : tally.losses + 1}.variance();
+        }
+        let (s0, s1) = (score_of_elo(self.elo0), score_of_elo(self.elo1));
+        tally.games() as f64 * (s1 - s0) * (2.0 * tally.score() - s0 - s1) / (2.0 * variance)
+    }
+
+    /// Lower and upper log-likelihood ratio bounds.
+    pub fn bounds(&self) -> (f64, f64){
+        ((self.beta / (1.0 - self.alpha)).ln(), ((1.0 - self.beta) / self.alpha).ln())
+    }
+
+    pub fn decision(&self, tally : &Tally) -> SprtDecision{
+        let llr = self.llr(tally);
+        let (lower, upper) = self.bounds();
+        if llr >= upper{
+            SprtDecision::AcceptH1
+        } else if llr <= lower{
+            SprtDecision::AcceptH0
+        } else {
+            SprtDecision::Continue
+        }
+    }
+}
+
+/// `elo0,elo1[,alpha,beta]`
+impl FromStr for Sprt{
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values = s.split(',').map(|v|v.trim().parse::<f64>())
+            .collect::<Result<Vec<_>,_>>()
+            .map_err(|_|format!("expected elo0,elo1[,alpha,beta], got {}", s))?;
+        match *values.as_slice(){
+            [elo0, elo1] if elo0 < elo1 => Ok(Sprt::new(elo0, elo1)),
+            [elo0, elo1, alpha, beta] if elo0 < elo1 && alpha > 0.0 && beta > 0.0 && alpha + beta < 1.0 =>
+                Ok(Sprt{elo0, elo1, alpha, beta}),
+            _ => Err(format!("expected elo0 < elo1 and error rates in (0,1), got {}", s))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn test_contestant_parsing(){
+        let contestant : Contestant = "depth=5,blunder=0.1".parse().unwrap();
+        assert_eq!((contestant.depth, contestant.blundering_probability), (Some(5), 0.1));
+        assert_eq!(contestant.name, "d5b0.1");
+
+        let contestant : Contestant = "level=sharp".parse().unwrap();
+        assert_eq!(contestant.name, "Sharp");
+        assert_eq!(contestant.depth, GamerSpec::Sharp.bot_settings().map(|s|s.depth));
+
+        let contestant : Contestant = "engine=./target/release/engine,movetime=100".parse().unwrap();
+        assert_eq!(contestant.name, "engine");
+        assert_eq!(contestant.go_params(), GoParams::movetime(Duration::from_millis(100)));
+
+        assert_eq!("movetime=100".parse::<Contestant>().unwrap().name, "t100");
+        assert_eq!("depth=3,name=three".parse::<Contestant>().unwrap().name, "three");
+        assert!("depth=x".parse::<Contestant>().is_err());
+        assert!("blunder=1".parse::<Contestant>().is_err());
+        assert!("colour=red".parse::<Contestant>().is_err());
+
+        let contestant : Contestant = "depth=3,book=0.5".parse().unwrap();
+        assert_eq!((contestant.book_variety, contestant.name.as_str()), (Some(0.5), "d3k0.5"));
+        assert!("book=-1".parse::<Contestant>().is_err());
+        assert!("engine=./engine,book=0".parse::<Contestant>().is_err());
+    }
+
+    #[test]
+    fn test_default_openings(){
+        let openings = default_openings(2);
+        assert_eq!(openings, default_openings(2));
+        assert!(openings.len() > 1 && openings.iter().all(|p|p.to_play() == Player::White));
+        let distinct : HashSet<_> = openings.iter().map(|p|p.zobrist()).collect();
+        assert_eq!(distinct.len(), openings.len());
+    }
+
+    #[test]
+    fn test_tally_elo(){
+        let even = Tally{wins : 10, draws : 5, losses : 10};
+        let (elo, margin) = even.elo().unwrap();
+        assert!(elo.abs() < 1e-9 && margin > 0.0);
+
+        let strong = Tally{wins : 70, draws : 10, losses : 20};
+        let (elo, margin) = strong.elo().unwrap();
+        // 75% is about +191
+        assert!((elo - 190.85).abs() < 0.1, "{}", elo);
+        let (flipped, flipped_margin) = strong.flip().elo().unwrap();
+        assert!((elo + flipped).abs() < 1e-9 && (margin - flipped_margin).abs() < 1e-9);
+
+        // more games, tighter bounds
+        let more = Tally{wins : 700, draws : 100, losses : 200};
+        assert!(more.elo().unwrap().1 < margin / 2.0);
+
+        assert_eq!(Tally{wins : 3, draws : 0, losses : 0}.elo(), None);
+        assert_eq!(Tally::default().elo(), None);
+    }
+
+    #[test]
+    fn test_sprt(){
+        let sprt : Sprt = "0,10".parse().unwrap();
+        let (lower, upper) = sprt.bounds();
+        assert!((lower + 2.944).abs() < 1e-3 && (upper - 2.944).abs() < 1e-3);
+
+        assert_eq!(sprt.decision(&Tally{wins : 10, draws : 10, losses : 10}), SprtDecision::Continue);
+        assert_eq!(sprt.decision(&Tally{wins : 600, draws : 200, losses : 400}), SprtDecision::AcceptH1);
+        assert_eq!(sprt.decision(&Tally{wins : 400, draws : 200, losses : 600}), SprtDecision::AcceptH0);
+        assert!(sprt.llr(&Tally{wins : 30, draws : 0, losses : 10}) > 0.0);
+        // sweeps conclude, but not at once
+        assert_eq!(sprt.decision(&Tally{wins : 1, draws : 0, losses : 0}), SprtDecision::Continue);
+        assert_eq!(sprt.decision(&Tally{wins : 50, draws : 0, losses : 0}), SprtDecision::AcceptH1);
+
+        assert!("10,0".parse::<Sprt>().is_err());
+        assert!("0,5,0.6,0.6".parse::<Sprt>().is_err());
+    }
+
+    #[test]
+    fn test_play_game(){
+        let (strong, weak) = (Contestant::depth(2), Contestant::depth(0));
+        let (mut strong_mover, mut weak_mover) = (Mover::new(&strong), Mover::new(&weak));
+        let names = PlayerMap::new(strong.name.clone(), weak.name.clone());
+        let record = play_game(
+            &mut PlayerMap::new(&mut strong_mover, &mut weak_mover),
+            &names, &Position::setup(), DrawRules::default()
+        );
+
+        assert_eq!(record.players, names);
+        assert!(record.outcome.is_some());
+        let replayed = MatchState::from_record(&record).unwrap();
+        assert_eq!(replayed.outcome(), record.outcome);
+    }
+}
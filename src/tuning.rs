@@ -0,0 +1,205 @@
+//! Texel tuning of the evaluation weights, for the `tune` binary.
+//!
+//! Each position of a finished game is labelled with the game's result.
+//! The weights are fitted so that `sigmoid(k * eval)` predicts that result,
+//! minimizing the cross-entropy over all positions.
+
+use crate::tokonoma::{EvalFeatures, EvalParams, GameRecord, MatchState, Player, RecordError, Termination};
+
+/// A position of a finished game.
+#[derive(Clone, Copy, Debug)]
+pub struct Sample{
+    pub features : EvalFeatures,
+    /// White's result: 1 for a win, 0.5 for a draw, 0 for a loss.
+    pub result : f32,
+}
+
+/// Positions of a record after the first `skip_plies`, leaving out those decided on the board.
+/// Unfinished and forfeited games give none, since their moves do not explain the result.
+pub fn samples_from_record(record : &GameRecord, skip_plies : usize) -> Result<Vec<Sample>, RecordError>{
+    let Some(outcome) = record.outcome else {return Ok(vec![])};
+    if record.termination == Termination::Forfeit{
+        return Ok(vec![]);
+    }
+    let result = match outcome.winner(){
+        Some(Player::White) => 1.0,
+        Some(Player::Black) => 0.0,
+        None => 0.5,
+    };
+
+    let match_state = MatchState::from_record(record)?;
+    Ok(match_state.history().iter().map(|entry|&entry.state_before)
+        .skip(skip_plies)
+        .filter_map(|position|position.eval_features().ok())
+        .map(|features|Sample{features, result})
+        .collect())
+}
+
+fn sigmoid(x : f64) -> f64{
+    1.0 / (1.0 + (-x).exp())
+}
+
+/// Mean cross-entropy of the predictions `sigmoid(k * eval)`.
+pub fn loss(samples : &[Sample], params : &EvalParams, k : f64) -> f64{
+    if samples.is_empty(){
+        return 0.0;
+    }
+    // keeps the logarithms finite
+    const EPSILON : f64 = 1e-12;
+    samples.iter().map(|sample|{
+        let predicted = sigmoid(k * params.evaluate(&sample.features) as f64).clamp(EPSILON, 1.0 - EPSILON);
+        let result = sample.result as f64;
+        -(result * predicted.ln() + (1.0 - result) * (1.0 - predicted).ln())
+    }).sum::<f64>() / samples.len() as f64
+}
+
+/// The scale `k` that best fits the samples with fixed weights,
+/// so that tuning changes their balance rather than their overall size.
+pub fn fit_scale(samples : &[Sample], params : &EvalParams) -> f64{
+    // ternary search on log k
+    let (mut low, mut high) = (1e-4f64.ln(), 10f64.ln());
+    for _ in 0..60{
+        let third = (high - low) / 3.0;
+        let (a, b) = (low + third, high - third);
+        if loss(samples, params, a.exp()) < loss(samples, params, b.exp()){
+            high = b;
+        } else {
+            low = a;
+        }
+    }
+    ((low + high) / 2.0).exp()
+}
+
+/// Gradient of `loss` with respect to the weights, in the order of `EvalParams::to_array`.
+fn gradient(samples : &[Sample], params : &EvalParams, k : f64) -> [f64; EvalParams::COUNT]{
+    let weights = params.to_array();
+    let mut gradient = [0.0; EvalParams::COUNT];
+    for sample in samples{
+        let raw : f32 = weights.iter().zip(&sample.features).map(|(w, f)|w * f).sum();
+        let eval = params.evaluate(&sample.features);
+        if eval != raw{
+            // clamped, the weights make no difference
+            continue;
+        }
+        let error = sigmoid(k * eval as f64) - sample.result as f64;
+        for (g, f) in gradient.iter_mut().zip(&sample.features){
+            *g += k * error * *f as f64;
+        }
+    }
+    gradient.map(|g|g / samples.len().max(1) as f64)
+}
+
+/// Keeps the weights meaningful: piece values, mobility and passed flat bonuses
+/// are not negative, and passed flats never outweigh a decided game.
+fn constrain(params : EvalParams) -> EvalParams{
+    let nonnegative = |v : f32|v.max(0.0);
+    EvalParams{
+        flat : nonnegative(params.flat),
+        hand : nonnegative(params.hand),
+        blind : nonnegative(params.blind),
+        star : nonnegative(params.star),
+        mobility : nonnegative(params.mobility),
+        passed_to_play : params.passed_to_play.map(|v|v.clamp(0.0, 200.0)),
+        passed_waiting : params.passed_waiting.map(|v|v.clamp(0.0, 200.0)),
+        ..params
+    }
+}
+
+/// Fits the weights to the samples from `start`, with `epochs` full-batch steps of Adam.
+/// `on_epoch` gets the epoch number, the loss before the step and the weights.
+pub fn tune(samples : &[Sample], start : &EvalParams, k : f64, epochs : usize, learning_rate : f64,
+    mut on_epoch : impl FnMut(usize, f64, &EvalParams)) -> EvalParams{
+    const BETA1 : f64 = 0.9;
+    const BETA2 : f64 = 0.999;
+    const EPSILON : f64 = 1e-8;
+
+    let mut params = *start;
+    let mut first_moment = [0.0; EvalParams::COUNT];
+    let mut second_moment = [0.0; EvalParams::COUNT];
+
+    for epoch in 0..epochs{
+        on_epoch(epoch, loss(samples, &params, k), &params);
+
+        let gradient = gradient(samples, &params, k);
+        let mut weights = params.to_array();
+        let step = (epoch + 1) as i32;
+        for i in 0..EvalParams::COUNT{
+            first_moment[i] = BETA1 * first_moment[i] + (1.0 - BETA1) * gradient[i];
+            second_moment[i] = BETA2 * second_moment[i] + (1.0 - BETA2) * gradient[i] * gradient[i];
+            let corrected_first = first_moment[i] / (1.0 - BETA1.powi(step));
+            let corrected_second = second_moment[i] / (1.0 - BETA2.powi(step));
+            weights[i] -= (learning_rate * corrected_first / (corrected_second.sqrt() + EPSILON)) as f32;
+        }
+        params = constrain(EvalParams::from_array(&weights));
+    }
+    params
+}
+
+#[cfg(test)]
+mod tests{
+    use ::rand::seq::SliceRandom;
+
+    use super::*;
+
+    /// Random games labelled by who is ahead in material at the end,
+    /// which tuning should pick up whatever the starting weights.
+    fn material_samples() -> Vec<Sample>{
+        let mut rng = ::rand::thread_rng();
+        let mut samples = vec![];
+        for _ in 0..40{
+            let mut match_state = MatchState::setup();
+            for _ in 0..30{
+                if match_state.outcome().is_some(){
+                    break;
+                }
+                let ply = *match_state.present_state().valid_moves().choose(&mut rng).unwrap();
+                match_state.apply_move(ply);
+            }
+            let Ok(last) = match_state.present_state().eval_features() else {continue};
+            let material = last[..4].iter().sum::<f32>();
+            let result = if material > 0.0 {1.0} else if material < 0.0 {0.0} else {0.5};
+            samples.extend(match_state.history().iter()
+                .filter_map(|entry|entry.state_before.eval_features().ok())
+                .map(|features|Sample{features, result}));
+        }
+        samples
+    }
+
+    #[test]
+    fn test_tuning_lowers_the_loss(){
+        let samples = material_samples();
+        let start = EvalParams{flat : 0.5, hand : 0.5, blind : 0.5, star : 0.5, ..EvalParams::DEFAULT};
+        let k = fit_scale(&samples, &start);
+        assert!(k > 0.0);
+
+        let mut losses = vec![];
+        let tuned = tune(&samples, &start, k, 50, 0.05, |_, loss, _|losses.push(loss));
+        assert_eq!(losses.len(), 50);
+        assert!(loss(&samples, &tuned, k) < losses[0]);
+        assert!(tuned.passed_to_play.iter().chain(&tuned.passed_waiting).all(|v|(0.0..=200.0).contains(v)));
+    }
+
+    #[test]
+    fn test_samples_from_record(){
+        let mut match_state = MatchState::setup();
+        while match_state.outcome().is_none(){
+            let ply = match_state.present_state().valid_moves()[0];
+            match_state.apply_move(ply);
+        }
+        let record = match_state.to_record();
+        let samples = samples_from_record(&record, 2).unwrap();
+        assert!(!samples.is_empty() && samples.len() <= record.moves.len() - 2);
+
+        let expected = match match_state.outcome().unwrap().winner(){
+            Some(Player::White) => 1.0,
+            Some(Player::Black) => 0.0,
+            None => 0.5,
+        };
+        assert!(samples.iter().all(|s|s.result == expected));
+
+        let forfeit = GameRecord{termination : Termination::Forfeit, ..record.clone()};
+        assert!(samples_from_record(&forfeit, 0).unwrap().is_empty());
+        let unfinished = GameRecord{outcome : None, termination : Termination::Unterminated, ..record};
+        assert!(samples_from_record(&unfinished, 0).unwrap().is_empty());
+    }
+}
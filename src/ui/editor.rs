@@ -37,7 +37,7 @@ impl PositionEditor{
     }
 
     pub fn tabulation_hash(&self)->u64{
-        self.state.tabulation_hash()
+        self.state.zobrist()
     }
 
     fn push_history(&mut self){
@@ -120,11 +120,11 @@ impl PositionEditor{
         }
 
         once(None).chain(
-            (0..7).map(|i|Species::from_code(i))
+            (0..7).map(Species::from_code)
             .flat_map(|species|[
                 Piece{color:Player::White,species},
                 Piece{color:Player::Black,species}
-            ]).map(|piece|Some(piece))
+            ]).map(Some)
         ).enumerate().for_each(|(i,brush)|{
 
             let y = -8.0 + ((i/5) as f32 ) * 1.2;
@@ -173,10 +173,8 @@ impl PositionEditor{
         }
 
         let mqui = MqUi::new(camera);
-        if self.can_undo(){
-            if self.btn_undo.process(&mqui){
-                self.undo();
-            }
+        if self.can_undo() && self.btn_undo.process(&mqui){
+            self.undo();
         }
     }
 
@@ -1,36 +1,43 @@
 use std::sync::{Arc, Mutex};
 
-use crate::{theme::{self, egui_ctx_setup, set_theme}, tokonoma::{Captured, EvalResult, HistoryEntry, PlayerMap, TranspositionalTable}, Ply, Position};
-use coroutines::stop_all_coroutines;
+use crate::{theme::{self, egui_ctx_setup, set_theme}, tokonoma::{Captured, EvalResult, HistoryEntry, PlayerMap, SearchLimits, SearchOutcome, StopToken, Tablebase, TranspositionalTable}, Ply};
 use egui::Margin;
 use macroquad::prelude::*;
 use macroquad::experimental::coroutines::{start_coroutine,Coroutine};
 
 use super::editor::PositionEditor;
 
-type EngineResults = Vec<(Ply, HistoryEntry, EvalResult)>;
+/// Root move, its notation, evaluation and the notation of the expected continuation.
+type EngineResults = Vec<(Ply, HistoryEntry, EvalResult, String)>;
 
 pub struct EngineEvalUI{
     editor : PositionEditor,
 
     max_depth : usize,
-    job : Option<Coroutine<(usize,EngineResults)>>,
+    job : Option<Coroutine<SearchOutcome>>,
+    stop : StopToken,
+    /// Last completed iteration of the running job, not yet shown
+    progress : Arc<Mutex<Option<SearchOutcome>>>,
     results : Option<(usize,EngineResults)>,
 
-    table : Arc<Mutex<TranspositionalTable>>,
+    table : Arc<TranspositionalTable>,
+    tablebase : Option<Arc<Tablebase>>,
 
     last_position_hash : u64,
 }
 
 impl EngineEvalUI{
-    pub fn new(editor : PositionEditor)->EngineEvalUI{
+    pub fn new(editor : PositionEditor, tablebase : Option<Arc<Tablebase>>)->EngineEvalUI{
         let hash = editor.tabulation_hash();
         EngineEvalUI{
             editor ,
             max_depth : 6,
             job : None,
+            stop : StopToken::new(),
+            progress : Arc::new(Mutex::new(None)),
             results : None,
-            table : Arc::new(Mutex::new(TranspositionalTable::new())),
+            table : Arc::new(TranspositionalTable::with_size_mb(64)),
+            tablebase,
             last_position_hash : hash,
         }
     }
@@ -38,37 +45,53 @@ impl EngineEvalUI{
 
     fn set_dirty(&mut self){
         self.results = None;
-        if let Some(..) = &self.job{
-            // stop_coroutine(_coroutine);
-            stop_all_coroutines();
+        if self.job.is_some(){
+            self.stop.stop();
             self.job = None;
         }
     }
 
-    async fn the_job(position : Position, depth : usize, transp : Arc<Mutex<TranspositionalTable>>) -> (usize, EngineResults){
-        let mquad_frame_await = depth > 5;
-        let dummy_captures = PlayerMap::twin(Captured::empty());
-
-        let results : EngineResults = position.clone()
-        .moves_with_score(depth, mquad_frame_await, Some(transp))
-        .await
-        .into_iter().map(move |(ply,eval)|(ply,position.compute_history_entry(ply, dummy_captures.clone()),eval))
-        .collect();
-
-
-        (depth, results)
-    }
-
-    fn start_scan(&mut self,  depth : usize){
+    fn start_scan(&mut self){
         let position =  self.editor.get_state_clone();
-        self.job = Some(start_coroutine(Self::the_job(position, depth, self.table.clone())))
+        let mquad_frame_await = self.max_depth > 5;
+
+        self.stop = StopToken::new();
+        self.progress = Arc::new(Mutex::new(None));
+        let progress = self.progress.clone();
+
+        self.job = Some(start_coroutine(position.search(
+            SearchLimits::depth(self.max_depth).with_tablebase(self.tablebase.clone()),
+            self.stop.clone(),
+            Some(self.table.clone()),
+            mquad_frame_await,
+            move |outcome|{*progress.lock().unwrap() = Some(outcome.clone());}
+        )))
     }
 
     fn recompute(&mut self){
         self.set_dirty();
-        
+        self.start_scan();
+    }
+
+    fn collect_progress(&mut self){
+        if let Some(outcome) = self.progress.lock().unwrap().take(){
+            let position = self.editor.get_state_clone();
+            let dummy_captures = PlayerMap::twin(Captured::empty());
 
-        self.start_scan(0);
+            let results : EngineResults = outcome.moves.into_iter()
+            .map(|(ply,eval)|{
+                let entry = position.compute_history_entry(ply, dummy_captures.clone());
+                let continuation = entry.state_after.line_notation(&eval.pv[1..]);
+                (ply,entry,eval,continuation)
+            })
+            .collect();
+
+            self.results = Some((outcome.depth, results));
+        }
+
+        if self.job.as_ref().is_some_and(|job|job.is_done()){
+            self.job = None;
+        }
     }
 
     fn apply_move(&mut self, ply : Ply){
@@ -89,15 +112,7 @@ impl EngineEvalUI{
                 self.last_position_hash = self.editor.tabulation_hash();
             }
 
-            if let Some(job) = &self.job {
-                if let Some((depth, results)) = job.retrieve(){
-                    self.results = Some((depth,results));
-
-                    if depth < self.max_depth{
-                        self.start_scan(depth+1);
-                    }
-                }
-            }
+            self.collect_progress();
 
             
 
@@ -141,9 +156,9 @@ impl EngineEvalUI{
                     if let Some((last_depth,results)) = &self.results{
                         egui::ScrollArea::vertical().id_source("engine_evals").show(ui,|ui|{
                             ui.label(format!("Computed at {}-ply depth.",last_depth));
-                            results.iter().for_each(|(ply,entry, eval_result)|{
+                            results.iter().for_each(|(ply,entry, eval_result, continuation)|{
                                 let lbl = ui.add(egui::Label::new(
-                                    format!("{} {} [{}]", eval_result.score, entry, eval_result.nodes)
+                                    format!("{} {} [{}]  {}", eval_result.score, entry, eval_result.nodes, continuation)
                                 )
                                 .sense(egui::Sense::click().union(egui::Sense::hover() )));
                                 if lbl.clicked(){
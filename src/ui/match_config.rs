@@ -2,7 +2,7 @@ use egui::{FontFamily, FontId, Margin, TextStyle};
 
 use super::{editor::PositionEditor, engine_eval::EngineEvalUI, theme_config};
 
-use crate::{ assets::{get_assets_unchecked, mipmaps::set_cam}, gameplay::{GamerSpec, MatchConfig}, theme::{self, egui_ctx_setup, set_theme}, Player, Tile};
+use crate::{ assets::{get_assets_unchecked, mipmaps::set_cam}, gameplay::{BotFiles, GamerSpec, MatchConfig, ViewConfig}, theme::{self, egui_ctx_setup, set_theme}, tokonoma::{correspondence, DrawRules}, Player, Tile};
 use macroquad::window::{clear_background, next_frame, screen_height};
 
 use macroquad::prelude::*;
@@ -28,25 +28,30 @@ impl Transition{
     }
 }
 
-pub async fn match_config_ui(last_match_config : Option<MatchConfig>) -> MatchConfig{
-    let choices : Vec<GamerSpec> = [
-        GamerSpec::Human,
-        GamerSpec::Gibberish,
-        GamerSpec::Noob,
-        GamerSpec::Decent,
-        GamerSpec::Sharp,
-        GamerSpec::Tough,
-        GamerSpec::GrandMaster
-        
-    ].into_iter().chain((5..=8).map(|depth|GamerSpec::Perfect { depth }))
+/// The match to play, or a game to look at instead.
+pub async fn match_config_ui(last_match_config : Option<MatchConfig>, files : &BotFiles) -> (MatchConfig, Option<ViewConfig>){
+    #[allow(unused_mut)]
+    let mut choices : Vec<GamerSpec> = GamerSpec::LEVELS.into_iter().chain((5..=8).map(|depth|GamerSpec::Perfect { depth }))
     .collect();
+    // no subprocesses or sockets on the web
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        use crate::networking::DEFAULT_PORT;
+        choices.push(GamerSpec::External {
+            command : crate::external_engine::bundled_engine_command().unwrap_or_default()
+        });
+        choices.push(GamerSpec::Online { host : true, address : format!("0.0.0.0:{}", DEFAULT_PORT) });
+        choices.push(GamerSpec::Online { host : false, address : format!("127.0.0.1:{}", DEFAULT_PORT) });
+    }
+    choices.push(GamerSpec::Correspondence { token : String::new() });
 
 
     let mut match_config = last_match_config.unwrap_or(MatchConfig{
         gamers : [GamerSpec::Human, GamerSpec::Noob],
         gamer_one_color : None,
         allow_takeback : true,
-        starting_position : None
+        starting_position : None,
+        draw_rules : DrawRules::default(),
     });
 
 
@@ -56,6 +61,11 @@ pub async fn match_config_ui(last_match_config : Option<MatchConfig>) -> MatchCo
 
     let mut open_engine_eval_ui = Transition::closed();
     let mut open_theming_ui = Transition::closed();
+    #[allow(unused_mut)]
+    let mut open_viewer_ui = Transition::closed();
+    // hosts waiting for a guest on the local network
+    #[cfg(not(target_arch = "wasm32"))]
+    let mut lobby = crate::networking::Lobby::open().map_err(|e|e.to_string());
     
     loop {
         clear_background(theme::BG_COLOR);
@@ -77,6 +87,11 @@ pub async fn match_config_ui(last_match_config : Option<MatchConfig>) -> MatchCo
         // let panel_col = egui::Color32::from_rgba_premultiplied(bg_col32.r(), bg_col32.g(), bg_col32.b(), 30);
         draw_rectangle(screen_width()*0.55, 0.0, screen_width()*0.5, screen_height(), panel_col);
 
+        #[cfg(not(target_arch = "wasm32"))]
+        let open_seats = lobby.as_mut().map(|lobby|lobby.seats()).map_err(|e|e.clone());
+        #[cfg(not(target_arch = "wasm32"))]
+        let mut joined = None;
+
         egui_macroquad::ui(|egui_ctx|{
             egui_ctx_setup(egui_ctx);
             egui::SidePanel::right(egui::Id::new("match_ui"))
@@ -113,19 +128,26 @@ pub async fn match_config_ui(last_match_config : Option<MatchConfig>) -> MatchCo
         
                             
                             egui::ComboBox::from_id_source(format!("player{}",gamer_idx+1))
-                            .selected_text(format!("{}",gamer_spec.name()))
+                            .selected_text(gamer_spec.name())
                             .width(150.0)
                             .show_ui(ui,|ui|{
                                 // ui.spacing_mut().item_spacing.y = 30.0;
-                                choices.iter().for_each(|&gamer_option|{
-                                    let lbl = egui::SelectableLabel::new(*gamer_spec == gamer_option, 
+                                choices.iter().for_each(|gamer_option|{
+                                    // external engines and online players match whatever their command or address
+                                    let selected = match (&*gamer_spec, gamer_option){
+                                        (GamerSpec::External{..}, GamerSpec::External{..}) => true,
+                                        (GamerSpec::Online{host, ..}, GamerSpec::Online{host : option_host, ..}) => host == option_host,
+                                        (GamerSpec::Correspondence{..}, GamerSpec::Correspondence{..}) => true,
+                                        (spec, option) => spec == option
+                                    };
+                                    let lbl = egui::SelectableLabel::new(selected, 
                                         egui::RichText::new(gamer_option.name())
                                         .size(18.0)
                                     );
 
                                     let mut resp = ui.add(lbl);
-                                    if resp.clicked() && *gamer_spec!=gamer_option{
-                                        *gamer_spec = gamer_option;
+                                    if resp.clicked() && !selected{
+                                        *gamer_spec = gamer_option.clone();
                                         resp.mark_changed();
                                     };
 
@@ -134,7 +156,31 @@ pub async fn match_config_ui(last_match_config : Option<MatchConfig>) -> MatchCo
                             });
 
         
-                            ui.label(gamer_spec.description());
+                            match gamer_spec{
+                                GamerSpec::External { command } => {
+                                    ui.label("Command:");
+                                    ui.add(egui::TextEdit::singleline(command).desired_width(180.0));
+                                },
+                                GamerSpec::Online { host, address } => {
+                                    ui.label("Address:");
+                                    ui.add(egui::TextEdit::singleline(address).desired_width(180.0));
+                                    if !*host{
+                                        ui.label("The host sets colours and takebacks.");
+                                    }
+                                },
+                                GamerSpec::Correspondence { token } => {
+                                    ui.label("Token received:");
+                                    ui.add(egui::TextEdit::singleline(token).desired_width(180.0)
+                                        .hint_text("empty for a new game"));
+                                    if !token.trim().is_empty(){
+                                        match correspondence::decode(token){
+                                            Ok(..) => ui.label("The token sets colours."),
+                                            Err(e) => ui.label(format!("Invalid token: {}.", e)),
+                                        };
+                                    }
+                                },
+                                _ => {ui.label(gamer_spec.description());}
+                            }
 
                             ui.add_space(20.0);
 
@@ -201,7 +247,8 @@ pub async fn match_config_ui(last_match_config : Option<MatchConfig>) -> MatchCo
 
                 ui.horizontal(|ui|{
                     ui.add_enabled(
-                        (match_config.gamers[0] == GamerSpec::Human) | (match_config.gamers[1] == GamerSpec::Human), 
+                        match_config.gamers.iter().any(|g|matches!(g, GamerSpec::Human | GamerSpec::Online{host : true, ..}))
+                            && !match_config.gamers.iter().any(|g|matches!(g, GamerSpec::Correspondence{..})),
                         egui::Checkbox::new(&mut match_config.allow_takeback, "Allow taking back moves")
                     );
                 });
@@ -210,9 +257,37 @@ pub async fn match_config_ui(last_match_config : Option<MatchConfig>) -> MatchCo
 
                 ui.add_space(15.0);
                 
-                if ui.button("Themes and colors...").clicked(){
-                    open_theming_ui.open();
-                };
+                ui.horizontal(|ui|{
+                    if ui.button("Themes and colors...").clicked(){
+                        open_theming_ui.open();
+                    };
+                    #[cfg(not(target_arch = "wasm32"))]
+                    if ui.button("Watch or replay...").clicked(){
+                        open_viewer_ui.open();
+                    };
+                });
+
+                #[cfg(not(target_arch = "wasm32"))]
+                {
+                    ui.add_space(15.0);
+                    ui.label("Open seats on this network:");
+                    egui::ScrollArea::vertical().id_source("lobby").max_height(80.0).show(ui, |ui|{
+                        match &open_seats{
+                            Err(e) => {ui.label(format!("Not listening: {}", e));},
+                            Ok(seats) if seats.is_empty() => {ui.label("None yet. Hosts appear here.");},
+                            Ok(seats) => for seat in seats{
+                                ui.horizontal(|ui|{
+                                    let guest = match seat.host_plays {Player::White => "Black", Player::Black => "White"};
+                                    ui.label(format!("{}: you play {}, takebacks {}",
+                                        seat.name, guest, if seat.takebacks {"on"} else {"off"}));
+                                    if ui.button("Join").clicked(){
+                                        joined = Some(seat.address);
+                                    }
+                                });
+                            },
+                        }
+                    });
+                }
                 
                 
 
@@ -230,10 +305,16 @@ pub async fn match_config_ui(last_match_config : Option<MatchConfig>) -> MatchCo
                             size: 30.0, 
                             family: FontFamily::Proportional 
                         });
-                    let start_button = ui.add_sized(
+                    let tokens_valid = match_config.gamers.iter().all(|g|match g{
+                        GamerSpec::Correspondence { token } => token.trim().is_empty() || correspondence::decode(token).is_ok(),
+                        _ => true
+                    });
+                    // a single connection per match
+                    let online_seats = match_config.gamers.iter().filter(|g|matches!(g, GamerSpec::Online{..})).count();
+                    let start_button = ui.add_enabled_ui(tokens_valid && online_seats <= 1, |ui|ui.add_sized(
                         [200.0,50.0],
                         egui::Button::new("Start Match")
-                    );
+                    ).on_disabled_hover_text(if online_seats > 1 {"Only one seat can be online."} else {"A correspondence token is not valid."})).inner;
                     if start_button.clicked(){
                         break_out = Some(());
                     };
@@ -249,10 +330,20 @@ pub async fn match_config_ui(last_match_config : Option<MatchConfig>) -> MatchCo
 
 
         egui_macroquad::draw();
+
+        // the host sets the terms, the first gamer plays locally
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(address) = joined{
+            if matches!(match_config.gamers[0], GamerSpec::Online{..} | GamerSpec::Correspondence{..}){
+                match_config.gamers[0] = GamerSpec::Human;
+            }
+            match_config.gamers[1] = GamerSpec::Online{host : false, address : address.to_string()};
+            break_out = Some(());
+        }
         
         if open_engine_eval_ui.pop(){
             let editor = match_config.starting_position.unwrap_or(PositionEditor::setup());
-            let evaled_state = EngineEvalUI::new(editor).run().await;
+            let evaled_state = EngineEvalUI::new(editor, files.tablebase.clone()).run().await;
             match_config.starting_position = Some(evaled_state);
             
         }
@@ -262,6 +353,13 @@ pub async fn match_config_ui(last_match_config : Option<MatchConfig>) -> MatchCo
             
         }
 
+        if open_viewer_ui.pop(){
+            #[cfg(not(target_arch = "wasm32"))]
+            if let Some(view_config) = super::viewer::viewer_ui().await{
+                return (match_config, Some(view_config));
+            }
+        }
+
         
         if let Some(()) = break_out{
             break;
@@ -287,14 +385,14 @@ pub async fn match_config_ui(last_match_config : Option<MatchConfig>) -> MatchCo
             );
             if pid > 0 {base_color = base_color.flip()};
 
-            let av_offset = if match_config.gamers[pid] == GamerSpec::Human {0} else {1};
+            let av_offset = if matches!(match_config.gamers[pid], GamerSpec::Human | GamerSpec::Online{..} | GamerSpec::Correspondence{..}) {0} else {1};
 
             
             let (avatar_tex,src) = assets.get_avatar(
                 base_color, 
                 av_offset);
 
-            if let Some(..) = match_config.gamer_one_color{
+            if match_config.gamer_one_color.is_some(){
                 avatar_tex.draw( 
                     x-size.x*0.5, 
                     y-size.y*0.5, 
@@ -369,5 +467,5 @@ pub async fn match_config_ui(last_match_config : Option<MatchConfig>) -> MatchCo
         next_frame().await
     };
 
-    match_config
+    (match_config, None)
 }
\ No newline at end of file
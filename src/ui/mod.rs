@@ -3,6 +3,10 @@ pub mod rulesheet;
 pub mod match_config;
 pub mod engine_eval;
 pub mod theme_config;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod online;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod viewer;
 
 
 use macroquad::prelude::*;
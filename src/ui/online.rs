@@ -0,0 +1,147 @@
+use std::net::TcpListener;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+
+use egui::Margin;
+use macroquad::prelude::*;
+use ::rand::Rng;
+
+use crate::gameplay::{GamerSpec, MatchConfig};
+use crate::networking::{default_player_name, with_default_port, Advertiser, Connection, MatchOffer, NetworkError, OnlineMatch, Spectator};
+use crate::theme::{self, egui_ctx_setup, set_theme};
+use crate::tokonoma::{Position, StopToken};
+use crate::{Player, Tile};
+
+use super::editor::PositionEditor;
+
+/// How often a waiting host checks for a guest or a cancellation.
+const ACCEPT_POLL : std::time::Duration = std::time::Duration::from_millis(50);
+
+/// Waits for a guest on `address`, announcing the seat on the local network meanwhile,
+/// then offers the match.
+fn host(address : &str, offer : MatchOffer, setup : Position, cancel : &StopToken) -> Result<Option<OnlineMatch>, NetworkError>{
+    let listener = TcpListener::bind(with_default_port(address))?;
+    listener.set_nonblocking(true)?;
+    // guests can still type the address without it
+    let advertiser = Advertiser::start(&default_player_name(), &offer, listener.local_addr()?.port()).ok();
+    let stream = loop{
+        if cancel.is_stopped(){
+            return Ok(None);
+        }
+        match listener.accept(){
+            Ok((stream, _)) => break stream,
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => std::thread::sleep(ACCEPT_POLL),
+            Err(e) => return Err(e.into()),
+        }
+    };
+    drop(advertiser);
+    stream.set_nonblocking(false)?;
+    let connection = Connection::open(stream, &default_player_name())?;
+    OnlineMatch::host(connection, offer, setup).map(Some)
+}
+
+/// Connects the online seat of `match_config`, if it has one, showing progress meanwhile.
+/// The terms agreed replace those configured. `None` when cancelled.
+pub async fn connect_ui(mut match_config : MatchConfig) -> Option<(MatchConfig, Option<OnlineMatch>)>{
+    let Some(seat) = match_config.gamers.iter().position(|g|matches!(g, GamerSpec::Online{..})) else {
+        return Some((match_config, None));
+    };
+    let GamerSpec::Online { host : hosting, address } = match_config.gamers[seat].clone() else {unreachable!()};
+
+    let cancel = StopToken::new();
+    let (sender, receiver) = mpsc::channel();
+    let shown_address = with_default_port(&address);
+    let status = if hosting{
+        // the colour of the first gamer, resolved now since the guest must know it
+        let first_color = match_config.gamer_one_color.unwrap_or_else(||
+            if ::rand::thread_rng().gen::<bool>() {Player::White} else {Player::Black});
+        let offer = MatchOffer{
+            host_plays : if seat == 0 {first_color.flip()} else {first_color},
+            takebacks : match_config.allow_takeback,
+            draw_rules : match_config.draw_rules,
+        };
+        let setup = match_config.starting_position.as_ref()
+            .map(|editor|editor.get_state_clone())
+            .unwrap_or(Position::setup());
+        let cancel = cancel.clone();
+        std::thread::spawn(move ||{
+            let _ = sender.send(host(&address, offer, setup, &cancel));
+        });
+        format!("Waiting for a guest on {}...", shown_address)
+    } else {
+        std::thread::spawn(move ||{
+            let _ = sender.send(Connection::connect(&address, &default_player_name()).and_then(OnlineMatch::join).map(Some));
+        });
+        format!("Connecting to {}...", shown_address)
+    };
+
+    let online = wait_ui(&status, &receiver, &cancel).await?;
+
+    // the first gamer's colour, from the seat of the online one
+    let local = online.local;
+    match_config.gamer_one_color = Some(if seat == 0 {local.flip()} else {local});
+    match_config.allow_takeback = online.offer().takebacks;
+    match_config.draw_rules = online.offer().draw_rules;
+    let position = online.match_state().present_state().clone();
+    match_config.starting_position = (position != Position::setup()).then(||PositionEditor::from_state(position));
+    Some((match_config, Some(online)))
+}
+
+/// Connects to the server at `address` to watch a room, showing progress meanwhile. `None` when cancelled.
+pub async fn watch_ui(address : &str, room : &str) -> Option<Spectator>{
+    let (sender, receiver) = mpsc::channel();
+    let status = format!("Connecting to room {} at {}...", room, with_default_port(address));
+    let (address, room) = (address.to_owned(), room.to_owned());
+    std::thread::spawn(move ||{
+        let _ = sender.send(Connection::connect(&address, &default_player_name())
+            .and_then(|connection|Spectator::watch(connection, &room)).map(Some));
+    });
+    wait_ui(&status, &receiver, &StopToken::new()).await
+}
+
+/// Shows `status` until the connecting thread is done, or an error and a way back.
+async fn wait_ui<T>(status : &str, receiver : &Receiver<Result<Option<T>, NetworkError>>, cancel : &StopToken) -> Option<T>{
+    let mut failure = None;
+    loop{
+        match receiver.try_recv(){
+            Ok(Ok(Some(connected))) => return Some(connected),
+            Ok(Ok(None)) => return None,
+            Ok(Err(e)) => failure = Some(e.to_string()),
+            Err(TryRecvError::Disconnected) if failure.is_none() => return None,
+            Err(_) => {},
+        }
+
+        clear_background(theme::BG_COLOR);
+        set_camera(&Camera2D{
+            zoom : 0.3*vec2(screen_height()/screen_width(), 1.0),
+            ..Default::default()
+        });
+        Tile::draw_board(false);
+        set_default_camera();
+
+        let mut back = false;
+        egui_macroquad::ui(|egui_ctx|{
+            egui_ctx_setup(egui_ctx);
+            egui::CentralPanel::default()
+            .frame(egui::Frame::none().inner_margin(Margin::symmetric(75.0, 250.0)))
+            .show(egui_ctx, |ui|{
+                set_theme(ui);
+                match &failure{
+                    Some(e) => {ui.label(format!("Could not start the match: {}", e));},
+                    None => {ui.label(status);},
+                }
+                ui.add_space(20.0);
+                if ui.button(if failure.is_some() {"Back"} else {"Cancel"}).clicked(){
+                    back = true;
+                }
+            });
+        });
+        egui_macroquad::draw();
+
+        if back{
+            // a guest still connecting gives up on its own, after the handshake timeout
+            cancel.stop();
+            return None;
+        }
+        next_frame().await;
+    }
+}
@@ -108,17 +108,14 @@ pub async fn theme_panel(){
                         }
                             
                         
-                        match cfg.board_palette{
-                            BoardPaletteConfig::Custom(ref mut pal) => {
-                                let mut cols = pal.to_egui();
+                        if let BoardPaletteConfig::Custom(ref mut pal) = cfg.board_palette {
+                            let mut cols = pal.to_egui();
 
-                                cols.iter_mut().enumerate().for_each(|(_,c)|{
-                                    ui.color_edit_button_srgb(c);
-                                });
+                            cols.iter_mut().for_each(|c|{
+                                ui.color_edit_button_srgb(c);
+                            });
 
-                                *pal = BoardPalette::from_egui(cols);
-                            },
-                            _ => {}
+                            *pal = BoardPalette::from_egui(cols);
                         }
                     },
                     _ => {}
@@ -149,9 +146,7 @@ pub async fn theme_panel(){
         egui_macroquad::draw();
 
         if let Some(pset) = pieceset_toset{
-            THEME_CONFIG.write().unwrap().set_pieceset(pset)
-            .await
-
+            THEME_CONFIG.write().unwrap().set_pieceset(pset);
         }
  
         next_frame().await;
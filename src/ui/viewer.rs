@@ -0,0 +1,113 @@
+use egui::Margin;
+use macroquad::prelude::*;
+
+use crate::gameplay::ViewConfig;
+use crate::networking::{is_room_code, DEFAULT_PORT};
+use crate::theme::{self, egui_ctx_setup, set_theme};
+use crate::tokonoma::{GameRecord, MatchState};
+use crate::{Player, Tile};
+
+/// Records the server appends to by default.
+const DEFAULT_RECORDS : &str = "server_games.txt";
+
+/// One line about a saved game.
+fn describe(record : &GameRecord) -> String{
+    let result = record.outcome.map_or("unfinished".to_owned(), |outcome|outcome.to_string());
+    format!("{} vs {}, {} plies, {}", record.players[Player::White], record.players[Player::Black],
+        record.moves.len(), result)
+}
+
+/// Asks for a room of a game server to watch, or a file of records to pick one from.
+/// `None` when going back.
+pub async fn viewer_ui() -> Option<ViewConfig>{
+    let mut address = format!("127.0.0.1:{}", DEFAULT_PORT);
+    let mut room = String::new();
+    let mut path = DEFAULT_RECORDS.to_owned();
+    let mut records : Vec<GameRecord> = vec![];
+    let mut message : Option<String> = None;
+
+    loop{
+        clear_background(theme::BG_COLOR);
+        set_camera(&Camera2D{
+            zoom : 0.3*vec2(screen_height()/screen_width(), 1.0),
+            ..Default::default()
+        });
+        Tile::draw_board(false);
+        set_default_camera();
+
+        let mut chosen = None;
+        let mut back = false;
+        egui_macroquad::ui(|egui_ctx|{
+            egui_ctx_setup(egui_ctx);
+            egui::CentralPanel::default()
+            .frame(egui::Frame::none().inner_margin(Margin::symmetric(75.0, 100.0)))
+            .show(egui_ctx, |ui|{
+                set_theme(ui);
+
+                ui.heading("Watch a game");
+                ui.horizontal(|ui|{
+                    ui.label("Server:");
+                    ui.add(egui::TextEdit::singleline(&mut address).desired_width(200.0));
+                    ui.label("Room:");
+                    ui.add(egui::TextEdit::singleline(&mut room).desired_width(120.0));
+                    if ui.button("Watch").clicked(){
+                        let code = room.trim();
+                        if is_room_code(code){
+                            chosen = Some(ViewConfig::Live{address : address.clone(), room : code.to_owned()});
+                        } else {
+                            message = Some(format!("\"{}\" is not a room code.", code));
+                        }
+                    }
+                });
+
+                ui.add_space(30.0);
+                ui.heading("Replay a game");
+                ui.horizontal(|ui|{
+                    ui.label("Records:");
+                    ui.add(egui::TextEdit::singleline(&mut path).desired_width(300.0));
+                    if ui.button("Open").clicked(){
+                        // only the games that replay are offered
+                        match std::fs::read_to_string(&path){
+                            Ok(text) => {
+                                let parsed = GameRecord::parse_all(&text);
+                                let count = parsed.len();
+                                records = parsed.into_iter().filter_map(Result::ok)
+                                    .filter(|record|MatchState::from_record(record).is_ok()).collect();
+                                message = match count{
+                                    0 => Some(format!("No games in {}.", path)),
+                                    _ => (records.len() < count)
+                                        .then(||format!("{} of {} games could not be read.", count - records.len(), count)),
+                                };
+                            },
+                            Err(e) => {
+                                records.clear();
+                                message = Some(format!("Could not open {}: {}", path, e));
+                            }
+                        }
+                    }
+                });
+                egui::ScrollArea::vertical().max_height(250.0).show(ui, |ui|{
+                    for record in &records{
+                        if ui.button(describe(record)).clicked(){
+                            chosen = Some(ViewConfig::Record(record.clone()));
+                        }
+                    }
+                });
+
+                ui.add_space(20.0);
+                if let Some(message) = &message{
+                    ui.label(message);
+                }
+                if ui.button("Back").clicked(){
+                    back = true;
+                }
+            });
+        });
+        egui_macroquad::draw();
+
+        if chosen.is_some() || back{
+            return chosen;
+        }
+        next_frame().await;
+    }
+}
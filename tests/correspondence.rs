@@ -0,0 +1,50 @@
+//! A correspondence game between `tui` processes, passing tokens by hand.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Runs `tui` on the whole input and gives its output.
+fn tui(args : &[&str], input : &str) -> String{
+    let mut child = Command::new(env!("CARGO_BIN_EXE_tui"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// The last token the output says to send.
+fn sent_token(output : &str) -> String{
+    output.lines().rev()
+        .find_map(|line|line.split_once("send this token to ").map(|(_, rest)|rest))
+        .and_then(|rest|rest.split_once(": "))
+        .unwrap_or_else(||panic!("no token in {}", output))
+        .1.to_owned()
+}
+
+#[test]
+fn correspondence_game_with_tokens(){
+    let ada = tui(&["--correspondence", "white"], "Bb5\nBd2\n");
+    assert!(ada.contains("waiting for Black's move; paste their token"), "{}", ada);
+    let first = sent_token(&ada);
+
+    let bob = tui(&["--token", &first], "Bd2\n");
+    let second = sent_token(&bob);
+
+    // Ada resumes from the token sent, then pastes a tampered token, an old one and Bob's
+    let mut tampered = second.clone().into_bytes();
+    let last = tampered.len() - 2;
+    tampered[last] = if tampered[last] == b'A' {b'B'} else {b'A'};
+    let tampered = String::from_utf8(tampered).unwrap();
+    let ada = tui(&["--correspondence", "white", "--token", &first],
+        &format!("{}\n{}\n{}\nrecord\n", tampered, first, second));
+
+    assert!(ada.contains("token rejected: the token was altered or damaged"), "{}", ada);
+    assert!(ada.contains("token rejected: the token is out of date"), "{}", ada);
+    assert!(ada.contains("Black plays Bd2"), "{}", ada);
+    assert!(ada.contains("1. Bb5 Bd2 *"), "{}", ada);
+}
@@ -0,0 +1,117 @@
+//! Drives the `engine` binary through its text protocol.
+
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Command, Stdio};
+
+use hexstack::engine_protocol::EngineReply;
+use hexstack::tokonoma::Position;
+
+#[test]
+fn engine_answers_go(){
+    let mut child = Command::new(env!("CARGO_BIN_EXE_engine"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let mut stdin = child.stdin.take().unwrap();
+    writeln!(stdin, "hxe\nisready\nposition startpos moves Bb5\ngo depth 3").unwrap();
+
+    let mut replies : Vec<EngineReply> = vec![];
+    for line in BufReader::new(child.stdout.take().unwrap()).lines(){
+        replies.push(line.unwrap().parse().unwrap());
+        if matches!(replies.last(), Some(EngineReply::BestMove(..))){
+            break;
+        }
+    }
+    drop(stdin);
+    assert!(child.wait().unwrap().success());
+
+    assert!(replies.contains(&EngineReply::HelloOk));
+    assert!(replies.contains(&EngineReply::ReadyOk));
+    let depths : Vec<usize> = replies.iter().filter_map(|r|match r{
+        EngineReply::Info(info) => Some(info.depth),
+        _ => None
+    }).collect();
+    assert_eq!(depths, vec![1, 2, 3]);
+
+    let Some(EngineReply::BestMove(Some(ply))) = replies.last() else {panic!("{:?}", replies)};
+    let mut position = Position::setup();
+    position.apply_move(position.parse_move("Bb5").unwrap());
+    assert!(position.valid_moves().contains(ply));
+}
+
+#[test]
+fn engine_stops_at_the_end_of_input(){
+    let mut child = Command::new(env!("CARGO_BIN_EXE_engine"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // the GUI goes away during an infinite search
+    writeln!(child.stdin.take().unwrap(), "hxe\ngo infinite").unwrap();
+
+    let replies : Vec<EngineReply> = BufReader::new(child.stdout.take().unwrap()).lines()
+        .map(|line|line.unwrap().parse().unwrap())
+        .collect();
+    assert!(child.wait().unwrap().success());
+    assert!(matches!(replies.last(), Some(EngineReply::BestMove(Some(..)))), "{:?}", replies);
+}
+
+#[test]
+fn external_engine_plays_the_engine_binary(){
+    use hexstack::engine_protocol::GoParams;
+    use hexstack::external_engine::{EngineError, ExternalEngine};
+    use web_time::{Duration, Instant};
+
+    let mut engine = ExternalEngine::launch(env!("CARGO_BIN_EXE_engine")).unwrap();
+    assert!(engine.name.starts_with("hexstack"));
+
+    let mut position = Position::setup();
+    for _ in 0..4{
+        engine.start_search(&position, GoParams::depth(2)).unwrap();
+        let ply = engine.wait_move(Instant::now() + Duration::from_secs(30)).unwrap();
+        assert!(position.valid_moves().contains(&ply));
+        assert_eq!(engine.last_info().map(|info|info.depth), Some(2));
+        position.apply_move(ply);
+    }
+
+    assert!(matches!(ExternalEngine::launch("./no-such-engine"), Err(EngineError::Launch(..))));
+}
+
+/// Engines that misbehave are reported, not trusted.
+#[cfg(unix)]
+#[test]
+fn external_engine_failures(){
+    use std::os::unix::fs::PermissionsExt;
+
+    use hexstack::engine_protocol::GoParams;
+    use hexstack::external_engine::{EngineError, ExternalEngine};
+    use web_time::{Duration, Instant};
+
+    let script = |name : &str, body : &str|{
+        let path = std::env::temp_dir().join(format!("hexstack-test-{}-{}", std::process::id(), name));
+        std::fs::write(&path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path.to_string_lossy().into_owned()
+    };
+    let deadline = ||Instant::now() + Duration::from_secs(10);
+    let position = Position::setup();
+
+    let illegal = script("illegal", "read line; echo hxeok; read line; read line; echo bestmove a1a2; sleep 5");
+    let mut engine = ExternalEngine::launch(&illegal).unwrap();
+    engine.start_search(&position, GoParams::depth(1)).unwrap();
+    assert!(matches!(engine.wait_move(deadline()), Err(EngineError::Illegal(..)) | Err(EngineError::Protocol(..))));
+
+    let crashing = script("crashing", "read line; echo hxeok; read line; exit 1");
+    let mut engine = ExternalEngine::launch(&crashing).unwrap();
+    engine.start_search(&position, GoParams::depth(1)).ok();
+    assert_eq!(engine.wait_move(deadline()), Err(EngineError::Crashed));
+
+    let silent = script("silent", "sleep 5");
+    assert!(matches!(ExternalEngine::launch(&silent).err(), Some(EngineError::Timeout) | Some(EngineError::Crashed)));
+
+    for path in [illegal, crashing, silent]{
+        std::fs::remove_file(path).ok();
+    }
+}
@@ -0,0 +1,141 @@
+//! `tui` processes playing each other on loopback, directly, through a server or found in the lobby.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process::{Child, Command, Stdio};
+
+use hexstack::networking::{Lobby, OpenSeat};
+use hexstack::Player;
+
+fn tui(args : &[&str], input : &str) -> Child{
+    let mut child = Command::new(env!("CARGO_BIN_EXE_tui"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // lines are only read at the prompt, so all of them can be given at once
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    child
+}
+
+#[test]
+fn online_game_between_two_processes(){
+    let mut host = tui(&["--host", "127.0.0.1:0", "--name", "Ada"], "Bb5\nAa3\naccept\nresign\nrecord\n");
+    let mut host_out = BufReader::new(host.stdout.take().unwrap());
+    let mut first = String::new();
+    host_out.read_line(&mut first).unwrap();
+    let address = first.trim().strip_prefix("waiting for an opponent on ").unwrap_or_else(||panic!("{}", first)).to_owned();
+
+    // asks to take back Bd2 and the reply to it, which Ada accepts, then plays Se2 instead
+    let guest = tui(&["--join", &address, "--name", "Bob"], "Bd2\nundo\nSe2\nrecord\n");
+    let guest_output = guest.wait_with_output().unwrap();
+    let mut host_output = String::new();
+    host_out.read_to_string(&mut host_output).unwrap();
+    assert!(host.wait().unwrap().success());
+    assert!(guest_output.status.success());
+    let guest_output = String::from_utf8(guest_output.stdout).unwrap();
+
+    assert!(host_output.contains("playing White against Bob"), "{}", host_output);
+    assert!(host_output.contains("Bob asks to take back 2 plies"), "{}", host_output);
+    assert!(guest_output.contains("Ada agrees to take back 2 plies"), "{}", guest_output);
+    assert!(guest_output.contains("playing Black against Ada"), "{}", guest_output);
+    assert!(guest_output.contains("Ada resigns; Black wins"), "{}", guest_output);
+    for output in [&host_output, &guest_output]{
+        assert!(output.contains("[White \"Ada\"]\n[Black \"Bob\"]"), "{}", output);
+        assert!(output.contains("[Termination \"resignation\"]"), "{}", output);
+        assert!(output.contains("1. Bb5 Se2 0-1"), "{}", output);
+    }
+}
+
+/// Reads lines until one starts with `prefix`, and gives the rest of it.
+fn read_until(output : &mut impl BufRead, prefix : &str) -> String{
+    let mut line = String::new();
+    loop{
+        line.clear();
+        assert!(output.read_line(&mut line).unwrap() > 0, "no line starting with {}", prefix);
+        if let Some(rest) = line.trim().strip_prefix(prefix){
+            return rest.to_owned();
+        }
+    }
+}
+
+#[test]
+fn online_game_through_a_server(){
+    let records = std::env::temp_dir().join(format!("hexstack-server-records-{}.txt", std::process::id()));
+    let _ = std::fs::remove_file(&records);
+    let mut server = Command::new(env!("CARGO_BIN_EXE_hexstack-server"))
+        .args(["--listen", "127.0.0.1:0", "--records", records.to_str().unwrap()])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let mut server_out = BufReader::new(server.stdout.take().unwrap());
+    let address = read_until(&mut server_out, "listening on ");
+
+    let mut ada = tui(&["--server", &address, "--room", "new", "--name", "Ada"], "Bb5\nresign\nrecord\n");
+    let mut ada_out = BufReader::new(ada.stdout.take().unwrap());
+    let code = read_until(&mut ada_out, "playing White in room ").strip_suffix("; waiting for an opponent").unwrap().to_owned();
+
+    let bob = tui(&["--server", &address, "--room", &code, "--name", "Bob"], "Bd2\nrecord\n");
+    let bob_output = bob.wait_with_output().unwrap();
+    let mut ada_output = String::new();
+    ada_out.read_to_string(&mut ada_output).unwrap();
+    assert!(ada.wait().unwrap().success());
+    let bob_output = String::from_utf8(bob_output.stdout).unwrap();
+    assert!(ada_output.contains("playing White against Bob"), "{}", ada_output);
+    assert!(bob_output.contains("playing Black against Ada"), "{}", bob_output);
+    assert!(bob_output.contains("Ada resigns; Black wins"), "{}", bob_output);
+
+    // the server records the game once it is over, and closes the room
+    let mut written = String::new();
+    for _ in 0..100{
+        written = std::fs::read_to_string(&records).unwrap_or_default();
+        if !written.is_empty(){
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    }
+    let _ = std::fs::remove_file(&records);
+    assert!(written.contains("[White \"Ada\"]\n[Black \"Bob\"]"), "{}", written);
+    assert!(written.contains(&format!("[Room \"{}\"]", code)), "{}", written);
+    assert!(written.contains("1. Bb5 Bd2 0-1"), "{}", written);
+
+    let mut console = server.stdin.take().unwrap();
+    console.write_all(b"list\nquit\n").unwrap();
+    assert_eq!(read_until(&mut server_out, "no "), "rooms");
+    assert!(server.wait().unwrap().success());
+}
+
+/// Waits for the lobby to list the seats of `name`, until `done` holds for them.
+fn watch_lobby(lobby : &mut Lobby, name : &str, done : impl Fn(&[OpenSeat]) -> bool) -> Vec<OpenSeat>{
+    for _ in 0..200{
+        let seats : Vec<OpenSeat> = lobby.seats().into_iter().filter(|seat|seat.name == name).collect();
+        if done(&seats){
+            return seats;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(25));
+    }
+    panic!("the lobby did not show the seat of {} as expected", name);
+}
+
+#[test]
+fn host_found_in_the_lobby(){
+    let mut lobby = Lobby::open().unwrap();
+    let name = format!("Lobby host {}", std::process::id());
+    let mut host = tui(&["--host", "127.0.0.1:0", "--name", &name, "--color", "black", "--takebacks", "off"], "");
+    let mut host_out = BufReader::new(host.stdout.take().unwrap());
+    let address = read_until(&mut host_out, "waiting for an opponent on ");
+
+    let seats = watch_lobby(&mut lobby, &name, |seats|!seats.is_empty());
+    assert_eq!(seats.len(), 1);
+    assert_eq!(seats[0].address.port().to_string(), address.rsplit_once(':').unwrap().1);
+    assert_eq!((seats[0].host_plays, seats[0].takebacks), (Player::Black, false));
+
+    // a guest takes the seat, which leaves the lobby
+    let guest = tui(&["--join", &seats[0].address.to_string(), "--name", "Bob"], "quit\n");
+    let guest_output = String::from_utf8(guest.wait_with_output().unwrap().stdout).unwrap();
+    assert!(guest_output.contains(&format!("playing White against {}", name)), "{}", guest_output);
+    watch_lobby(&mut lobby, &name, |seats|seats.is_empty());
+    assert!(host.wait().unwrap().success());
+}
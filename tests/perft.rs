@@ -0,0 +1,35 @@
+//! Move generation regression suite.
+//! Reference counts live in `perft_reference.txt`; any change to move
+//! generation, `toss` or `kill` that alters them shows up here.
+
+use hexstack::tokonoma::Position;
+
+const REFERENCE : &str = include_str!("perft_reference.txt");
+
+fn reference_entries() -> impl Iterator<Item = (&'static str, usize, u64)>{
+    REFERENCE.lines()
+    .filter(|l|!l.trim().is_empty() && !l.starts_with('#'))
+    .map(|l|{
+        let fields : Vec<&str> = l.split_whitespace().collect();
+        assert_eq!(fields.len(), 3, "malformed reference line: {}", l);
+        (fields[0], fields[1].parse().unwrap(), fields[2].parse().unwrap())
+    })
+}
+
+#[test]
+fn perft_reference_counts(){
+    for (pstring, depth, expected) in reference_entries(){
+        let position : Position = pstring.parse().unwrap();
+        assert_eq!(position.to_position_string().to_string(), pstring);
+        assert_eq!(position.perft(depth), expected, "perft({}) of {}", depth, pstring);
+    }
+}
+
+#[test]
+fn divide_sums_to_perft(){
+    for (pstring, depth, expected) in reference_entries().filter(|(_,depth,_)|*depth <= 3){
+        let position : Position = pstring.parse().unwrap();
+        let divided : u64 = position.divide(depth).into_iter().map(|(_,count)|count).sum();
+        assert_eq!(divided, expected, "divide({}) of {}", depth, pstring);
+    }
+}